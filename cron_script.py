@@ -17,11 +17,13 @@ if __name__ == '__main__':
     if len(sys.argv) > 1:
         os.chdir(sys.argv[1])
 
-    # remove the session file in case the expiration date is more than 1 day
+    # remove the session file once neither the access token nor the refresh token
+    # is usable anymore - an expired access token alone gets refreshed by the client.
     try:
         with open(".garmin_session.json") as f:
             contents = json.load(f)
-        if time.time() > int(contents['expires_at']):
+        expires_at = max(int(contents['expires_at']), int(contents.get('refresh_token_expires_at', 0)))
+        if time.time() > expires_at:
             print("Removing state session file")
             os.remove(".garmin_session.json")
         else:
@@ -33,27 +35,8 @@ if __name__ == '__main__':
     # also make sure that 'num_days_from_start_date' is set to correct 
     # number of days. If this is a daily job then set to 1.
 
-    # First go through and prune the non-*.zip output files so we don't
-    # parse them the next day.
-    config_file = os.path.join(os.getcwd(), "config", "influxdb_config.json")
-    with open(config_file, "r") as file:
-        config = json.load(file)
-
-    # remove files older than midnight - allows us to keep yesterday's files for a whole day.
-    output_folder = config["file_base_path"]
-    tz = datetime.timezone(-timedelta(hours=5))
-    midnight = datetime.datetime.combine(date.today(), datetime.time(0, 0, 0, 0, tz), tzinfo=tz)
-    print(f"Attempting to prune files in output directory {output_folder}")
-    for root, dirs, files in os.walk(output_folder):
-        for name in files:
-            filename = os.path.join(root, name)
-            timestamp = os.path.getctime(filename)
-            creation_date = datetime.datetime.fromtimestamp(timestamp, tz=tz)
-
-            _, ext = os.path.splitext(filename)
-            if creation_date < midnight and ext in config["files_to_prune"]:
-                print(f"Pruning old file {filename}, created {creation_date}")
-                os.remove(filename)
+    # old output files are pruned by the uploader itself after each run, see
+    # 'files_to_prune' in config/influxdb_config.json.
 
     # set the dates for yesterday so we get a whole days of data
     exe = [os.path.join(os.getcwd(), "target", "debug", "garmin")]
@@ -1,14 +1,26 @@
-use anyhow::Error;
-use log::{error, info};
-use std::{env, path::Path};
+use anyhow::{anyhow, Error};
+use log::{error, info, LevelFilter};
+use std::{
+    collections::{BTreeMap, BTreeSet, HashMap, HashSet},
+    env, fs,
+    path::{Path, PathBuf},
+    str::FromStr,
+};
 
 use config::{Config, File, FileFormat};
 
 use getopts::{Matches, Options};
+use indicatif::{ProgressBar, ProgressStyle};
 
-use garmin_download::DownloadManager;
+use garmin_download::{
+    activity_id_from_url, parse_date_range, ActivityFormat, DownloadManager, Metric, Progress,
+    ProgressCallback,
+};
 use influx_upload::UploadManager;
 
+mod run_report;
+use run_report::RunReport;
+
 fn build_options() -> Options {
     // the presence of any of these options automatically enables
     // the download of the associated data
@@ -55,6 +67,13 @@ fn build_options() -> Options {
         "use YYY-MM-DD format",
     );
 
+    options.optopt(
+        "v",
+        "hrv_date",
+        "download date for HRV data",
+        "use YYY-MM-DD format",
+    );
+
     options.optopt(
         "a",
         "activity_date",
@@ -69,6 +88,13 @@ fn build_options() -> Options {
         "should be relative to file_base_path config",
     );
 
+    options.optopt(
+        "",
+        "examine_output",
+        "with -e, writes the records/field info to this file as JSON instead of printing it",
+        "e.g. fields.json",
+    );
+
     options.optopt(
         "d",
         "download_activity",
@@ -76,17 +102,391 @@ fn build_options() -> Options {
         "saves FIT and json files in <file_base_path>/activities",
     );
 
+    options.optopt(
+        "",
+        "activity_url",
+        "Garmin Connect URL of an activity to download, instead of its ID with -d",
+        "e.g. https://connect.garmin.com/modern/activity/123456789",
+    );
+
+    options.optopt(
+        "",
+        "activity_format",
+        "format to download activity data in with -d, defaults to fit",
+        "one of fit, tcx, gpx or original",
+    );
+
+    options.optflag(
+        "",
+        "since_last",
+        "download each day since the last successful download, up to today",
+    );
+
+    options.optopt(
+        "",
+        "backfill",
+        "download this many days of every enabled stat, up to and including today",
+        "number of days, e.g. 365",
+    );
+
+    options.optopt(
+        "",
+        "activities_since",
+        "downloads every activity since this date, however many that is",
+        "use YYY-MM-DD format",
+    );
+
+    options.optopt(
+        "",
+        "backfill_start",
+        "download every enabled stat from this date up to today",
+        "use YYY-MM-DD format",
+    );
+
+    options.optopt(
+        "",
+        "fill_gaps",
+        "instead of the usual download, downloads each day in this range that isn't uploaded yet",
+        "START,END in YYYY-MM-DD format, e.g. 2024-01-01,2024-03-31",
+    );
+
     options.optflag("", "print_activity_ids", "print all known activity IDs");
 
+    options.optopt(
+        "",
+        "export_csv",
+        "writes a summary of every downloaded activity to a CSV file",
+        "path of the CSV file, e.g. activities.csv",
+    );
+
+    options.optflag(
+        "",
+        "force_upload",
+        "uploads every monitoring FIT file, even ones unchanged since they were last uploaded",
+    );
+
+    options.optflag(
+        "",
+        "no_prune",
+        "keeps all local files after upload, ignoring files_to_prune",
+    );
+
+    options.optflag(
+        "",
+        "prune_dry_run",
+        "logs the local files that would be pruned after upload, without deleting them",
+    );
+
+    options.optopt(
+        "",
+        "garmin_config",
+        "path to the garmin config file",
+        "defaults to config/garmin_config.json",
+    );
+
+    options.optopt(
+        "",
+        "influx_config",
+        "path to the upload config file",
+        "defaults to config/influxdb_config.json",
+    );
+
+    options.optopt(
+        "",
+        "influx_bucket",
+        "uploads to this bucket instead of the one in the upload config",
+        "e.g. a scratch bucket for testing",
+    );
+
+    options.optopt(
+        "",
+        "influx_org",
+        "uploads to this org instead of the one in the upload config",
+        "InfluxDB org name",
+    );
+
+    options.optopt(
+        "",
+        "log_config",
+        "path to the log4rs config file",
+        "defaults to config/log4rs.yml",
+    );
+
+    options.optopt(
+        "",
+        "log_level",
+        "logs at this level instead of the one in the log config",
+        "trace, debug, info, warn or error",
+    );
+
+    options.optflag(
+        "q",
+        "quiet",
+        "only logs warnings and errors, same as --log_level warn",
+    );
+
+    options.optflag("", "verbose", "logs everything, same as --log_level trace");
+
+    options.optopt(
+        "",
+        "run_report",
+        "writes a JSON summary of what this run downloaded, uploaded and failed to this file",
+        "e.g. run_report.json",
+    );
+
+    options.optflag(
+        "",
+        "list_config",
+        "prints the garmin config after all overrides are applied (passwords redacted), then exits",
+    );
+
+    options.optflag(
+        "",
+        "test_login",
+        "only logs in and prints the display name, exiting nonzero on failure",
+    );
+
+    options.optflag(
+        "",
+        "test_influx",
+        "only checks the InfluxDB url, token and bucket, exiting nonzero on failure",
+    );
+
+    options.optopt(
+        "",
+        "reupload",
+        "only parses and uploads this FIT file, logging every field written or skipped",
+        "e.g. data/activities/1234_ACTIVITY.fit",
+    );
+
     options.optflag("h", "help", "print this help menu");
 
     options.optflag("", "disable_download", "ignores data download entirely");
 
     options.optflag("", "disable_upload", "ignores data upload entirely");
 
+    options.optflag(
+        "",
+        "stream",
+        "uploads just the files downloaded this run, rather than everything in file_base_path",
+    );
+
     options
 }
 
+// the path given via 'option', or 'default' (relative to the current dir), as long as it exists
+fn config_path(matches: &Matches, option: &str, default: &Path) -> Result<PathBuf, Error> {
+    let path = match matches.opt_str(option) {
+        Some(path) => PathBuf::from(path),
+        None => default.to_path_buf(),
+    };
+    if !path.is_file() {
+        return Err(anyhow!(
+            "Config file {} does not exist, use --{} to point at another one",
+            path.display(),
+            option
+        ));
+    }
+    Ok(path)
+}
+
+// the level asked for via --log_level, --quiet or --verbose, if any
+fn log_level(matches: &Matches) -> Result<Option<LevelFilter>, Error> {
+    let given = ["log_level", "quiet", "verbose"]
+        .iter()
+        .filter(|option| matches.opt_present(option))
+        .count();
+    if given > 1 {
+        return Err(anyhow!(
+            "Only one of --log_level, --quiet and --verbose can be given"
+        ));
+    }
+    if let Some(level) = matches.opt_str("log_level") {
+        return match LevelFilter::from_str(&level) {
+            Ok(level) if level != LevelFilter::Off => Ok(Some(level)),
+            _ => Err(anyhow!(
+                "Invalid --log_level '{}', expected trace, debug, info, warn or error",
+                level
+            )),
+        };
+    }
+    if matches.opt_present("quiet") {
+        return Ok(Some(LevelFilter::Warn));
+    }
+    if matches.opt_present("verbose") {
+        return Ok(Some(LevelFilter::Trace));
+    }
+    Ok(None)
+}
+
+// the format asked for via --activity_format, FIT if it isn't given
+fn activity_format(matches: &Matches) -> Result<ActivityFormat, Error> {
+    match matches.opt_str("activity_format") {
+        Some(format) => ActivityFormat::from_str(&format)
+            .map_err(|e| anyhow!("Invalid --activity_format: {}", e)),
+        None => Ok(ActivityFormat::default()),
+    }
+}
+
+// sets up logging from 'log_config', with the root logger at 'level' if one was given
+fn init_logging(log_config: &Path, level: Option<LevelFilter>) -> Result<(), Error> {
+    let Some(level) = level else {
+        // reloads the file every 'refresh_rate', if it's set
+        return log4rs::init_file(log_config, Default::default());
+    };
+    let mut config = log4rs::config::load_config_file(log_config, Default::default())?;
+    config.root_mut().set_level(level);
+    // the handle could swap in a new config at runtime, but nothing changes it after this
+    log4rs::init_config(config)?;
+    // loggers configured with their own level log no more than 'level' either
+    log::set_max_level(level);
+    Ok(())
+}
+
+// logs in (reusing a saved session if there is one) and reports who we're logged in as
+async fn test_login(download_manager: &mut DownloadManager) -> Result<(), Error> {
+    let had_session = download_manager.has_valid_session();
+    download_manager
+        .login()
+        .await
+        .map_err(|e| anyhow!("Login failed: {}", e))?;
+    let display_name = download_manager.get_display_name().await;
+    if display_name.is_empty() {
+        return Err(anyhow!("Logged in, but unable to get the user profile"));
+    }
+    if had_session {
+        println!("Found a valid session for {}", display_name);
+    } else {
+        println!("Logged in as {}", display_name);
+    }
+    Ok(())
+}
+
+// the upload config, with any --influx_bucket/--influx_org overrides applied
+fn load_influx_config(matches: &Matches, influx_config: &Path) -> Result<Config, Error> {
+    let mut builder =
+        Config::builder().add_source(File::new(influx_config.to_str().unwrap(), FileFormat::Json));
+    for (option, key) in [("influx_bucket", "bucket"), ("influx_org", "org")] {
+        if let Some(value) = matches.opt_str(option) {
+            if value.trim().is_empty() {
+                return Err(anyhow!("--{} can't be empty", option));
+            }
+            builder = builder.set_override(key, value)?;
+        }
+    }
+    Ok(builder.build()?)
+}
+
+// checks the configured InfluxDB is reachable and accepts our credentials
+async fn test_influx(matches: &Matches, influx_config: &Path) -> Result<(), Error> {
+    let config = load_influx_config(matches, influx_config)?;
+    UploadManager::new(config)
+        .validate()
+        .await
+        .map_err(|e| anyhow!("InfluxDB check failed: {}", e))?;
+    println!("InfluxDB connection OK");
+    Ok(())
+}
+
+// uploads one FIT file on its own, for trying out records_to_include and field filters
+async fn reupload(matches: &Matches, influx_config: &Path, filename: &str) -> Result<(), Error> {
+    let config = load_influx_config(matches, influx_config)?;
+    let written = UploadManager::new(config)
+        .reupload_fit_file(filename)
+        .await
+        .map_err(|e| anyhow!("Unable to reupload {}: {}", filename, e))?;
+    info!("Wrote {} datapoints from {}", written, filename);
+    Ok(())
+}
+
+// the uploaded dataset whose dates show which days of 'metric' are already uploaded
+fn gap_dataset(metric: Metric) -> Option<&'static str> {
+    match metric {
+        Metric::Rhr => Some("resting_heart_rate"),
+        Metric::Summary
+        | Metric::Sleep
+        | Metric::Monitoring
+        | Metric::Hydration
+        | Metric::Hrv
+        | Metric::Vo2Max
+        | Metric::TrainingStatus
+        | Metric::Floors
+        | Metric::IntensityMinutes => Some(metric.name()),
+        _ => None,
+    }
+}
+
+// downloads each day of every enabled metric within 'range' that isn't uploaded yet,
+// returning the files saved
+async fn fill_gaps(
+    matches: &Matches,
+    influx_config: &Path,
+    download_manager: &mut DownloadManager,
+    range: &str,
+) -> Result<Vec<PathBuf>, Error> {
+    let (start, end) =
+        parse_date_range(range).map_err(|e| anyhow!("Invalid --fill_gaps: {}", e))?;
+    let mut upload_manager = UploadManager::new(load_influx_config(matches, influx_config)?);
+    let mut saved: Vec<PathBuf> = Vec::new();
+    for metric in download_manager.enabled_metrics() {
+        let Some(dataset) = gap_dataset(metric) else {
+            info!(
+                "Unable to tell which days of {} are uploaded, not filling its gaps",
+                metric.name()
+            );
+            continue;
+        };
+        let existing = upload_manager.get_existing_dates(dataset).await;
+        saved.extend(
+            download_manager
+                .fill_gaps(metric, start, end, &existing)
+                .await,
+        );
+    }
+    Ok(saved)
+}
+
+// prints each record kind in a FIT file with its field names, or with 'path' writes
+// them there as JSON, e.g. { "monitoring": ["activity_type", "cycles", ...] }
+fn examine_output(
+    record_map: HashMap<String, HashSet<String>>,
+    path: Option<String>,
+) -> Result<(), Error> {
+    // sorted, so the output is the same from run to run
+    let record_map: BTreeMap<String, BTreeSet<String>> = record_map
+        .into_iter()
+        .map(|(kind, fields)| (kind, fields.into_iter().collect()))
+        .collect();
+    match path {
+        Some(path) => {
+            fs::write(&path, serde_json::to_string_pretty(&record_map)?)
+                .map_err(|e| anyhow!("Unable to write {}: {}", path, e))?;
+            info!("Wrote records/field info to {}", path);
+        }
+        None => {
+            for (kind, fields) in record_map {
+                println!("{}: {:?}", kind, fields);
+            }
+        }
+    }
+    Ok(())
+}
+
+// draws download_all() progress on stderr, which indicatif hides when it isn't a terminal
+fn progress_bar() -> ProgressCallback {
+    let bar = ProgressBar::new(0);
+    bar.set_style(
+        ProgressStyle::with_template("{msg:>14} [{bar:40}] {pos}/{len}")
+            .unwrap()
+            .progress_chars("=> "),
+    );
+    Box::new(move |progress: Progress| {
+        bar.set_message(progress.metric.name());
+        bar.set_length(progress.total as u64);
+        bar.set_position(progress.completed as u64);
+    })
+}
+
 fn print_usage(program: &str, opts: Options) {
     let brief = format!("Usage: {} [options]", program);
     print!("{}", opts.usage(&brief));
@@ -111,15 +511,29 @@ async fn main() -> Result<(), Error> {
         return Ok(());
     }
 
-    // use handle to change logger configuration at runtime.
-    // example use cases: https://crates.io/crates/log4rs
     let file_path = env::current_exe().unwrap();
     let cwd: Box<Path> = env::current_dir().unwrap().as_path().into();
 
     println!("Current executable path: {:?}", file_path);
 
-    let handle = log4rs::init_file(cwd.join("config").join("log4rs.yml"), Default::default());
-    match handle {
+    let log_config = config_path(
+        &matches,
+        "log_config",
+        &cwd.join("config").join("log4rs.yml"),
+    )?;
+    let garmin_config = config_path(
+        &matches,
+        "garmin_config",
+        &cwd.join("config").join("garmin_config.json"),
+    )?;
+    let influx_config = config_path(
+        &matches,
+        "influx_config",
+        &cwd.join("config").join("influxdb_config.json"),
+    )?;
+
+    let log_level = log_level(&matches)?;
+    match init_logging(&log_config, log_level) {
         Ok(()) => {
             info!("Successfully loaded log config!");
         }
@@ -129,14 +543,40 @@ async fn main() -> Result<(), Error> {
         }
     }
 
+    let mut report = RunReport::new();
+    let result = run(&matches, &garmin_config, &influx_config, &mut report).await;
+    if let Some(path) = matches.opt_str("run_report") {
+        if let Err(e) = &result {
+            report.error(e.to_string());
+        }
+        match report.write(Path::new(&path)) {
+            Ok(()) => info!("Wrote run report to {}", path),
+            Err(e) => error!("Unable to write run report {}: {}", path, e),
+        }
+    }
+    result
+}
+
+// downloads and uploads everything asked for, noting what happened in 'report'
+async fn run(
+    matches: &Matches,
+    garmin_config: &Path,
+    influx_config: &Path,
+    report: &mut RunReport,
+) -> Result<(), Error> {
+    if matches.opt_present("test_influx") {
+        return test_influx(matches, influx_config).await;
+    }
+    // checked up front, rather than once everything else is downloaded
+    let activity_format = activity_format(matches)?;
+    if let Some(filename) = matches.opt_str("reupload") {
+        return reupload(matches, influx_config, &filename).await;
+    }
+
+    // every file saved this run, for --stream
+    let mut downloaded: Vec<PathBuf> = Vec::new();
     let handle = Config::builder()
-        .add_source(File::new(
-            cwd.join("config")
-                .join("garmin_config.json")
-                .to_str()
-                .unwrap(),
-            FileFormat::Json,
-        ))
+        .add_source(File::new(garmin_config.to_str().unwrap(), FileFormat::Json))
         .build();
     match handle {
         Ok(config) => {
@@ -144,23 +584,77 @@ async fn main() -> Result<(), Error> {
 
             // login and download all configured stats
             let mut download_manager = DownloadManager::new(config, Some(matches.clone()));
+            if matches.opt_present("list_config") {
+                println!("{}", download_manager.get_config().to_redacted_json());
+                return Ok(());
+            }
+            download_manager.set_progress_callback(progress_bar());
+            if matches.opt_present("test_login") {
+                return test_login(&mut download_manager).await;
+            }
+            let mut logged_in = false;
             if matches.opt_present("disable_download") {
                 info!("Not downloading any garmin data");
             } else {
-                download_manager.login().await;
-                download_manager.download_all().await;
+                match download_manager.login().await {
+                    Ok(()) => {
+                        logged_in = true;
+                        let saved = match matches.opt_str("fill_gaps") {
+                            Some(range) => {
+                                fill_gaps(matches, influx_config, &mut download_manager, &range)
+                                    .await?
+                            }
+                            None => download_manager.download_all().await,
+                        };
+                        report.record_files(&saved);
+                        downloaded.extend(saved);
+                    }
+                    Err(e) => {
+                        error!("Unable to log in, skipping download: {}", e);
+                        report.error(format!("Unable to log in: {}", e));
+                    }
+                }
             }
 
-            if let Ok(Some(id)) = matches.opt_get::<String>("d") {
+            let activity_id = match matches.opt_str("activity_url") {
+                Some(url) => match activity_id_from_url(&url) {
+                    Ok(id) => Some(id.to_string()),
+                    Err(e) => {
+                        error!("Not downloading activity from --activity_url: {}", e);
+                        report.error(format!("Invalid --activity_url: {}", e));
+                        None
+                    }
+                },
+                None => matches.opt_get::<String>("d").ok().flatten(),
+            };
+            if let Some(id) = activity_id {
                 info!("Attempting to download activity ID {}...", id);
-                download_manager.login().await; // should be able to call this twice - client looks for session file
-                download_manager
-                    .get_activity_info(id.to_string().parse::<u64>().unwrap())
-                    .await;
-                download_manager
-                    .get_activity_details(id.to_string().parse::<u64>().unwrap())
-                    .await;
+                // should be able to call this twice - client looks for session file
+                if !logged_in {
+                    if let Err(e) = download_manager.login().await {
+                        error!("Unable to log in, not downloading activity {}: {}", id, e);
+                        report.error(format!("Unable to log in: {}", e));
+                    } else {
+                        logged_in = true;
+                    }
+                }
+                if logged_in {
+                    let saved = download_manager
+                        .get_activity_info(id.to_string().parse::<u64>().unwrap())
+                        .await;
+                    report.record_files(&saved);
+                    downloaded.extend(saved);
+                    let saved = download_manager
+                        .get_activity_details(
+                            id.to_string().parse::<u64>().unwrap(),
+                            activity_format,
+                        )
+                        .await;
+                    report.record_files(&saved);
+                    downloaded.extend(saved);
+                }
             }
+            report.record_downloads(download_manager.get_download_summary());
         }
         Err(error) => {
             error!("Error loading garmin config: {:}", error);
@@ -169,34 +663,61 @@ async fn main() -> Result<(), Error> {
     }
 
     // create config for use with uploader
-    let handle = Config::builder()
-        .add_source(File::new(
-            cwd.join("config")
-                .join("influxdb_config.json")
-                .to_str()
-                .unwrap(),
-            FileFormat::Json,
-        ))
-        .build();
-    match handle {
+    match load_influx_config(matches, influx_config) {
         Ok(config) => {
             info!("Successfully loaded influx config!");
 
             // spin up influx publisher and publish data
             let mut upload_manager = UploadManager::new(config);
+            upload_manager.set_force_upload(matches.opt_present("force_upload"));
             if matches.opt_present("disable_upload") {
                 info!("Not uploading any garmin data");
             } else {
-                upload_manager.upload_all().await;
+                if matches.opt_present("stream") {
+                    info!(
+                        "Uploading the {} files downloaded this run",
+                        downloaded.len()
+                    );
+                    upload_manager.upload_files(&downloaded).await;
+                } else {
+                    upload_manager.upload_all().await;
+                }
+                report.record_upload(&upload_manager.get_upload_summary());
+                if matches.opt_present("no_prune") {
+                    info!("Not pruning any local files");
+                } else {
+                    upload_manager
+                        .prune_files(matches.opt_present("prune_dry_run"))
+                        .await;
+                }
             }
 
             if let Ok(Some(filename)) = matches.opt_get::<String>("e") {
-                upload_manager.examine_fit_file_records(&filename);
+                let result = upload_manager
+                    .examine_fit_file_records(&filename)
+                    .map_err(|e| anyhow!(e))
+                    .and_then(|record_map| {
+                        examine_output(record_map, matches.opt_str("examine_output"))
+                    });
+                if let Err(e) = result {
+                    error!("{}", e);
+                    report.error(e.to_string());
+                }
+            }
+
+            if let Some(path) = matches.opt_str("export_csv") {
+                if let Err(e) = upload_manager.export_activities_csv(&path) {
+                    error!("Unable to export activities to {}: {}", path, e);
+                    report.error(format!("Unable to export activities to {}: {}", path, e));
+                }
             }
 
             if matches.opt_present("print_activity_ids") {
-                // TODO: complete this function
-                // let _ = upload_manager.get_uploaded_activity_ids();
+                let ids = upload_manager.get_uploaded_activity_ids().await;
+                println!("{} uploaded activity IDs:", ids.len());
+                for id in ids {
+                    println!("{}", id);
+                }
             }
         }
         Err(error) => {
@@ -207,3 +728,51 @@ async fn main() -> Result<(), Error> {
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn examine_output_test() {
+        let path = std::env::temp_dir().join(format!(
+            "garmin_examine_output_test_{}.json",
+            std::process::id()
+        ));
+        let record_map = HashMap::from([
+            (
+                String::from("monitoring"),
+                HashSet::from([String::from("steps"), String::from("activity_type")]),
+            ),
+            (
+                String::from("file_id"),
+                HashSet::from([String::from("serial_number")]),
+            ),
+        ]);
+        examine_output(record_map, Some(String::from(path.to_str().unwrap()))).unwrap();
+
+        let written: serde_json::Value =
+            serde_json::from_str(&fs::read_to_string(&path).unwrap()).unwrap();
+        assert_eq!(
+            written,
+            serde_json::json!({
+                "file_id": ["serial_number"],
+                "monitoring": ["activity_type", "steps"]
+            })
+        );
+
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn activity_format_test() {
+        let parse = |args: &[&str]| build_options().parse(args).unwrap();
+        assert_eq!(activity_format(&parse(&[])).unwrap(), ActivityFormat::Fit);
+        assert_eq!(
+            activity_format(&parse(&["--activity_format", "TCX"])).unwrap(),
+            ActivityFormat::Tcx
+        );
+        let error = activity_format(&parse(&["--activity_format", "tcxx"])).unwrap_err();
+        assert!(error.to_string().contains("fit, tcx, gpx, original"));
+    }
+}
@@ -0,0 +1,116 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use chrono::Local;
+use serde_derive::Serialize;
+
+use garmin_download::DownloadSummary;
+use influx_upload::UploadSummary;
+
+/// What one run did, written out as JSON with --run_report for monitoring to pick up.
+#[derive(Debug, Serialize)]
+pub struct RunReport {
+    started: String,
+    finished: Option<String>,
+    /// False if anything failed, i.e. 'errors' isn't empty.
+    succeeded: bool,
+    downloads: Vec<MetricReport>,
+    files_written: Vec<String>,
+    bytes_written: u64,
+    upload: Option<UploadSummary>,
+    errors: Vec<String>,
+}
+
+#[derive(Debug, Serialize)]
+struct MetricReport {
+    metric: &'static str,
+    start_date: Option<String>,
+    end_date: Option<String>,
+    requested: usize,
+    succeeded: usize,
+    skipped: usize,
+    failed: usize,
+    failed_dates: Vec<String>,
+}
+
+impl RunReport {
+    pub fn new() -> RunReport {
+        RunReport {
+            started: Local::now().to_rfc3339(),
+            finished: None,
+            succeeded: true,
+            downloads: Vec::new(),
+            files_written: Vec::new(),
+            bytes_written: 0,
+            upload: None,
+            errors: Vec::new(),
+        }
+    }
+
+    pub fn error(&mut self, error: String) {
+        self.errors.push(error);
+    }
+
+    /// Adds every metric in 'summary', and a failure for each with failed days.
+    pub fn record_downloads(&mut self, summary: &DownloadSummary) {
+        for metric in summary.metrics() {
+            let counts = summary.counts(metric);
+            let range = summary.date_range(metric);
+            let failed_dates: Vec<String> = summary
+                .failed_dates(metric)
+                .iter()
+                .map(|date| date.format("%Y-%m-%d").to_string())
+                .collect();
+            if counts.failed > 0 {
+                self.error(format!(
+                    "{} of {} {} downloads failed",
+                    counts.failed,
+                    counts.requested,
+                    metric.name()
+                ));
+            }
+            self.downloads.push(MetricReport {
+                metric: metric.name(),
+                start_date: range.map(|(start, _)| start.format("%Y-%m-%d").to_string()),
+                end_date: range.map(|(_, end)| end.format("%Y-%m-%d").to_string()),
+                requested: counts.requested,
+                succeeded: counts.succeeded,
+                skipped: counts.skipped,
+                failed: counts.failed,
+                failed_dates,
+            });
+        }
+    }
+
+    /// Adds the downloaded 'files' and their size on disk.
+    pub fn record_files(&mut self, files: &[PathBuf]) {
+        for file in files {
+            self.bytes_written += fs::metadata(file).map(|m| m.len()).unwrap_or(0);
+            self.files_written.push(file.display().to_string());
+        }
+    }
+
+    /// Adds the upload counts, and a failure if anything couldn't be read or written.
+    pub fn record_upload(&mut self, summary: &UploadSummary) {
+        if summary.files_skipped > 0 {
+            self.error(format!(
+                "{} files couldn't be uploaded",
+                summary.files_skipped
+            ));
+        }
+        if summary.points_failed > 0 {
+            self.error(format!(
+                "{} datapoints couldn't be written",
+                summary.points_failed
+            ));
+        }
+        self.upload = Some(summary.clone());
+    }
+
+    /// Stamps the end time and writes the report to 'path' as pretty-printed JSON.
+    pub fn write(&mut self, path: &Path) -> Result<(), std::io::Error> {
+        self.finished = Some(Local::now().to_rfc3339());
+        self.succeeded = self.errors.is_empty();
+        fs::write(path, serde_json::to_string_pretty(self)?)
+    }
+}
@@ -1,32 +1,40 @@
 use chrono::{DateTime, Local};
-use log::{debug, info};
+use log::{debug, info, warn};
 use std::collections::HashMap;
-use std::time::{SystemTime, UNIX_EPOCH};
+use std::fs;
+use std::io::Write;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 
-use reqwest;
-use reqwest::header::HeaderMap;
-
-use reqwest_oauth1;
 use reqwest_oauth1::{OAuthClientProvider, TokenReaderError, TokenReaderResult, TokenResponse};
 
-use serde::Deserialize;
+use serde::{Deserialize, Serialize};
+
+use crate::auth_error::{AuthError, AuthStep};
+use crate::headers::ClientHeaders;
+
+/// Caches the OAuth consumer key/secret between runs, so we only fetch them when needed.
+pub const CONSUMER_FILE: &str = ".garmin_oauth_consumer.json";
+
+// retries of the consumer info fetch, each waiting twice as long as the one before
+const CONSUMER_INFO_RETRIES: u32 = 2;
+const CONSUMER_INFO_RETRY_DELAY: Duration = Duration::from_secs(1);
 
 const OAUTH_TOKEN_KEY: &str = "oauth_token";
 const OAUTH_TOKEN_SECRET_KEY: &str = "oauth_token_secret";
 
-#[derive(Default, Deserialize)]
+#[derive(Clone, Default, Deserialize, Serialize)]
 struct ConsumerInfo {
     consumer_key: String,
     consumer_secret: String,
 }
 
-#[derive(Default, Deserialize)]
+#[derive(Clone, Default, Deserialize)]
 struct TokenInfo {
     token_key: String,
     token_secret: String,
 }
 
-#[derive(Default)]
+#[derive(Clone, Default)]
 #[allow(dead_code)]
 pub struct OAuth1Token {
     token_info: TokenInfo,
@@ -35,7 +43,7 @@ pub struct OAuth1Token {
     domain: String,
 }
 
-#[derive(Default, Deserialize)]
+#[derive(Clone, Default, Deserialize, Serialize)]
 #[allow(dead_code)] // need to deserialize message body into this struct
 pub struct OAuth2Token {
     scope: String,
@@ -46,7 +54,8 @@ pub struct OAuth2Token {
     expires_in: u64,
     refresh_token_expires_in: u64,
 }
-#[derive(Default)]
+/// The full OAuth2.0 token plus both expirations, as persisted in the session file.
+#[derive(Clone, Default, Deserialize, Serialize)]
 pub struct OAuth2TokenWrapper {
     pub oauth2_token: OAuth2Token,
     pub expires_at: u64,
@@ -71,6 +80,16 @@ impl OAuth2TokenWrapper {
                 .unwrap()
                 .as_secs()
     }
+
+    /// True if there's a refresh token that can still be exchanged for a new access token.
+    pub fn can_refresh(&self) -> bool {
+        !self.oauth2_token.refresh_token.is_empty()
+            && self.refresh_token_expires_at
+                > SystemTime::now()
+                    .duration_since(UNIX_EPOCH)
+                    .unwrap()
+                    .as_secs()
+    }
 }
 
 // copied from the reqwest oauth1 crate because it's hidden behind private::sealed trait.
@@ -102,11 +121,17 @@ fn read_oauth_token(text: String) -> TokenReaderResult<TokenResponse> {
     }
 }
 
+#[derive(Clone)]
 pub struct GaminOAuthManager {
-    oauth_consumer_url: String,
+    pub(crate) oauth_consumer_url: String,
+    // before the first retry of a failed consumer info fetch
+    pub(crate) consumer_retry_delay: Duration,
+    // e.g. "garmin.com" or "garmin.cn"
+    domain: String,
     consumer_info: ConsumerInfo,
     oauth1_token: OAuth1Token,
     pub oauth2_token: OAuth2TokenWrapper,
+    pub(crate) headers: ClientHeaders,
 }
 
 impl GaminOAuthManager {
@@ -115,45 +140,139 @@ impl GaminOAuthManager {
             oauth_consumer_url: String::from(
                 "https://thegarth.s3.amazonaws.com/oauth_consumer.json",
             ),
+            consumer_retry_delay: CONSUMER_INFO_RETRY_DELAY,
+            domain: String::from(crate::DEFAULT_DOMAIN),
             consumer_info: Default::default(),
             oauth1_token: Default::default(),
             oauth2_token: Default::default(),
+            headers: Default::default(),
+        }
+    }
+
+    pub fn set_domain(&mut self, domain: &str) {
+        self.domain = String::from(domain);
+    }
+
+    pub fn set_headers(&mut self, headers: ClientHeaders) {
+        self.headers = headers;
+    }
+
+    // loads the consumer key/secret from CONSUMER_FILE, fetching (and caching) them if absent.
+    // fetched with the caller's client, so any configured proxy applies here too.
+    async fn load_consumer_info(&mut self, client: &reqwest::Client) -> Result<(), AuthError> {
+        if !self.consumer_info.consumer_key.is_empty() {
+            return Ok(());
+        }
+        if let Ok(contents) = fs::read_to_string(CONSUMER_FILE) {
+            match serde_json::from_str::<ConsumerInfo>(&contents) {
+                Ok(consumer_info) if !consumer_info.consumer_key.is_empty() => {
+                    debug!("Using cached OAuth consumer info from {}", CONSUMER_FILE);
+                    self.consumer_info = consumer_info;
+                    return Ok(());
+                }
+                _ => warn!("Ignoring unreadable {}, fetching again", CONSUMER_FILE),
+            }
+        }
+
+        self.consumer_info = self.fetch_consumer_info(client).await?;
+
+        let result = crate::write_atomically(CONSUMER_FILE, |file| {
+            file.write_all(serde_json::to_string_pretty(&self.consumer_info)?.as_bytes())
+        });
+        if let Err(e) = result {
+            warn!(
+                "Unable to cache OAuth consumer info in {}: {}",
+                CONSUMER_FILE, e
+            );
+        }
+        Ok(())
+    }
+
+    // fetches the consumer key/secret, retrying a couple of times since the bucket
+    // holding them is occasionally unreachable (or serves an error page)
+    async fn fetch_consumer_info(
+        &self,
+        client: &reqwest::Client,
+    ) -> Result<ConsumerInfo, AuthError> {
+        let mut delay = self.consumer_retry_delay;
+        let mut attempt = 0;
+        loop {
+            attempt += 1;
+            info!(
+                "Fetching OAuth consumer info from {}",
+                self.oauth_consumer_url
+            );
+            let consumer_info = match client
+                .get(&self.oauth_consumer_url)
+                .send()
+                .await
+                .and_then(|response| response.error_for_status())
+            {
+                Ok(response) => response.json::<ConsumerInfo>().await,
+                Err(e) => Err(e),
+            };
+            match consumer_info {
+                Ok(consumer_info) => return Ok(consumer_info),
+                Err(e) if attempt <= CONSUMER_INFO_RETRIES => {
+                    warn!(
+                        "Unable to fetch OAuth consumer info (attempt {}/{}), retrying in {}ms: {}",
+                        attempt,
+                        CONSUMER_INFO_RETRIES + 1,
+                        delay.as_millis(),
+                        e
+                    );
+                    tokio::time::sleep(delay).await;
+                    delay *= 2;
+                }
+                Err(e) => {
+                    return Err(AuthError::ConsumerInfo(format!(
+                        "{} after {} attempts ({})",
+                        e, attempt, self.oauth_consumer_url
+                    )))
+                }
+            }
         }
     }
 
+    /// Forgets the cached consumer info, e.g. after Garmin rejected it, so the
+    /// next login fetches it again.
+    pub fn invalidate_consumer_info(&mut self) {
+        self.consumer_info = Default::default();
+        let _ = fs::remove_file(CONSUMER_FILE);
+    }
+
     pub async fn set_oauth1_token(
         &mut self,
         ticket: &str,
         client: reqwest::Client,
-    ) -> Result<String, reqwest_oauth1::Error> {
-        self.consumer_info = reqwest::get(&self.oauth_consumer_url)
-            .await
-            .unwrap()
-            .json::<ConsumerInfo>()
-            .await
-            .unwrap();
+    ) -> Result<String, AuthError> {
+        self.load_consumer_info(&client).await?;
 
         let secrets = reqwest_oauth1::Secrets::new(
             &self.consumer_info.consumer_key,
             &self.consumer_info.consumer_secret,
         );
 
-        let mut endpoint_reqtoken: String =
-            String::from("https://connectapi.garmin.com/oauth-service/oauth/preauthorized");
+        let mut endpoint_reqtoken: String = format!(
+            "https://connectapi.{}/oauth-service/oauth/preauthorized",
+            self.domain
+        );
         endpoint_reqtoken.push_str("?ticket=");
         endpoint_reqtoken.push_str(ticket);
-        endpoint_reqtoken
-            .push_str("&login-url=https://sso.garmin.com/sso/embed&accepts-mfa-tokens=true");
+        endpoint_reqtoken.push_str(&format!(
+            "&login-url=https://sso.{}/sso/embed&accepts-mfa-tokens=true",
+            self.domain
+        ));
 
         debug!("====================================================");
-        debug!("OAuth1.0 endpoint: {}", &endpoint_reqtoken);
+        // the endpoint carries the login ticket, so don't log it as is
+        debug!(
+            "OAuth1.0 endpoint: {}",
+            endpoint_reqtoken.replace(ticket, &crate::redact(ticket))
+        );
         debug!("====================================================");
 
-        let mut headers = HeaderMap::new();
-        headers.insert(
-            "User-Agent",
-            "com.garmin.android.apps.connectmobile".parse().unwrap(),
-        );
+        let headers = self.headers.api();
 
         let response = client
             .oauth1(secrets)
@@ -161,22 +280,27 @@ impl GaminOAuthManager {
             .headers(headers)
             .send()
             .await
-            .unwrap();
+            .map_err(|e| AuthError::Request(AuthStep::OAuth1, e))?;
 
-        let body_text = response.text().await.unwrap();
+        let body_text = response
+            .text()
+            .await
+            .map_err(|e| AuthError::Request(AuthStep::OAuth1, e.into()))?;
 
         debug!("====================================================");
-        debug!("OAuth1.0 response body: {}", &body_text);
+        debug!("OAuth1.0 response body: {}", crate::redact(&body_text));
         debug!("====================================================");
 
-        let token: TokenResponse = read_oauth_token(body_text).unwrap();
+        let token: TokenResponse = read_oauth_token(body_text)
+            .map_err(|e| AuthError::MissingToken(AuthStep::OAuth1, e.to_string()))?;
         self.oauth1_token.token_info.token_key = String::from(&token.oauth_token);
         self.oauth1_token.token_info.token_secret = String::from(&token.oauth_token_secret);
 
         info!("====================================================");
         info!(
             "OAuth1.0 token and secret is: \n token: {}\n secret: {}",
-            token.oauth_token, token.oauth_token_secret
+            crate::redact(&token.oauth_token),
+            crate::redact(&token.oauth_token_secret)
         );
         info!("====================================================");
 
@@ -187,15 +311,9 @@ impl GaminOAuthManager {
         &self.oauth2_token
     }
 
-    pub async fn set_oauth2_token(
-        &mut self,
-        client: reqwest::Client,
-    ) -> Result<String, reqwest_oauth1::Error> {
-        let mut headers = HeaderMap::new();
-        headers.insert(
-            "User-Agent",
-            "com.garmin.android.apps.connectmobile".parse().unwrap(),
-        );
+    pub async fn set_oauth2_token(&mut self, client: reqwest::Client) -> Result<String, AuthError> {
+        self.load_consumer_info(&client).await?;
+        let mut headers = self.headers.api();
         headers.insert(
             "Content-Type",
             "application/x-www-form-urlencoded".parse().unwrap(),
@@ -212,15 +330,22 @@ impl GaminOAuthManager {
 
         let response = client
             .oauth1(secrets)
-            .post("https://connectapi.garmin.com/oauth-service/oauth/exchange/user/2.0")
+            .post(format!(
+                "https://connectapi.{}/oauth-service/oauth/exchange/user/2.0",
+                self.domain
+            ))
             .headers(headers)
             .send()
             .await
-            .unwrap();
+            .map_err(|e| AuthError::Request(AuthStep::OAuth2, e))?;
 
-        let body_text = response.text().await.unwrap();
+        let body_text = response
+            .text()
+            .await
+            .map_err(|e| AuthError::Request(AuthStep::OAuth2, e.into()))?;
 
-        self.oauth2_token.oauth2_token = serde_json::from_str(&body_text).unwrap();
+        self.oauth2_token.oauth2_token = serde_json::from_str(&body_text)
+            .map_err(|e| AuthError::MissingToken(AuthStep::OAuth2, e.to_string()))?;
         self.oauth2_token.update();
         info!(
             "OAuth2.0 refresh expires in {} secs",
@@ -229,4 +354,143 @@ impl GaminOAuthManager {
 
         Ok(String::from(&self.oauth2_token.oauth2_token.access_token))
     }
+
+    /// Exchanges the refresh token for a new access token, skipping the whole
+    /// SSO login (and any MFA prompt).
+    pub async fn refresh_oauth2_token(
+        &mut self,
+        client: reqwest::Client,
+    ) -> Result<String, AuthError> {
+        self.load_consumer_info(&client).await?;
+
+        let headers = self.headers.api();
+
+        let secrets = reqwest_oauth1::Secrets::new(
+            String::from(&self.consumer_info.consumer_key),
+            String::from(&self.consumer_info.consumer_secret),
+        );
+        let form = HashMap::from([
+            ("grant_type", String::from("refresh_token")),
+            (
+                "refresh_token",
+                String::from(&self.oauth2_token.oauth2_token.refresh_token),
+            ),
+        ]);
+
+        let response = client
+            .oauth1(secrets)
+            .post(format!(
+                "https://connectapi.{}/oauth-service/oauth/exchange/user/2.0",
+                self.domain
+            ))
+            .headers(headers)
+            .form(&form)
+            .send()
+            .await
+            .map_err(|e| AuthError::Request(AuthStep::Refresh, e))?;
+
+        let body_text = response
+            .text()
+            .await
+            .map_err(|e| AuthError::Request(AuthStep::Refresh, e.into()))?;
+
+        self.oauth2_token.oauth2_token = serde_json::from_str(&body_text)
+            .map_err(|e| AuthError::MissingToken(AuthStep::Refresh, e.to_string()))?;
+        self.oauth2_token.update();
+        info!(
+            "Refreshed OAuth2.0 token, expires in {} secs",
+            self.oauth2_token.oauth2_token.expires_in
+        );
+
+        Ok(String::from(&self.oauth2_token.oauth2_token.access_token))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // serves each canned HTTP response on a local port in turn, one per connection
+    fn serve_in_order(responses: Vec<&'static [u8]>) -> String {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/", listener.local_addr().unwrap());
+        std::thread::spawn(move || {
+            for response in responses {
+                let (mut stream, _) = listener.accept().unwrap();
+                let mut request = [0u8; 1024];
+                let _ = std::io::Read::read(&mut stream, &mut request);
+                stream.write_all(response).unwrap();
+            }
+        });
+        url
+    }
+
+    #[tokio::test]
+    async fn consumer_info_retry_test() {
+        let mut manager = GaminOAuthManager::new();
+        manager.consumer_retry_delay = Duration::from_millis(10);
+        manager.oauth_consumer_url = serve_in_order(vec![
+            b"HTTP/1.1 503 Service Unavailable\r\nContent-Length: 0\r\nConnection: close\r\n\r\n",
+            b"HTTP/1.1 200 OK\r\nContent-Length: 52\r\nConnection: close\r\n\r\n{\"consumer_key\": \"key\", \"consumer_secret\": \"secret\"}",
+        ]);
+        let client = reqwest::Client::new();
+        let consumer_info = manager.fetch_consumer_info(&client).await.unwrap();
+        assert_eq!(consumer_info.consumer_key, "key");
+        assert_eq!(consumer_info.consumer_secret, "secret");
+
+        // still failing after every retry
+        manager.oauth_consumer_url = serve_in_order(vec![
+            b"HTTP/1.1 503 Service Unavailable\r\nContent-Length: 0\r\nConnection: close\r\n\r\n",
+            b"HTTP/1.1 200 OK\r\nContent-Length: 8\r\nConnection: close\r\n\r\nnot json",
+            b"HTTP/1.1 503 Service Unavailable\r\nContent-Length: 0\r\nConnection: close\r\n\r\n",
+        ]);
+        let Err(error) = manager.fetch_consumer_info(&client).await else {
+            panic!("expected the fetch to fail");
+        };
+        assert!(matches!(error, AuthError::ConsumerInfo(_)));
+        assert!(error.to_string().contains("after 3 attempts"));
+    }
+
+    #[test]
+    fn can_refresh_test() {
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_secs();
+        let mut token = OAuth2TokenWrapper::default();
+        assert!(!token.can_refresh());
+
+        token.oauth2_token.refresh_token = String::from("refresh");
+        token.refresh_token_expires_at = now + 60;
+        assert!(token.is_expired());
+        assert!(token.can_refresh());
+
+        token.refresh_token_expires_at = now - 60;
+        assert!(!token.can_refresh());
+    }
+
+    #[test]
+    fn session_round_trip_test() {
+        let mut token = OAuth2TokenWrapper::default();
+        token.oauth2_token.access_token = String::from("access");
+        token.oauth2_token.refresh_token = String::from("refresh");
+        token.oauth2_token.token_type = String::from("Bearer");
+        token.oauth2_token.scope = String::from("CONNECT_READ");
+        token.oauth2_token.refresh_token_expires_in = 7200;
+        token.expires_at = 100;
+        token.refresh_token_expires_at = 200;
+
+        // the download manager adds user profile info to the same file
+        let mut session = serde_json::to_value(&token).unwrap();
+        session["displayName"] = serde_json::json!("runner");
+
+        let restored: OAuth2TokenWrapper = serde_json::from_value(session).unwrap();
+        assert_eq!(restored.oauth2_token.access_token, "access");
+        assert_eq!(restored.oauth2_token.refresh_token, "refresh");
+        assert_eq!(restored.oauth2_token.token_type, "Bearer");
+        assert_eq!(restored.oauth2_token.scope, "CONNECT_READ");
+        assert_eq!(restored.oauth2_token.refresh_token_expires_in, 7200);
+        assert_eq!(restored.expires_at, 100);
+        assert_eq!(restored.refresh_token_expires_at, 200);
+    }
 }
@@ -0,0 +1,66 @@
+use std::fmt;
+
+/// A step of the OAuth exchange that follows the SSO login.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AuthStep {
+    /// Exchanging the login ticket for an OAuth1.0 token.
+    OAuth1,
+    /// Exchanging the OAuth1.0 token for an OAuth2.0 token.
+    OAuth2,
+    /// Exchanging the OAuth2.0 refresh token for a new access token.
+    Refresh,
+}
+
+impl fmt::Display for AuthStep {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AuthStep::OAuth1 => write!(f, "OAuth1.0 token exchange"),
+            AuthStep::OAuth2 => write!(f, "OAuth2.0 token exchange"),
+            AuthStep::Refresh => write!(f, "OAuth2.0 token refresh"),
+        }
+    }
+}
+
+/// Why the OAuth exchange after the SSO login failed.
+#[derive(Debug)]
+pub enum AuthError {
+    /// The OAuth consumer key/secret couldn't be fetched.
+    ConsumerInfo(String),
+    /// The token request for a step couldn't be sent or signed, e.g. a connection error.
+    Request(AuthStep, reqwest_oauth1::Error),
+    /// Garmin responded without the token we asked for, e.g. after an auth change.
+    MissingToken(AuthStep, String),
+}
+
+impl AuthError {
+    /// The step that failed, if it got past fetching the consumer info.
+    pub fn step(&self) -> Option<AuthStep> {
+        match self {
+            AuthError::ConsumerInfo(_) => None,
+            AuthError::Request(step, _) | AuthError::MissingToken(step, _) => Some(*step),
+        }
+    }
+}
+
+impl fmt::Display for AuthError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AuthError::ConsumerInfo(reason) => {
+                write!(f, "unable to get OAuth consumer info: {}", reason)
+            }
+            AuthError::Request(step, e) => write!(f, "{} request failed: {}", step, e),
+            AuthError::MissingToken(step, reason) => {
+                write!(f, "{} returned no token: {}", step, reason)
+            }
+        }
+    }
+}
+
+impl std::error::Error for AuthError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            AuthError::Request(_, e) => Some(e),
+            _ => None,
+        }
+    }
+}
@@ -0,0 +1,82 @@
+use log::warn;
+use reqwest::header::{HeaderMap, HeaderName, HeaderValue, USER_AGENT};
+
+/// Sent with the SSO login pages, which expect a mobile browser.
+pub const DEFAULT_SSO_USER_AGENT: &str = "Mozilla/5.0 (iPhone; CPU iPhone OS 16_5 like Mac OS X) AppleWebKit/605.1.15 (KHTML, like Gecko) Mobile/15E148";
+
+/// Sent with the OAuth exchanges and API requests, which expect the Connect app.
+pub const DEFAULT_API_USER_AGENT: &str = "com.garmin.android.apps.connectmobile";
+
+/// Headers sent with every request, overridable in case Garmin changes what it accepts.
+#[derive(Clone, Debug)]
+pub struct ClientHeaders {
+    sso_user_agent: HeaderValue,
+    api_user_agent: HeaderValue,
+    // sent with every request, after (so overriding) the user agent
+    extra: HeaderMap,
+}
+
+impl Default for ClientHeaders {
+    fn default() -> Self {
+        ClientHeaders {
+            sso_user_agent: HeaderValue::from_static(DEFAULT_SSO_USER_AGENT),
+            api_user_agent: HeaderValue::from_static(DEFAULT_API_USER_AGENT),
+            extra: HeaderMap::new(),
+        }
+    }
+}
+
+impl ClientHeaders {
+    /// Replaces the user agents, keeping the current ones for empty or invalid values.
+    pub fn set_user_agents(&mut self, sso_user_agent: &str, api_user_agent: &str) {
+        if let Some(value) = parse_value("User-Agent", sso_user_agent) {
+            self.sso_user_agent = value;
+        }
+        if let Some(value) = parse_value("User-Agent", api_user_agent) {
+            self.api_user_agent = value;
+        }
+    }
+
+    /// Adds a header to every request, skipping (and logging) invalid names or values.
+    pub fn add_extra(&mut self, name: &str, value: &str) {
+        let Ok(header_name) = HeaderName::from_bytes(name.as_bytes()) else {
+            warn!("Ignoring invalid header name '{}'", name);
+            return;
+        };
+        if let Some(value) = parse_value(name, value) {
+            self.extra.insert(header_name, value);
+        }
+    }
+
+    /// Headers for the SSO login flow.
+    pub fn sso(&self) -> HeaderMap {
+        self.build(&self.sso_user_agent)
+    }
+
+    /// Headers for the OAuth exchanges and API requests.
+    pub fn api(&self) -> HeaderMap {
+        self.build(&self.api_user_agent)
+    }
+
+    fn build(&self, user_agent: &HeaderValue) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        headers.insert(USER_AGENT, user_agent.clone());
+        for (name, value) in &self.extra {
+            headers.insert(name, value.clone());
+        }
+        headers
+    }
+}
+
+fn parse_value(name: &str, value: &str) -> Option<HeaderValue> {
+    if value.is_empty() {
+        return None;
+    }
+    match HeaderValue::from_str(value) {
+        Ok(value) => Some(value),
+        Err(_) => {
+            warn!("Ignoring invalid value for header '{}'", name);
+            None
+        }
+    }
+}
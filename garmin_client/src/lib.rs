@@ -1,25 +1,109 @@
 use log::{debug, error, info, warn};
 use regex::Regex;
-use reqwest::header::HeaderMap;
-use reqwest::{Client, Response};
+use reqwest::header::{HeaderMap, RETRY_AFTER};
+use reqwest::{Client, Proxy, Response, StatusCode};
+use serde::de::DeserializeOwned;
+use serde::Serialize;
 use serde_json::Value;
 use std::cmp::min;
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
 use std::fs;
 use std::fs::File;
 use std::io::{stdin, stdout};
 use std::io::{BufWriter, Write};
-use std::path::Path;
-use std::time::{SystemTime, UNIX_EPOCH};
-use zip;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
 
 mod auth;
+mod auth_error;
+mod headers;
+mod login_error;
+mod rate_limit;
+
+pub use auth::CONSUMER_FILE;
+pub use auth_error::{AuthError, AuthStep};
+pub use headers::{DEFAULT_API_USER_AGENT, DEFAULT_SSO_USER_AGENT};
+pub use login_error::LoginError;
 
 pub const SESSION_FILE: &str = ".garmin_session.json";
 
+/// Lists the files extracted from each downloaded archive, since the archive itself
+/// is removed (or moved) afterwards. See GarminClient::already_extracted().
+/// DownloadManager keeps it in file_base_path.
+pub const EXTRACTED_FILE: &str = ".garmin_extracted.json";
+
+/// Used when no domain is configured. Users in China should use "garmin.cn".
+pub const DEFAULT_DOMAIN: &str = "garmin.com";
+
+/// Describes a secret (password, token, etc.) without revealing it, for logging.
+pub fn redact(secret: &str) -> String {
+    format!("<redacted, {} chars>", secret.len())
+}
+
+// used when a 429 response doesn't say how long to wait
+const DEFAULT_RETRY_AFTER: Duration = Duration::from_secs(60);
+const MAX_RATE_LIMIT_RETRIES: u32 = 3;
+// times an interrupted binary download is started over
+const DEFAULT_DOWNLOAD_RETRIES: u32 = 2;
+
+// how a binary download ended, see save_as_binary()
+#[derive(Debug, PartialEq)]
+enum BinaryDownload {
+    // the files now on disk, i.e. the download itself or the archive's contents
+    Complete(Vec<PathBuf>),
+    // nothing to save
+    Empty,
+    // the connection dropped or ended early, worth downloading again
+    Interrupted,
+    // the file couldn't be written
+    Failed,
+}
+
+// every request goes through this client. without a proxy, reqwest falls back to
+// the HTTP_PROXY/HTTPS_PROXY environment variables.
+fn build_http_client(proxy: Option<Proxy>) -> Result<Client, reqwest::Error> {
+    let mut builder = Client::builder().cookie_store(true);
+    if let Some(proxy) = proxy {
+        builder = builder.proxy(proxy);
+    }
+    builder.build()
+}
+
+// "https://www.garmin.cn/" -> "garmin.cn"
+fn normalize_domain(domain: &str) -> String {
+    let domain = domain.trim();
+    let domain = domain
+        .strip_prefix("https://")
+        .or_else(|| domain.strip_prefix("http://"))
+        .unwrap_or(domain);
+    let domain = domain.strip_prefix("www.").unwrap_or(domain);
+    let domain = domain.trim_end_matches('/');
+    if domain.is_empty() {
+        String::from(DEFAULT_DOMAIN)
+    } else {
+        String::from(domain)
+    }
+}
+
+// Maps the title of the login response page to the login outcome.
+fn login_title_result(title: Option<String>) -> Result<String, LoginError> {
+    match title.as_deref() {
+        Some("Success") => Ok(String::from("Success")),
+        // testing shows that this title is received with incorrect credentials.
+        Some("GARMIN Authentication Application") => Err(LoginError::BadCredentials),
+        Some(title) => Err(LoginError::Unexpected(format!(
+            "login page title '{}'",
+            title
+        ))),
+        None => Err(LoginError::Unexpected(String::from(
+            "no title in login response",
+        ))),
+    }
+}
+
 /// Basic set of public functions required to use this client.
 pub trait ClientTraits {
-    fn login(&mut self, username: &str, password: &str) -> bool;
+    fn login(&mut self, username: &str, password: &str) -> Result<(), LoginError>;
     fn api_request(&mut self, endpoint: &str) -> ();
 }
 
@@ -31,7 +115,11 @@ pub trait ClientTraits {
 /// This client is intended for use with the garmin_download crate, which
 /// is already configured with the various garmin backends, although that
 /// integration is obviously not required to operate this client separately.
+///
+/// Cloning is cheap (the underlying connection pool is shared), and lets callers run
+/// several requests concurrently. Each clone tracks its own last response and token.
 // #[allow(dead_code)]
+#[derive(Clone)]
 pub struct GarminClient {
     client: Client,
     auth_host: String,
@@ -40,7 +128,24 @@ pub struct GarminClient {
     last_sso_resp_text: String,
     last_api_resp_url: String,
     last_api_resp_text: String,
+    last_api_status: Option<StatusCode>,
+    last_saved_files: Vec<PathBuf>,
     oauth_manager: auth::GaminOAuthManager,
+    extract_archives: bool,
+    keep_archives: bool,
+    download_retries: u32,
+    rate_limiter: rate_limit::RateLimiter,
+    session_file: String,
+    extracted_file: String,
+    headers: headers::ClientHeaders,
+    // from the last login(), to log in again (once) if the token can't be refreshed
+    credentials: Option<(String, String)>,
+}
+
+impl Default for GarminClient {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl GarminClient {
@@ -48,14 +153,24 @@ impl GarminClient {
     // https://github.com/matin/garth/blob/main/garth/sso.py
     pub fn new() -> GarminClient {
         GarminClient {
-            client: Client::builder().cookie_store(true).build().unwrap(),
-            auth_host: String::from("sso.garmin.com"),
-            api_host: String::from("connectapi.garmin.com"),
+            client: build_http_client(None).unwrap(),
+            auth_host: format!("sso.{}", DEFAULT_DOMAIN),
+            api_host: format!("connectapi.{}", DEFAULT_DOMAIN),
             last_sso_resp_url: String::new(),
             last_sso_resp_text: String::new(),
             last_api_resp_url: String::new(),
             last_api_resp_text: String::new(),
+            last_api_status: None,
+            last_saved_files: Vec::new(),
             oauth_manager: auth::GaminOAuthManager::new(),
+            extract_archives: true,
+            keep_archives: false,
+            download_retries: DEFAULT_DOWNLOAD_RETRIES,
+            rate_limiter: Default::default(),
+            session_file: String::from(SESSION_FILE),
+            extracted_file: String::from(EXTRACTED_FILE),
+            headers: Default::default(),
+            credentials: None,
         }
     }
 
@@ -91,7 +206,7 @@ impl GarminClient {
         ub.build()
     }
 
-    async fn set_cookie(&mut self) -> bool {
+    async fn set_cookie(&mut self) -> Result<(), LoginError> {
         /*
         Called before actual login so we can get csrf token.
         */
@@ -115,30 +230,39 @@ impl GarminClient {
         debug!("Requesting url for cookies: {}", url);
         debug!("====================================================");
 
-        let response = self.client.get(&url).send().await.unwrap();
+        let response = self
+            .client
+            .get(&url)
+            .headers(self.headers.sso())
+            .send()
+            .await?;
         self.last_sso_resp_url = response.url().to_string();
-        self.last_sso_resp_text = response.text().await.unwrap();
-        true
+        self.last_sso_resp_text = response.text().await?;
+        Ok(())
     }
 
-    async fn get_csrf_token(&mut self) -> String {
+    async fn get_csrf_token(&mut self) -> Result<String, LoginError> {
         let url: String = self.build_auth_url("signin");
 
         debug!("====================================================");
         debug!("Requesting url for csrf: {}", url);
         debug!("====================================================");
 
-        let mut headers = HeaderMap::new();
+        let mut headers = self.headers.sso();
         headers.insert("referer", self.last_sso_resp_url.as_str().parse().unwrap());
-        headers.insert("User-Agent", "Mozilla/5.0 (iPhone; CPU iPhone OS 16_5 like Mac OS X) AppleWebKit/605.1.15 (KHTML, like Gecko) Mobile/15E148".parse().unwrap());
 
-        let response = self.client.get(&url).headers(headers).send().await.unwrap();
+        let response = self.client.get(&url).headers(headers).send().await?;
         self.last_sso_resp_url = response.url().to_string();
-        self.last_sso_resp_text = response.text().await.unwrap();
+        self.last_sso_resp_text = response.text().await?;
         self.parse_csrf_token(&self.last_sso_resp_text)
     }
 
-    async fn submit_login(&mut self, username: &str, password: &str, csrf_token: &str) -> bool {
+    async fn submit_login(
+        &mut self,
+        username: &str,
+        password: &str,
+        csrf_token: &str,
+    ) -> Result<(), LoginError> {
         // is this broken? I'm to get csrf token and get here, but am getting a 500 internal server
         // error when making this request.
         let url = self.build_auth_url("signin");
@@ -147,9 +271,8 @@ impl GarminClient {
         debug!("Requesting url for login: {}", url);
         debug!("====================================================");
 
-        let mut headers = HeaderMap::new();
+        let mut headers = self.headers.sso();
         headers.insert("referer", self.last_sso_resp_url.as_str().parse().unwrap());
-        headers.insert("User-Agent", "Mozilla/5.0 (iPhone; CPU iPhone OS 16_5 like Mac OS X) AppleWebKit/605.1.15 (KHTML, like Gecko) Mobile/15E148".parse().unwrap());
 
         // these need to be sent as request body
         let form = HashMap::from([
@@ -165,139 +288,117 @@ impl GarminClient {
             .headers(headers)
             .form(&form)
             .send()
-            .await
-            .unwrap();
+            .await?;
 
         self.last_sso_resp_url = response.url().to_string();
-        self.last_sso_resp_text = response.text().await.unwrap();
+        self.last_sso_resp_text = response.text().await?;
 
         debug!("====================================================");
         debug!("Got login reponse: {}", self.last_sso_resp_text);
         debug!("====================================================");
-        true
+        Ok(())
     }
 
-    fn parse_csrf_token(&self, response_html: &String) -> String {
+    fn parse_csrf_token(&self, response_html: &str) -> Result<String, LoginError> {
         let re = Regex::new(r#"name="_csrf"\s+value="(\w+)"#).unwrap();
-        for (_, [csrf]) in re.captures_iter(&response_html).map(|c| c.extract()) {
+        if let Some((_, [csrf])) = re.captures_iter(response_html).map(|c| c.extract()).next() {
             debug!("====================================================");
-            debug!("Found csrf token: {}", csrf);
+            debug!("Found csrf token: {}", redact(csrf));
             debug!("====================================================");
-            return String::from(csrf);
+            return Ok(String::from(csrf));
         }
-        error!("====================================================");
-        panic!("Unable to find csrf token in body: {}", response_html);
+        debug!("Unable to find csrf token in body: {}", response_html);
+        Err(LoginError::MissingCsrfToken)
     }
 
-    fn parse_title(&self, response_html: &String) -> String {
+    fn parse_title(&self, response_html: &str) -> Option<String> {
         let re = Regex::new(r#"<title>(.+?)</title>"#).unwrap();
-        for (_, [title]) in re.captures_iter(&response_html).map(|c| c.extract()) {
-            debug!("====================================================");
-            if title == "Success" {
-                info!("Got successful login!");
-                return String::from(title);
-            } else if title == "GARMIN Authentication Application" {
-                // testing shows that this title is received with incorrect credentials.
-                error!("Got unsuccessful login :( check your credentials?");
-            } else {
-                // could possibly have MFA requirement, just return it
-                return String::from(title);
-            }
-            debug!("====================================================");
-        }
-        error!("====================================================");
-        panic!("Unable to find title in body: {}", response_html);
+        let (_, [title]) = re
+            .captures_iter(response_html)
+            .map(|c| c.extract())
+            .next()?;
+        debug!("Got login response title: {}", title);
+        Some(String::from(title))
     }
 
-    fn parse_ticket(&self, response_html: &String) -> String {
+    fn parse_ticket(&self, response_html: &str) -> Result<String, LoginError> {
         let re = Regex::new(r#"embed\?ticket=([^"]+)""#).unwrap();
-        for (_, [ticket]) in re.captures_iter(&response_html).map(|c| c.extract()) {
+        if let Some((_, [ticket])) = re.captures_iter(response_html).map(|c| c.extract()).next() {
             debug!("====================================================");
-            debug!("Found ticket: {}", ticket);
+            debug!("Found ticket: {}", redact(ticket));
             debug!("====================================================");
-            return String::from(ticket);
+            return Ok(String::from(ticket));
         }
-        error!("====================================================");
-        panic!("Unable to find ticket in body: {}", response_html);
+        debug!("Unable to find ticket in body: {}", response_html);
+        Err(LoginError::MissingTicket)
     }
 
     /// The first main interface - requires just a username and password,
-    /// and obtains an OAuth2.0 access token. Returns false if unsuccessful.
-    pub async fn login(&mut self, username: &str, password: &str) -> bool {
+    /// and obtains an OAuth2.0 access token.
+    pub async fn login(&mut self, username: &str, password: &str) -> Result<(), LoginError> {
+        self.credentials = Some((String::from(username), String::from(password)));
+
         // if we have a valid token then continue to use it
         if self.retrieve_json_session() {
-            return true;
+            return Ok(());
         }
 
-        // set cookies (looks like this still works)
-        if !self.set_cookie().await {
-            error!("Unable to set session, cannot proceed with authentication");
-            return false;
+        // otherwise a still valid refresh token saves us the whole SSO flow
+        if self.oauth_manager.get_oauth2_token().can_refresh() && self.refresh_session().await {
+            return Ok(());
         }
 
-        // get csrf token (appears to work still as well, although not 100% its correct)
-        let csrf_token: String = self.get_csrf_token().await;
+        // set cookies (looks like this still works)
+        self.set_cookie().await?;
 
-        if csrf_token.len() == 0 {
-            panic!(
-                "Unable to find CSRF token in response: {}",
-                &self.last_sso_resp_text
-            );
-        }
+        // get csrf token (appears to work still as well, although not 100% its correct)
+        let csrf_token: String = self.get_csrf_token().await?;
 
         // Submit login form with email and password
-        self.submit_login(username, password, &csrf_token).await;
-        let mut title = self.parse_title(&self.last_sso_resp_text);
-        if title.len() == 0 {
-            panic!(
-                "Unable to find 'title' field in response: {}",
-                &self.last_sso_resp_text
-            );
-        }
+        self.submit_login(username, password, &csrf_token).await?;
+        let title = self.parse_title(&self.last_sso_resp_text);
 
         // handle any MFA for user
-        if title.contains("MFA") {
-            self.handle_mfa().await;
-            title = self.parse_title(&self.last_sso_resp_text);
-        }
-
-        if title != "Success" {
-            error!("Unable to authenticate user!");
-            return false;
-        }
+        let title = match title {
+            Some(title) if title.contains("MFA") => {
+                self.handle_mfa().await?;
+                match self.parse_title(&self.last_sso_resp_text) {
+                    Some(title) if title == "Success" => title,
+                    _ => return Err(LoginError::MfaFailed),
+                }
+            }
+            title => login_title_result(title)?,
+        };
+        info!("Got successful login! ({})", title);
 
-        let ticket = self.parse_ticket(&self.last_sso_resp_text);
-        if ticket.len() == 0 {
-            return false;
-        }
+        let ticket = self.parse_ticket(&self.last_sso_resp_text)?;
 
-        self.set_oauth1_token(&ticket).await;
-        if !(self.set_oauth2_token().await) {
-            return false;
-        }
+        self.set_oauth1_token(&ticket).await?;
+        self.set_oauth2_token().await?;
         self.save_json_session();
-        true
+        Ok(())
     }
 
-    async fn handle_mfa(&mut self) {
-        let csrf_token: String = self.get_csrf_token().await;
+    async fn handle_mfa(&mut self) -> Result<(), LoginError> {
+        let csrf_token: String = self.get_csrf_token().await?;
 
         let mut mfa_code = String::new();
         print!("Enter MFA code: ");
         let _ = stdout().flush();
-        stdin()
-            .read_line(&mut mfa_code)
-            .expect("Did not enter a correct string");
+        // e.g. running from cron, with nobody around to type the code
+        if stdin().read_line(&mut mfa_code).is_err() || mfa_code.trim().is_empty() {
+            return Err(LoginError::MfaRequired);
+        }
+        let mfa_code = String::from(mfa_code.trim());
 
-        let mut headers = HeaderMap::new();
+        let mut headers = self.headers.sso();
         headers.insert("referer", self.last_sso_resp_url.as_str().parse().unwrap());
-        headers.insert("User-Agent", "Mozilla/5.0 (iPhone; CPU iPhone OS 16_5 like Mac OS X) AppleWebKit/605.1.15 (KHTML, like Gecko) Mobile/15E148".parse().unwrap());
 
         let form = HashMap::from([
-            ("mfa-code", String::from(mfa_code)),
+            ("mfa-code", mfa_code),
             ("fromPage", String::from("setupEnterMfaCode")),
             ("embed", String::from("true")),
-            ("_csrf", String::from(csrf_token)),
+            ("_csrf", csrf_token),
         ]);
 
         let url = self.build_auth_url("verifyMFA/loginEnterMfaCode");
@@ -308,39 +409,40 @@ impl GarminClient {
             .headers(headers)
             .form(&form)
             .send()
-            .await
-            .unwrap();
+            .await?;
 
         self.last_sso_resp_url = response.url().to_string();
-        self.last_sso_resp_text = response.text().await.unwrap();
-    }
-
-    async fn set_oauth1_token(&mut self, ticket: &str) {
-        let oauth1_token: String = self
-            .oauth_manager
-            .set_oauth1_token(ticket, self.client.clone())
-            .await
-            .unwrap();
-        info!("Got oauth1 token: {}", oauth1_token);
+        self.last_sso_resp_text = response.text().await?;
+        Ok(())
     }
 
-    async fn set_oauth2_token(&mut self) -> bool {
+    async fn set_oauth1_token(&mut self, ticket: &str) -> Result<(), AuthError> {
         match self
             .oauth_manager
-            .set_oauth2_token(self.client.clone())
+            .set_oauth1_token(ticket, self.client.clone())
             .await
         {
-            Ok(token) => {
-                info!("Got oauth2 token: {}", token);
-                true
+            Ok(oauth1_token) => {
+                info!("Got oauth1 token: {}", redact(&oauth1_token));
+                Ok(())
             }
             Err(e) => {
-                error!("Unable to obtain oauth2_token: {}", e);
-                false
+                // the cached consumer info may be stale, fetch it again next time
+                self.oauth_manager.invalidate_consumer_info();
+                Err(e)
             }
         }
     }
 
+    async fn set_oauth2_token(&mut self) -> Result<(), AuthError> {
+        let token = self
+            .oauth_manager
+            .set_oauth2_token(self.client.clone())
+            .await?;
+        info!("Got oauth2 token: {}", redact(&token));
+        Ok(())
+    }
+
     /// After logging in, use this API interface to download data. Some URLs download
     /// json data, and some download zip files (that are auto-extracted into FIT files here).
     ///
@@ -350,6 +452,9 @@ impl GarminClient {
     /// By specifying filepath=None, the data is not saved to file. JSON text responses
     /// can be retrieved via the get_last_resp_text() method; however binary (i.e., FIT file)
     /// downloads are dropped if not saved to file currently.
+    ///
+    /// Returns false if the request failed, Garmin answered with an error status, or
+    /// the response couldn't be saved to 'filepath'.
     pub async fn api_request(
         &mut self,
         endpoint: &str,
@@ -359,12 +464,12 @@ impl GarminClient {
     ) -> bool {
         // use for actual application data downloads
         let url = self.build_api_url(endpoint);
+        self.last_saved_files.clear();
 
-        if self.oauth_manager.get_oauth2_token().is_expired() {
-            info!("====================================================");
-            info!("ConnectAPI refreshing OAuth2.0 token...");
-            info!("====================================================");
-            self.set_oauth2_token().await;
+        // a stale token only gets a 401, which looks just like an empty response
+        if !self.refresh_oauth2_token_if_expired().await && !self.relogin().await {
+            error!("No valid OAuth2.0 token, not requesting {}", &url);
+            return false;
         }
 
         let access_token: String = String::from(
@@ -377,29 +482,42 @@ impl GarminClient {
 
         debug!("ConnectAPI requesting from: {}", &url);
 
-        let mut headers = HeaderMap::new();
+        let mut headers = self.headers.api();
         headers.insert(
             "Authorization",
             format!("Bearer {}", access_token).parse().unwrap(),
         );
 
-        let mut builder = self.client.get(url).headers(headers);
-
-        match params {
-            Some(param_map) => {
-                builder = builder.query(&param_map);
+        let response = match self.send_api_request(&url, &headers, &params).await {
+            Ok(response) => response,
+            Err(e) => {
+                error!("Request for {} failed, error: {}", &url, e);
+                return false;
             }
-            None => {}
-        }
-
-        let response = builder.send().await.unwrap();
+        };
+        self.record_response(&response);
+        let status = response.status();
 
         if json_or_binary {
-            self.last_api_resp_url = response.url().to_string();
-            self.last_api_resp_text = response.text().await.unwrap();
+            self.last_api_resp_text = match response.text().await {
+                Ok(text) => text,
+                Err(e) => {
+                    error!("Unable to read response from {}, error: {}", &url, e);
+                    return false;
+                }
+            };
+            if !status.is_success() {
+                error!("Request for {} failed with status {}", &url, status);
+                return false;
+            }
             match filepath {
+                // nothing to save for a day without data
+                Some(_) if self.last_api_resp_text.is_empty() => true,
                 Some(filename) => {
-                    self.save_as_json(&self.last_api_resp_text, filename);
+                    if !self.save_as_json(&self.last_api_resp_text, &filename) {
+                        return false;
+                    }
+                    self.last_saved_files.push(PathBuf::from(filename));
                     true
                 }
                 None => {
@@ -411,10 +529,42 @@ impl GarminClient {
                 }
             }
         } else {
+            if !status.is_success() {
+                error!("Request for {} failed with status {}", &url, status);
+                return false;
+            }
             match filepath {
                 Some(filename) => {
-                    self.save_as_binary(response, filename).await;
-                    true
+                    let mut response = response;
+                    let mut attempt = 0;
+                    loop {
+                        match self.save_as_binary(response, &filename).await {
+                            BinaryDownload::Complete(saved) => {
+                                self.last_saved_files = saved;
+                                return true;
+                            }
+                            BinaryDownload::Interrupted if attempt < self.download_retries => {
+                                attempt += 1;
+                                warn!(
+                                    "Retrying download of {} (attempt {}/{})",
+                                    &url, attempt, self.download_retries
+                                );
+                                response =
+                                    match self.send_api_request(&url, &headers, &params).await {
+                                        Ok(response) => response,
+                                        Err(e) => {
+                                            error!("Request for {} failed, error: {}", &url, e);
+                                            return false;
+                                        }
+                                    };
+                                self.record_response(&response);
+                                if !response.status().is_success() {
+                                    return false;
+                                }
+                            }
+                            _ => return false,
+                        }
+                    }
                 }
                 None => {
                     debug!(
@@ -427,111 +577,462 @@ impl GarminClient {
         }
     }
 
-    fn save_as_json(&self, data: &str, filepath: String) {
-        if data.len() == 0 {
-            return;
+    // keeps the final url (after any redirects) and status of the latest response, for debugging
+    fn record_response(&mut self, response: &Response) {
+        self.last_api_resp_url = response.url().to_string();
+        self.last_api_status = Some(response.status());
+    }
+
+    // sends the request, waiting out (and retrying) any 429 responses. Connection
+    // errors (e.g. a reset or timeout) are returned rather than retried.
+    async fn send_api_request(
+        &self,
+        url: &str,
+        headers: &HeaderMap,
+        params: &Option<HashMap<&str, &str>>,
+    ) -> Result<Response, reqwest::Error> {
+        let mut retries = 0;
+        loop {
+            self.rate_limiter.wait().await;
+
+            let mut builder = self.client.get(url).headers(headers.clone());
+            if let Some(param_map) = params {
+                builder = builder.query(param_map);
+            }
+            let response = builder.send().await?;
+
+            if response.status() != StatusCode::TOO_MANY_REQUESTS
+                || retries >= MAX_RATE_LIMIT_RETRIES
+            {
+                return Ok(response);
+            }
+            retries += 1;
+            let delay = response
+                .headers()
+                .get(RETRY_AFTER)
+                .and_then(|value| value.to_str().ok())
+                .and_then(rate_limit::parse_retry_after)
+                .unwrap_or(DEFAULT_RETRY_AFTER);
+            warn!(
+                "Rate limited by Garmin (attempt {}/{}), retrying {} in {}s",
+                retries,
+                MAX_RATE_LIMIT_RETRIES,
+                url,
+                delay.as_secs()
+            );
+            // back off every clone of this client, not just this request
+            self.rate_limiter.back_off(delay).await;
         }
-        match File::create(&filepath) {
-            Ok(file) => {
-                let mut writer = BufWriter::new(file);
-                let json_data: HashMap<String, serde_json::Value> =
-                    serde_json::from_str(data).unwrap();
-                match serde_json::to_writer_pretty(&mut writer, &json_data) {
-                    Ok(_) => match writer.flush() {
-                        Ok(_) => {}
-                        Err(e) => {
-                            error!("Error flushing writer: {}", e);
-                        }
-                    },
-                    Err(e) => {
-                        error!("Error writing json to buffer: {}", e);
-                    }
-                }
+    }
+
+    /// Refreshes the OAuth2.0 access token if it has expired, returning false if
+    /// there's still no valid token afterwards.
+    ///
+    /// api_request() already does this, but callers sharing clones of this client
+    /// should call it first so every clone starts with a valid token.
+    pub async fn refresh_oauth2_token_if_expired(&mut self) -> bool {
+        if !self.oauth_manager.get_oauth2_token().is_expired() {
+            return true;
+        }
+        info!("====================================================");
+        info!("ConnectAPI refreshing OAuth2.0 token...");
+        info!("====================================================");
+        if self.oauth_manager.get_oauth2_token().can_refresh() && self.refresh_session().await {
+            return true;
+        }
+        match self.set_oauth2_token().await {
+            Ok(()) => {
+                self.save_json_session();
+                true
+            }
+            Err(e) => {
+                error!("Unable to obtain oauth2_token: {}", e);
+                false
+            }
+        }
+    }
+
+    // logs in again with the credentials from the last login(). only tried once, so
+    // a run with bad credentials doesn't hit the SSO again for every request.
+    async fn relogin(&mut self) -> bool {
+        let Some((username, password)) = self.credentials.take() else {
+            return false;
+        };
+        warn!("Unable to refresh the session, logging in again...");
+        match self.login(&username, &password).await {
+            Ok(()) => true,
+            Err(e) => {
+                error!("Unable to log in again: {}", e);
+                self.credentials = None;
+                false
+            }
+        }
+    }
+
+    // exchanges the refresh token for a new access token and saves it, returning false on failure
+    async fn refresh_session(&mut self) -> bool {
+        match self
+            .oauth_manager
+            .refresh_oauth2_token(self.client.clone())
+            .await
+        {
+            Ok(token) => {
+                info!("Refreshed oauth2 token: {}", redact(&token));
+                self.save_json_session();
+                true
             }
             Err(e) => {
-                error!("Unable to create file {}, error: {}", filepath, e);
+                warn!(
+                    "Unable to refresh oauth2 token, need to log in again: {}",
+                    e
+                );
+                false
             }
         }
     }
 
-    async fn save_as_binary(&self, mut response: Response, filepath: String) {
+    /// Marks the current access token as expired, e.g. after Garmin stopped accepting it,
+    /// so the next login() refreshes it (or logs in again) instead of reusing it.
+    pub fn expire_session(&mut self) {
+        self.oauth_manager.oauth2_token.expires_at = 0;
+        self.save_json_session();
+    }
+
+    /// True if the session file holds an access token that hasn't expired yet,
+    /// i.e. login() won't need to talk to Garmin's SSO.
+    pub fn has_valid_session(&self) -> bool {
+        fs::read_to_string(&self.session_file)
+            .ok()
+            .and_then(|contents| serde_json::from_str::<auth::OAuth2TokenWrapper>(&contents).ok())
+            .is_some_and(|token| !token.is_expired())
+    }
+
+    /// Sets where the session (tokens and user profile) is saved between runs,
+    /// SESSION_FILE in the working directory by default. Use a separate file per account.
+    pub fn set_session_file(&mut self, session_file: &str) {
+        self.session_file = String::from(session_file);
+    }
+
+    pub fn get_session_file(&self) -> &str {
+        &self.session_file
+    }
+
+    /// Points the client at a regional Garmin domain, e.g. "garmin.cn", so
+    /// requests go to sso.<domain> and connectapi.<domain>. Accepts full URLs
+    /// like "https://www.garmin.com" too, and falls back to garmin.com if empty.
+    pub fn set_domain(&mut self, domain: &str) {
+        let domain = normalize_domain(domain);
+        self.auth_host = format!("sso.{}", domain);
+        self.api_host = format!("connectapi.{}", domain);
+        self.oauth_manager.set_domain(&domain);
+    }
+
+    /// Overrides the User-Agent sent with the SSO login pages and with the OAuth/API
+    /// requests, in case Garmin stops accepting the defaults. Empty values keep the defaults.
+    pub fn set_user_agents(&mut self, sso_user_agent: &str, api_user_agent: &str) {
+        self.headers.set_user_agents(sso_user_agent, api_user_agent);
+        self.oauth_manager.set_headers(self.headers.clone());
+    }
+
+    /// Sends an extra header with every request, e.g. one Garmin starts checking for.
+    pub fn add_header(&mut self, name: &str, value: &str) {
+        self.headers.add_extra(name, value);
+        self.oauth_manager.set_headers(self.headers.clone());
+    }
+
+    /// Sends every request (including the login and OAuth exchanges) through the proxy
+    /// at 'url', e.g. "http://proxy.example.com:8080", with basic auth if 'username' isn't
+    /// empty. An empty 'url' goes back to the HTTP_PROXY/HTTPS_PROXY environment variables.
+    ///
+    /// Call this before logging in, since it replaces the client (and its cookies).
+    pub fn set_proxy(
+        &mut self,
+        url: &str,
+        username: &str,
+        password: &str,
+    ) -> Result<(), reqwest::Error> {
+        let proxy = if url.is_empty() {
+            None
+        } else {
+            let mut proxy = Proxy::all(url)?;
+            if !username.is_empty() {
+                proxy = proxy.basic_auth(username, password);
+            }
+            Some(proxy)
+        };
+        self.client = build_http_client(proxy)?;
+        Ok(())
+    }
+
+    /// Sets the minimum time between API requests, shared with any clones of this client.
+    pub fn set_min_request_interval(&mut self, interval: Duration) {
+        self.rate_limiter.set_min_interval(interval);
+    }
+
+    /// Controls whether binary downloads saved as .zip files are extracted
+    /// next to the archive after downloading (the default).
+    pub fn set_extract_archives(&mut self, extract_archives: bool) {
+        self.extract_archives = extract_archives;
+    }
+
+    /// Sets how many times a binary download that's cut short (or doesn't match its
+    /// Content-Length) is started over before giving up.
+    pub fn set_download_retries(&mut self, download_retries: u32) {
+        self.download_retries = download_retries;
+    }
+
+    /// Controls whether extracted archives are moved into an 'archive' subfolder
+    /// rather than deleted (the default), leaving only their contents behind.
+    pub fn set_keep_archives(&mut self, keep_archives: bool) {
+        self.keep_archives = keep_archives;
+    }
+
+    /// Sets where the files extracted from each archive are recorded, see EXTRACTED_FILE.
+    pub fn set_extracted_file(&mut self, extracted_file: &str) {
+        self.extracted_file = String::from(extracted_file);
+    }
+
+    /// Whether the archive that would be saved at 'filepath' was already downloaded
+    /// and extracted, i.e. its copy is in the 'archive' subfolder, or every file
+    /// extracted from it is still in place.
+    pub fn already_extracted(&self, filepath: &str) -> bool {
+        archive_path_for(filepath).exists()
+            || self
+                .load_extracted()
+                .get(filepath)
+                .is_some_and(|files| !files.is_empty() && files.iter().all(|f| f.exists()))
+    }
+
+    // archive path -> the files extracted from it
+    fn load_extracted(&self) -> BTreeMap<String, Vec<PathBuf>> {
+        fs::read_to_string(&self.extracted_file)
+            .ok()
+            .and_then(|contents| serde_json::from_str(&contents).ok())
+            .unwrap_or_default()
+    }
+
+    // remembers what was extracted from the archive at 'filepath', see already_extracted().
+    // archives whose files have since been removed (e.g. pruned after upload) are
+    // forgotten, so the list only grows with what's still on disk
+    fn record_extracted(&self, filepath: &str, files: &[PathBuf]) {
+        let mut extracted = self.load_extracted();
+        extracted.retain(|archive, files| {
+            archive_path_for(archive).exists() || files.iter().all(|f| f.exists())
+        });
+        extracted.insert(String::from(filepath), files.to_vec());
+        if let Err(e) = write_atomically(&self.extracted_file, |file| {
+            serde_json::to_writer_pretty(file, &extracted).map_err(std::io::Error::from)
+        }) {
+            warn!(
+                "Unable to record files extracted from {} in {}, error: {}",
+                filepath, self.extracted_file, e
+            );
+        }
+    }
+
+    /// Saves the JSON in 'data' to 'filepath', pretty printed. The file is only replaced
+    /// once it's completely written, so an interrupted save never leaves half a file.
+    /// Returns true if the file was written, errors are logged.
+    pub fn save_as_json(&self, data: &str, filepath: &str) -> bool {
+        if data.is_empty() {
+            return false;
+        }
+        // some endpoints (e.g. gear, body battery) respond with an array
+        let json_data: serde_json::Value = match serde_json::from_str(data) {
+            Ok(json_data) => json_data,
+            Err(e) => {
+                error!("Not saving invalid json to {}, error: {}", filepath, e);
+                return false;
+            }
+        };
+        if let Err(e) = write_atomically(filepath, |file| {
+            let mut writer = BufWriter::new(file);
+            serde_json::to_writer_pretty(&mut writer, &json_data)?;
+            writer.flush()
+        }) {
+            error!("Unable to write json to {}, error: {}", filepath, e);
+            return false;
+        }
+        true
+    }
+
+    // streams the response to 'filepath' (via a temporary file), and only once every
+    // byte Garmin said it would send has arrived moves it into place (or extracts it)
+    async fn save_as_binary(&self, mut response: Response, filepath: &str) -> BinaryDownload {
         // .FIT files are saved as .ZIP files FYI
+        let tmp_path = temp_path_for(filepath);
+        let expected_bytes = response.content_length();
         let mut num_chunks = 0;
-        match File::create(&filepath) {
-            Ok(mut file) => {
-                while let Ok(Some(chunk)) = response.chunk().await {
-                    match file.write(&chunk) {
-                        Ok(_) => {
-                            num_chunks += 1;
-                            info!(
-                                "Wrote chunk #{} to {}. Size: {}",
-                                num_chunks,
-                                &filepath,
-                                &chunk.len()
-                            );
-                        }
-                        Err(e) => {
-                            error!(
-                                "Error writing chunk #{} to {}, error: {}",
-                                num_chunks, &filepath, e
-                            );
-                        }
+        let mut num_bytes: u64 = 0;
+        let mut file = match File::create(&tmp_path) {
+            Ok(file) => file,
+            Err(e) => {
+                error!("Unable to create file {}, error: {}", &tmp_path, e);
+                return BinaryDownload::Failed;
+            }
+        };
+        loop {
+            match response.chunk().await {
+                Ok(Some(chunk)) => match file.write_all(&chunk) {
+                    Ok(_) => {
+                        num_chunks += 1;
+                        num_bytes += chunk.len() as u64;
+                        info!(
+                            "Wrote chunk #{} to {}. Size: {}",
+                            num_chunks,
+                            filepath,
+                            &chunk.len()
+                        );
                     }
+                    Err(e) => {
+                        error!(
+                            "Error writing chunk #{} to {}, error: {}",
+                            num_chunks, filepath, e
+                        );
+                        let _ = fs::remove_file(&tmp_path);
+                        return BinaryDownload::Failed;
+                    }
+                },
+                Ok(None) => break,
+                Err(e) => {
+                    error!(
+                        "Download of {} interrupted after {} chunks, error: {}",
+                        filepath, num_chunks, e
+                    );
+                    let _ = fs::remove_file(&tmp_path);
+                    return BinaryDownload::Interrupted;
                 }
             }
-            Err(e) => {
-                error!("Unable to create file {}, error: {}", &filepath, e);
-            }
         }
         if num_chunks == 0 {
-            warn!("Didn't save any binary zip file data");
-            return;
+            warn!("Didn't save any binary file data");
+            let _ = fs::remove_file(&tmp_path);
+            return BinaryDownload::Empty;
         }
-        // now unzip the downloaded zip
-        info!("Attempting to unzip files...");
-        let file = File::open(&filepath).unwrap();
-        match zip::ZipArchive::new(file) {
-            Ok(mut archive) => {
-                for i in 0..archive.len() {
-                    let mut file = archive.by_index(i).unwrap();
-                    let mut buffer: Vec<u8> = vec![];
-                    std::io::copy(&mut file, &mut buffer)
-                        .expect("Unable to copy archive file contents to buffer :(");
-
-                    // get folder from filepath
-                    let new_path = Path::new(&filepath).parent().unwrap().join(&file.name());
-                    info!("Saving FIT file contents: {}", new_path.display());
-                    fs::write(new_path, &buffer).expect("Unable to write FIT file contents :(");
+        if let Some(expected) = expected_bytes.filter(|expected| *expected != num_bytes) {
+            error!(
+                "Incomplete download of {}, expected {} bytes but got {}",
+                filepath, expected, num_bytes
+            );
+            let _ = fs::remove_file(&tmp_path);
+            return BinaryDownload::Interrupted;
+        }
+        BinaryDownload::Complete(self.finish_download(&tmp_path, filepath))
+    }
+
+    // moves the completed download at 'tmp_path' into place, or for zip archives
+    // (e.g. FIT downloads), extracts it and removes (or archives) the zip itself,
+    // since a leftover .zip next to the FIT files only confuses the uploader.
+    // Returns the files it left in place.
+    fn finish_download(&self, tmp_path: &str, filepath: &str) -> Vec<PathBuf> {
+        // only zip archives need extracting, TCX/GPX exports are plain files
+        let is_archive = Path::new(filepath).extension().and_then(|e| e.to_str()) == Some("zip");
+        let mut extracted: Vec<PathBuf> = Vec::new();
+        if self.extract_archives
+            && is_archive
+            && self.extract_archive(tmp_path, filepath, &mut extracted)
+        {
+            self.record_extracted(filepath, &extracted);
+            if self.keep_archives {
+                let archive_path = archive_path_for(filepath);
+                if let Err(e) = fs::create_dir_all(archive_path.parent().unwrap())
+                    .and_then(|_| fs::rename(tmp_path, &archive_path))
+                {
+                    error!(
+                        "Unable to move {} to {}, error: {}",
+                        filepath,
+                        archive_path.display(),
+                        e
+                    );
+                    let _ = fs::remove_file(tmp_path);
                 }
+            } else if let Err(e) = fs::remove_file(tmp_path) {
+                error!("Unable to remove extracted {}, error: {}", filepath, e);
             }
+            return extracted;
+        }
+        // only move the download into place once everything else succeeded
+        if let Err(e) = fs::rename(tmp_path, filepath) {
+            error!("Unable to move {} into place, error: {}", filepath, e);
+            let _ = fs::remove_file(tmp_path);
+            return extracted;
+        }
+        extracted.push(PathBuf::from(filepath));
+        extracted
+    }
+
+    // extracts every file in the archive at 'archive_path' next to 'filepath', adding
+    // each one written to 'extracted' and returning true only if all of them were.
+    fn extract_archive(
+        &self,
+        archive_path: &str,
+        filepath: &str,
+        extracted_files: &mut Vec<PathBuf>,
+    ) -> bool {
+        info!("Attempting to unzip files...");
+        let mut archive = match File::open(archive_path)
+            .map_err(zip::result::ZipError::Io)
+            .and_then(zip::ZipArchive::new)
+        {
+            Ok(archive) => archive,
             Err(e) => {
-                error!("Unable to unzip file {}, error: {}", &filepath, e);
+                error!("Unable to unzip file {}, error: {}", filepath, e);
+                return false;
+            }
+        };
+        let mut extracted = true;
+        for i in 0..archive.len() {
+            let mut file = match archive.by_index(i) {
+                Ok(file) => file,
+                Err(e) => {
+                    error!("Unable to read entry #{} of {}, error: {}", i, filepath, e);
+                    extracted = false;
+                    continue;
+                }
+            };
+
+            // get folder from filepath
+            let new_path = Path::new(filepath).parent().unwrap().join(file.name());
+            info!("Saving FIT file contents: {}", new_path.display());
+            if let Err(e) = write_atomically(new_path.to_str().unwrap(), |output| {
+                std::io::copy(&mut file, output).map(|_| ())
+            }) {
+                error!(
+                    "Unable to write FIT file contents {}, error: {}",
+                    new_path.display(),
+                    e
+                );
+                extracted = false;
+            } else {
+                extracted_files.push(new_path);
             }
         }
+        extracted
     }
 
     /// Sets the token and expiration value from a HashMap.
     ///
     /// Returns true if valid access_token found
     fn retrieve_json_session(&mut self) -> bool {
-        match fs::read_to_string(&SESSION_FILE) {
+        match fs::read_to_string(&self.session_file) {
             Ok(file_contents) => {
-                let map: Value = serde_json::from_str(&file_contents).unwrap();
-                let expires_at_str = map["expires_at"].to_string().replace('"', "");
-                let expiration: u64 = expires_at_str.parse::<u64>().unwrap();
-                if expiration
-                    < SystemTime::now()
-                        .duration_since(UNIX_EPOCH)
-                        .unwrap()
-                        .as_secs()
-                {
+                let token: auth::OAuth2TokenWrapper = match serde_json::from_str(&file_contents) {
+                    Ok(token) => token,
+                    Err(e) => {
+                        warn!(
+                            "Unable to read token from session file {} ({}), need to re-authenticate",
+                            self.session_file, e
+                        );
+                        return false;
+                    }
+                };
+                // keep the token even if expired, its refresh token may still be usable
+                self.oauth_manager.oauth2_token = token;
+                if self.oauth_manager.oauth2_token.is_expired() {
                     warn!("Found garmin session token, but it expired. Need to re-authenticate");
                     return false;
                 }
                 info!("Successfully read garmin session token!");
-                self.oauth_manager.oauth2_token.expires_at = expiration;
-                self.oauth_manager.oauth2_token.oauth2_token.access_token =
-                    map["token"].to_string();
                 return true;
             }
             Err(e) => {
@@ -540,37 +1041,32 @@ impl GarminClient {
         }
         false
     }
-    /// Saves the current access token if valid
+    /// Saves the current token (including its refresh token and expirations),
+    /// keeping anything else already in the session file, e.g. the user profile.
     fn save_json_session(&self) {
-        match File::create(&SESSION_FILE) {
-            Ok(file) => {
-                let mut writer = BufWriter::new(file);
-                let json_data: HashMap<String, String> = HashMap::from([
-                    (
-                        String::from("expires_at"),
-                        format!("{}", self.oauth_manager.oauth2_token.expires_at),
-                    ),
-                    (
-                        String::from("token"),
-                        String::from(&self.oauth_manager.oauth2_token.oauth2_token.access_token),
-                    ),
-                ]);
-                match serde_json::to_writer_pretty(&mut writer, &json_data) {
-                    Ok(_) => match writer.flush() {
-                        Ok(_) => {}
-                        Err(e) => {
-                            error!("Error flushing writer: {}", e);
-                        }
-                    },
-                    Err(e) => {
-                        error!("Error writing json to buffer: {}", e);
-                    }
-                }
-            }
+        let mut session: Value = match serde_json::to_value(&self.oauth_manager.oauth2_token) {
+            Ok(session) => session,
             Err(e) => {
-                error!("Unable to create file {}, error: {}", SESSION_FILE, e);
+                error!("Error serializing session token: {}", e);
+                return;
+            }
+        };
+        if let Ok(Value::Object(existing)) = fs::read_to_string(&self.session_file)
+            .map_err(serde_json::Error::io)
+            .and_then(|contents| serde_json::from_str::<Value>(&contents))
+        {
+            for (key, value) in existing {
+                session.as_object_mut().unwrap().entry(key).or_insert(value);
             }
         }
+        if let Err(e) = write_atomically(&self.session_file, |file| {
+            file.write_all(serde_json::to_string_pretty(&session)?.as_bytes())
+        }) {
+            error!(
+                "Unable to write session file {}, error: {}",
+                self.session_file, e
+            );
+        }
     }
 
     /// When specifying a JSON download in the api_request() function, this
@@ -578,4 +1074,408 @@ impl GarminClient {
     pub fn get_last_resp_text(&self) -> &str {
         &self.last_api_resp_text
     }
+
+    /// The files written by the latest api_request(), e.g. the FIT files extracted
+    /// from a downloaded archive. Empty if nothing was saved.
+    pub fn get_last_saved_files(&self) -> &[PathBuf] {
+        &self.last_saved_files
+    }
+
+    /// The final url (after any redirects) of the latest api_request() response,
+    /// e.g. to see whether Garmin redirected it to a login page.
+    pub fn get_last_resp_url(&self) -> &str {
+        &self.last_api_resp_url
+    }
+
+    /// The HTTP status of the latest api_request() response, None before the first one.
+    pub fn get_last_resp_status(&self) -> Option<StatusCode> {
+        self.last_api_status
+    }
+}
+
+// partially written files live here until they're complete
+fn temp_path_for(filepath: &str) -> String {
+    format!("{}.part", filepath)
+}
+
+// where kept archives go, e.g. 'activities/archive/2024-02-01-1234.zip'
+fn archive_path_for(filepath: &str) -> PathBuf {
+    let path = Path::new(filepath);
+    path.parent()
+        .unwrap_or(Path::new(""))
+        .join("archive")
+        .join(path.file_name().unwrap_or_default())
+}
+
+/// Reads JSON state (e.g. a download or upload state file) saved by save_json(), or
+/// None if 'path' doesn't exist yet. Unreadable files are an error.
+pub fn load_json<T: DeserializeOwned>(path: &Path) -> std::io::Result<Option<T>> {
+    match fs::read_to_string(path) {
+        Ok(contents) => Ok(Some(serde_json::from_str(&contents)?)),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+        Err(e) => Err(e),
+    }
+}
+
+/// Saves 'value' to 'path' as pretty printed JSON. The file is only replaced once
+/// it's completely written, so a crash never leaves half of it behind.
+pub fn save_json(path: &Path, value: &impl Serialize) -> std::io::Result<()> {
+    write_atomically(&path.to_string_lossy(), |file| {
+        let mut writer = BufWriter::new(file);
+        serde_json::to_writer_pretty(&mut writer, value)?;
+        writer.flush()
+    })
+}
+
+// runs 'write' against a temporary file in the same folder as 'filepath', and only renames
+// it into place if that succeeds, so readers never see a truncated file.
+fn write_atomically(
+    filepath: &str,
+    write: impl FnOnce(&mut File) -> std::io::Result<()>,
+) -> std::io::Result<()> {
+    let tmp_path = temp_path_for(filepath);
+    let result = File::create(&tmp_path)
+        .and_then(|mut file| write(&mut file))
+        .and_then(|_| fs::rename(&tmp_path, filepath));
+    if result.is_err() {
+        let _ = fs::remove_file(&tmp_path);
+    }
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn partial_write_leaves_no_file_test() {
+        let folder =
+            std::env::temp_dir().join(format!("garmin_client_test_{}", std::process::id()));
+        fs::create_dir_all(&folder).unwrap();
+        let filepath = folder.join("2024-02-01.json");
+        let filepath = filepath.to_str().unwrap();
+
+        let result = write_atomically(filepath, |file| {
+            file.write_all(b"{\"partial\": ")?;
+            Err(std::io::Error::new(
+                std::io::ErrorKind::Interrupted,
+                "connection dropped",
+            ))
+        });
+        assert!(result.is_err());
+        assert!(!Path::new(filepath).exists());
+        assert!(!Path::new(&temp_path_for(filepath)).exists());
+
+        write_atomically(filepath, |file| file.write_all(b"{}")).unwrap();
+        assert_eq!(fs::read_to_string(filepath).unwrap(), "{}");
+
+        fs::remove_dir_all(&folder).unwrap();
+    }
+
+    #[test]
+    fn save_as_json_test() {
+        let folder = std::env::temp_dir().join(format!("garmin_json_test_{}", std::process::id()));
+        fs::create_dir_all(&folder).unwrap();
+        let filepath = folder.join("2024-02-01.json");
+        let filepath = filepath.to_str().unwrap();
+        let client = GarminClient::new();
+
+        assert!(!client.save_as_json("", filepath));
+        assert!(!client.save_as_json("<html>not json</html>", filepath));
+        assert!(!Path::new(filepath).exists());
+        assert!(client.save_as_json(r#"{"calendarDate": "2024-02-01"}"#, filepath));
+        assert!(Path::new(filepath).exists());
+
+        fs::remove_dir_all(&folder).unwrap();
+    }
+
+    #[test]
+    fn extracted_archive_is_removed_test() {
+        let folder =
+            std::env::temp_dir().join(format!("garmin_archive_test_{}", std::process::id()));
+        fs::create_dir_all(&folder).unwrap();
+        let filepath = folder.join("2024-02-01-1234.zip");
+        let filepath = filepath.to_str().unwrap();
+        let write_zip = || {
+            let tmp_path = temp_path_for(filepath);
+            let mut writer = zip::ZipWriter::new(File::create(&tmp_path).unwrap());
+            writer
+                .start_file(
+                    "1234_ACTIVITY.fit",
+                    zip::write::SimpleFileOptions::default(),
+                )
+                .unwrap();
+            writer.write_all(b"fit data").unwrap();
+            writer.finish().unwrap();
+            tmp_path
+        };
+
+        let extracted_file =
+            std::env::temp_dir().join(format!("garmin_extracted_test_{}.json", std::process::id()));
+        let mut client = GarminClient::new();
+        client.set_extracted_file(extracted_file.to_str().unwrap());
+        assert!(!client.already_extracted(filepath));
+        assert_eq!(
+            client.finish_download(&write_zip(), filepath),
+            vec![folder.join("1234_ACTIVITY.fit")]
+        );
+        // the zip is gone, but downloading it again would only extract the same file
+        assert!(client.already_extracted(filepath));
+        let mut files: Vec<_> = fs::read_dir(&folder)
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name())
+            .collect();
+        files.sort();
+        assert_eq!(files, vec!["1234_ACTIVITY.fit"]);
+        assert_eq!(
+            fs::read_to_string(folder.join("1234_ACTIVITY.fit")).unwrap(),
+            "fit data"
+        );
+
+        client.set_keep_archives(true);
+        client.finish_download(&write_zip(), filepath);
+        assert!(!Path::new(filepath).exists());
+        assert!(folder.join("archive").join("2024-02-01-1234.zip").exists());
+
+        // the kept copy is enough, even once the extracted file is removed
+        fs::remove_file(folder.join("1234_ACTIVITY.fit")).unwrap();
+        assert!(client.already_extracted(filepath));
+        fs::remove_dir_all(folder.join("archive")).unwrap();
+        assert!(!client.already_extracted(filepath));
+
+        // recording another archive forgets the one whose files are all gone
+        let other = folder.join("2024-02-02-5678.zip");
+        let other = other.to_str().unwrap();
+        fs::write(folder.join("5678_ACTIVITY.fit"), "fit data").unwrap();
+        client.record_extracted(other, &[folder.join("5678_ACTIVITY.fit")]);
+        let recorded: Vec<_> = client.load_extracted().into_keys().collect();
+        assert_eq!(recorded, vec![other]);
+
+        fs::remove_dir_all(&folder).unwrap();
+        fs::remove_file(&extracted_file).unwrap();
+    }
+
+    // serves one canned HTTP response on a local port, returning its url
+    fn serve_once(response: &'static [u8]) -> String {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/", listener.local_addr().unwrap());
+        std::thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut request = [0u8; 1024];
+            let _ = std::io::Read::read(&mut stream, &mut request);
+            stream.write_all(response).unwrap();
+        });
+        url
+    }
+
+    #[tokio::test]
+    async fn incomplete_download_test() {
+        let folder =
+            std::env::temp_dir().join(format!("garmin_incomplete_test_{}", std::process::id()));
+        fs::create_dir_all(&folder).unwrap();
+        let filepath = folder.join("2024-02-01.tcx");
+        let filepath = filepath.to_str().unwrap();
+        let client = GarminClient::new();
+
+        // the connection closes 5 bytes into a 100 byte download
+        let url = serve_once(b"HTTP/1.1 200 OK\r\nContent-Length: 100\r\n\r\nshort");
+        let response = reqwest::get(url).await.unwrap();
+        assert_eq!(
+            client.save_as_binary(response, filepath).await,
+            BinaryDownload::Interrupted
+        );
+        assert!(!Path::new(filepath).exists());
+        assert!(!Path::new(&temp_path_for(filepath)).exists());
+
+        let url = serve_once(b"HTTP/1.1 200 OK\r\nContent-Length: 8\r\n\r\ncomplete");
+        let response = reqwest::get(url).await.unwrap();
+        assert_eq!(
+            client.save_as_binary(response, filepath).await,
+            BinaryDownload::Complete(vec![PathBuf::from(filepath)])
+        );
+        assert_eq!(fs::read_to_string(filepath).unwrap(), "complete");
+
+        fs::remove_dir_all(&folder).unwrap();
+    }
+
+    #[test]
+    fn custom_session_file_test() {
+        let session_file =
+            std::env::temp_dir().join(format!("garmin_session_test_{}.json", std::process::id()));
+        let session_file = session_file.to_str().unwrap();
+
+        let mut client = GarminClient::new();
+        client.set_session_file(session_file);
+        assert!(!client.has_valid_session());
+        client.oauth_manager.oauth2_token.expires_at = u64::MAX;
+        client.oauth_manager.oauth2_token.oauth2_token.access_token = String::from("access");
+        client.save_json_session();
+        assert!(Path::new(session_file).exists());
+        assert!(client.has_valid_session());
+
+        let mut restored = GarminClient::new();
+        restored.set_session_file(session_file);
+        assert!(restored.retrieve_json_session());
+        assert_eq!(
+            restored
+                .oauth_manager
+                .get_oauth2_token()
+                .oauth2_token
+                .access_token,
+            "access"
+        );
+
+        fs::remove_file(session_file).unwrap();
+    }
+
+    #[test]
+    fn proxy_test() {
+        let mut client = GarminClient::new();
+        // no proxy configured, i.e. only the environment variables apply
+        assert!(client.set_proxy("", "", "").is_ok());
+        assert_eq!(
+            client.build_api_url("hrv-service/hrv"),
+            "https://connectapi.garmin.com/hrv-service/hrv"
+        );
+
+        assert!(client
+            .set_proxy("http://proxy.example.com:8080", "me", "pw")
+            .is_ok());
+        assert_eq!(
+            client.build_api_url("hrv-service/hrv"),
+            "https://connectapi.garmin.com/hrv-service/hrv"
+        );
+        assert!(client.set_proxy("not a proxy url", "", "").is_err());
+    }
+
+    #[test]
+    fn header_override_test() {
+        let mut client = GarminClient::new();
+        assert_eq!(client.headers.sso()["User-Agent"], DEFAULT_SSO_USER_AGENT);
+        assert_eq!(client.headers.api()["User-Agent"], DEFAULT_API_USER_AGENT);
+
+        client.set_user_agents("", "GCM-iOS-5.7.2.1");
+        client.add_header("X-Garmin-Test", "1");
+        client.add_header("bad header", "1");
+        assert_eq!(client.headers.sso()["User-Agent"], DEFAULT_SSO_USER_AGENT);
+        assert_eq!(client.headers.api()["User-Agent"], "GCM-iOS-5.7.2.1");
+        assert_eq!(client.headers.sso()["X-Garmin-Test"], "1");
+        assert_eq!(client.headers.api().len(), 2);
+        // the OAuth exchanges use the same overrides
+        assert_eq!(
+            client.oauth_manager.headers.api()["User-Agent"],
+            "GCM-iOS-5.7.2.1"
+        );
+    }
+
+    #[test]
+    fn domain_url_test() {
+        let mut client = GarminClient::new();
+        assert_eq!(
+            client.build_api_url("userprofile-service/socialProfile"),
+            "https://connectapi.garmin.com/userprofile-service/socialProfile"
+        );
+        assert!(client
+            .build_auth_url("signin")
+            .starts_with("https://sso.garmin.com/sso/signin?"));
+
+        client.set_domain("garmin.cn");
+        assert_eq!(
+            client.build_api_url("userprofile-service/socialProfile"),
+            "https://connectapi.garmin.cn/userprofile-service/socialProfile"
+        );
+        assert!(client
+            .build_auth_url("signin")
+            .starts_with("https://sso.garmin.cn/sso/signin?"));
+
+        assert_eq!(normalize_domain("https://www.garmin.com/"), "garmin.com");
+        assert_eq!(normalize_domain(""), DEFAULT_DOMAIN);
+    }
+
+    #[tokio::test]
+    async fn expired_token_test() {
+        let session_file = std::env::temp_dir().join(format!(
+            "garmin_expired_session_test_{}.json",
+            std::process::id()
+        ));
+        let mut client = GarminClient::new();
+        client.set_session_file(session_file.to_str().unwrap());
+        // nowhere to get a new token from
+        client.set_domain("garmin.invalid");
+        client.oauth_manager.oauth_consumer_url = String::from("http://127.0.0.1:1/");
+        client.oauth_manager.consumer_retry_delay = Duration::from_millis(1);
+        assert!(client.oauth_manager.get_oauth2_token().is_expired());
+
+        // never logged in, so there's nothing to log in again with
+        let endpoint = "usersummary-service/usersummary/daily";
+        assert!(!client.api_request(endpoint, None, true, None).await);
+        assert_eq!(client.get_last_resp_status(), None);
+
+        // logging in again fails too, and isn't tried again for the next request
+        client.credentials = Some((String::from("user"), String::from("password")));
+        assert!(!client.api_request(endpoint, None, true, None).await);
+        assert!(client.credentials.is_none());
+        assert_eq!(client.get_last_resp_status(), None);
+        assert!(!session_file.exists());
+    }
+
+    #[test]
+    fn login_title_result_test() {
+        assert!(login_title_result(Some(String::from("Success"))).is_ok());
+        assert!(matches!(
+            login_title_result(Some(String::from("GARMIN Authentication Application"))),
+            Err(LoginError::BadCredentials)
+        ));
+        assert!(matches!(
+            login_title_result(Some(String::from("Account Locked"))),
+            Err(LoginError::Unexpected(_))
+        ));
+        assert!(matches!(
+            login_title_result(None),
+            Err(LoginError::Unexpected(_))
+        ));
+    }
+
+    #[test]
+    fn parse_login_response_test() {
+        let client = GarminClient::new();
+        assert_eq!(
+            client
+                .parse_csrf_token(r#"<input type="hidden" name="_csrf" value="abc123" />"#)
+                .unwrap(),
+            "abc123"
+        );
+        assert!(matches!(
+            client.parse_csrf_token("<html></html>"),
+            Err(LoginError::MissingCsrfToken)
+        ));
+        assert_eq!(
+            client
+                .parse_ticket(r#"var url = "https://sso.garmin.com/sso/embed?ticket=ST-0123-abc";"#)
+                .unwrap(),
+            "ST-0123-abc"
+        );
+        assert!(matches!(
+            client.parse_ticket("<title>Success</title>"),
+            Err(LoginError::MissingTicket)
+        ));
+    }
+
+    #[test]
+    fn auth_error_step_test() {
+        let error: LoginError =
+            AuthError::MissingToken(AuthStep::OAuth2, String::from("expected value")).into();
+        match &error {
+            LoginError::Auth(e) => assert_eq!(e.step(), Some(AuthStep::OAuth2)),
+            _ => panic!("expected an auth error, got {:?}", error),
+        }
+        assert_eq!(
+            error.to_string(),
+            "OAuth2.0 token exchange returned no token: expected value"
+        );
+        assert_eq!(
+            AuthError::ConsumerInfo(String::from("timed out")).step(),
+            None
+        );
+    }
 }
@@ -0,0 +1,68 @@
+use std::fmt;
+
+use crate::auth_error::AuthError;
+
+/// Why GarminClient::login() couldn't authenticate.
+#[derive(Debug)]
+pub enum LoginError {
+    /// Garmin rejected the username/password.
+    BadCredentials,
+    /// No password to log in with, e.g. its environment variable isn't set.
+    MissingPassword(String),
+    /// The account needs an MFA code, but none was entered.
+    MfaRequired,
+    /// The entered MFA code was rejected.
+    MfaFailed,
+    /// A request to Garmin didn't go through.
+    Network(reqwest::Error),
+    /// The SSO login worked, but exchanging it for OAuth tokens didn't.
+    Auth(AuthError),
+    /// The sign in page has no CSRF token to submit the login form with.
+    MissingCsrfToken,
+    /// The login response has no ticket to exchange for OAuth tokens.
+    MissingTicket,
+    /// Garmin responded with something the login flow doesn't understand.
+    Unexpected(String),
+}
+
+impl fmt::Display for LoginError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LoginError::BadCredentials => {
+                write!(f, "invalid credentials, check your username and password")
+            }
+            LoginError::MissingPassword(reason) => write!(f, "no password: {}", reason),
+            LoginError::MfaRequired => {
+                write!(f, "account requires an MFA code, but none was entered")
+            }
+            LoginError::MfaFailed => write!(f, "MFA code was not accepted"),
+            LoginError::Network(e) => write!(f, "network error during login: {}", e),
+            LoginError::Auth(e) => write!(f, "{}", e),
+            LoginError::MissingCsrfToken => write!(f, "no CSRF token in the sign in page"),
+            LoginError::MissingTicket => write!(f, "no ticket in the login response"),
+            LoginError::Unexpected(reason) => write!(f, "unexpected login response: {}", reason),
+        }
+    }
+}
+
+impl std::error::Error for LoginError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            LoginError::Network(e) => Some(e),
+            LoginError::Auth(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<reqwest::Error> for LoginError {
+    fn from(e: reqwest::Error) -> Self {
+        LoginError::Network(e)
+    }
+}
+
+impl From<AuthError> for LoginError {
+    fn from(e: AuthError) -> Self {
+        LoginError::Auth(e)
+    }
+}
@@ -0,0 +1,86 @@
+use std::sync::Arc;
+use std::time::Duration;
+
+use chrono::{DateTime, Utc};
+use tokio::sync::Mutex;
+use tokio::time::Instant;
+
+/// Spaces out API requests by at least a minimum interval.
+///
+/// Clones share the same schedule, so clones of a GarminClient making requests
+/// concurrently are still paced (and back off) together.
+#[derive(Clone, Default)]
+pub struct RateLimiter {
+    min_interval: Duration,
+    next_slot: Arc<Mutex<Option<Instant>>>,
+}
+
+impl RateLimiter {
+    pub fn set_min_interval(&mut self, min_interval: Duration) {
+        self.min_interval = min_interval;
+    }
+
+    /// Waits until the next request is allowed, reserving that slot.
+    pub async fn wait(&self) {
+        let slot = {
+            let mut next_slot = self.next_slot.lock().await;
+            let now = Instant::now();
+            let slot = next_slot.map_or(now, |next| next.max(now));
+            *next_slot = Some(slot + self.min_interval);
+            slot
+        };
+        tokio::time::sleep_until(slot).await;
+    }
+
+    /// Holds off every request until at least 'delay' from now, e.g. after a 429.
+    pub async fn back_off(&self, delay: Duration) {
+        let until = Instant::now() + delay;
+        let mut next_slot = self.next_slot.lock().await;
+        if next_slot.map_or(true, |next| next < until) {
+            *next_slot = Some(until);
+        }
+    }
+}
+
+/// Parses a Retry-After header value, either in seconds or as an HTTP date.
+pub fn parse_retry_after(value: &str) -> Option<Duration> {
+    let value = value.trim();
+    if let Ok(seconds) = value.parse::<u64>() {
+        return Some(Duration::from_secs(seconds));
+    }
+    let date = DateTime::parse_from_rfc2822(value).ok()?;
+    // a date in the past just means we can go again right away
+    Some(
+        (date.with_timezone(&Utc) - Utc::now())
+            .to_std()
+            .unwrap_or_default(),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_retry_after_test() {
+        assert_eq!(parse_retry_after("120"), Some(Duration::from_secs(120)));
+        assert_eq!(
+            parse_retry_after("Wed, 21 Oct 2015 07:28:00 GMT"),
+            Some(Duration::ZERO)
+        );
+        assert_eq!(parse_retry_after("soon"), None);
+    }
+
+    #[tokio::test]
+    async fn rate_limiter_spacing_test() {
+        let mut limiter = RateLimiter::default();
+        limiter.set_min_interval(Duration::from_millis(20));
+        let shared = limiter.clone();
+
+        let start = Instant::now();
+        limiter.wait().await;
+        shared.wait().await;
+        limiter.wait().await;
+        assert!(start.elapsed() >= Duration::from_millis(40));
+    }
+}
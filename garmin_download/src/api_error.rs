@@ -0,0 +1,69 @@
+use std::fmt;
+
+/// Why a JSON request to the Garmin API didn't produce usable data.
+#[derive(Debug)]
+pub enum ApiError {
+    /// The request itself failed, see the client's logs.
+    RequestFailed,
+    /// Garmin responded with an empty body.
+    EmptyResponse,
+    /// Garmin responded with an HTML page (usually the login page) instead of JSON,
+    /// which happens when the session silently expires.
+    NotAuthenticated,
+    /// The body wasn't the JSON we expected.
+    InvalidJson(serde_json::Error),
+}
+
+impl fmt::Display for ApiError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ApiError::RequestFailed => write!(f, "request failed"),
+            ApiError::EmptyResponse => write!(
+                f,
+                "got empty response from API, are you using the latest client version?"
+            ),
+            ApiError::NotAuthenticated => {
+                write!(
+                    f,
+                    "got an HTML page instead of JSON, session may have expired"
+                )
+            }
+            ApiError::InvalidJson(e) => write!(f, "unable to parse response: {}", e),
+        }
+    }
+}
+
+impl ApiError {
+    /// True for responses that usually mean the session is no longer valid.
+    pub fn needs_relogin(&self) -> bool {
+        matches!(self, ApiError::NotAuthenticated | ApiError::InvalidJson(_))
+    }
+}
+
+/// Parses a JSON response body, recognizing HTML error/login pages served with a 200.
+pub fn parse_json_response(text: &str) -> Result<serde_json::Value, ApiError> {
+    let text = text.trim_start();
+    if text.is_empty() {
+        return Err(ApiError::EmptyResponse);
+    }
+    let start = text.as_bytes()[..text.len().min(16)].to_ascii_lowercase();
+    if start.starts_with(b"<!doctype") || start.starts_with(b"<html") {
+        return Err(ApiError::NotAuthenticated);
+    }
+    Ok(serde_json::from_str(text)?)
+}
+
+impl std::error::Error for ApiError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            ApiError::InvalidJson(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<serde_json::Error> for ApiError {
+    fn from(e: serde_json::Error) -> Self {
+        ApiError::InvalidJson(e)
+    }
+}
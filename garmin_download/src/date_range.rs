@@ -0,0 +1,146 @@
+use chrono::NaiveDate;
+use serde_json::Value;
+
+use crate::Metric;
+
+/// The most days requested at once from an endpoint that takes a range of dates.
+pub(crate) const MAX_RANGE_DAYS: i64 = 31;
+
+/// How a response covering a range of days is laid out, so it can be split
+/// into the same per-day files a request for each day would have saved.
+pub(crate) struct RangeResponse {
+    /// Subfolder each day's file is saved in.
+    pub folder: &'static str,
+    /// Path to the list of entries, each with the "calendarDate" it's for.
+    pub list: &'static [&'static str],
+    /// Top level keys holding the start and end of the range.
+    pub date_keys: &'static [&'static str],
+    /// Top level summaries of the whole range, which don't apply to any one day.
+    pub range_keys: &'static [&'static str],
+}
+
+const WEIGHT: RangeResponse = RangeResponse {
+    folder: "weight",
+    list: &["dateWeightList"],
+    date_keys: &["startDate", "endDate"],
+    range_keys: &["totalAverage"],
+};
+
+const RHR: RangeResponse = RangeResponse {
+    folder: "heartrate",
+    list: &["allMetrics", "metricsMap", "WELLNESS_RESTING_HEART_RATE"],
+    date_keys: &["statisticsStartDate", "statisticsEndDate"],
+    range_keys: &[],
+};
+
+/// The layout of 'metric's range response, if its endpoint takes a range of dates.
+pub(crate) fn response_for(metric: Metric) -> Option<&'static RangeResponse> {
+    match metric {
+        Metric::Weight => Some(&WEIGHT),
+        Metric::Rhr => Some(&RHR),
+        _ => None,
+    }
+}
+
+/// Groups ascending 'dates' into (start, end) ranges of at most MAX_RANGE_DAYS days.
+/// Gaps between dates stay inside a range, their entries are just ignored.
+pub(crate) fn chunk(dates: &[NaiveDate]) -> Vec<(NaiveDate, NaiveDate)> {
+    let mut ranges: Vec<(NaiveDate, NaiveDate)> = Vec::new();
+    for date in dates {
+        match ranges.last_mut() {
+            Some((start, end)) if (*date - *start).num_days() < MAX_RANGE_DAYS => *end = *date,
+            _ => ranges.push((*date, *date)),
+        }
+    }
+    ranges
+}
+
+/// A copy of 'response' narrowed to a single day, as if only 'date' had been requested.
+pub(crate) fn split_day(response: &Value, layout: &RangeResponse, date: NaiveDate) -> Value {
+    let calendar_date = date.format("%Y-%m-%d").to_string();
+    let mut day = response.clone();
+    if let Some(object) = day.as_object_mut() {
+        for key in layout.date_keys {
+            if object.contains_key(*key) {
+                object.insert(String::from(*key), Value::from(calendar_date.as_str()));
+            }
+        }
+        for key in layout.range_keys {
+            if object.contains_key(*key) {
+                object.insert(String::from(*key), Value::Null);
+            }
+        }
+    }
+    let list = format!("/{}", layout.list.join("/"));
+    if let Some(entries) = day.pointer_mut(&list).and_then(Value::as_array_mut) {
+        entries.retain(|entry| entry["calendarDate"].as_str() == Some(calendar_date.as_str()));
+    }
+    day
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn date(date: &str) -> NaiveDate {
+        NaiveDate::parse_from_str(date, "%Y-%m-%d").unwrap()
+    }
+
+    #[test]
+    fn chunk_test() {
+        let dates: Vec<NaiveDate> = date("2024-01-01").iter_days().take(70).collect();
+        assert_eq!(
+            chunk(&dates),
+            vec![
+                (date("2024-01-01"), date("2024-01-31")),
+                (date("2024-02-01"), date("2024-03-02")),
+                (date("2024-03-03"), date("2024-03-10")),
+            ]
+        );
+        assert_eq!(
+            chunk(&[date("2024-01-01"), date("2024-01-05")]),
+            vec![(date("2024-01-01"), date("2024-01-05"))]
+        );
+        assert!(chunk(&[]).is_empty());
+    }
+
+    #[test]
+    fn split_day_test() {
+        let weight: Value = serde_json::from_str(
+            r#"{
+                "startDate": "2024-02-01", "endDate": "2024-02-03",
+                "dateWeightList": [
+                    { "calendarDate": "2024-02-01", "weight": 80000.0 },
+                    { "calendarDate": "2024-02-03", "weight": 79500.0 },
+                    { "calendarDate": "2024-02-03", "weight": 79400.0 }
+                ],
+                "totalAverage": { "weight": 79633.3 }
+            }"#,
+        )
+        .unwrap();
+        let day = split_day(&weight, &WEIGHT, date("2024-02-03"));
+        assert_eq!(day["startDate"], "2024-02-03");
+        assert_eq!(day["endDate"], "2024-02-03");
+        assert_eq!(day["dateWeightList"].as_array().unwrap().len(), 2);
+        assert!(day["totalAverage"].is_null());
+        let day = split_day(&weight, &WEIGHT, date("2024-02-02"));
+        assert!(day["dateWeightList"].as_array().unwrap().is_empty());
+
+        let rhr: Value = serde_json::from_str(
+            r#"{
+                "statisticsStartDate": "2024-02-01", "statisticsEndDate": "2024-02-02",
+                "allMetrics": { "metricsMap": { "WELLNESS_RESTING_HEART_RATE": [
+                    { "value": 52.0, "calendarDate": "2024-02-01" },
+                    { "value": 50.0, "calendarDate": "2024-02-02" }
+                ] } }
+            }"#,
+        )
+        .unwrap();
+        let day = split_day(&rhr, &RHR, date("2024-02-02"));
+        assert_eq!(day["statisticsStartDate"], "2024-02-02");
+        assert_eq!(
+            day["allMetrics"]["metricsMap"]["WELLNESS_RESTING_HEART_RATE"],
+            serde_json::json!([{ "value": 50.0, "calendarDate": "2024-02-02" }])
+        );
+    }
+}
@@ -0,0 +1,90 @@
+use std::collections::BTreeMap;
+use std::io;
+use std::path::Path;
+
+use chrono::NaiveDate;
+use log::warn;
+
+use crate::Metric;
+
+/// Tracks the last successfully downloaded day of each metric between runs.
+pub const STATE_FILE: &str = ".garmin_state.json";
+
+const STATE_DATE_FORMAT: &str = "%Y-%m-%d";
+
+#[derive(Debug, Default)]
+pub struct DownloadState {
+    // metric name -> last downloaded date
+    last_downloaded: BTreeMap<String, String>,
+}
+
+impl DownloadState {
+    /// Loads state from 'path', starting fresh if it doesn't exist (e.g. the first ever run).
+    pub fn load(path: &str) -> DownloadState {
+        match garmin_client::load_json(Path::new(path)) {
+            Ok(last_downloaded) => DownloadState {
+                last_downloaded: last_downloaded.unwrap_or_default(),
+            },
+            Err(e) => {
+                warn!("Ignoring unreadable download state {}, error: {}", path, e);
+                DownloadState::default()
+            }
+        }
+    }
+
+    pub fn last_downloaded(&self, metric: Metric) -> Option<NaiveDate> {
+        self.last_downloaded
+            .get(metric.name())
+            .and_then(|date| NaiveDate::parse_from_str(date, STATE_DATE_FORMAT).ok())
+    }
+
+    /// Records a successful download, ignoring dates older than what's already recorded.
+    pub fn record(&mut self, metric: Metric, date: NaiveDate) {
+        if self
+            .last_downloaded(metric)
+            .is_some_and(|last| last >= date)
+        {
+            return;
+        }
+        self.last_downloaded.insert(
+            String::from(metric.name()),
+            format!("{}", date.format(STATE_DATE_FORMAT)),
+        );
+    }
+
+    /// Writes state to 'path', without ever leaving a half written state file behind.
+    pub fn save(&self, path: &str) -> io::Result<()> {
+        garmin_client::save_json(Path::new(path), &self.last_downloaded)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    #[test]
+    fn record_and_reload_state_test() {
+        let path =
+            std::env::temp_dir().join(format!("garmin_state_test_{}.json", std::process::id()));
+        let path = path.to_str().unwrap();
+
+        let mut state = DownloadState::load(path);
+        assert_eq!(state.last_downloaded(Metric::Sleep), None);
+
+        let first = NaiveDate::from_ymd_opt(2024, 2, 1).unwrap();
+        let second = NaiveDate::from_ymd_opt(2024, 2, 2).unwrap();
+        state.record(Metric::Sleep, second);
+        // backfilling older days doesn't move the state backwards
+        state.record(Metric::Sleep, first);
+        state.record(Metric::Weight, first);
+        state.save(path).unwrap();
+
+        let state = DownloadState::load(path);
+        assert_eq!(state.last_downloaded(Metric::Sleep), Some(second));
+        assert_eq!(state.last_downloaded(Metric::Weight), Some(first));
+        assert_eq!(state.last_downloaded(Metric::Hrv), None);
+
+        fs::remove_file(path).unwrap();
+    }
+}
@@ -0,0 +1,200 @@
+use chrono::NaiveDate;
+use log::{info, warn};
+
+use crate::Metric;
+
+/// What happened to a single day (or activity file) of a metric.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Outcome {
+    Succeeded,
+    Failed,
+    /// Nothing to save to, e.g. the file already exists and overwrite is disabled.
+    Skipped,
+}
+
+impl Outcome {
+    /// Outcome of a request that either had a file to save to or was 'skipped'.
+    pub fn new(success: bool, skipped: bool) -> Outcome {
+        match (success, skipped) {
+            (_, true) => Outcome::Skipped,
+            (true, false) => Outcome::Succeeded,
+            (false, false) => Outcome::Failed,
+        }
+    }
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct MetricCounts {
+    pub requested: usize,
+    pub succeeded: usize,
+    pub skipped: usize,
+    pub failed: usize,
+}
+
+/// Per-metric rollup of a download run, logged as a table once it's done.
+#[derive(Debug, Default)]
+pub struct DownloadSummary {
+    // in the order metrics were first downloaded
+    counts: Vec<(Metric, MetricCounts)>,
+    // days that failed, so they can be retried by date
+    failed_dates: Vec<(Metric, NaiveDate)>,
+    // first and last day requested of each metric
+    date_ranges: Vec<(Metric, NaiveDate, NaiveDate)>,
+}
+
+impl DownloadSummary {
+    pub fn record(&mut self, metric: Metric, outcome: Outcome) {
+        let index = match self.counts.iter().position(|(m, _)| *m == metric) {
+            Some(index) => index,
+            None => {
+                self.counts.push((metric, MetricCounts::default()));
+                self.counts.len() - 1
+            }
+        };
+        let counts = &mut self.counts[index].1;
+        counts.requested += 1;
+        match outcome {
+            Outcome::Succeeded => counts.succeeded += 1,
+            Outcome::Failed => counts.failed += 1,
+            Outcome::Skipped => counts.skipped += 1,
+        }
+    }
+
+    /// Remembers which day of 'metric' failed, on top of counting it with record().
+    pub fn record_failed_date(&mut self, metric: Metric, date: NaiveDate) {
+        self.failed_dates.push((metric, date));
+    }
+
+    /// Widens the range of days requested of 'metric' to include 'start' through 'end'.
+    pub fn record_range(&mut self, metric: Metric, start: NaiveDate, end: NaiveDate) {
+        match self.date_ranges.iter_mut().find(|(m, _, _)| *m == metric) {
+            Some((_, first, last)) => {
+                *first = (*first).min(start);
+                *last = (*last).max(end);
+            }
+            None => self.date_ranges.push((metric, start, end)),
+        }
+    }
+
+    /// The first and last day requested of 'metric', if it was downloaded by date.
+    pub fn date_range(&self, metric: Metric) -> Option<(NaiveDate, NaiveDate)> {
+        self.date_ranges
+            .iter()
+            .find(|(m, _, _)| *m == metric)
+            .map(|(_, start, end)| (*start, *end))
+    }
+
+    /// Every metric with anything recorded, in the order they were first downloaded.
+    pub fn metrics(&self) -> Vec<Metric> {
+        self.counts.iter().map(|(metric, _)| *metric).collect()
+    }
+
+    pub fn failed_dates(&self, metric: Metric) -> Vec<NaiveDate> {
+        self.failed_dates
+            .iter()
+            .filter(|(m, _)| *m == metric)
+            .map(|(_, date)| *date)
+            .collect()
+    }
+
+    pub fn counts(&self, metric: Metric) -> MetricCounts {
+        self.counts
+            .iter()
+            .find(|(m, _)| *m == metric)
+            .map(|(_, counts)| *counts)
+            .unwrap_or_default()
+    }
+
+    pub fn clear(&mut self) {
+        self.counts.clear();
+        self.failed_dates.clear();
+        self.date_ranges.clear();
+    }
+
+    /// The summary as table rows, header first.
+    pub fn lines(&self) -> Vec<String> {
+        let mut lines = vec![format!(
+            "{:<14}{:>10}{:>10}{:>10}{:>10}",
+            "metric", "requested", "succeeded", "skipped", "failed"
+        )];
+        for (metric, counts) in &self.counts {
+            lines.push(format!(
+                "{:<14}{:>10}{:>10}{:>10}{:>10}",
+                metric.name(),
+                counts.requested,
+                counts.succeeded,
+                counts.skipped,
+                counts.failed
+            ));
+        }
+        lines
+    }
+
+    pub fn log(&self) {
+        if self.counts.is_empty() {
+            info!("Download summary: nothing was requested");
+            return;
+        }
+        info!("Download summary:");
+        for line in self.lines() {
+            info!("{}", line);
+        }
+        for (metric, _) in &self.counts {
+            let dates = self.failed_dates(*metric);
+            if !dates.is_empty() {
+                let dates: Vec<String> = dates
+                    .iter()
+                    .map(|date| date.format("%Y-%m-%d").to_string())
+                    .collect();
+                warn!("Failed {} days: {}", metric.name(), dates.join(", "));
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn summary_counts_test() {
+        let mut summary = DownloadSummary::default();
+        summary.record(Metric::Sleep, Outcome::Succeeded);
+        summary.record(Metric::Sleep, Outcome::Skipped);
+        summary.record(Metric::Monitoring, Outcome::Failed);
+        summary.record(Metric::Sleep, Outcome::Succeeded);
+
+        assert_eq!(
+            summary.counts(Metric::Sleep),
+            MetricCounts {
+                requested: 3,
+                succeeded: 2,
+                skipped: 1,
+                failed: 0
+            }
+        );
+        assert_eq!(summary.counts(Metric::Hrv), MetricCounts::default());
+
+        let lines = summary.lines();
+        assert_eq!(lines.len(), 3);
+        assert!(lines[1].starts_with("sleep"));
+        assert!(lines[2].starts_with("monitoring"));
+
+        assert_eq!(summary.metrics(), vec![Metric::Sleep, Metric::Monitoring]);
+
+        let day = NaiveDate::from_ymd_opt(2024, 2, 2).unwrap();
+        summary.record_failed_date(Metric::Monitoring, day);
+        assert_eq!(summary.failed_dates(Metric::Monitoring), vec![day]);
+        assert!(summary.failed_dates(Metric::Sleep).is_empty());
+
+        let first = NaiveDate::from_ymd_opt(2024, 2, 1).unwrap();
+        let last = NaiveDate::from_ymd_opt(2024, 2, 5).unwrap();
+        summary.record_range(Metric::Sleep, day, last);
+        summary.record_range(Metric::Sleep, first, day);
+        assert_eq!(summary.date_range(Metric::Sleep), Some((first, last)));
+        assert_eq!(summary.date_range(Metric::Monitoring), None);
+        summary.clear();
+        assert!(summary.failed_dates(Metric::Monitoring).is_empty());
+        assert_eq!(summary.date_range(Metric::Sleep), None);
+    }
+}
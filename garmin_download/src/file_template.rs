@@ -0,0 +1,154 @@
+use chrono::NaiveDateTime;
+
+/// Layout used before output templates were configurable: <base>/<metric>/<date><addons>.<ext>
+pub const DEFAULT_FILE_TEMPLATE: &str = "{base}/{metric}/{date}{addons}.{ext}";
+
+// {year}/{month}/{day} are always numeric, {date} uses file_date_format
+const KNOWN_TOKENS: [&str; 8] = [
+    "base", "metric", "date", "year", "month", "day", "addons", "ext",
+];
+
+#[derive(Debug, PartialEq)]
+enum Segment {
+    Literal(String),
+    Token(String),
+}
+
+/// Values substituted into a file template when building an output path.
+pub struct TemplateValues<'a> {
+    pub base: &'a str,
+    pub metric: &'a str,
+    pub date: NaiveDateTime,
+    pub date_format: &'a str,
+    pub addons: &'a [String],
+    pub ext: &'a str,
+}
+
+/// Checks that a template only contains known tokens and balanced braces, and keeps
+/// each metric's files directly in <base>/<metric>, which is the only place the
+/// uploader looks for them.
+pub fn validate(template: &str) -> Result<(), String> {
+    parse(template)?;
+    let Some(name) = template.strip_prefix("{base}/{metric}/") else {
+        return Err(format!(
+            "File template '{}' must start with '{{base}}/{{metric}}/'",
+            template
+        ));
+    };
+    if name.contains('/') || name.contains('\\') {
+        return Err(format!(
+            "File template '{}' can't add subfolders below '{{metric}}'",
+            template
+        ));
+    }
+    Ok(())
+}
+
+/// Renders a validated template into a file path.
+pub fn render(template: &str, values: &TemplateValues) -> String {
+    let mut path = String::new();
+    // templates are validated when the DownloadManager is built
+    for segment in parse(template).unwrap() {
+        match segment {
+            Segment::Literal(text) => path.push_str(&text),
+            Segment::Token(token) => path.push_str(&match token.as_str() {
+                "base" => String::from(values.base),
+                "metric" => String::from(values.metric),
+                "date" => format!("{}", values.date.format(values.date_format)).replace('"', ""),
+                "year" => format!("{}", values.date.format("%Y")),
+                "month" => format!("{}", values.date.format("%m")),
+                "day" => format!("{}", values.date.format("%d")),
+                "addons" => values
+                    .addons
+                    .iter()
+                    .map(|addon| format!("-{}", addon))
+                    .collect::<String>(),
+                "ext" => String::from(values.ext.trim_start_matches('.')),
+                _ => unreachable!(),
+            }),
+        }
+    }
+    path
+}
+
+fn parse(template: &str) -> Result<Vec<Segment>, String> {
+    let mut segments: Vec<Segment> = Vec::new();
+    let mut rest = template;
+    while let Some(start) = rest.find('{') {
+        if start > 0 {
+            segments.push(Segment::Literal(String::from(&rest[..start])));
+        }
+        let Some(end) = rest[start..].find('}') else {
+            return Err(format!("Unclosed '{{' in file template: '{}'", template));
+        };
+        let token = &rest[start + 1..start + end];
+        if !KNOWN_TOKENS.contains(&token) {
+            return Err(format!(
+                "Unknown token '{{{}}}' in file template: '{}'. Known tokens: {}",
+                token,
+                template,
+                KNOWN_TOKENS
+                    .iter()
+                    .map(|t| format!("{{{}}}", t))
+                    .collect::<Vec<String>>()
+                    .join(", ")
+            ));
+        }
+        segments.push(Segment::Token(String::from(token)));
+        rest = &rest[start + end + 1..];
+    }
+    if rest.contains('}') {
+        return Err(format!("Unmatched '}}' in file template: '{}'", template));
+    }
+    if !rest.is_empty() {
+        segments.push(Segment::Literal(String::from(rest)));
+    }
+    Ok(segments)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn values<'a>(addons: &'a [String]) -> TemplateValues<'a> {
+        TemplateValues {
+            base: "/data",
+            metric: "activities",
+            date: NaiveDateTime::parse_from_str("2024-02-01 00:00:00", "%Y-%m-%d %H:%M:%S")
+                .unwrap(),
+            date_format: "%Y-%m-%d",
+            addons,
+            ext: ".json",
+        }
+    }
+
+    #[test]
+    fn render_template_test() {
+        let addons = vec![String::from("1234")];
+        assert_eq!(
+            render(DEFAULT_FILE_TEMPLATE, &values(&addons)),
+            "/data/activities/2024-02-01-1234.json"
+        );
+        assert_eq!(
+            render("{base}/{metric}/{year}_{month}{day}.{ext}", &values(&[])),
+            "/data/activities/2024_0201.json"
+        );
+    }
+
+    #[test]
+    fn validate_template_test() {
+        assert!(validate(DEFAULT_FILE_TEMPLATE).is_ok());
+        assert!(validate("{base}/{week}/{date}.{ext}")
+            .unwrap_err()
+            .contains("'{week}'"));
+        assert!(validate("{base}/{metric}/{year}{month}{day}.{ext}").is_ok());
+        assert!(validate("{base}/{metric").is_err());
+        assert!(validate("{base}/metric}").is_err());
+        assert!(validate("{base}/{year}/{metric}/{date}.{ext}")
+            .unwrap_err()
+            .contains("must start with"));
+        assert!(validate("{base}/{metric}/{year}/{date}.{ext}")
+            .unwrap_err()
+            .contains("subfolders"));
+    }
+}
@@ -1,18 +1,23 @@
-use serde_derive::Deserialize;
+use std::collections::HashMap;
 
-#[derive(Debug, Deserialize, Default)]
+use serde_derive::{Deserialize, Serialize};
+
+#[derive(Debug, Deserialize, Serialize, Default)]
 pub struct Domain {
     pub domain: String,
 }
 
-#[derive(Debug, Deserialize, Default)]
+#[derive(Debug, Deserialize, Serialize, Default)]
 pub struct Credentials {
     pub user: String,
     pub secure_password: bool,
     pub password: String,
+    // logs the username and (redacted) password on login
+    #[serde(default)]
+    pub log_credentials: bool,
 }
 
-#[derive(Debug, Deserialize, Default)]
+#[derive(Debug, Deserialize, Serialize, Default)]
 pub struct DataConfig {
     pub summary_date: String,
     pub weight_start_date: String,
@@ -21,25 +26,126 @@ pub struct DataConfig {
     pub monitoring_start_date: String,
     pub hydration_start_date: String,
     pub activity_start_date: String,
+    #[serde(default)]
+    pub stress_start_date: String,
+    #[serde(default)]
+    pub body_battery_start_date: String,
+    #[serde(default)]
+    pub hrv_start_date: String,
+    #[serde(default)]
+    pub vo2max_start_date: String,
+    #[serde(default)]
+    pub training_status_start_date: String,
+    #[serde(default)]
+    pub spo2_start_date: String,
+    #[serde(default)]
+    pub floors_start_date: String,
+    #[serde(default)]
+    pub intensity_minutes_start_date: String,
     pub download_today_data: bool,
+    // with download_today_data, also download this many days before today
+    #[serde(default)]
+    pub download_today_plus_days: u64,
+    // download up to this many days before today instead, e.g. 1 for yesterday's
+    // complete data. Anything above 0 implies download_today_data
+    #[serde(default)]
+    pub relative_date_offset: u64,
     pub num_days_from_start_date: u64,
+    // number of days downloaded in parallel
+    #[serde(default = "default_max_concurrent_downloads")]
+    pub max_concurrent_downloads: usize,
+    // awake time either side of the sleep window included in sleep downloads,
+    // 0 for just the sleep window
+    #[serde(default = "default_sleep_buffer_minutes")]
+    pub sleep_buffer_minutes: u32,
+    // the statistic requested for resting heart rate, 60 is WELLNESS_RESTING_HEART_RATE
+    #[serde(default = "default_rhr_metric_id")]
+    pub rhr_metric_id: u32,
+}
+
+fn default_max_concurrent_downloads() -> usize {
+    4
+}
+
+fn default_sleep_buffer_minutes() -> u32 {
+    60
+}
+
+fn default_rhr_metric_id() -> u32 {
+    60
 }
 
-#[derive(Debug, Deserialize, Default)]
+/// The most sleep_buffer_minutes allowed, 12 hours either side of the sleep window.
+pub const MAX_SLEEP_BUFFER_MINUTES: u32 = 720;
+
+impl DataConfig {
+    /// Checks the values that are passed straight through to Garmin as request parameters.
+    pub fn validate(&self) -> Result<(), String> {
+        if self.sleep_buffer_minutes > MAX_SLEEP_BUFFER_MINUTES {
+            return Err(format!(
+                "'sleep_buffer_minutes' must be at most {}, not {}",
+                MAX_SLEEP_BUFFER_MINUTES, self.sleep_buffer_minutes
+            ));
+        }
+        if self.rhr_metric_id == 0 {
+            return Err(String::from("'rhr_metric_id' must be above 0"));
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Deserialize, Serialize, Default)]
 pub struct ActivityConfig {
     pub num_activities_to_download: String,
+    // YYYY-MM-DD: every activity since this date, paging back as far as it takes,
+    // with num_activities_to_download as the page size
+    #[serde(default)]
+    pub activities_since: String,
     pub save_regardless_of_date: bool,
+    // one of "fit" (default), "tcx", "gpx" or "original"
+    #[serde(default)]
+    pub activity_format: String,
+    #[serde(default)]
+    pub download_gear: bool,
+    // per-lap splits JSON, uploaded as the 'splits' measurement
+    #[serde(default)]
+    pub download_splits: bool,
+    // weather during each activity, uploaded as the 'activity_weather' measurement
+    #[serde(default)]
+    pub download_weather: bool,
 }
 
-#[derive(Debug, Deserialize, Default)]
+#[derive(Debug, Deserialize, Serialize, Default)]
 pub struct FileConfig {
     pub file_date_format: String,
     pub file_base_path: String,
     pub save_to_file: bool,
     pub overwrite: bool,
+    // with overwrite disabled, still overwrite files for days within this many days of today,
+    // since Garmin keeps revising recent data as devices sync
+    #[serde(default)]
+    pub overwrite_within_days: u64,
+    // e.g. "{base}/{metric}/{year}{month}{day}{addons}.{ext}", see file_template.rs
+    #[serde(default = "default_file_template")]
+    pub file_template: String,
+    // move extracted zip archives into an 'archive' subfolder instead of deleting them
+    #[serde(default)]
+    pub keep_archives: bool,
+    // also append each day's JSON response to <ndjson_path>/<metric>.ndjson, one line per day
+    #[serde(default)]
+    pub ndjson_output: bool,
+    // defaults to file_base_path
+    #[serde(default)]
+    pub ndjson_path: String,
 }
 
-#[derive(Debug, Deserialize, Default)]
+fn default_file_template() -> String {
+    String::from(crate::file_template::DEFAULT_FILE_TEMPLATE)
+}
+
+// every metric download_all() knows about, anything missing from the config is disabled
+#[derive(Debug, Deserialize, Serialize, Default)]
+#[serde(default)]
 pub struct EnabledStats {
     pub daily_summary: bool,
     pub monitoring: bool,
@@ -48,9 +154,53 @@ pub struct EnabledStats {
     pub weight: bool,
     pub activities: bool,
     pub hydration: bool,
+    pub stress: bool,
+    pub body_battery: bool,
+    pub hrv: bool,
+    pub vo2max: bool,
+    pub training_status: bool,
+    pub spo2: bool,
+    pub floors: bool,
+    pub intensity_minutes: bool,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+#[serde(default)]
+pub struct ClientConfig {
+    // minimum delay between API requests, across all parallel downloads
+    pub min_request_interval_ms: u64,
+    // where the OAuth session is kept, e.g. one file per account
+    pub session_file: String,
+    // User-Agent for the SSO login pages and for OAuth/API requests
+    pub sso_user_agent: String,
+    pub api_user_agent: String,
+    // sent with every request, e.g. { "X-Some-Header": "value" }
+    pub extra_headers: HashMap<String, String>,
+    // times an interrupted FIT/zip download is started over before giving up
+    pub download_retries: u32,
+    // e.g. "http://proxy.example.com:8080", empty falls back to HTTP_PROXY/HTTPS_PROXY
+    pub proxy_url: String,
+    pub proxy_username: String,
+    pub proxy_password: String,
+}
+
+impl Default for ClientConfig {
+    fn default() -> Self {
+        ClientConfig {
+            min_request_interval_ms: 250,
+            session_file: String::from(garmin_client::SESSION_FILE),
+            sso_user_agent: String::from(garmin_client::DEFAULT_SSO_USER_AGENT),
+            api_user_agent: String::from(garmin_client::DEFAULT_API_USER_AGENT),
+            extra_headers: HashMap::new(),
+            download_retries: 2,
+            proxy_url: String::new(),
+            proxy_username: String::new(),
+            proxy_password: String::new(),
+        }
+    }
 }
 
-#[derive(Debug, Deserialize, Default)]
+#[derive(Debug, Deserialize, Serialize, Default)]
 pub struct GarminConfig {
     pub garmin: Domain,
     pub credentials: Credentials,
@@ -58,4 +208,100 @@ pub struct GarminConfig {
     pub activities: ActivityConfig,
     pub file: FileConfig,
     pub enabled_stats: EnabledStats,
+    #[serde(default)]
+    pub client: ClientConfig,
+}
+
+impl GarminConfig {
+    /// The config as pretty-printed JSON, with the passwords redacted.
+    pub fn to_redacted_json(&self) -> String {
+        let mut json = serde_json::to_value(self).unwrap();
+        for (section, key) in [("credentials", "password"), ("client", "proxy_password")] {
+            if let Some(serde_json::Value::String(secret)) = json[section].get_mut(key) {
+                if !secret.is_empty() {
+                    *secret = garmin_client::redact(secret);
+                }
+            }
+        }
+        serde_json::to_string_pretty(&json).unwrap()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn enabled_stats_test() {
+        let all: EnabledStats = serde_json::from_str(
+            r#"{
+                "daily_summary": true, "monitoring": true, "sleep": true, "rhr": true,
+                "weight": true, "activities": true, "hydration": true, "stress": true,
+                "body_battery": true, "hrv": true, "vo2max": true, "training_status": true,
+                "spo2": true, "floors": true, "intensity_minutes": true
+            }"#,
+        )
+        .unwrap();
+        assert!(
+            all.daily_summary
+                && all.monitoring
+                && all.sleep
+                && all.rhr
+                && all.weight
+                && all.activities
+                && all.hydration
+                && all.stress
+                && all.body_battery
+                && all.hrv
+                && all.vo2max
+                && all.training_status
+                && all.spo2
+                && all.floors
+                && all.intensity_minutes
+        );
+
+        let some: EnabledStats = serde_json::from_str(r#"{ "sleep": true }"#).unwrap();
+        assert!(some.sleep);
+        assert!(!some.hydration && !some.activities && !some.hrv && !some.vo2max);
+    }
+
+    #[test]
+    fn data_config_validate_test() {
+        let data: DataConfig = serde_json::from_str(
+            r#"{
+                "summary_date": "", "weight_start_date": "", "sleep_start_date": "",
+                "rhr_start_date": "", "monitoring_start_date": "", "hydration_start_date": "",
+                "activity_start_date": "", "download_today_data": false,
+                "num_days_from_start_date": 1
+            }"#,
+        )
+        .unwrap();
+        assert_eq!(data.sleep_buffer_minutes, 60);
+        assert_eq!(data.rhr_metric_id, 60);
+        assert!(data.validate().is_ok());
+
+        let mut data = data;
+        data.sleep_buffer_minutes = 0;
+        assert!(data.validate().is_ok());
+        data.sleep_buffer_minutes = MAX_SLEEP_BUFFER_MINUTES + 1;
+        assert!(data.validate().is_err());
+        data.sleep_buffer_minutes = 60;
+        data.rhr_metric_id = 0;
+        assert!(data.validate().is_err());
+    }
+
+    #[test]
+    fn redacted_json_test() {
+        let mut config = GarminConfig::default();
+        config.credentials.user = String::from("me@example.com");
+        config.credentials.password = String::from("hunter2");
+        config.data.num_days_from_start_date = 3;
+
+        let json: serde_json::Value = serde_json::from_str(&config.to_redacted_json()).unwrap();
+        assert_eq!(json["credentials"]["user"], "me@example.com");
+        assert_eq!(json["credentials"]["password"], "<redacted, 7 chars>");
+        // nothing to hide
+        assert_eq!(json["client"]["proxy_password"], "");
+        assert_eq!(json["data"]["num_days_from_start_date"], 3);
+    }
 }
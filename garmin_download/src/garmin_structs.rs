@@ -1,12 +1,16 @@
 use serde::Deserialize;
 
+// fields missing from (or null in) a response are left at their defaults, since
+// Garmin adds, drops and nulls out fields between API versions
+
 #[allow(non_snake_case, dead_code)]
-#[derive(Default, Deserialize)]
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
 pub struct BioMetricProfile {
     pub userId: u64,
-    pub height: f64,
-    pub weight: f64,
-    pub vo2Max: f64,
+    pub height: Option<f64>,
+    pub weight: Option<f64>,
+    pub vo2Max: Option<f64>,
     pub vo2MaxCycling: Option<f64>,
     pub lactateThresholdHeartRate: Option<f64>,
     pub activityClass: Option<f64>,
@@ -15,24 +19,76 @@ pub struct BioMetricProfile {
 }
 
 #[allow(non_snake_case, dead_code)]
-#[derive(Default, Deserialize)]
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
 pub struct UserInfo {
-    pub birthDate: String,
-    pub genderType: String,
-    pub email: String,
-    pub locale: String,
-    pub timeZone: String,
-    pub age: u32,
-    pub countryCode: String,
+    pub birthDate: Option<String>,
+    pub genderType: Option<String>,
+    pub email: Option<String>,
+    pub locale: Option<String>,
+    pub timeZone: Option<String>,
+    pub age: Option<u32>,
+    pub countryCode: Option<String>,
 }
 
 #[allow(non_snake_case, dead_code)]
-#[derive(Default, Deserialize)]
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
 pub struct PersonalInfo {
     pub userInfo: UserInfo,
     pub biometricProfile: BioMetricProfile,
-    pub timeZone: String,
-    pub locale: String,
-    pub birthDate: String,
-    pub gender: String,
+    pub timeZone: Option<String>,
+    pub locale: Option<String>,
+    pub birthDate: Option<String>,
+    pub gender: Option<String>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn personal_info_test() {
+        let info: PersonalInfo = serde_json::from_str(
+            r#"{
+                "userInfo": {
+                    "birthDate": "1990-05-17",
+                    "genderType": "MALE",
+                    "email": "runner@example.com",
+                    "locale": "en",
+                    "timeZone": "America/New_York",
+                    "age": 34,
+                    "isBlocked": false,
+                    "countryCode": null
+                },
+                "biometricProfile": {
+                    "userId": 12345678,
+                    "height": 180.0,
+                    "weight": 75000.0,
+                    "vo2Max": 52.0,
+                    "vo2MaxCycling": null,
+                    "lactateThresholdHeartRate": 168.0,
+                    "activityClass": null,
+                    "lactateThresholdSpeed": 0.34,
+                    "functionalThresholdPower": null,
+                    "criticalSwimSpeed": null
+                },
+                "timeZone": "America/New_York",
+                "locale": "en",
+                "birthDate": "1990-05-17"
+            }"#,
+        )
+        .unwrap();
+        assert_eq!(info.biometricProfile.userId, 12345678);
+        assert_eq!(info.biometricProfile.vo2Max, Some(52.0));
+        assert_eq!(info.biometricProfile.vo2MaxCycling, None);
+        assert_eq!(info.userInfo.birthDate.as_deref(), Some("1990-05-17"));
+        assert_eq!(info.userInfo.age, Some(34));
+        assert_eq!(info.userInfo.countryCode, None);
+        // not in the response at all
+        assert_eq!(info.gender, None);
+
+        let empty: PersonalInfo = serde_json::from_str("{}").unwrap();
+        assert_eq!(empty.biometricProfile.userId, 0);
+    }
 }
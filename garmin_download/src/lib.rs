@@ -1,20 +1,292 @@
-use chrono::{Days, Local, NaiveDateTime, ParseError};
+use chrono::{Days, Local, NaiveDate, NaiveDateTime};
 use config::Config;
+use futures::future::BoxFuture;
+use futures::stream::{self, StreamExt};
+use futures::{Future, FutureExt};
 use getopts::Matches;
 use log::{debug, error, info, warn};
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::fs::{self, File};
 use std::io::{BufWriter, Write};
-use std::path::Path;
-
-use garmin_client;
-
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::time::Duration;
+
+mod api_error;
+mod date_range;
+mod download_state;
+mod download_summary;
+mod file_template;
 mod garmin_config;
 mod garmin_structs;
+mod ndjson;
+mod progress;
 
-pub use crate::garmin_client::{ClientTraits, GarminClient, SESSION_FILE};
+pub use crate::api_error::ApiError;
+pub use crate::download_state::{DownloadState, STATE_FILE};
+pub use crate::download_summary::{DownloadSummary, MetricCounts, Outcome};
 pub use crate::garmin_config::GarminConfig;
 pub use crate::garmin_structs::PersonalInfo;
+pub use crate::progress::{Progress, ProgressCallback};
+pub use garmin_client::{ClientTraits, GarminClient, LoginError, EXTRACTED_FILE, SESSION_FILE};
+
+impl Metric {
+    /// Name used for this metric in the download state file.
+    pub fn name(&self) -> &'static str {
+        match self {
+            Metric::Sleep => "sleep",
+            Metric::Rhr => "rhr",
+            Metric::Weight => "weight",
+            Metric::Summary => "daily_summary",
+            Metric::Monitoring => "monitoring",
+            Metric::Hydration => "hydration",
+            Metric::Stress => "stress",
+            Metric::BodyBattery => "body_battery",
+            Metric::Hrv => "hrv",
+            Metric::Vo2Max => "vo2max",
+            Metric::TrainingStatus => "training_status",
+            Metric::Spo2 => "spo2",
+            Metric::Floors => "floors",
+            Metric::IntensityMinutes => "intensity_minutes",
+            Metric::Activities => "activities",
+        }
+    }
+
+    /// The subfolder of file_base_path (the {metric} in 'file_template') this metric's
+    /// files are saved in. Besides these, activities also save to "gear", "splits" and
+    /// "weather" (each file named with the activity ID as an addon), and the list of
+    /// activity types goes in "activity_types".
+    pub fn folder(&self) -> &'static str {
+        match self {
+            Metric::Rhr => "heartrate",
+            Metric::Summary => "day_summary",
+            metric => metric.name(),
+        }
+    }
+}
+
+/// Keyring service name used to look up the password when 'secure_password' is set.
+pub const KEYRING_SERVICE: &str = "garmin_connect";
+
+/// Individual metrics that can be downloaded via DownloadManager::download_metric().
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Metric {
+    Sleep,
+    Rhr,
+    Weight,
+    Summary,
+    Monitoring,
+    Hydration,
+    Stress,
+    BodyBattery,
+    Hrv,
+    Vo2Max,
+    TrainingStatus,
+    Spo2,
+    Floors,
+    IntensityMinutes,
+    Activities,
+}
+
+/// File formats activities can be downloaded in via DownloadManager::get_activity_details().
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ActivityFormat {
+    /// Original upload, extracted from its zip archive (FIT for Garmin devices)
+    #[default]
+    Fit,
+    Tcx,
+    Gpx,
+    /// Original upload, left inside the zip archive Garmin serves it in
+    Original,
+}
+
+impl FromStr for ActivityFormat {
+    type Err = String;
+
+    fn from_str(format: &str) -> Result<Self, Self::Err> {
+        match format.to_lowercase().as_str() {
+            "" | "fit" => Ok(ActivityFormat::Fit),
+            "tcx" => Ok(ActivityFormat::Tcx),
+            "gpx" => Ok(ActivityFormat::Gpx),
+            "original" => Ok(ActivityFormat::Original),
+            _ => Err(format!(
+                "Unknown activity format '{}', expected one of: fit, tcx, gpx, original",
+                format
+            )),
+        }
+    }
+}
+
+// the '_' query param the web client sends, so Garmin doesn't serve a cached response
+fn cache_buster() -> String {
+    format!("{}", Local::now().timestamp_millis())
+}
+
+/// Pulls the activity ID out of a Garmin Connect activity URL copied from the
+/// browser, e.g. "https://connect.garmin.com/modern/activity/123456789".
+pub fn activity_id_from_url(url: &str) -> Result<u64, String> {
+    let trimmed = url.trim();
+    let without_scheme = trimmed.split_once("://").map_or(trimmed, |(_, rest)| rest);
+    // ignore any query string or fragment, e.g. "?tab=laps"
+    let path = without_scheme.split(['?', '#']).next().unwrap_or_default();
+    let mut segments = path.split('/');
+    if !segments.next().is_some_and(|host| host.contains("garmin")) {
+        return Err(format!("'{}' isn't a Garmin Connect URL", url));
+    }
+    match segments.skip_while(|segment| *segment != "activity").nth(1) {
+        Some(id) => id
+            .parse::<u64>()
+            .map_err(|_| format!("'{}' in '{}' isn't an activity ID", id, url)),
+        None => Err(format!("no activity ID in '{}'", url)),
+    }
+}
+
+/// Parses a "START,END" range of dates, e.g. "2024-01-01,2024-01-31" for --fill_gaps.
+pub fn parse_date_range(range: &str) -> Result<(NaiveDate, NaiveDate), String> {
+    let Some((start, end)) = range.split_once(',') else {
+        return Err(format!("expected 'START,END', got '{}'", range));
+    };
+    let parse = |date: &str| {
+        NaiveDate::parse_from_str(date.trim(), "%Y-%m-%d")
+            .map_err(|e| format!("'{}' isn't a YYYY-MM-DD date: {}", date.trim(), e))
+    };
+    let (start, end) = (parse(start)?, parse(end)?);
+    if start > end {
+        return Err(format!("start {} is after end {}", start, end));
+    }
+    Ok((start, end))
+}
+
+/// Every date from 'start' up to and including 'end' that isn't in 'existing'.
+pub fn missing_dates(
+    start: NaiveDate,
+    end: NaiveDate,
+    existing: &HashSet<NaiveDate>,
+) -> Vec<NaiveDate> {
+    start
+        .iter_days()
+        .take_while(|date| *date <= end)
+        .filter(|date| !existing.contains(date))
+        .collect()
+}
+
+// the IDs of the activities in a page of search results that started within 'date_range'
+// (if any), and whether any started before it. entries without a usable ID or start time
+// are logged and skipped, rather than failing the whole page.
+fn activity_ids_in_range(
+    lookup: &[serde_json::Value],
+    date_range: Option<(NaiveDateTime, NaiveDateTime)>,
+) -> (Vec<u64>, bool) {
+    // results are newest first, so once one is older than the range so is the rest
+    let mut reached_start = false;
+    let mut activity_ids: Vec<u64> = Vec::new();
+
+    for activity in lookup {
+        let name = activity["activityName"].as_str().unwrap_or("<unnamed>");
+        let id = &activity["activityId"];
+        let Some(id) = id
+            .as_u64()
+            .or_else(|| id.as_str().and_then(|id| id.parse::<u64>().ok()))
+        else {
+            warn!(
+                "Skipping activity '{}' with invalid activityId: {}",
+                name, id
+            );
+            continue;
+        };
+
+        if let Some((start, end)) = date_range {
+            let activity_string = activity["startTimeLocal"].as_str().unwrap_or_default();
+            let Ok(activity_date) =
+                NaiveDateTime::parse_from_str(activity_string, "%Y-%m-%d %H:%M:%S")
+            else {
+                warn!(
+                    "Skipping activity {} with invalid startTimeLocal: {}",
+                    id, activity["startTimeLocal"]
+                );
+                continue;
+            };
+            if activity_date < start {
+                reached_start = true;
+            }
+            if activity_date < start || activity_date >= end {
+                info!("Ignoring activity '{}' from: {}", name, activity_string);
+                continue;
+            }
+        }
+        activity_ids.push(id);
+    }
+    (activity_ids, reached_start)
+}
+
+// "${GARMIN_PASSWORD}" -> the value of $GARMIN_PASSWORD, anything else as is
+fn resolve_password(password: &str) -> Result<String, LoginError> {
+    let Some(name) = password
+        .strip_prefix("${")
+        .and_then(|rest| rest.strip_suffix('}'))
+    else {
+        return Ok(String::from(password));
+    };
+    std::env::var(name).map_err(|_| {
+        LoginError::MissingPassword(format!("environment variable {} is not set", name))
+    })
+}
+
+/// A single API call, built up front so several can be run concurrently.
+struct ApiRequest {
+    endpoint: String,
+    params: Vec<(String, String)>,
+    json: bool,
+    filename: Option<String>,
+    // the day this request is for and the .ndjson file its response is appended to,
+    // see DownloadManager::ndjson_file()
+    ndjson: Option<(NaiveDate, PathBuf)>,
+}
+
+impl ApiRequest {
+    fn new(
+        endpoint: String,
+        params: Vec<(&str, &str)>,
+        json: bool,
+        filename: Option<String>,
+    ) -> ApiRequest {
+        ApiRequest {
+            endpoint,
+            params: params
+                .into_iter()
+                .map(|(key, value)| (String::from(key), String::from(value)))
+                .collect(),
+            json,
+            filename,
+            ndjson: None,
+        }
+    }
+}
+
+// builds the request for one day of a metric, or None if it can't be built
+type RequestBuilder = fn(&DownloadManager, NaiveDateTime) -> Option<ApiRequest>;
+
+// how each day-by-day metric's requests are built; activities aren't downloaded by day
+fn request_builder(metric: Metric) -> Option<RequestBuilder> {
+    let build_request: RequestBuilder = match metric {
+        Metric::Sleep => DownloadManager::sleep_request,
+        Metric::Rhr => DownloadManager::resting_heart_rate_request,
+        Metric::Weight => DownloadManager::weight_request,
+        Metric::Summary => DownloadManager::summary_request,
+        Metric::Monitoring => DownloadManager::monitoring_request,
+        Metric::Hydration => DownloadManager::hydration_request,
+        Metric::Stress => DownloadManager::stress_request,
+        Metric::BodyBattery => DownloadManager::body_battery_request,
+        Metric::Hrv => DownloadManager::hrv_request,
+        Metric::Vo2Max => DownloadManager::vo2max_request,
+        Metric::TrainingStatus => DownloadManager::training_status_request,
+        Metric::Spo2 => DownloadManager::spo2_request,
+        Metric::Floors => DownloadManager::floors_request,
+        Metric::IntensityMinutes => DownloadManager::intensity_minutes_request,
+        Metric::Activities => return None,
+    };
+    Some(build_request)
+}
 
 /// Class for downloading health data from Garmin Connect.
 /// This class requires the garmin_client crate to provide authentication
@@ -38,18 +310,35 @@ pub struct DownloadManager {
     garmin_connect_activity_service_url: String,
 
     garmin_connect_download_service_url: String,
+    garmin_connect_export_service_url: String,
 
     garmin_connect_usersummary_url: String,
     garmin_connect_daily_summary_url: String,
     garmin_connect_daily_hydration_url: String,
+    garmin_connect_gear_url: String,
+    garmin_connect_daily_stress_url: String,
+    garmin_connect_daily_body_battery_url: String,
+    garmin_connect_hrv_url: String,
+    garmin_connect_vo2max_url: String,
+    garmin_connect_training_status_url: String,
+    garmin_connect_daily_spo2_url: String,
+    garmin_connect_floors_url: String,
+    garmin_connect_intensity_minutes_url: String,
 
     garmin_user_profile_url: String,
 
     garmin_client: GarminClient,
     garmin_config: GarminConfig,
-    personal_info: PersonalInfo,
+    // fetched once by get_personal_info()
+    personal_info: Option<PersonalInfo>,
     full_name: String,
     display_name: String,
+    download_state: DownloadState,
+    since_last: bool,
+    // page through activity search results until they're older than the range
+    backfill: bool,
+    download_summary: DownloadSummary,
+    progress: progress::ProgressReporter,
 }
 
 impl DownloadManager {
@@ -62,6 +351,10 @@ impl DownloadManager {
     ///     &nbsp;&nbsp;&nbsp;&nbsp;"s": "YYY-MM-DD" -> overrides the download date for sleeep info (JSON)<br />
     ///     &nbsp;&nbsp;&nbsp;&nbsp;"r": "YYY-MM-DD" -> overrides the download date for heart_rate info (JSON)<br />
     ///     &nbsp;&nbsp;&nbsp;&nbsp;"m": "YYY-MM-DD" -> overrides the download date for monitoring data (FIT file)<br />
+    ///     &nbsp;&nbsp;&nbsp;&nbsp;"v": "YYY-MM-DD" -> overrides the download date for HRV info (JSON)<br />
+    ///     &nbsp;&nbsp;&nbsp;&nbsp;"since_last" -> downloads each day since the last successful download, up to today<br />
+    ///     &nbsp;&nbsp;&nbsp;&nbsp;"backfill": "N" -> downloads the last N days (including today) of every enabled stat<br />
+    ///     &nbsp;&nbsp;&nbsp;&nbsp;"backfill_start": "YYY-MM-DD" -> downloads every enabled stat from this date up to today<br />
     ///
     /// Each API call saves the response url and text in case users want more info from the call. These are saved after
     /// the most recent call (i.e., no API response 'history' included) and overwritten with each call.
@@ -82,20 +375,43 @@ impl DownloadManager {
             garmin_connect_activity_service_url: String::from("activity-service/activity"),
 
             garmin_connect_download_service_url: String::from("download-service/files"),
+            garmin_connect_export_service_url: String::from("download-service/export"),
 
             garmin_connect_usersummary_url: String::from("usersummary-service/usersummary"),
             garmin_connect_daily_summary_url: String::from("usersummary-service/usersummary/daily"),
             garmin_connect_daily_hydration_url: String::from(
                 "usersummary-service/usersummary/hydration/allData",
             ),
+            garmin_connect_gear_url: String::from("gear-service/gear/filterGear"),
+            garmin_connect_daily_stress_url: String::from("wellness-service/wellness/dailyStress"),
+            garmin_connect_daily_body_battery_url: String::from(
+                "wellness-service/wellness/bodyBattery/reports/daily",
+            ),
+            garmin_connect_hrv_url: String::from("hrv-service/hrv"),
+            garmin_connect_vo2max_url: String::from("metrics-service/metrics/maxmet/daily"),
+            garmin_connect_training_status_url: String::from(
+                "metrics-service/metrics/trainingstatus/aggregated",
+            ),
+            garmin_connect_daily_spo2_url: String::from("wellness-service/wellness/daily/spo2"),
+            garmin_connect_floors_url: String::from(
+                "wellness-service/wellness/floorsChartData/daily",
+            ),
+            garmin_connect_intensity_minutes_url: String::from(
+                "wellness-service/wellness/daily/im",
+            ),
 
             garmin_user_profile_url: String::from("userprofile-service/socialProfile"),
 
             garmin_client: GarminClient::new(),
             garmin_config: config.try_deserialize().unwrap(),
-            personal_info: Default::default(),
+            personal_info: None,
             full_name: String::new(),
             display_name: String::new(),
+            download_state: DownloadState::load(STATE_FILE),
+            since_last: false,
+            backfill: false,
+            download_summary: Default::default(),
+            progress: Default::default(),
         };
 
         if let Some(options) = options {
@@ -124,50 +440,241 @@ impl DownloadManager {
                 dm.garmin_config.data.monitoring_start_date = date;
                 dm.garmin_config.enabled_stats.monitoring = true;
             }
+            if let Ok(Some(date)) = options.opt_get::<String>("v") {
+                dm.garmin_config.data.hrv_start_date = date;
+                dm.garmin_config.enabled_stats.hrv = true;
+            }
+            dm.since_last = options.opt_present("since_last");
             if let Ok(Some(date)) = options.opt_get::<String>("a") {
                 dm.garmin_config.data.activity_start_date = date;
                 dm.garmin_config.enabled_stats.activities = true;
             }
+            if let Ok(Some(date)) = options.opt_get::<String>("activities_since") {
+                dm.garmin_config.activities.activities_since = date;
+                dm.garmin_config.enabled_stats.activities = true;
+            }
+            match options.opt_get::<u64>("backfill") {
+                Ok(Some(days)) => {
+                    let start = Local::now()
+                        .date_naive()
+                        .checked_sub_days(Days::new(days.saturating_sub(1)))
+                        .unwrap();
+                    dm.set_backfill_start(start);
+                }
+                Ok(None) => {}
+                Err(e) => panic!("Expected a number of days for --backfill, error: {}", e),
+            }
+            if let Ok(Some(date)) = options.opt_get::<String>("backfill_start") {
+                match NaiveDate::parse_from_str(&date, "%Y-%m-%d") {
+                    Ok(start) => dm.set_backfill_start(start),
+                    Err(e) => panic!(
+                        "Expected --backfill_start in '%Y-%m-%d' format, got: {}, error: {}",
+                        date, e
+                    ),
+                }
+            }
+        }
+        dm.garmin_client.set_domain(&dm.garmin_config.garmin.domain);
+        let client_config = &dm.garmin_config.client;
+        if let Err(e) = dm.garmin_client.set_proxy(
+            &client_config.proxy_url,
+            &client_config.proxy_username,
+            &client_config.proxy_password,
+        ) {
+            panic!("Invalid 'proxy_url' in garmin config: {}", e);
+        }
+        if !dm.garmin_config.client.session_file.is_empty() {
+            dm.garmin_client
+                .set_session_file(&dm.garmin_config.client.session_file);
+        }
+        // kept with the downloads it describes, rather than wherever the run started
+        let extracted_file = Path::new(&dm.garmin_config.file.file_base_path).join(EXTRACTED_FILE);
+        dm.garmin_client
+            .set_extracted_file(&extracted_file.to_string_lossy());
+        dm.garmin_client.set_user_agents(
+            &dm.garmin_config.client.sso_user_agent,
+            &dm.garmin_config.client.api_user_agent,
+        );
+        for (name, value) in &dm.garmin_config.client.extra_headers {
+            dm.garmin_client.add_header(name, value);
+        }
+        dm.garmin_client
+            .set_keep_archives(dm.garmin_config.file.keep_archives);
+        dm.garmin_client
+            .set_download_retries(dm.garmin_config.client.download_retries);
+        dm.garmin_client
+            .set_min_request_interval(Duration::from_millis(
+                dm.garmin_config.client.min_request_interval_ms,
+            ));
+        if let Err(e) = ActivityFormat::from_str(&dm.garmin_config.activities.activity_format) {
+            panic!("Invalid 'activity_format' in garmin config: {}", e);
+        }
+        if let Err(e) = file_template::validate(&dm.garmin_config.file.file_template) {
+            panic!("Invalid 'file_template' in garmin config: {}", e);
+        }
+        let since = &dm.garmin_config.activities.activities_since;
+        if !since.is_empty() && NaiveDate::parse_from_str(since, "%Y-%m-%d").is_err() {
+            panic!(
+                "Expected 'activities_since' in '%Y-%m-%d' format, got: {}",
+                since
+            );
+        }
+        if let Err(e) = dm.garmin_config.data.validate() {
+            panic!("Invalid garmin config: {}", e);
+        }
+        if dm.garmin_config.data.relative_date_offset > 0 {
+            // same as today's data, just ending a few days earlier
+            dm.garmin_config.data.download_today_data = true;
         }
         if dm.garmin_config.data.download_today_data {
-            dm.garmin_config.data.num_days_from_start_date = 1;
+            // today, plus however many days before it
+            dm.garmin_config.data.num_days_from_start_date =
+                1 + dm.garmin_config.data.download_today_plus_days;
         }
         dm
     }
 
-    /// Downloads all data enabled in config provided in 'new()'
-    pub async fn download_all(&mut self) {
+    /// Points every enabled stat at 'start' and downloads each day from there up to
+    /// and including today, paging through activities until they're older than 'start'.
+    ///
+    /// Requests still go through the client's rate limiter ('min_request_interval_ms'),
+    /// so long backfills don't trip Garmin's throttling.
+    pub fn set_backfill_start(&mut self, start: NaiveDate) {
+        let today = Local::now().date_naive();
+        let num_days = (today - start).num_days().max(0) as u64 + 1;
+        info!(
+            "Backfilling {} day(s) of data, starting {}",
+            num_days,
+            start.format("%Y-%m-%d")
+        );
+
+        let start = format!("{}", start.format("%Y-%m-%d"));
+        let data = &mut self.garmin_config.data;
+        for date in [
+            &mut data.summary_date,
+            &mut data.weight_start_date,
+            &mut data.sleep_start_date,
+            &mut data.rhr_start_date,
+            &mut data.monitoring_start_date,
+            &mut data.hydration_start_date,
+            &mut data.activity_start_date,
+            &mut data.stress_start_date,
+            &mut data.body_battery_start_date,
+            &mut data.hrv_start_date,
+            &mut data.vo2max_start_date,
+            &mut data.training_status_start_date,
+            &mut data.spo2_start_date,
+            &mut data.floors_start_date,
+            &mut data.intensity_minutes_start_date,
+        ] {
+            *date = start.clone();
+        }
+        data.num_days_from_start_date = num_days;
+        data.download_today_data = false;
+        // the date range is what limits activities, not the number of them
+        self.garmin_config.activities.save_regardless_of_date = false;
+        self.since_last = false;
+        self.backfill = true;
+    }
+
+    /// Downloads all data enabled in config provided in 'new()', then logs a
+    /// per-metric summary of what was requested, saved, skipped and failed.
+    ///
+    /// Returns every file saved, e.g. to process the fresh files right away.
+    pub async fn download_all(&mut self) -> Vec<PathBuf> {
+        self.download_summary.clear();
+        let mut saved: Vec<PathBuf> = Vec::new();
         if self.garmin_config.enabled_stats.activities {
-            let num_activities = self
-                .garmin_config
-                .activities
-                .num_activities_to_download
-                .parse::<u32>()
-                .unwrap();
-            self.get_activity_summaries(num_activities).await;
+            let num_activities = self.get_num_activities_to_download();
+            saved.extend(self.get_activity_summaries(num_activities).await);
         }
         if self.garmin_config.enabled_stats.sleep {
-            self.get_sleep().await;
+            saved.extend(self.get_sleep().await);
         }
         if self.garmin_config.enabled_stats.rhr {
-            self.get_resting_heart_rate().await;
+            saved.extend(self.get_resting_heart_rate().await);
         }
         if self.garmin_config.enabled_stats.weight {
-            self.get_weight().await;
+            saved.extend(self.get_weight().await);
         }
         if self.garmin_config.enabled_stats.daily_summary {
-            self.get_summary_day().await;
+            saved.extend(self.get_summary_day().await);
         }
         if self.garmin_config.enabled_stats.monitoring {
-            self.monitoring().await;
+            saved.extend(self.monitoring().await);
         }
         if self.garmin_config.enabled_stats.hydration {
-            self.get_hydration().await;
+            saved.extend(self.get_hydration().await);
+        }
+        if self.garmin_config.enabled_stats.stress {
+            saved.extend(self.get_stress().await);
+        }
+        if self.garmin_config.enabled_stats.body_battery {
+            saved.extend(self.get_body_battery().await);
         }
+        if self.garmin_config.enabled_stats.hrv {
+            saved.extend(self.get_hrv().await);
+        }
+        if self.garmin_config.enabled_stats.vo2max {
+            saved.extend(self.get_vo2max().await);
+        }
+        if self.garmin_config.enabled_stats.training_status {
+            saved.extend(self.get_training_status().await);
+        }
+        if self.garmin_config.enabled_stats.spo2 {
+            saved.extend(self.get_spo2().await);
+        }
+        if self.garmin_config.enabled_stats.floors {
+            saved.extend(self.get_floors().await);
+        }
+        if self.garmin_config.enabled_stats.intensity_minutes {
+            saved.extend(self.get_intensity_minutes().await);
+        }
+        self.download_summary.log();
+        saved
+    }
+
+    /// Reports progress through each metric to 'callback' as download_all() runs,
+    /// e.g. to render a progress bar during long backfills.
+    pub fn set_progress_callback(&mut self, callback: ProgressCallback) {
+        self.progress.set_callback(callback);
+    }
+
+    /// Counts of what's been downloaded since the start of the last download_all().
+    pub fn get_download_summary(&self) -> &DownloadSummary {
+        &self.download_summary
+    }
+
+    /// The config downloads will run with, i.e. after any command line overrides
+    /// and 'download_today_data'/backfill adjustments.
+    pub fn get_config(&self) -> &GarminConfig {
+        &self.garmin_config
     }
 
     pub fn get_last_resp_text(&self) -> &str {
-        &self.garmin_client.get_last_resp_text()
+        self.garmin_client.get_last_resp_text()
+    }
+
+    // the final url and status of the latest response, for debugging API changes
+    fn log_last_response(&self) {
+        match self.garmin_client.get_last_resp_status() {
+            Some(status) => warn!(
+                "Last response was {} from {}",
+                status,
+                self.garmin_client.get_last_resp_url()
+            ),
+            None => warn!("No response received yet"),
+        }
+    }
+
+    // requests 'endpoint' without saving it, and parses the response as JSON.
+    // failures are logged here, so callers can just bail out on Err.
+    async fn request_json(
+        &mut self,
+        endpoint: &str,
+        params: Option<HashMap<&str, &str>>,
+    ) -> Result<serde_json::Value, ApiError> {
+        request_json_from(self, endpoint, params).await
     }
 
     /// Retrives user profile, which includes fields like displayName and fullName.
@@ -176,7 +683,7 @@ impl DownloadManager {
     pub async fn get_user_profile(&mut self) {
         // check session file for displayName and fullName
         // if session file exists: open, append, save user profile info
-        match fs::read_to_string(&SESSION_FILE) {
+        match fs::read_to_string(self.garmin_client.get_session_file()) {
             Ok(file_contents) => {
                 let map: HashMap<String, serde_json::Value> =
                     serde_json::from_str(&file_contents).unwrap();
@@ -200,18 +707,17 @@ impl DownloadManager {
         }
 
         // response will contain displayName and fullName
-        self.garmin_client
-            .api_request(&self.garmin_user_profile_url, None, true, None)
-            .await;
-
-        let response_text = self.get_last_resp_text();
-        if response_text.len() == 0 {
-            warn!("Got empty response from API, unable to get user profile. Are you using the latest client version?");
+        let endpoint = String::from(&self.garmin_user_profile_url);
+        let Ok(response) = self.request_json(&endpoint, None).await else {
             return;
-        }
-
-        let lookup: HashMap<String, serde_json::Value> =
-            serde_json::from_str(response_text).unwrap();
+        };
+        let lookup: HashMap<String, serde_json::Value> = match serde_json::from_value(response) {
+            Ok(lookup) => lookup,
+            Err(e) => {
+                warn!("Unable to parse user profile, error: {}", e);
+                return;
+            }
+        };
 
         if lookup.contains_key("displayName") {
             self.display_name = lookup["displayName"].to_string().replace('"', "");
@@ -224,15 +730,15 @@ impl DownloadManager {
         }
 
         // if session file exists: open, append, save user profile info
-        match fs::read_to_string(&SESSION_FILE) {
+        match fs::read_to_string(self.garmin_client.get_session_file()) {
             Ok(file_contents) => {
                 let mut map: HashMap<String, serde_json::Value> =
                     serde_json::from_str(&file_contents).unwrap();
                 map.extend(lookup);
 
-                let file = File::create(&SESSION_FILE).unwrap();
+                let file = File::create(self.garmin_client.get_session_file()).unwrap();
                 let mut writer = BufWriter::new(file);
-                if let Ok(_) = serde_json::to_writer_pretty(&mut writer, &map) {
+                if serde_json::to_writer_pretty(&mut writer, &map).is_ok() {
                     match writer.flush() {
                         Ok(_) => {}
                         Err(e) => {
@@ -249,18 +755,18 @@ impl DownloadManager {
 
     /// Retrieves the user's display name.
     pub async fn get_display_name(&mut self) -> String {
-        if self.display_name.len() == 0 {
+        if self.display_name.is_empty() {
             self.get_user_profile().await;
         }
-        return String::from(&self.display_name);
+        String::from(&self.display_name)
     }
 
     /// Retrieves the user's full name.
     pub async fn get_full_name(&mut self) -> String {
-        if self.full_name.len() == 0 {
+        if self.full_name.is_empty() {
             self.get_user_profile().await;
         }
-        return String::from(&self.full_name);
+        String::from(&self.full_name)
     }
 
     fn get_download_date(&self, default_date: &str, day_offset: u64) -> NaiveDateTime {
@@ -268,7 +774,15 @@ impl DownloadManager {
         // overriding to today and 2) make sure the format is correct if not
         if self.garmin_config.data.download_today_data {
             info!("download_today_data set - ignoring any config or command line dates");
-            return Local::now().naive_local();
+            // the range starts 'download_today_plus_days' before the (offset) end date
+            let data = &self.garmin_config.data;
+            return Local::now()
+                .naive_local()
+                .checked_sub_days(Days::new(
+                    data.download_today_plus_days + data.relative_date_offset,
+                ))
+                .and_then(|start| start.checked_add_days(Days::new(day_offset)))
+                .unwrap();
         }
         let mut temp_date: String = String::from(default_date);
         temp_date.push_str(" 00:00:00");
@@ -283,55 +797,81 @@ impl DownloadManager {
     }
 
     /// Logs in using the configured username and password.
-    pub async fn login(&mut self) {
+    pub async fn login(&mut self) -> Result<(), LoginError> {
         // connect to domain using login url
-        let username: &str = &self.garmin_config.credentials.user;
-        let password: &str = &self.garmin_config.credentials.password;
+        let username = String::from(&self.garmin_config.credentials.user);
+        let password = self.get_password()?;
         let domain: &str = &self.garmin_config.garmin.domain;
 
-        debug!(
-            "login domain: {}, username: {}, password: {}",
-            domain, username, password
-        );
+        if self.garmin_config.credentials.log_credentials {
+            debug!(
+                "login domain: {}, username: {}, password: {}",
+                domain,
+                username,
+                garmin_client::redact(&password)
+            );
+        } else {
+            debug!("login domain: {}", domain);
+        }
 
         // login, get OAuth2.0 token, get user profile, and save all to session file
-        self.garmin_client.login(username, password).await;
+        self.garmin_client.login(&username, &password).await?;
         self.get_user_profile().await;
+        Ok(())
     }
 
-    /// Retrieves and prints the user's personal info (e.g., userId, birthday, email, etc)
-    pub async fn get_personal_info(&mut self) {
+    /// True if a saved session can be used without logging in again.
+    pub fn has_valid_session(&self) -> bool {
+        self.garmin_client.has_valid_session()
+    }
+
+    /// Retrieves and logs the user's personal info (e.g., userId, birthday, vo2Max, etc).
+    ///
+    /// Only the first successful call hits the API, later ones return the same info.
+    pub async fn get_personal_info(&mut self) -> Option<&PersonalInfo> {
+        if self.personal_info.is_none() {
+            self.personal_info = self.fetch_personal_info().await;
+        }
+        self.personal_info.as_ref()
+    }
+
+    async fn fetch_personal_info(&mut self) -> Option<PersonalInfo> {
         let mut personal_info_endpoint: String =
             String::from(&self.garmin_connect_user_profile_url);
         personal_info_endpoint.push_str("/personal-information");
 
-        if !self
-            .garmin_client
-            .api_request(&personal_info_endpoint, None, true, None)
+        let response = self
+            .request_json(&personal_info_endpoint, None)
             .await
-        {
-            return;
-        }
-
-        let response_text = self.get_last_resp_text();
-        if response_text.len() == 0 {
-            warn!("Got empty response from API, unable to get personal info");
-            return;
-        }
+            .ok()?;
 
         // deserialize into struct
-        self.personal_info = serde_json::from_str(response_text).unwrap();
+        let personal_info: PersonalInfo = match serde_json::from_value(response) {
+            Ok(personal_info) => personal_info,
+            Err(e) => {
+                warn!("Unable to parse personal info, error: {}", e);
+                return None;
+            }
+        };
         info!(
             "Got personal info. \nuserId: {}\nbirthday: {}\nemail: {}\nage: {}",
-            &self.personal_info.biometricProfile.userId,
-            &self.personal_info.userInfo.birthDate,
-            &self.personal_info.userInfo.email,
-            &self.personal_info.userInfo.age
-        )
+            personal_info.biometricProfile.userId,
+            personal_info
+                .userInfo
+                .birthDate
+                .as_deref()
+                .unwrap_or("unknown"),
+            personal_info.userInfo.email.as_deref().unwrap_or("unknown"),
+            personal_info
+                .userInfo
+                .age
+                .map_or(String::from("unknown"), |age| age.to_string())
+        );
+        Some(personal_info)
     }
 
     /// Retrieves the activity: activityId mapping from garmin.
-    pub async fn get_activity_types(&mut self) {
+    pub async fn get_activity_types(&mut self) -> Vec<PathBuf> {
         // retrieves all possible activity types from Garmin. Included activityTypeIds for each.
         let mut endpoint: String = String::from(&self.garmin_connect_activity_service_url);
         endpoint.push_str("/activityTypes");
@@ -339,86 +879,142 @@ impl DownloadManager {
         self.garmin_client
             .api_request(&endpoint, None, true, filename)
             .await;
+        self.garmin_client.get_last_saved_files().to_vec()
     }
 
     /// Downloads last activity_count JSON summary and associated FIT files.
     ///
     /// If this DownloadManager was configured with 'download_today_data': true
     /// then only those activities that occurred today will be actually saved.
-    pub async fn get_activity_summaries(&mut self, activity_count: u32) {
-        // get high level activity summary, each entry contains activity ID that
-        // can be used to get more specific info
-        if activity_count == 0 {
-            warn!("User requested 0 activities, check config");
-            return;
+    pub async fn get_activity_summaries(&mut self, activity_count: u32) -> Vec<PathBuf> {
+        let date_range = self.activity_date_range();
+        self.get_activity_summaries_in_range(activity_count, date_range)
+            .await
+    }
+
+    // the start times of the activities to download, None for the latest ones regardless
+    fn activity_date_range(&self) -> Option<(NaiveDateTime, NaiveDateTime)> {
+        if let Some(since) = self.activities_since() {
+            // up to the end of today
+            let end = Local::now().date_naive().succ_opt().unwrap();
+            return Some((
+                since.and_hms_opt(0, 0, 0).unwrap(),
+                end.and_hms_opt(0, 0, 0).unwrap(),
+            ));
         }
-        let endpoint: String = String::from(&self.garmin_connect_activity_search_url);
-        let count = format!("{}", activity_count);
-        let params = HashMap::from([("start", "0"), ("limit", &count)]);
-        self.garmin_client
-            .api_request(&endpoint, Some(params), true, None)
-            .await;
+        let mut date_range: Option<(NaiveDateTime, NaiveDateTime)> = None;
+        let mut start_string: Option<String> = None;
 
-        let response_text = self.get_last_resp_text();
-        if response_text.len() == 0 {
-            warn!(
-                "Got empty response from API, unable to get summaries for last {} activities",
-                activity_count
+        if self.garmin_config.data.download_today_data {
+            // check if the activity started today (or in the extra days before it)
+            start_string = Some(format!(
+                "{}",
+                self.get_download_date("", 0).format("%Y-%m-%d 00:00:00")
+            ));
+        } else if !self.garmin_config.activities.save_regardless_of_date {
+            // check if activity started on the date specified
+            start_string = Some(
+                format!("{} 00:00:00", self.garmin_config.data.activity_start_date)
+                    .replace('"', ""),
             );
-            return;
         }
 
-        let lookup: Vec<serde_json::Value> = serde_json::from_str(response_text).unwrap();
-
-        for activity in lookup {
-            let id = &activity["activityId"];
-            let name = &activity["activityName"].to_string().replace('"', "");
-            let activity_string = &activity["startTimeLocal"].to_string().replace('"', "");
-            let activity_date =
-                NaiveDateTime::parse_from_str(activity_string, "%Y-%m-%d %H:%M:%S").unwrap();
-
-            let mut start_string: Option<String> = None;
+        if let Some(start_string) = start_string {
+            let start = NaiveDateTime::parse_from_str(&start_string, "%Y-%m-%d %H:%M:%S").unwrap();
+            let end = start
+                .checked_add_days(Days::new(self.garmin_config.data.num_days_from_start_date))
+                .unwrap();
+            date_range = Some((start, end));
+        }
+        date_range
+    }
 
-            if self.garmin_config.data.download_today_data {
-                // check if the activity started today
-                start_string = Some(format!("{}", Local::now().format("%Y-%m-%d 00:00:00")));
-            } else if !self.garmin_config.activities.save_regardless_of_date {
-                // check if activity started on the date specified
-                start_string = Some(
-                    format!("{} 00:00:00", self.garmin_config.data.activity_start_date)
-                        .replace('"', ""),
-                );
-            }
+    // 'activities_since' as a date, checked in new()
+    fn activities_since(&self) -> Option<NaiveDate> {
+        NaiveDate::parse_from_str(&self.garmin_config.activities.activities_since, "%Y-%m-%d").ok()
+    }
 
-            if let Some(start_string) = start_string {
-                let start =
-                    NaiveDateTime::parse_from_str(&start_string, "%Y-%m-%d %H:%M:%S").unwrap();
-                let end = start
-                    .clone()
-                    .checked_add_days(Days::new(self.garmin_config.data.num_days_from_start_date))
-                    .unwrap();
+    // whether activity summaries are paged through back to the start of the date range,
+    // rather than stopping after the first 'num_activities_to_download'
+    fn pages_all_activities(&self) -> bool {
+        self.backfill || self.activities_since().is_some()
+    }
 
-                if (activity_date.timestamp_nanos_opt() < start.timestamp_nanos_opt())
-                    || (activity_date.timestamp_nanos_opt() >= end.timestamp_nanos_opt())
-                {
-                    info!("Ignoring activity '{}' from: {}", &name, activity_string);
-                    continue;
+    async fn get_activity_summaries_in_range(
+        &mut self,
+        activity_count: u32,
+        date_range: Option<(NaiveDateTime, NaiveDateTime)>,
+    ) -> Vec<PathBuf> {
+        let mut saved: Vec<PathBuf> = Vec::new();
+        // get high level activity summary, each entry contains activity ID that
+        // can be used to get more specific info
+        if activity_count == 0 {
+            warn!("User requested 0 activities, check config");
+            return saved;
+        }
+        if let Some((start, end)) = date_range {
+            // the range ends at midnight after its last day
+            let last = end
+                .date()
+                .pred_opt()
+                .unwrap_or(start.date())
+                .max(start.date());
+            self.download_summary
+                .record_range(Metric::Activities, start.date(), last);
+        }
+        let format = self.get_activity_format();
+        let mut page_start: u32 = 0;
+        let mut completed: usize = 0;
+        let mut total: usize = 0;
+
+        loop {
+            let Some(lookup) = self.search_activities(page_start, activity_count).await else {
+                return saved;
+            };
+            let page_len = lookup.len() as u32;
+            let (activity_ids, reached_start) = activity_ids_in_range(&lookup, date_range);
+
+            total += activity_ids.len();
+            for id in activity_ids {
+                saved.extend(self.get_activity_info(id).await);
+                saved.extend(self.get_activity_details(id, format).await);
+                if self.garmin_config.activities.download_gear {
+                    saved.extend(self.get_activity_gear(id).await);
                 }
+                if self.garmin_config.activities.download_splits {
+                    saved.extend(self.get_activity_splits(id).await);
+                }
+                if self.garmin_config.activities.download_weather {
+                    saved.extend(self.get_activity_weather(id).await);
+                }
+                completed += 1;
+                self.progress.report(Metric::Activities, completed, total);
             }
 
-            self.get_activity_info(id.to_string().parse::<u64>().unwrap())
-                .await;
-            self.get_activity_details(id.to_string().parse::<u64>().unwrap())
-                .await;
+            if !self.pages_all_activities() || reached_start || page_len < activity_count {
+                return saved;
+            }
+            page_start += activity_count;
+            info!("Getting next {} activities", activity_count);
         }
     }
 
+    // one page of activity summaries, newest first
+    async fn search_activities(
+        &mut self,
+        start: u32,
+        limit: u32,
+    ) -> Option<Vec<serde_json::Value>> {
+        let endpoint: String = String::from(&self.garmin_connect_activity_search_url);
+        search_activities_from(self, &endpoint, start, limit).await
+    }
+
     /// Downloads JSON info for a particular activity ID, as JSON.
     ///
     /// While this DownloadManager provides a progammatic way of doing
     /// this, you can go to your activity on the garmin connect website,
     /// get the id via the url, and provide that ID to this function.
-    pub async fn get_activity_info(&mut self, activity_id: u64) {
+    pub async fn get_activity_info(&mut self, activity_id: u64) -> Vec<PathBuf> {
         // Given specific activity ID, retrieves all basic info as json response body
         let mut endpoint: String = String::from(&self.garmin_connect_activity_service_url);
         endpoint.push_str(&format!("/{}", activity_id));
@@ -432,214 +1028,941 @@ impl DownloadManager {
             Some(vec![activity_id.to_string()]),
             ".json",
         );
-        self.garmin_client
+        let skipped = filename.is_none();
+        let success = self
+            .garmin_client
             .api_request(&endpoint, None, true, filename)
             .await;
+        self.record_outcome(Metric::Activities, success, skipped);
+        self.garmin_client.get_last_saved_files().to_vec()
     }
 
-    /// Downloads FIT file for a particular activity ID.
+    /// Downloads activity data for a particular activity ID, in the given format.
     ///
     /// While this DownloadManager provides a progammatic way of doing
     /// this, you can go to your activity on the garmin connect website,
     /// get the id via the url, and provide that ID to this function.
-    pub async fn get_activity_details(&mut self, activity_id: u64) {
-        // activity data downloaded as a zip file containing the fit file.
-        let mut endpoint: String = String::from(&self.garmin_connect_download_service_url);
+    pub async fn get_activity_details(
+        &mut self,
+        activity_id: u64,
+        format: ActivityFormat,
+    ) -> Vec<PathBuf> {
+        let (mut endpoint, extension) = match format {
+            // FIT/original data is downloaded as a zip file containing the fit file.
+            ActivityFormat::Fit | ActivityFormat::Original => (
+                String::from(&self.garmin_connect_download_service_url),
+                ".zip",
+            ),
+            ActivityFormat::Tcx => (
+                format!("{}/tcx", self.garmin_connect_export_service_url),
+                ".tcx",
+            ),
+            ActivityFormat::Gpx => (
+                format!("{}/gpx", self.garmin_connect_export_service_url),
+                ".gpx",
+            ),
+        };
         endpoint.push_str(&format!("/activity/{}", activity_id));
 
         info!("====================================================");
-        info!("Getting details for activity {:}", &activity_id);
-
-        let filename = self.build_file_name(
-            "activities",
-            None,
-            Some(vec![activity_id.to_string()]),
-            ".zip",
+        info!(
+            "Getting details for activity {:} as {:?}",
+            &activity_id, format
         );
+
+        let filename = self.activity_details_file_name(activity_id, format, extension);
+        let skipped = filename.is_none();
         self.garmin_client
+            .set_extract_archives(format != ActivityFormat::Original);
+        let success = self
+            .garmin_client
             .api_request(&endpoint, None, false, filename)
             .await;
+        self.garmin_client.set_extract_archives(true);
+        self.record_outcome(Metric::Activities, success, skipped);
+        self.garmin_client.get_last_saved_files().to_vec()
     }
 
-    /// Downloads FIT file info for the configured monitoring date.
-    pub async fn monitoring(&mut self) {
-        // monitoring data downloaded as a zip file containing the fit file.
-        for i in 0..self.garmin_config.data.num_days_from_start_date {
-            let date = self.get_download_date(&self.garmin_config.data.monitoring_start_date, i);
-            let mut endpoint: String = String::from(&self.garmin_connect_download_service_url);
-            endpoint.push_str("/wellness/");
-            endpoint.push_str(&format!("{}", date.format("%Y-%m-%d")).replace('"', ""));
-
-            let filename = self.build_file_name("monitoring", Some(date), None, ".zip");
-            self.garmin_client
-                .api_request(&endpoint, None, false, filename)
-                .await;
+    // activity files are named by the download date, so for FIT downloads also look for
+    // the file extracted from an earlier day's zip, which is always '<id>_ACTIVITY.fit'
+    fn activity_details_file_name(
+        &self,
+        activity_id: u64,
+        format: ActivityFormat,
+        extension: &str,
+    ) -> Option<String> {
+        let addons = vec![activity_id.to_string()];
+        if format == ActivityFormat::Fit && !self.garmin_config.file.overwrite {
+            let fit_file = self
+                .expected_file_path("activities", None, &addons, extension)
+                .with_file_name(format!("{}_ACTIVITY.fit", activity_id));
+            if fit_file.exists() {
+                info!(
+                    "File: {} exists, but overwrite is disabled, ignoring",
+                    fit_file.display()
+                );
+                return None;
+            }
         }
+        self.build_file_name("activities", None, Some(addons), extension)
     }
 
-    /// Downloads sleep info as JSON file, for the configured sleep date.
-    pub async fn get_sleep(&mut self) {
-        for i in 0..self.garmin_config.data.num_days_from_start_date {
-            let date = self.get_download_date(&self.garmin_config.data.sleep_start_date, i);
-            let date_str = String::from(format!("{}", date.format("%Y-%m-%d"))).replace('"', "");
-            let mut endpoint: String = String::from(&self.garmin_connect_sleep_daily_url);
-            endpoint.push_str(&format!("/{}", &self.get_display_name().await));
-
-            let params =
-                HashMap::from([("date", date_str.as_str()), ("nonSleepBufferMinutes", "60")]);
-
-            let filename = self.build_file_name("sleep", Some(date), None, ".json");
-            self.garmin_client
-                .api_request(&endpoint, Some(params), true, filename)
-                .await;
-        }
-    }
+    /// Downloads the gear (e.g. shoes, bike) used for a particular activity ID, as JSON.
+    ///
+    /// Garmin responds with an empty list for activities without any gear.
+    pub async fn get_activity_gear(&mut self, activity_id: u64) -> Vec<PathBuf> {
+        let endpoint: String = String::from(&self.garmin_connect_gear_url);
+        let id = activity_id.to_string();
+        let params = HashMap::from([("activityId", id.as_str())]);
 
-    /// Downloads resting heart rate info as JSON file, for the configured date.
-    pub async fn get_resting_heart_rate(&mut self) {
-        for i in 0..self.garmin_config.data.num_days_from_start_date {
-            let date = self.get_download_date(&self.garmin_config.data.rhr_start_date, i);
-            let date_str = String::from(format!("{}", date.format("%Y-%m-%d"))).replace('"', "");
-            let mut endpoint = String::from(&self.garmin_connect_rhr);
-            endpoint.push_str(&format!("/{}", &self.get_display_name().await));
-
-            let params = HashMap::from([
-                ("fromDate", date_str.as_str()),
-                ("untilDate", date_str.as_str()),
-                ("metricId", "60"),
-            ]);
-            let filename = self.build_file_name("heartrate", Some(date), None, ".json");
-            self.garmin_client
-                .api_request(&endpoint, Some(params), true, filename)
-                .await;
-        }
-    }
+        info!("Getting gear for activity {:}", &activity_id);
 
-    /// Downloads weight info as JSON file, for the configured date.
-    pub async fn get_weight(&mut self) {
-        for i in 0..self.garmin_config.data.num_days_from_start_date {
-            let date = self.get_download_date(&self.garmin_config.data.weight_start_date, i);
-            let date_str = String::from(format!("{}", date.format("%Y-%m-%d")).replace('"', ""));
-            match self.get_date_in_epoch_ms(&date_str) {
-                Ok(epoch_millis) => {
-                    let endpoint = String::from(&self.garmin_connect_weight_url);
-                    let params = HashMap::from([
-                        ("startDate", date_str.as_str()),
-                        ("endDate", date_str.as_str()),
-                        ("_", &epoch_millis.as_str()),
-                    ]);
-                    let filename = self.build_file_name("weight", Some(date), None, ".json");
-                    self.garmin_client
-                        .api_request(&endpoint, Some(params), true, filename)
-                        .await;
-                }
-                Err(_) => {}
-            }
-        }
-    }
+        let filename = self.build_file_name("gear", None, Some(vec![id.clone()]), ".json");
+        self.garmin_client
+            .api_request(&endpoint, Some(params), true, filename)
+            .await;
 
-    /// Downloads summary info as JSON file, for the configured date.
-    pub async fn get_summary_day(&mut self) {
-        for i in 0..self.garmin_config.data.num_days_from_start_date {
-            let date = self.get_download_date(&self.garmin_config.data.summary_date, i);
-            let date_str = String::from(format!("{}", date.format("%Y-%m-%d")).replace('"', ""));
-            match self.get_date_in_epoch_ms(&date_str) {
-                Ok(epoch_millis) => {
-                    let mut endpoint = String::from(&self.garmin_connect_daily_summary_url);
-                    endpoint.push_str(&format!("/{}", &self.get_display_name().await));
-
-                    let params = HashMap::from([
-                        ("calendarDate", date_str.as_str()),
-                        ("_", epoch_millis.as_str()),
-                    ]);
-                    let filename = self.build_file_name("day_summary", Some(date), None, ".json");
-                    self.garmin_client
-                        .api_request(&endpoint, Some(params), true, filename)
-                        .await;
-                }
-                Err(e) => {
-                    warn!("Unable to properly parse date: {}. Error: {}", &date_str, e);
-                }
+        match serde_json::from_str::<serde_json::Value>(self.get_last_resp_text()) {
+            Ok(serde_json::Value::Array(gear)) if gear.is_empty() => {
+                info!("No gear associated with activity {}", activity_id);
+            }
+            Ok(_) => {}
+            Err(e) => {
+                warn!(
+                    "Unable to parse gear response for activity {}, error: {}",
+                    activity_id, e
+                );
+                self.log_last_response();
             }
         }
+        self.garmin_client.get_last_saved_files().to_vec()
     }
 
-    /// Downloads hydration info as JSON file, for the configured date.
-    pub async fn get_hydration(&mut self) {
-        for i in 0..self.garmin_config.data.num_days_from_start_date {
-            let date = self.get_download_date(&self.garmin_config.data.hydration_start_date, i);
-            let date_str = String::from(format!("{}", date.format("%Y-%m-%d")).replace('"', ""));
+    /// Downloads the splits (laps) of a particular activity ID, as JSON.
+    ///
+    /// Activities without any laps, e.g. manually entered ones, come back with an
+    /// empty "lapDTOs" list.
+    pub async fn get_activity_splits(&mut self, activity_id: u64) -> Vec<PathBuf> {
+        let endpoint = format!(
+            "{}/{}/splits",
+            self.garmin_connect_activity_service_url, activity_id
+        );
 
-            let mut endpoint = String::from(&self.garmin_connect_daily_hydration_url);
-            endpoint.push_str(&format!("/hydration_{}", &date_str));
+        info!("Getting splits for activity {:}", &activity_id);
 
-            let filename = self.build_file_name("hydration", Some(date), None, ".json");
-            self.garmin_client
-                .api_request(&endpoint, None, true, filename)
-                .await;
-        }
-    }
+        let filename =
+            self.build_file_name("splits", None, Some(vec![activity_id.to_string()]), ".json");
+        self.garmin_client
+            .api_request(&endpoint, None, true, filename)
+            .await;
 
-    fn get_date_in_epoch_ms(&self, date_str: &str) -> Result<String, ParseError> {
-        let mut qualified_date = String::from(date_str);
-        qualified_date.push_str(" 00:00:00");
-        let datetime_result = NaiveDateTime::parse_from_str(&qualified_date, "%Y-%m-%d %H:%M:%S");
-        match datetime_result {
-            Ok(datetime) => {
-                let epoch_millis = format!("{}", datetime.timestamp_millis());
-                return Ok(epoch_millis);
+        match serde_json::from_str::<serde_json::Value>(self.get_last_resp_text()) {
+            Ok(splits) => {
+                if splits["lapDTOs"].as_array().map_or(true, Vec::is_empty) {
+                    info!("No splits for activity {}", activity_id);
+                }
             }
             Err(e) => {
-                error!(
-                    "Unable to parse config datetime into '%Y-%m-%d': {}",
-                    date_str
+                warn!(
+                    "Unable to parse splits response for activity {}, error: {}",
+                    activity_id, e
                 );
-                Err(e)
+                self.log_last_response();
             }
         }
+        self.garmin_client.get_last_saved_files().to_vec()
     }
 
-    fn build_file_name(
-        &self,
-        sub_folder: &str,
-        activity_date: Option<NaiveDateTime>,
-        filename_addons: Option<Vec<String>>,
-        extension: &str,
-    ) -> Option<String> {
-        if !self.garmin_config.file.save_to_file {
-            info!("Save file config is disabled, ignoring");
-            return None;
-        }
+    /// Downloads the weather during a particular activity ID, as JSON.
+    ///
+    /// Indoor activities (and ones without a location) have no weather, which Garmin
+    /// answers with an empty response, so nothing is saved for them.
+    pub async fn get_activity_weather(&mut self, activity_id: u64) -> Vec<PathBuf> {
+        let endpoint = format!(
+            "{}/{}/weather",
+            self.garmin_connect_activity_service_url, activity_id
+        );
 
-        let base_path = String::from(&self.garmin_config.file.file_base_path);
+        info!("Getting weather for activity {:}", &activity_id);
 
-        let mut filename: String;
-        match activity_date {
-            Some(d) => {
-                filename = format!("{}", d.format(&self.garmin_config.file.file_date_format))
-                    .replace('"', "");
-            }
-            None => {
-                filename = format!(
-                    "{}",
-                    Local::now().format(&self.garmin_config.file.file_date_format)
-                )
-                .replace('"', "");
-            }
-        }
+        let filename = self.build_file_name(
+            "weather",
+            None,
+            Some(vec![activity_id.to_string()]),
+            ".json",
+        );
+        self.garmin_client
+            .api_request(&endpoint, None, true, filename)
+            .await;
 
-        if let Some(s) = filename_addons {
-            for addon in s {
-                filename.push_str("-");
-                filename.push_str(&addon);
-            }
+        let text = self.get_last_resp_text().trim();
+        if text.is_empty() || text == "null" {
+            info!("No weather for activity {}", activity_id);
+        } else if let Err(e) = serde_json::from_str::<serde_json::Value>(text) {
+            warn!(
+                "Unable to parse weather response for activity {}, error: {}",
+                activity_id, e
+            );
+            self.log_last_response();
         }
+        self.garmin_client.get_last_saved_files().to_vec()
+    }
 
-        filename.push_str(extension);
-
-        let path = Path::new(&base_path).join(&sub_folder).join(&filename);
-        if path.exists() {
-            if !self.garmin_config.file.overwrite {
+    /// Downloads FIT file info for the configured monitoring date.
+    pub async fn monitoring(&mut self) -> Vec<PathBuf> {
+        let start_date = String::from(&self.garmin_config.data.monitoring_start_date);
+        self.download_days(
+            Metric::Monitoring,
+            &start_date,
+            DownloadManager::monitoring_request,
+        )
+        .await
+    }
+
+    fn monitoring_request(&self, date: NaiveDateTime) -> Option<ApiRequest> {
+        // monitoring data downloaded as a zip file containing the fit file.
+        let mut endpoint: String = String::from(&self.garmin_connect_download_service_url);
+        endpoint.push_str("/wellness/");
+        endpoint.push_str(&format!("{}", date.format("%Y-%m-%d")).replace('"', ""));
+
+        let filename = self.build_file_name(Metric::Monitoring.folder(), Some(date), None, ".zip");
+        Some(ApiRequest::new(endpoint, vec![], false, filename))
+    }
+
+    /// Downloads sleep info as JSON file, for the configured sleep date.
+    pub async fn get_sleep(&mut self) -> Vec<PathBuf> {
+        self.get_display_name().await;
+        let start_date = String::from(&self.garmin_config.data.sleep_start_date);
+        self.download_days(Metric::Sleep, &start_date, DownloadManager::sleep_request)
+            .await
+    }
+
+    fn sleep_request(&self, date: NaiveDateTime) -> Option<ApiRequest> {
+        let date_str = format!("{}", date.format("%Y-%m-%d")).replace('"', "");
+        let mut endpoint: String = String::from(&self.garmin_connect_sleep_daily_url);
+        endpoint.push_str(&format!("/{}", &self.display_name));
+
+        let buffer = format!("{}", self.garmin_config.data.sleep_buffer_minutes);
+        let params = vec![
+            ("date", date_str.as_str()),
+            ("nonSleepBufferMinutes", buffer.as_str()),
+        ];
+
+        let filename = self.build_file_name(Metric::Sleep.folder(), Some(date), None, ".json");
+        Some(ApiRequest::new(endpoint, params, true, filename))
+    }
+
+    /// Downloads resting heart rate info as JSON file, for the configured date.
+    pub async fn get_resting_heart_rate(&mut self) -> Vec<PathBuf> {
+        self.get_display_name().await;
+        let start_date = String::from(&self.garmin_config.data.rhr_start_date);
+        self.download_days(
+            Metric::Rhr,
+            &start_date,
+            DownloadManager::resting_heart_rate_request,
+        )
+        .await
+    }
+
+    fn resting_heart_rate_request(&self, date: NaiveDateTime) -> Option<ApiRequest> {
+        let filename = self.build_file_name(Metric::Rhr.folder(), Some(date), None, ".json");
+        Some(self.resting_heart_rate_range_request(date.date(), date.date(), filename))
+    }
+
+    fn resting_heart_rate_range_request(
+        &self,
+        start: NaiveDate,
+        end: NaiveDate,
+        filename: Option<String>,
+    ) -> ApiRequest {
+        let start_str = format!("{}", start.format("%Y-%m-%d"));
+        let end_str = format!("{}", end.format("%Y-%m-%d"));
+        let metric_id = format!("{}", self.garmin_config.data.rhr_metric_id);
+        let mut endpoint = String::from(&self.garmin_connect_rhr);
+        endpoint.push_str(&format!("/{}", &self.display_name));
+
+        let params = vec![
+            ("fromDate", start_str.as_str()),
+            ("untilDate", end_str.as_str()),
+            ("metricId", metric_id.as_str()),
+        ];
+        ApiRequest::new(endpoint, params, true, filename)
+    }
+
+    /// Downloads weight info as JSON file, for the configured date.
+    pub async fn get_weight(&mut self) -> Vec<PathBuf> {
+        let start_date = String::from(&self.garmin_config.data.weight_start_date);
+        self.download_days(Metric::Weight, &start_date, DownloadManager::weight_request)
+            .await
+    }
+
+    fn weight_request(&self, date: NaiveDateTime) -> Option<ApiRequest> {
+        let filename = self.build_file_name(Metric::Weight.folder(), Some(date), None, ".json");
+        Some(self.weight_range_request(date.date(), date.date(), filename))
+    }
+
+    fn weight_range_request(
+        &self,
+        start: NaiveDate,
+        end: NaiveDate,
+        filename: Option<String>,
+    ) -> ApiRequest {
+        let start_str = format!("{}", start.format("%Y-%m-%d"));
+        let end_str = format!("{}", end.format("%Y-%m-%d"));
+        let cache_buster = cache_buster();
+        let endpoint = String::from(&self.garmin_connect_weight_url);
+        let params = vec![
+            ("startDate", start_str.as_str()),
+            ("endDate", end_str.as_str()),
+            ("_", cache_buster.as_str()),
+        ];
+        ApiRequest::new(endpoint, params, true, filename)
+    }
+
+    /// Downloads summary info as JSON file, for the configured date.
+    pub async fn get_summary_day(&mut self) -> Vec<PathBuf> {
+        self.get_display_name().await;
+        let start_date = String::from(&self.garmin_config.data.summary_date);
+        self.download_days(
+            Metric::Summary,
+            &start_date,
+            DownloadManager::summary_request,
+        )
+        .await
+    }
+
+    fn summary_request(&self, date: NaiveDateTime) -> Option<ApiRequest> {
+        let date_str = format!("{}", date.format("%Y-%m-%d")).replace('"', "");
+        let cache_buster = cache_buster();
+        let mut endpoint = String::from(&self.garmin_connect_daily_summary_url);
+        endpoint.push_str(&format!("/{}", &self.display_name));
+
+        let params = vec![
+            ("calendarDate", date_str.as_str()),
+            ("_", cache_buster.as_str()),
+        ];
+        let filename = self.build_file_name(Metric::Summary.folder(), Some(date), None, ".json");
+        Some(ApiRequest::new(endpoint, params, true, filename))
+    }
+
+    /// Downloads hydration info as JSON file, for the configured date.
+    pub async fn get_hydration(&mut self) -> Vec<PathBuf> {
+        let start_date = String::from(&self.garmin_config.data.hydration_start_date);
+        self.download_days(
+            Metric::Hydration,
+            &start_date,
+            DownloadManager::hydration_request,
+        )
+        .await
+    }
+
+    fn hydration_request(&self, date: NaiveDateTime) -> Option<ApiRequest> {
+        let date_str = format!("{}", date.format("%Y-%m-%d")).replace('"', "");
+
+        let mut endpoint = String::from(&self.garmin_connect_daily_hydration_url);
+        endpoint.push_str(&format!("/hydration_{}", &date_str));
+
+        let filename = self.build_file_name(Metric::Hydration.folder(), Some(date), None, ".json");
+        Some(ApiRequest::new(endpoint, vec![], true, filename))
+    }
+
+    /// Downloads all-day stress info as JSON file, for the configured date.
+    pub async fn get_stress(&mut self) -> Vec<PathBuf> {
+        let start_date = String::from(&self.garmin_config.data.stress_start_date);
+        self.download_days(Metric::Stress, &start_date, DownloadManager::stress_request)
+            .await
+    }
+
+    fn stress_request(&self, date: NaiveDateTime) -> Option<ApiRequest> {
+        let date_str = format!("{}", date.format("%Y-%m-%d")).replace('"', "");
+
+        let mut endpoint = String::from(&self.garmin_connect_daily_stress_url);
+        endpoint.push_str(&format!("/{}", &date_str));
+
+        let params = vec![("calendarDate", date_str.as_str())];
+        let filename = self.build_file_name(Metric::Stress.folder(), Some(date), None, ".json");
+        Some(ApiRequest::new(endpoint, params, true, filename))
+    }
+
+    /// Downloads body battery info as JSON file, for the configured date.
+    pub async fn get_body_battery(&mut self) -> Vec<PathBuf> {
+        let start_date = String::from(&self.garmin_config.data.body_battery_start_date);
+        self.download_days(
+            Metric::BodyBattery,
+            &start_date,
+            DownloadManager::body_battery_request,
+        )
+        .await
+    }
+
+    fn body_battery_request(&self, date: NaiveDateTime) -> Option<ApiRequest> {
+        let date_str = format!("{}", date.format("%Y-%m-%d")).replace('"', "");
+
+        let endpoint = String::from(&self.garmin_connect_daily_body_battery_url);
+        let params = vec![
+            ("startDate", date_str.as_str()),
+            ("endDate", date_str.as_str()),
+        ];
+        let filename =
+            self.build_file_name(Metric::BodyBattery.folder(), Some(date), None, ".json");
+        Some(ApiRequest::new(endpoint, params, true, filename))
+    }
+
+    /// Downloads nightly HRV info as JSON file, for the configured date.
+    pub async fn get_hrv(&mut self) -> Vec<PathBuf> {
+        let start_date = String::from(&self.garmin_config.data.hrv_start_date);
+        self.download_days(Metric::Hrv, &start_date, DownloadManager::hrv_request)
+            .await
+    }
+
+    fn hrv_request(&self, date: NaiveDateTime) -> Option<ApiRequest> {
+        let date_str = format!("{}", date.format("%Y-%m-%d")).replace('"', "");
+
+        let mut endpoint = String::from(&self.garmin_connect_hrv_url);
+        endpoint.push_str(&format!("/{}", &date_str));
+
+        let filename = self.build_file_name(Metric::Hrv.folder(), Some(date), None, ".json");
+        Some(ApiRequest::new(endpoint, vec![], true, filename))
+    }
+
+    /// Downloads VO2max info as JSON file, for the configured date. Unlike the
+    /// biometric profile's 'vo2Max', this is the estimate as of that day.
+    pub async fn get_vo2max(&mut self) -> Vec<PathBuf> {
+        let start_date = String::from(&self.garmin_config.data.vo2max_start_date);
+        self.download_days(Metric::Vo2Max, &start_date, DownloadManager::vo2max_request)
+            .await
+    }
+
+    fn vo2max_request(&self, date: NaiveDateTime) -> Option<ApiRequest> {
+        let date_str = format!("{}", date.format("%Y-%m-%d")).replace('"', "");
+
+        let mut endpoint = String::from(&self.garmin_connect_vo2max_url);
+        endpoint.push_str(&format!("/{}/{}", &date_str, &date_str));
+
+        let filename = self.build_file_name(Metric::Vo2Max.folder(), Some(date), None, ".json");
+        Some(ApiRequest::new(endpoint, vec![], true, filename))
+    }
+
+    /// Downloads training status (and training load) info as JSON file, for the configured date.
+    pub async fn get_training_status(&mut self) -> Vec<PathBuf> {
+        let start_date = String::from(&self.garmin_config.data.training_status_start_date);
+        self.download_days(
+            Metric::TrainingStatus,
+            &start_date,
+            DownloadManager::training_status_request,
+        )
+        .await
+    }
+
+    fn training_status_request(&self, date: NaiveDateTime) -> Option<ApiRequest> {
+        let date_str = format!("{}", date.format("%Y-%m-%d")).replace('"', "");
+
+        let mut endpoint = String::from(&self.garmin_connect_training_status_url);
+        endpoint.push_str(&format!("/{}", &date_str));
+
+        let filename =
+            self.build_file_name(Metric::TrainingStatus.folder(), Some(date), None, ".json");
+        Some(ApiRequest::new(endpoint, vec![], true, filename))
+    }
+
+    /// Downloads all-day pulse ox info as JSON file, for the configured date.
+    pub async fn get_spo2(&mut self) -> Vec<PathBuf> {
+        let start_date = String::from(&self.garmin_config.data.spo2_start_date);
+        self.download_days(Metric::Spo2, &start_date, DownloadManager::spo2_request)
+            .await
+    }
+
+    fn spo2_request(&self, date: NaiveDateTime) -> Option<ApiRequest> {
+        let date_str = format!("{}", date.format("%Y-%m-%d")).replace('"', "");
+
+        let mut endpoint = String::from(&self.garmin_connect_daily_spo2_url);
+        endpoint.push_str(&format!("/{}", &date_str));
+
+        let filename = self.build_file_name(Metric::Spo2.folder(), Some(date), None, ".json");
+        Some(ApiRequest::new(endpoint, vec![], true, filename))
+    }
+
+    /// Downloads the floors climbed and descended throughout the day as JSON file,
+    /// for the configured date.
+    pub async fn get_floors(&mut self) -> Vec<PathBuf> {
+        let start_date = String::from(&self.garmin_config.data.floors_start_date);
+        self.download_days(Metric::Floors, &start_date, DownloadManager::floors_request)
+            .await
+    }
+
+    fn floors_request(&self, date: NaiveDateTime) -> Option<ApiRequest> {
+        let date_str = format!("{}", date.format("%Y-%m-%d")).replace('"', "");
+
+        let mut endpoint = String::from(&self.garmin_connect_floors_url);
+        endpoint.push_str(&format!("/{}", &date_str));
+
+        let filename = self.build_file_name(Metric::Floors.folder(), Some(date), None, ".json");
+        Some(ApiRequest::new(endpoint, vec![], true, filename))
+    }
+
+    /// Downloads the intensity minutes earned throughout the day as JSON file, for
+    /// the configured date.
+    pub async fn get_intensity_minutes(&mut self) -> Vec<PathBuf> {
+        let start_date = String::from(&self.garmin_config.data.intensity_minutes_start_date);
+        self.download_days(
+            Metric::IntensityMinutes,
+            &start_date,
+            DownloadManager::intensity_minutes_request,
+        )
+        .await
+    }
+
+    fn intensity_minutes_request(&self, date: NaiveDateTime) -> Option<ApiRequest> {
+        let date_str = format!("{}", date.format("%Y-%m-%d")).replace('"', "");
+
+        let mut endpoint = String::from(&self.garmin_connect_intensity_minutes_url);
+        endpoint.push_str(&format!("/{}", &date_str));
+
+        let filename =
+            self.build_file_name(Metric::IntensityMinutes.folder(), Some(date), None, ".json");
+        Some(ApiRequest::new(endpoint, vec![], true, filename))
+    }
+
+    /// Downloads a single metric for a single day, regardless of which stats are
+    /// enabled in the config or which dates are configured for them.
+    ///
+    /// For Metric::Activities, any of the last 'num_activities_to_download' activities
+    /// that started on 'date' are downloaded.
+    pub async fn download_metric(&mut self, metric: Metric, date: NaiveDate) -> Vec<PathBuf> {
+        let Some(build_request) = request_builder(metric) else {
+            let num_activities = self.get_num_activities_to_download();
+            let start = date.and_hms_opt(0, 0, 0).unwrap();
+            let end = start.checked_add_days(Days::new(1)).unwrap();
+            return self
+                .get_activity_summaries_in_range(num_activities, Some((start, end)))
+                .await;
+        };
+        self.download_dates(metric, &[date], build_request).await
+    }
+
+    /// Downloads each day of 'metric' from 'start' up to and including 'end' that
+    /// isn't in 'existing', e.g. the dates UploadManager::get_existing_dates() found
+    /// already uploaded, so only the gaps are fetched again.
+    ///
+    /// Like download_metric(), this ignores the enabled stats and configured dates,
+    /// and doesn't move the --since_last download state.
+    pub async fn fill_gaps(
+        &mut self,
+        metric: Metric,
+        start: NaiveDate,
+        end: NaiveDate,
+        existing: &HashSet<NaiveDate>,
+    ) -> Vec<PathBuf> {
+        let missing = missing_dates(start, end, existing);
+        info!(
+            "Filling {} missing day(s) of {} between {} and {}",
+            missing.len(),
+            metric.name(),
+            start,
+            end
+        );
+        let Some(build_request) = request_builder(metric) else {
+            let mut saved: Vec<PathBuf> = Vec::new();
+            for date in missing {
+                saved.extend(self.download_metric(metric, date).await);
+            }
+            return saved;
+        };
+        self.download_dates(metric, &missing, build_request).await
+    }
+
+    /// Every metric enabled in the config, in the order download_all() fetches them.
+    pub fn enabled_metrics(&self) -> Vec<Metric> {
+        let stats = &self.garmin_config.enabled_stats;
+        [
+            (stats.activities, Metric::Activities),
+            (stats.sleep, Metric::Sleep),
+            (stats.rhr, Metric::Rhr),
+            (stats.weight, Metric::Weight),
+            (stats.daily_summary, Metric::Summary),
+            (stats.monitoring, Metric::Monitoring),
+            (stats.hydration, Metric::Hydration),
+            (stats.stress, Metric::Stress),
+            (stats.body_battery, Metric::BodyBattery),
+            (stats.hrv, Metric::Hrv),
+            (stats.vo2max, Metric::Vo2Max),
+            (stats.training_status, Metric::TrainingStatus),
+            (stats.spo2, Metric::Spo2),
+            (stats.floors, Metric::Floors),
+            (stats.intensity_minutes, Metric::IntensityMinutes),
+        ]
+        .into_iter()
+        .filter_map(|(enabled, metric)| enabled.then_some(metric))
+        .collect()
+    }
+
+    // downloads the given days of a metric concurrently, without touching the
+    // download state. Returns the files saved.
+    async fn download_dates(
+        &mut self,
+        metric: Metric,
+        dates: &[NaiveDate],
+        build_request: RequestBuilder,
+    ) -> Vec<PathBuf> {
+        if matches!(metric, Metric::Sleep | Metric::Rhr | Metric::Summary) {
+            self.get_display_name().await;
+        }
+        if let (Some(first), Some(last)) = (dates.iter().min(), dates.iter().max()) {
+            self.download_summary.record_range(metric, *first, *last);
+        }
+        let mut requests: Vec<ApiRequest> = Vec::new();
+        for date in dates {
+            match build_request(self, date.and_hms_opt(0, 0, 0).unwrap()) {
+                Some(request) => requests.push(request),
+                None => self.download_summary.record(metric, Outcome::Failed),
+            }
+        }
+        let skipped: Vec<bool> = requests.iter().map(|r| r.filename.is_none()).collect();
+        let mut saved: Vec<PathBuf> = Vec::new();
+        for (index, result) in self
+            .execute_requests(requests)
+            .await
+            .into_iter()
+            .enumerate()
+        {
+            self.record_outcome(metric, result.is_some(), skipped[index]);
+            saved.extend(result.unwrap_or_default());
+        }
+        saved
+    }
+
+    fn record_outcome(&mut self, metric: Metric, success: bool, skipped: bool) {
+        self.download_summary
+            .record(metric, Outcome::new(success, skipped));
+    }
+
+    // builds one request per day to download, then runs them all, recording
+    // each day in the download state as it completes. Returns the files saved.
+    async fn download_days(
+        &mut self,
+        metric: Metric,
+        start_date: &str,
+        build_request: RequestBuilder,
+    ) -> Vec<PathBuf> {
+        let days = self.get_download_dates(metric, start_date);
+        if let (Some(first), Some(last)) = (days.first(), days.last()) {
+            self.download_summary
+                .record_range(metric, first.date(), last.date());
+        }
+        let save_to_file = self.garmin_config.file.save_to_file;
+        if let (Some(layout), true) = (date_range::response_for(metric), save_to_file) {
+            if days.len() > 1 {
+                return self.download_range(metric, &days, layout).await;
+            }
+        }
+        let mut saved: Vec<PathBuf> = Vec::new();
+        let mut dates: Vec<NaiveDateTime> = Vec::new();
+        let mut requests: Vec<ApiRequest> = Vec::new();
+        let ndjson_file = self.ndjson_file(metric);
+        // days whose file is already downloaded aren't appended again
+        for date in days {
+            match build_request(self, date) {
+                Some(mut request) => {
+                    if request.json && (request.filename.is_some() || !save_to_file) {
+                        request.ndjson = ndjson_file.clone().map(|path| (date.date(), path));
+                    }
+                    dates.push(date);
+                    requests.push(request);
+                }
+                None => self.download_summary.record(metric, Outcome::Failed),
+            }
+        }
+        if requests.is_empty() {
+            return saved;
+        }
+        let skipped: Vec<bool> = requests.iter().map(|r| r.filename.is_none()).collect();
+        self.garmin_client.refresh_oauth2_token_if_expired().await;
+
+        let max_in_flight = self.garmin_config.data.max_concurrent_downloads;
+        let download_state = &mut self.download_state;
+        let download_summary = &mut self.download_summary;
+        let progress = &mut self.progress;
+        let total = dates.len();
+        let mut completed: usize = 0;
+        // stop advancing at the first failure so the next --since_last run retries from there
+        let mut all_succeeded = true;
+        let client = &self.garmin_client;
+        run_requests(
+            max_in_flight,
+            requests,
+            |request| {
+                // a day that's already saved (and isn't to be overwritten) needs no request
+                let already_saved = save_to_file && request.filename.is_none();
+                let client = client.clone();
+                async move {
+                    if already_saved {
+                        return Some(Vec::new());
+                    }
+                    send_request(client, request).await
+                }
+            },
+            |index, result| {
+                let success = result.is_some() || skipped[index];
+                saved.extend(result.unwrap_or_default());
+                download_summary.record(metric, Outcome::new(success, skipped[index]));
+                if !success {
+                    download_summary.record_failed_date(metric, dates[index].date());
+                }
+                completed += 1;
+                progress.report(metric, completed, total);
+                all_succeeded &= success;
+                if all_succeeded {
+                    download_state.record(metric, dates[index].date());
+                    if let Err(e) = download_state.save(STATE_FILE) {
+                        error!("Unable to save download state {}, error: {}", STATE_FILE, e);
+                    }
+                }
+            },
+        )
+        .await;
+        saved
+    }
+
+    // for endpoints that take a range of dates, downloads up to a month of days per
+    // request and saves each day to the same file its own request would have
+    async fn download_range(
+        &mut self,
+        metric: Metric,
+        days: &[NaiveDateTime],
+        layout: &date_range::RangeResponse,
+    ) -> Vec<PathBuf> {
+        let mut saved: Vec<PathBuf> = Vec::new();
+        // days whose file already exists (and isn't to be overwritten) are skipped
+        let mut wanted: Vec<(NaiveDate, String)> = Vec::new();
+        for date in days {
+            match self.build_file_name(layout.folder, Some(*date), None, ".json") {
+                Some(filename) => wanted.push((date.date(), filename)),
+                None => self.download_summary.record(metric, Outcome::Skipped),
+            }
+        }
+        let dates: Vec<NaiveDate> = wanted.iter().map(|(date, _)| *date).collect();
+        let ranges = date_range::chunk(&dates);
+        info!(
+            "Downloading {} day(s) of {} in {} request(s)",
+            wanted.len(),
+            metric.name(),
+            ranges.len()
+        );
+        let ndjson_file = self.ndjson_file(metric);
+        let total = wanted.len();
+        let mut completed: usize = 0;
+        // stop advancing at the first failure so the next --since_last run retries from there
+        let mut all_succeeded = true;
+        for (start, end) in ranges {
+            let request = match metric {
+                Metric::Weight => self.weight_range_request(start, end, None),
+                _ => self.resting_heart_rate_range_request(start, end, None),
+            };
+            let params: HashMap<&str, &str> = request
+                .params
+                .iter()
+                .map(|(key, value)| (key.as_str(), value.as_str()))
+                .collect();
+            let response = self
+                .request_json(&request.endpoint, Some(params))
+                .await
+                .ok();
+            for (date, filename) in wanted
+                .iter()
+                .filter(|(date, _)| (start..=end).contains(date))
+            {
+                let success = match &response {
+                    Some(response) => {
+                        let day = date_range::split_day(response, layout, *date);
+                        let day = day.to_string();
+                        let saved_day = self.garmin_client.save_as_json(&day, filename);
+                        if saved_day {
+                            saved.push(PathBuf::from(filename));
+                            if let Some(path) = &ndjson_file {
+                                if let Err(e) = ndjson::append(path, *date, &day) {
+                                    warn!("Unable to append response to {}: {}", path.display(), e);
+                                }
+                            }
+                        }
+                        saved_day
+                    }
+                    None => false,
+                };
+                self.download_summary
+                    .record(metric, Outcome::new(success, false));
+                if !success {
+                    self.download_summary.record_failed_date(metric, *date);
+                }
+                completed += 1;
+                self.progress.report(metric, completed, total);
+                all_succeeded &= success;
+                if all_succeeded {
+                    self.download_state.record(metric, *date);
+                    if let Err(e) = self.download_state.save(STATE_FILE) {
+                        error!("Unable to save download state {}, error: {}", STATE_FILE, e);
+                    }
+                }
+            }
+        }
+        saved
+    }
+
+    // either the configured range, or with --since_last every day after the
+    // last successful download (falling back to the configured start date)
+    fn get_download_dates(&self, metric: Metric, start_date: &str) -> Vec<NaiveDateTime> {
+        if !self.since_last || self.garmin_config.data.download_today_data {
+            return (0..self.garmin_config.data.num_days_from_start_date)
+                .map(|i| self.get_download_date(start_date, i))
+                .collect();
+        }
+        let mut date = match self.download_state.last_downloaded(metric) {
+            Some(last) => last.succ_opt().unwrap().and_hms_opt(0, 0, 0).unwrap(),
+            None => self.get_download_date(start_date, 0),
+        };
+        let today = Local::now().date_naive();
+        let mut dates: Vec<NaiveDateTime> = Vec::new();
+        while date.date() <= today {
+            dates.push(date);
+            date = date.checked_add_days(Days::new(1)).unwrap();
+        }
+        info!(
+            "Downloading {} day(s) of {} since last run",
+            dates.len(),
+            metric.name()
+        );
+        dates
+    }
+
+    /// Runs requests concurrently on clones of the client, with at most
+    /// 'max_concurrent_downloads' in flight at once. Returns the files each request
+    /// saved, or None if it failed.
+    async fn execute_requests(&mut self, requests: Vec<ApiRequest>) -> Vec<Option<Vec<PathBuf>>> {
+        let mut results = vec![None; requests.len()];
+        if requests.is_empty() {
+            return results;
+        }
+        // refresh once up front rather than separately in every clone
+        self.garmin_client.refresh_oauth2_token_if_expired().await;
+
+        let max_in_flight = self.garmin_config.data.max_concurrent_downloads;
+        let client = &self.garmin_client;
+        run_requests(
+            max_in_flight,
+            requests,
+            |request| send_request(client.clone(), request),
+            |index, result| results[index] = result,
+        )
+        .await;
+        results
+    }
+
+    // with 'secure_password' set, the password lives in the OS keyring under
+    // KEYRING_SERVICE/<user> rather than in the config file. A password of
+    // "${NAME}" is read from the environment variable NAME instead.
+    fn get_password(&self) -> Result<String, LoginError> {
+        let credentials = &self.garmin_config.credentials;
+        if !credentials.secure_password {
+            return resolve_password(&credentials.password);
+        }
+        match keyring::Entry::new(KEYRING_SERVICE, &credentials.user)
+            .and_then(|entry| entry.get_password())
+        {
+            Ok(password) => Ok(password),
+            Err(e) => panic!(
+                "Unable to read password for '{}' from the '{}' keyring entry: {}",
+                credentials.user, KEYRING_SERVICE, e
+            ),
+        }
+    }
+
+    fn get_activity_format(&self) -> ActivityFormat {
+        // validated in new()
+        ActivityFormat::from_str(&self.garmin_config.activities.activity_format).unwrap()
+    }
+
+    fn get_num_activities_to_download(&self) -> u32 {
+        self.garmin_config
+            .activities
+            .num_activities_to_download
+            .parse::<u32>()
+            .unwrap()
+    }
+
+    // where JSON responses for 'metric' are appended with 'ndjson_output' enabled,
+    // e.g. <ndjson_path>/sleep.ndjson
+    fn ndjson_file(&self, metric: Metric) -> Option<PathBuf> {
+        let file = &self.garmin_config.file;
+        if !file.ndjson_output {
+            return None;
+        }
+        let folder = if file.ndjson_path.is_empty() {
+            &file.file_base_path
+        } else {
+            &file.ndjson_path
+        };
+        Some(Path::new(folder).join(format!("{}.ndjson", metric.name())))
+    }
+
+    // whether 'date' is within 'overwrite_within_days' of today. Files without a
+    // data date (e.g. activities) don't get revised, so they never count as recent.
+    fn is_recent(&self, date: Option<NaiveDateTime>) -> bool {
+        let within_days = self.garmin_config.file.overwrite_within_days;
+        let Some(date) = date else {
+            return false;
+        };
+        within_days > 0
+            && Local::now()
+                .date_naive()
+                .checked_sub_days(Days::new(within_days))
+                .is_some_and(|cutoff| date.date() >= cutoff)
+    }
+
+    /// Where a file is saved, going by 'file_template', so other tools can find downloads
+    /// without repeating the template logic. Nothing is checked or created. 'folder' is
+    /// the subfolder, see Metric::folder(), and 'date' the day the data is for; files
+    /// without one (e.g. activities) are named by the download date, i.e. now.
+    /// 'addons' are appended to the name (e.g. an activity ID), and 'extension' may
+    /// start with a dot or not.
+    pub fn expected_file_path(
+        &self,
+        folder: &str,
+        date: Option<NaiveDateTime>,
+        addons: &[String],
+        extension: &str,
+    ) -> PathBuf {
+        PathBuf::from(file_template::render(
+            &self.garmin_config.file.file_template,
+            &file_template::TemplateValues {
+                base: &self.garmin_config.file.file_base_path,
+                metric: folder,
+                date: date.unwrap_or_else(|| Local::now().naive_local()),
+                date_format: &self.garmin_config.file.file_date_format,
+                addons,
+                ext: extension,
+            },
+        ))
+    }
+
+    fn build_file_name(
+        &self,
+        sub_folder: &str,
+        activity_date: Option<NaiveDateTime>,
+        filename_addons: Option<Vec<String>>,
+        extension: &str,
+    ) -> Option<String> {
+        if !self.garmin_config.file.save_to_file {
+            info!("Save file config is disabled, ignoring");
+            return None;
+        }
+
+        let addons = filename_addons.unwrap_or_default();
+        let path = self.expected_file_path(sub_folder, activity_date, &addons, extension);
+        let rendered = path.to_string_lossy().to_string();
+        // zips are removed once extracted, see GarminClient::already_extracted()
+        let is_archive = path.extension().is_some_and(|ext| ext == "zip");
+
+        if path.exists() || (is_archive && self.garmin_client.already_extracted(&rendered)) {
+            if !self.garmin_config.file.overwrite && !self.is_recent(activity_date) {
                 info!(
                     "File: {} exists, but overwrite is disabled, ignoring",
                     path.display()
@@ -654,6 +1977,740 @@ impl DownloadManager {
         } else {
             info!("Saving any received data to file: {}", path.display())
         }
-        Some(path.to_str().unwrap().to_string())
+
+        if let Some(parent) = path.parent() {
+            if let Err(e) = fs::create_dir_all(parent) {
+                error!("Unable to create folder {}, error: {}", parent.display(), e);
+                return None;
+            }
+        }
+        Some(rendered)
+    }
+}
+
+// What request_json() needs from a DownloadManager, split out so the
+// re-login handling can be exercised without talking to Garmin.
+trait JsonSource {
+    fn fetch_text<'a>(
+        &'a mut self,
+        endpoint: &'a str,
+        params: Option<HashMap<&'a str, &'a str>>,
+    ) -> BoxFuture<'a, Result<String, ApiError>>;
+    fn relogin(&mut self) -> BoxFuture<'_, bool>;
+    // logs whatever is known about the response that couldn't be used
+    fn log_last_response(&self) {}
+}
+
+impl JsonSource for DownloadManager {
+    fn fetch_text<'a>(
+        &'a mut self,
+        endpoint: &'a str,
+        params: Option<HashMap<&'a str, &'a str>>,
+    ) -> BoxFuture<'a, Result<String, ApiError>> {
+        Box::pin(async move {
+            if self
+                .garmin_client
+                .api_request(endpoint, params, true, None)
+                .await
+            {
+                return Ok(String::from(self.get_last_resp_text()));
+            }
+            // a stale session can get a 401 rather than the login page
+            match self.garmin_client.get_last_resp_status() {
+                Some(status) if status.as_u16() == 401 => Err(ApiError::NotAuthenticated),
+                _ => Err(ApiError::RequestFailed),
+            }
+        })
+    }
+
+    fn log_last_response(&self) {
+        DownloadManager::log_last_response(self);
+    }
+
+    fn relogin(&mut self) -> BoxFuture<'_, bool> {
+        Box::pin(async move {
+            // make sure login() doesn't just pick the rejected token back up from the session file
+            self.garmin_client.expire_session();
+            let username = String::from(&self.garmin_config.credentials.user);
+            let login = match self.get_password() {
+                Ok(password) => self.garmin_client.login(&username, &password).await,
+                Err(e) => Err(e),
+            };
+            match login {
+                Ok(()) => true,
+                Err(e) => {
+                    error!("Unable to log in again: {}", e);
+                    false
+                }
+            }
+        })
+    }
+}
+
+// Requests and parses 'endpoint', logging in again and retrying once if Garmin
+// answers with something other than JSON (e.g. its login page).
+async fn request_json_from(
+    source: &mut impl JsonSource,
+    endpoint: &str,
+    params: Option<HashMap<&str, &str>>,
+) -> Result<serde_json::Value, ApiError> {
+    let mut result = match source.fetch_text(endpoint, params.clone()).await {
+        Ok(text) => api_error::parse_json_response(&text),
+        Err(e) => Err(e),
+    };
+    if result.as_ref().is_err_and(ApiError::needs_relogin) {
+        warn!(
+            "Unexpected response from {}, logging in again and retrying...",
+            endpoint
+        );
+        if source.relogin().await {
+            result = match source.fetch_text(endpoint, params).await {
+                Ok(text) => api_error::parse_json_response(&text),
+                Err(e) => Err(e),
+            };
+        }
+    }
+    if let Err(e) = &result {
+        warn!("Unable to get {}: {}", endpoint, e);
+        source.log_last_response();
+    }
+    result
+}
+
+// One page of activity summaries from 'endpoint'. A new account gets an empty list, but
+// Garmin answers some failures with an error object instead, which is logged and skipped.
+async fn search_activities_from(
+    source: &mut impl JsonSource,
+    endpoint: &str,
+    start: u32,
+    limit: u32,
+) -> Option<Vec<serde_json::Value>> {
+    let start_str = format!("{}", start);
+    let count = format!("{}", limit);
+    let params = HashMap::from([("start", start_str.as_str()), ("limit", count.as_str())]);
+    let Ok(response) = request_json_from(source, endpoint, Some(params)).await else {
+        warn!(
+            "Unable to get summaries for {} activities from {}",
+            limit, start
+        );
+        return None;
+    };
+    match response {
+        serde_json::Value::Array(lookup) => Some(lookup),
+        other => {
+            warn!("Expected a list of activity summaries, got: {}", other);
+            None
+        }
+    }
+}
+
+// Runs requests through 'send', at most 'max_in_flight' at a time. 'on_result' is called
+// with each request's index and result (None if it failed), in request order, as soon as
+// it's known.
+async fn run_requests<F, Fut, T>(
+    max_in_flight: usize,
+    requests: Vec<ApiRequest>,
+    send: F,
+    mut on_result: impl FnMut(usize, Option<T>),
+) where
+    F: Fn(ApiRequest) -> Fut,
+    Fut: Future<Output = Option<T>>,
+{
+    // 'buffered' hands back results in request order, so logging stays in date order
+    let mut results = stream::iter(requests)
+        .map(|request| {
+            let endpoint = request.endpoint.clone();
+            send(request).map(move |result| (endpoint, result))
+        })
+        .buffered(max_in_flight.max(1));
+
+    let mut index = 0;
+    while let Some((endpoint, result)) = results.next().await {
+        if result.is_some() {
+            debug!("Finished request for: {}", endpoint);
+        } else {
+            warn!("Request for {} did not save any data", endpoint);
+        }
+        on_result(index, result);
+        index += 1;
+    }
+}
+
+// runs a single request on its own clone of the client, appending the JSON
+// response to its .ndjson file (see ndjson::append) if it has one. Returns the
+// files saved, or None if the request failed.
+async fn send_request(mut client: GarminClient, request: ApiRequest) -> Option<Vec<PathBuf>> {
+    let params: HashMap<&str, &str> = request
+        .params
+        .iter()
+        .map(|(key, value)| (key.as_str(), value.as_str()))
+        .collect();
+    let success = client
+        .api_request(
+            &request.endpoint,
+            (!params.is_empty()).then_some(params),
+            request.json,
+            request.filename,
+        )
+        .await;
+    if let (true, Some((date, path))) = (success, &request.ndjson) {
+        if let Err(e) = ndjson::append(path, *date, client.get_last_resp_text()) {
+            warn!("Unable to append response to {}: {}", path.display(), e);
+        }
+    }
+    success.then(|| client.get_last_saved_files().to_vec())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use config::{File, FileFormat};
+    use futures::executor::block_on;
+    use futures::future;
+    use std::env::current_dir;
+
+    #[test]
+    fn build_file_name_creates_subfolder_test() {
+        let base_path =
+            std::env::temp_dir().join(format!("garmin_download_test_{}", std::process::id()));
+        let config = Config::builder()
+            .add_source(File::new(
+                current_dir()
+                    .unwrap()
+                    .join("..")
+                    .join("config")
+                    .join("garmin_config.json")
+                    .to_str()
+                    .unwrap(),
+                FileFormat::Json,
+            ))
+            .set_override("file.file_base_path", base_path.to_str().unwrap())
+            .unwrap()
+            .set_override(
+                "file.file_template",
+                "{base}/{metric}/{year}{month}{day}{addons}.{ext}",
+            )
+            .unwrap()
+            .build()
+            .unwrap();
+        let dm = DownloadManager::new(config, None);
+
+        let date =
+            NaiveDateTime::parse_from_str("2024-02-01 00:00:00", "%Y-%m-%d %H:%M:%S").unwrap();
+        let filename = dm
+            .build_file_name("sleep", Some(date), None, ".json")
+            .unwrap();
+        let expected = base_path.join("sleep").join("20240201.json");
+        assert_eq!(filename, expected.to_str().unwrap());
+        assert!(expected.parent().unwrap().is_dir());
+
+        std::fs::remove_dir_all(&base_path).unwrap();
+    }
+
+    #[test]
+    fn expected_file_path_test() {
+        let base_path =
+            std::env::temp_dir().join(format!("garmin_expected_path_test_{}", std::process::id()));
+        let config = Config::builder()
+            .add_source(File::new(
+                current_dir()
+                    .unwrap()
+                    .join("..")
+                    .join("config")
+                    .join("garmin_config.json")
+                    .to_str()
+                    .unwrap(),
+                FileFormat::Json,
+            ))
+            .set_override("file.file_base_path", base_path.to_str().unwrap())
+            .unwrap()
+            .build()
+            .unwrap();
+        let dm = DownloadManager::new(config, None);
+
+        let date =
+            NaiveDateTime::parse_from_str("2024-02-01 00:00:00", "%Y-%m-%d %H:%M:%S").unwrap();
+        let path = dm.expected_file_path(Metric::Rhr.folder(), Some(date), &[], "json");
+        assert_eq!(path, base_path.join("heartrate").join("2024-02-01.json"));
+        // nothing is created
+        assert!(!base_path.exists());
+
+        let addons = [String::from("1234")];
+        assert_eq!(
+            dm.expected_file_path("splits", Some(date), &addons, ".json"),
+            base_path.join("splits").join("2024-02-01-1234.json")
+        );
+        // the same place a download goes
+        assert_eq!(
+            dm.build_file_name("splits", Some(date), Some(addons.to_vec()), ".json"),
+            Some(String::from(
+                base_path
+                    .join("splits")
+                    .join("2024-02-01-1234.json")
+                    .to_str()
+                    .unwrap()
+            ))
+        );
+
+        std::fs::remove_dir_all(&base_path).unwrap();
+    }
+
+    #[test]
+    fn overwrite_within_days_test() {
+        let base_path =
+            std::env::temp_dir().join(format!("garmin_overwrite_test_{}", std::process::id()));
+        let config = Config::builder()
+            .add_source(File::new(
+                current_dir()
+                    .unwrap()
+                    .join("..")
+                    .join("config")
+                    .join("garmin_config.json")
+                    .to_str()
+                    .unwrap(),
+                FileFormat::Json,
+            ))
+            .set_override("file.file_base_path", base_path.to_str().unwrap())
+            .unwrap()
+            .set_override("file.overwrite", false)
+            .unwrap()
+            .set_override("file.overwrite_within_days", 3)
+            .unwrap()
+            .build()
+            .unwrap();
+        let dm = DownloadManager::new(config, None);
+
+        let today = Local::now().naive_local();
+        let recent = today.checked_sub_days(Days::new(2)).unwrap();
+        let old = today.checked_sub_days(Days::new(10)).unwrap();
+        for date in [recent, old] {
+            let filename = dm
+                .build_file_name("sleep", Some(date), None, ".json")
+                .unwrap();
+            std::fs::write(filename, "{}").unwrap();
+        }
+
+        // recent data may still be revised, so it's downloaded again
+        assert!(dm
+            .build_file_name("sleep", Some(recent), None, ".json")
+            .is_some());
+        assert!(dm
+            .build_file_name("sleep", Some(old), None, ".json")
+            .is_none());
+
+        std::fs::remove_dir_all(&base_path).unwrap();
+    }
+
+    #[test]
+    fn extracted_download_is_skipped_test() {
+        let base_path =
+            std::env::temp_dir().join(format!("garmin_extracted_skip_test_{}", std::process::id()));
+        let config = Config::builder()
+            .add_source(File::new(
+                current_dir()
+                    .unwrap()
+                    .join("..")
+                    .join("config")
+                    .join("garmin_config.json")
+                    .to_str()
+                    .unwrap(),
+                FileFormat::Json,
+            ))
+            .set_override("file.file_base_path", base_path.to_str().unwrap())
+            .unwrap()
+            .set_override("file.overwrite", false)
+            .unwrap()
+            .build()
+            .unwrap();
+        let dm = DownloadManager::new(config, None);
+
+        // the zip was extracted and moved to the archive folder
+        let date =
+            NaiveDateTime::parse_from_str("2024-02-01 00:00:00", "%Y-%m-%d %H:%M:%S").unwrap();
+        let zip = dm.monitoring_request(date).unwrap().filename.unwrap();
+        let archived = base_path
+            .join("monitoring")
+            .join("archive")
+            .join("2024-02-01.zip");
+        std::fs::create_dir_all(archived.parent().unwrap()).unwrap();
+        std::fs::write(&archived, "zip").unwrap();
+        assert!(dm.monitoring_request(date).unwrap().filename.is_none());
+        std::fs::remove_file(&archived).unwrap();
+        assert_eq!(dm.monitoring_request(date).unwrap().filename, Some(zip));
+
+        // the activity's FIT file was extracted on an earlier day
+        assert!(dm
+            .activity_details_file_name(1234, ActivityFormat::Fit, ".zip")
+            .is_some());
+        std::fs::write(
+            base_path.join("activities").join("1234_ACTIVITY.fit"),
+            "fit",
+        )
+        .unwrap();
+        assert!(dm
+            .activity_details_file_name(1234, ActivityFormat::Fit, ".zip")
+            .is_none());
+        // other formats aren't extracted from it
+        assert!(dm
+            .activity_details_file_name(1234, ActivityFormat::Tcx, ".tcx")
+            .is_some());
+
+        std::fs::remove_dir_all(&base_path).unwrap();
+    }
+
+    #[test]
+    fn download_today_plus_days_test() {
+        let config = Config::builder()
+            .add_source(File::new(
+                current_dir()
+                    .unwrap()
+                    .join("..")
+                    .join("config")
+                    .join("garmin_config.json")
+                    .to_str()
+                    .unwrap(),
+                FileFormat::Json,
+            ))
+            .set_override("data.download_today_data", true)
+            .unwrap()
+            .set_override("data.download_today_plus_days", 2)
+            .unwrap()
+            .build()
+            .unwrap();
+        let dm = DownloadManager::new(config, None);
+
+        let today = Local::now().date_naive();
+        let dates: Vec<NaiveDate> = dm
+            .get_download_dates(Metric::Sleep, "2023-12-31")
+            .iter()
+            .map(|date| date.date())
+            .collect();
+        assert_eq!(
+            dates,
+            vec![
+                today.checked_sub_days(Days::new(2)).unwrap(),
+                today.checked_sub_days(Days::new(1)).unwrap(),
+                today
+            ]
+        );
+    }
+
+    #[test]
+    fn relative_date_offset_test() {
+        let config = Config::builder()
+            .add_source(File::new(
+                current_dir()
+                    .unwrap()
+                    .join("..")
+                    .join("config")
+                    .join("garmin_config.json")
+                    .to_str()
+                    .unwrap(),
+                FileFormat::Json,
+            ))
+            .set_override("data.relative_date_offset", 1)
+            .unwrap()
+            .build()
+            .unwrap();
+        let mut dm = DownloadManager::new(config, None);
+        assert!(dm.garmin_config.data.download_today_data);
+        assert_eq!(dm.garmin_config.data.num_days_from_start_date, 1);
+
+        let yesterday = Local::now()
+            .date_naive()
+            .checked_sub_days(Days::new(1))
+            .unwrap();
+        let dates = dm.get_download_dates(Metric::Sleep, "2023-12-31");
+        assert_eq!(dates.len(), 1);
+        assert_eq!(dates[0].date(), yesterday);
+
+        // a backfill still runs up to today
+        dm.set_backfill_start(yesterday);
+        let dates = dm.get_download_dates(Metric::Sleep, &dm.garmin_config.data.sleep_start_date);
+        assert_eq!(dates.len(), 2);
+        assert_eq!(dates[1].date(), Local::now().date_naive());
+    }
+
+    #[test]
+    fn activities_since_test() {
+        let config = Config::builder()
+            .add_source(File::new(
+                current_dir()
+                    .unwrap()
+                    .join("..")
+                    .join("config")
+                    .join("garmin_config.json")
+                    .to_str()
+                    .unwrap(),
+                FileFormat::Json,
+            ))
+            .build()
+            .unwrap();
+        let mut dm = DownloadManager::new(config, None);
+        assert!(!dm.pages_all_activities());
+
+        dm.garmin_config.activities.activities_since = String::from("2024-01-15");
+        assert!(dm.pages_all_activities());
+        let (start, end) = dm.activity_date_range().unwrap();
+        assert_eq!(
+            start,
+            NaiveDateTime::parse_from_str("2024-01-15 00:00:00", "%Y-%m-%d %H:%M:%S").unwrap()
+        );
+        assert_eq!(end.date(), Local::now().date_naive().succ_opt().unwrap());
+    }
+
+    #[test]
+    fn backfill_start_test() {
+        let config = Config::builder()
+            .add_source(File::new(
+                current_dir()
+                    .unwrap()
+                    .join("..")
+                    .join("config")
+                    .join("garmin_config.json")
+                    .to_str()
+                    .unwrap(),
+                FileFormat::Json,
+            ))
+            .set_override("data.download_today_data", true)
+            .unwrap()
+            .build()
+            .unwrap();
+        let mut dm = DownloadManager::new(config, None);
+
+        let today = Local::now().date_naive();
+        let start = today.checked_sub_days(Days::new(9)).unwrap();
+        dm.set_backfill_start(start);
+
+        assert!(dm.backfill);
+        assert!(!dm.garmin_config.data.download_today_data);
+        assert_eq!(dm.garmin_config.data.num_days_from_start_date, 10);
+        for metric_start in [
+            &dm.garmin_config.data.hrv_start_date,
+            &dm.garmin_config.data.activity_start_date,
+        ] {
+            assert_eq!(*metric_start, format!("{}", start.format("%Y-%m-%d")));
+        }
+        let dates = dm.get_download_dates(Metric::Sleep, &dm.garmin_config.data.sleep_start_date);
+        assert_eq!(dates.len(), 10);
+        assert_eq!(dates[0].date(), start);
+        assert_eq!(dates[9].date(), today);
+    }
+
+    #[test]
+    fn cache_buster_test() {
+        let config = Config::builder()
+            .add_source(File::new(
+                current_dir()
+                    .unwrap()
+                    .join("..")
+                    .join("config")
+                    .join("garmin_config.json")
+                    .to_str()
+                    .unwrap(),
+                FileFormat::Json,
+            ))
+            .set_override("file.save_to_file", false)
+            .unwrap()
+            .build()
+            .unwrap();
+        let dm = DownloadManager::new(config, None);
+        let date =
+            NaiveDateTime::parse_from_str("2024-02-01 00:00:00", "%Y-%m-%d %H:%M:%S").unwrap();
+        let before = Local::now().timestamp_millis();
+
+        for request in [
+            dm.weight_request(date).unwrap(),
+            dm.summary_request(date).unwrap(),
+        ] {
+            let cache_buster = request
+                .params
+                .iter()
+                .find(|(key, _)| key == "_")
+                .map(|(_, value)| value.parse::<i64>().unwrap())
+                .unwrap();
+            // the time of the request, not of the requested day
+            assert!(cache_buster >= before);
+            assert!(request
+                .params
+                .iter()
+                .any(|(_, value)| value == "2024-02-01"));
+        }
+    }
+
+    #[test]
+    fn resolve_password_test() {
+        let name = format!("GARMIN_TEST_PASSWORD_{}", std::process::id());
+        assert_eq!(resolve_password("hunter2").unwrap(), "hunter2");
+        assert!(matches!(
+            resolve_password(&format!("${{{}}}", name)),
+            Err(LoginError::MissingPassword(_))
+        ));
+
+        std::env::set_var(&name, "from_env");
+        assert_eq!(
+            resolve_password(&format!("${{{}}}", name)).unwrap(),
+            "from_env"
+        );
+        std::env::remove_var(&name);
+    }
+
+    #[test]
+    fn activity_id_from_url_test() {
+        assert_eq!(
+            activity_id_from_url("https://connect.garmin.com/modern/activity/123456789"),
+            Ok(123456789)
+        );
+        assert_eq!(
+            activity_id_from_url(" connect.garmin.cn/modern/activity/42/?tab=laps#map "),
+            Ok(42)
+        );
+        assert!(activity_id_from_url("https://connect.garmin.com/modern/activity/").is_err());
+        assert!(activity_id_from_url("https://connect.garmin.com/modern/activity/abc").is_err());
+        assert!(activity_id_from_url("https://example.com/activity/123").is_err());
+        assert!(activity_id_from_url("123456789").is_err());
+    }
+
+    #[test]
+    fn parse_date_range_test() {
+        let date = |d: &str| NaiveDate::parse_from_str(d, "%Y-%m-%d").unwrap();
+        assert_eq!(
+            parse_date_range("2024-01-30, 2024-02-02"),
+            Ok((date("2024-01-30"), date("2024-02-02")))
+        );
+        assert!(parse_date_range("2024-01-30").is_err());
+        assert!(parse_date_range("2024-01-30,yesterday").is_err());
+        assert!(parse_date_range("2024-02-02,2024-01-30").is_err());
+    }
+
+    #[test]
+    fn missing_dates_test() {
+        let date = |d: &str| NaiveDate::parse_from_str(d, "%Y-%m-%d").unwrap();
+        // dates outside the range don't matter
+        let existing: HashSet<NaiveDate> = ["2024-01-29", "2024-01-31", "2024-02-01"]
+            .into_iter()
+            .map(date)
+            .collect();
+        assert_eq!(
+            missing_dates(date("2024-01-30"), date("2024-02-02"), &existing),
+            vec![date("2024-01-30"), date("2024-02-02")]
+        );
+        assert_eq!(
+            missing_dates(date("2024-01-31"), date("2024-01-31"), &existing),
+            vec![]
+        );
+        assert_eq!(
+            missing_dates(date("2024-01-30"), date("2024-01-30"), &HashSet::new()),
+            vec![date("2024-01-30")]
+        );
+    }
+
+    #[test]
+    fn failed_day_does_not_stop_others_test() {
+        let requests: Vec<ApiRequest> = ["day1", "day2", "day3"]
+            .into_iter()
+            .map(|day| ApiRequest::new(String::from(day), vec![], true, None))
+            .collect();
+        let mut results: Vec<(usize, bool)> = Vec::new();
+        block_on(run_requests(
+            2,
+            requests,
+            |request| async move { (request.endpoint != "day2").then_some(()) },
+            |index, result| results.push((index, result.is_some())),
+        ));
+        assert_eq!(results, vec![(0, true), (1, false), (2, true)]);
+    }
+
+    // serves canned responses, counting re-logins
+    struct FakeSource {
+        responses: Vec<&'static str>,
+        relogins: usize,
+        logged: std::cell::Cell<usize>,
+    }
+
+    impl JsonSource for FakeSource {
+        fn fetch_text<'a>(
+            &'a mut self,
+            _endpoint: &'a str,
+            _params: Option<HashMap<&'a str, &'a str>>,
+        ) -> BoxFuture<'a, Result<String, ApiError>> {
+            Box::pin(future::ready(Ok(String::from(self.responses.remove(0)))))
+        }
+
+        fn relogin(&mut self) -> BoxFuture<'_, bool> {
+            self.relogins += 1;
+            Box::pin(future::ready(true))
+        }
+
+        fn log_last_response(&self) {
+            self.logged.set(self.logged.get() + 1);
+        }
+    }
+
+    #[test]
+    fn activity_ids_in_range_test() {
+        let lookup: Vec<serde_json::Value> = serde_json::from_str(
+            r#"[
+                { "activityId": 3, "activityName": "Run", "startTimeLocal": "2024-02-03 07:00:00" },
+                { "activityId": null, "activityName": "Broken", "startTimeLocal": "2024-02-02 07:00:00" },
+                { "activityId": "abc", "startTimeLocal": "2024-02-02 08:00:00" },
+                { "activityId": "2", "activityName": null, "startTimeLocal": "2024-02-02 09:00:00" },
+                { "activityId": 1, "activityName": "Ride", "startTimeLocal": "2024-01-31 07:00:00" }
+            ]"#,
+        )
+        .unwrap();
+        assert_eq!(activity_ids_in_range(&lookup, None), (vec![3, 2, 1], false));
+
+        let start =
+            NaiveDateTime::parse_from_str("2024-02-01 00:00:00", "%Y-%m-%d %H:%M:%S").unwrap();
+        let end = start.checked_add_days(Days::new(2)).unwrap();
+        assert_eq!(
+            activity_ids_in_range(&lookup, Some((start, end))),
+            (vec![2], true)
+        );
+    }
+
+    #[test]
+    fn html_response_triggers_relogin_test() {
+        let login_page = "<!DOCTYPE html><html><head><title>Sign In</title></head></html>";
+        let mut source = FakeSource {
+            responses: vec![login_page, r#"{"displayName": "runner"}"#],
+            relogins: 0,
+            logged: Default::default(),
+        };
+        let value = block_on(request_json_from(&mut source, "userprofile", None)).unwrap();
+        assert_eq!(value["displayName"], "runner");
+        assert_eq!(source.relogins, 1);
+        assert_eq!(source.logged.get(), 0);
+
+        // only retried once
+        let mut source = FakeSource {
+            responses: vec![login_page, login_page],
+            relogins: 0,
+            logged: Default::default(),
+        };
+        assert!(matches!(
+            block_on(request_json_from(&mut source, "userprofile", None)),
+            Err(ApiError::NotAuthenticated)
+        ));
+        assert_eq!(source.relogins, 1);
+        // the url and status of the unusable response are logged
+        assert_eq!(source.logged.get(), 1);
+    }
+
+    #[test]
+    fn activity_search_error_object_test() {
+        let mut source = FakeSource {
+            responses: vec![r#"{"error": "Service Unavailable"}"#, "[]"],
+            relogins: 0,
+            logged: Default::default(),
+        };
+        assert!(block_on(search_activities_from(&mut source, "search", 0, 10)).is_none());
+        // a new account without any activities
+        assert_eq!(
+            block_on(search_activities_from(&mut source, "search", 0, 10)),
+            Some(Vec::new())
+        );
+        assert_eq!(source.relogins, 0);
     }
 }
@@ -0,0 +1,59 @@
+use std::fs::{self, OpenOptions};
+use std::io::{self, Write};
+use std::path::Path;
+
+use chrono::NaiveDate;
+use serde_json::{Map, Value};
+
+/// Appends a JSON response to the .ndjson file at 'path' as one compact line, with the
+/// day it's for injected as "date". Responses that aren't an object (e.g. a list, or
+/// null for days without data) are wrapped as { "date": ..., "data": ... }.
+pub(crate) fn append(path: &Path, date: NaiveDate, response: &str) -> io::Result<()> {
+    let date = Value::String(date.format("%Y-%m-%d").to_string());
+    let line = match serde_json::from_str(response)? {
+        Value::Object(mut object) => {
+            object.insert(String::from("date"), date);
+            Value::Object(object)
+        }
+        data => {
+            let mut object = Map::new();
+            object.insert(String::from("date"), date);
+            object.insert(String::from("data"), data);
+            Value::Object(object)
+        }
+    };
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    let mut line = serde_json::to_string(&line)?;
+    line.push('\n');
+    OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)?
+        .write_all(line.as_bytes())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn append_test() {
+        let folder =
+            std::env::temp_dir().join(format!("garmin_ndjson_test_{}", std::process::id()));
+        let path = folder.join("hrv.ndjson");
+        let date = NaiveDate::from_ymd_opt(2024, 2, 1).unwrap();
+
+        append(&path, date, "{\n  \"lastNightAvg\": 48\n}").unwrap();
+        append(&path, date, "[1, 2]").unwrap();
+        assert!(append(&path, date, "<html>").is_err());
+
+        assert_eq!(
+            fs::read_to_string(&path).unwrap(),
+            "{\"date\":\"2024-02-01\",\"lastNightAvg\":48}\n{\"data\":[1,2],\"date\":\"2024-02-01\"}\n"
+        );
+
+        fs::remove_dir_all(&folder).unwrap();
+    }
+}
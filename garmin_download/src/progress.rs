@@ -0,0 +1,73 @@
+use crate::Metric;
+
+/// How far download_all() has gotten through a metric, reported after each
+/// day (or activity) completes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Progress {
+    pub metric: Metric,
+    pub completed: usize,
+    /// For activities this grows as more pages of search results are found.
+    pub total: usize,
+}
+
+/// Called with each update, e.g. to render a progress bar.
+pub type ProgressCallback = Box<dyn FnMut(Progress) + Send>;
+
+// forwards progress to the callback, if there is one
+#[derive(Default)]
+pub(crate) struct ProgressReporter {
+    callback: Option<ProgressCallback>,
+}
+
+impl ProgressReporter {
+    pub fn set_callback(&mut self, callback: ProgressCallback) {
+        self.callback = Some(callback);
+    }
+
+    pub fn report(&mut self, metric: Metric, completed: usize, total: usize) {
+        if let Some(callback) = self.callback.as_mut() {
+            callback(Progress {
+                metric,
+                completed,
+                total,
+            });
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::{Arc, Mutex};
+
+    #[test]
+    fn progress_reporter_test() {
+        let mut reporter = ProgressReporter::default();
+        // nothing to report to yet
+        reporter.report(Metric::Sleep, 1, 2);
+
+        let received = Arc::new(Mutex::new(Vec::new()));
+        let sink = received.clone();
+        reporter.set_callback(Box::new(move |progress| {
+            sink.lock().unwrap().push(progress)
+        }));
+        reporter.report(Metric::Sleep, 1, 2);
+        reporter.report(Metric::Activities, 0, 5);
+
+        assert_eq!(
+            *received.lock().unwrap(),
+            vec![
+                Progress {
+                    metric: Metric::Sleep,
+                    completed: 1,
+                    total: 2
+                },
+                Progress {
+                    metric: Metric::Activities,
+                    completed: 0,
+                    total: 5
+                },
+            ]
+        );
+    }
+}
@@ -0,0 +1,156 @@
+use std::fs::{self, File};
+use std::io::{self, BufReader, BufWriter, Read, Write};
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+use chrono::{DateTime, Local};
+use flate2::read::GzDecoder;
+use flate2::write::GzEncoder;
+use flate2::Compression;
+
+// Monthly .tar.gz archives of uploaded files, for 'archive_downloads'.
+
+const ARCHIVE_EXTENSION: &str = ".tar.gz";
+
+/// One file in an archive: its path relative to file_base_path, e.g.
+/// "monitoring/1234_WELLNESS.fit", and its contents.
+#[derive(Debug, PartialEq)]
+pub struct Entry {
+    pub name: String,
+    pub data: Vec<u8>,
+}
+
+/// The archive a file last modified at 'modified' belongs in, e.g. <folder>/2024-02.tar.gz
+pub fn archive_for(folder: &Path, modified: SystemTime) -> PathBuf {
+    let month = DateTime::<Local>::from(modified).format("%Y-%m");
+    folder.join(format!("{}{}", month, ARCHIVE_EXTENSION))
+}
+
+/// Adds 'entries' to the archive at 'path', replacing any entries of the same name,
+/// and creating it if it doesn't exist yet.
+///
+/// The archive is rewritten to a temporary file first, so it's never left half written.
+pub fn add(path: &Path, entries: Vec<Entry>) -> io::Result<()> {
+    let mut all = if path.exists() {
+        read_all(path)?
+    } else {
+        Vec::new()
+    };
+    all.retain(|existing| !entries.iter().any(|entry| entry.name == existing.name));
+    all.extend(entries);
+
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    let tmp_path = path.with_extension("gz.tmp");
+    let result = write_all(&tmp_path, &all).and_then(|_| fs::rename(&tmp_path, path));
+    if result.is_err() {
+        let _ = fs::remove_file(&tmp_path);
+    }
+    result
+}
+
+/// Every entry in the archive at 'path', in the order they were added.
+pub fn read_all(path: &Path) -> io::Result<Vec<Entry>> {
+    let mut tar = tar::Archive::new(GzDecoder::new(BufReader::new(File::open(path)?)));
+    let mut entries: Vec<Entry> = Vec::new();
+    for entry in tar.entries()? {
+        let mut entry = entry?;
+        let name = entry.path()?.to_string_lossy().to_string();
+        let mut data: Vec<u8> = Vec::new();
+        entry.read_to_end(&mut data)?;
+        entries.push(Entry { name, data });
+    }
+    Ok(entries)
+}
+
+/// The contents of 'name' in the archive at 'path', if it's there.
+pub fn read_entry(path: &Path, name: &str) -> io::Result<Option<Vec<u8>>> {
+    Ok(read_all(path)?
+        .into_iter()
+        .find(|entry| entry.name == name)
+        .map(|entry| entry.data))
+}
+
+/// Splits a path into an archived file, e.g. "archive/2024-02.tar.gz/monitoring/1234_WELLNESS.fit",
+/// into the archive and the entry name within it.
+pub fn split_archived_path(path: &str) -> Option<(PathBuf, String)> {
+    let (archive, name) = path.split_once(&format!("{}/", ARCHIVE_EXTENSION))?;
+    Some((
+        PathBuf::from(format!("{}{}", archive, ARCHIVE_EXTENSION)),
+        String::from(name),
+    ))
+}
+
+fn write_all(path: &Path, entries: &[Entry]) -> io::Result<()> {
+    let encoder = GzEncoder::new(BufWriter::new(File::create(path)?), Compression::default());
+    let mut tar = tar::Builder::new(encoder);
+    let mtime = SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .map_or(0, |since| since.as_secs());
+    for entry in entries {
+        let mut header = tar::Header::new_gnu();
+        header.set_size(entry.data.len() as u64);
+        header.set_mode(0o644);
+        header.set_mtime(mtime);
+        tar.append_data(&mut header, &entry.name, entry.data.as_slice())?;
+    }
+    tar.into_inner()?.finish()?.flush()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn archive_round_trip_test() {
+        let folder =
+            std::env::temp_dir().join(format!("garmin_archive_tar_{}", std::process::id()));
+        let path = folder.join("2024-02.tar.gz");
+        let entry = |name: &str, data: &[u8]| Entry {
+            name: String::from(name),
+            data: data.to_vec(),
+        };
+
+        add(
+            &path,
+            vec![
+                entry("sleep/2024-02-01.json", b"{}"),
+                entry("monitoring/1234_WELLNESS.fit", &[7u8; 1000]),
+            ],
+        )
+        .unwrap();
+        // re-archiving a file replaces it
+        add(&path, vec![entry("sleep/2024-02-01.json", b"{\"v\": 2}")]).unwrap();
+
+        assert_eq!(
+            read_all(&path).unwrap(),
+            vec![
+                entry("monitoring/1234_WELLNESS.fit", &[7u8; 1000]),
+                entry("sleep/2024-02-01.json", b"{\"v\": 2}"),
+            ]
+        );
+        assert_eq!(
+            read_entry(&path, "sleep/2024-02-01.json").unwrap(),
+            Some(b"{\"v\": 2}".to_vec())
+        );
+        assert_eq!(read_entry(&path, "missing.json").unwrap(), None);
+
+        fs::remove_dir_all(&folder).unwrap();
+    }
+
+    #[test]
+    fn split_archived_path_test() {
+        assert_eq!(
+            split_archived_path("data/archive/2024-02.tar.gz/monitoring/1234_WELLNESS.fit"),
+            Some((
+                PathBuf::from("data/archive/2024-02.tar.gz"),
+                String::from("monitoring/1234_WELLNESS.fit")
+            ))
+        );
+        assert_eq!(
+            split_archived_path("data/monitoring/1234_WELLNESS.fit"),
+            None
+        );
+    }
+}
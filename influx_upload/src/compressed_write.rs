@@ -0,0 +1,125 @@
+use influxdb2::models::data_point::DataPoint;
+use influxdb2::RequestError;
+use reqwest::header::CONTENT_ENCODING;
+use reqwest::Client;
+
+use crate::line_protocol;
+
+/// Writes gzip compressed line protocol to an InfluxDB 2.x server's /api/v2/write
+/// endpoint. The influxdb2 client can only compress behind a crate feature we don't
+/// build with, so 'compress_writes' goes through here instead.
+pub struct CompressedWriter {
+    client: Client,
+    url: String,
+    org: String,
+    bucket: String,
+    token: String,
+}
+
+impl CompressedWriter {
+    pub fn new(url: &str, org: &str, bucket: &str, token: &str) -> CompressedWriter {
+        CompressedWriter {
+            client: Client::new(),
+            url: String::from(url.trim_end_matches('/')),
+            org: String::from(org),
+            bucket: String::from(bucket),
+            token: String::from(token),
+        }
+    }
+
+    /// Writes the points as gzip compressed line protocol with nanosecond timestamps.
+    pub async fn write(&self, points: &[DataPoint]) -> Result<(), RequestError> {
+        let response = self
+            .client
+            .post(format!("{}/api/v2/write", self.url))
+            .query(&[
+                ("org", self.org.as_str()),
+                ("bucket", self.bucket.as_str()),
+                ("precision", "ns"),
+            ])
+            .header("Authorization", format!("Token {}", self.token))
+            .header(CONTENT_ENCODING, "gzip")
+            .body(line_protocol::encode_gzip(points))
+            .send()
+            .await
+            .map_err(|source| RequestError::ReqwestProcessing { source })?;
+        let status = response.status();
+        if status.is_success() {
+            return Ok(());
+        }
+        let text = response
+            .text()
+            .await
+            .map_err(|source| RequestError::ReqwestProcessing { source })?;
+        Err(RequestError::Http { status, text })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use flate2::read::GzDecoder;
+    use std::io::{BufRead, BufReader, Read, Write};
+    use std::net::TcpListener;
+    use std::sync::mpsc;
+
+    // accepts one request, sending its headers and decompressed body back over the channel
+    fn serve_write() -> (String, mpsc::Receiver<(String, String)>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        let (sender, receiver) = mpsc::channel();
+        std::thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut reader = BufReader::new(stream.try_clone().unwrap());
+            let mut headers = String::new();
+            let mut content_length = 0;
+            loop {
+                let mut line = String::new();
+                reader.read_line(&mut line).unwrap();
+                if line == "\r\n" {
+                    break;
+                }
+                if let Some(length) = line.to_lowercase().strip_prefix("content-length:") {
+                    content_length = length.trim().parse::<usize>().unwrap();
+                }
+                headers.push_str(&line);
+            }
+            let mut body = vec![0u8; content_length];
+            reader.read_exact(&mut body).unwrap();
+            let mut decoded = String::new();
+            GzDecoder::new(&body[..])
+                .read_to_string(&mut decoded)
+                .unwrap();
+            stream
+                .write_all(b"HTTP/1.1 204 No Content\r\nConnection: close\r\n\r\n")
+                .unwrap();
+            sender.send((headers, decoded)).unwrap();
+        });
+        (url, receiver)
+    }
+
+    #[tokio::test]
+    async fn large_compressed_write_test() {
+        let points: Vec<DataPoint> = (0..20_000)
+            .map(|i| {
+                DataPoint::builder("heart_rate")
+                    .tag("device", "fenix")
+                    .field("heart_rate", 60 + i % 100)
+                    .timestamp(1_700_000_000_000_000_000 + i * 1_000_000_000)
+                    .build()
+                    .unwrap()
+            })
+            .collect();
+        let (url, receiver) = serve_write();
+        let writer = CompressedWriter::new(&url, "org", "garmin", "API_TOKEN");
+        writer.write(&points).await.unwrap();
+
+        let (headers, body) = receiver.recv().unwrap();
+        let headers = headers.to_lowercase();
+        assert!(headers.starts_with("post /api/v2/write?org=org&bucket=garmin&precision=ns"));
+        assert!(headers.contains("content-encoding: gzip"));
+        assert!(headers.contains("authorization: token api_token"));
+        assert_eq!(body.as_bytes(), line_protocol::encode(&points).as_slice());
+        assert_eq!(body.lines().count(), 20_000);
+    }
+}
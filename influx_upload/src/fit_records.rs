@@ -0,0 +1,375 @@
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::Read;
+
+use chrono::{DateTime, FixedOffset, NaiveDateTime};
+use fitparser::Value;
+use influxdb2::models::data_point::FieldValue;
+use log::{debug, error};
+
+const GARMIN_FIT_DATE_FORMAT: &str = "%Y-%m-%d %H:%M:%S %z";
+// some fields render without an offset, those are UTC
+const GARMIN_FIT_NAIVE_DATE_FORMAT: &str = "%Y-%m-%d %H:%M:%S";
+const GARMIN_EPOCH_OFFSET: i64 = 631065600;
+const GARMIN_POSITION_FACTOR: f64 = 11930465.0;
+// what FIT files hold for a position the device didn't have a fix for
+const INVALID_POSITION: f64 = 0x7FFFFFFF as f64;
+
+/// A single record from a FIT file, with Garmin specific encodings already resolved.
+#[derive(Debug, Clone, PartialEq)]
+pub struct FitRecord {
+    /// Record type, e.g. "monitoring" or "record"
+    pub kind: String,
+    /// Nanoseconds since the unix epoch, from the 'timestamp' or 'timestamp_16' field
+    pub timestamp: Option<i64>,
+    /// Every field in the record, keyed by name. Position fields ('*_lat'/'*_long')
+    /// are converted from semicircles to degrees.
+    pub fields: BTreeMap<String, Value>,
+}
+
+// Expands 'timestamp_16' values (the lower 16 bits of a garmin epoch timestamp) into full
+// timestamps. fitparser emits records in file order, so the most recent timestamp of *any*
+// record kind is the right reference, and every expanded value becomes the new reference so
+// files spanning several 16 bit rollovers keep counting forward.
+#[derive(Debug, Default)]
+struct TimestampResolver {
+    // garmin epoch seconds
+    last: Option<i64>,
+}
+
+impl TimestampResolver {
+    fn set_absolute(&mut self, unix_seconds: i64) {
+        self.last = Some(unix_seconds - GARMIN_EPOCH_OFFSET);
+    }
+
+    // returns unix epoch seconds, or None if no absolute timestamp has been seen yet
+    fn resolve_16(&mut self, timestamp_16: i64) -> Option<i64> {
+        let last = self.last?;
+        // masking keeps the offset in 0..=0xFFFF, so a wrap of the lower 16 bits
+        // (e.g. 0xFFF0 -> 0x0010) moves forward into the next window, never backwards
+        let garmin_date = last + ((timestamp_16 - (last & 0xFFFF)) & 0xFFFF);
+        self.last = Some(garmin_date);
+        Some(garmin_date + GARMIN_EPOCH_OFFSET)
+    }
+}
+
+// garmin represents position data as 32 bit semicircles, so we have to divide by representation
+// range to get actual degrees. Positions without a fix (or outside the globe) are dropped, rather
+// than ending up as bogus points at 0,0.
+fn resolve_field(name: &str, value: Value) -> Option<Value> {
+    let max_degrees = if name.contains("_lat") {
+        90.0
+    } else if name.contains("_long") {
+        180.0
+    } else {
+        return Some(value);
+    };
+    let Ok(raw) = value.to_string().parse::<f64>() else {
+        return Some(value);
+    };
+    if raw == 0.0 || raw.abs() >= INVALID_POSITION {
+        return None;
+    }
+    let degrees = raw / GARMIN_POSITION_FACTOR;
+    if degrees.abs() > max_degrees {
+        return None;
+    }
+    Some(Value::Float64(degrees))
+}
+
+// parses a 'timestamp' value as fitparser renders it, with or without a UTC offset
+fn parse_fit_timestamp(value: &str) -> Result<DateTime<FixedOffset>, chrono::ParseError> {
+    let value = value.replace('"', "");
+    DateTime::parse_from_str(&value, GARMIN_FIT_DATE_FORMAT).or_else(|e| {
+        let naive =
+            NaiveDateTime::parse_from_str(&value, GARMIN_FIT_NAIVE_DATE_FORMAT).map_err(|_| e)?;
+        debug!("No offset in timestamp {}, assuming UTC", value);
+        Ok(naive.and_utc().fixed_offset())
+    })
+}
+
+/// Parses every record in a FIT file.
+pub fn parse_fit_to_records(filename: &str) -> Result<Vec<FitRecord>, fitparser::Error> {
+    read_fit_records(&mut File::open(filename)?)
+}
+
+/// Parses every record of FIT data, e.g. a file read back out of an archive.
+pub fn read_fit_records<R: Read>(source: &mut R) -> Result<Vec<FitRecord>, fitparser::Error> {
+    let mut records: Vec<FitRecord> = Vec::new();
+    let mut resolver = TimestampResolver::default();
+
+    for record in fitparser::from_reader(source)? {
+        let kind = record.kind().to_string();
+        let mut timestamp: Option<i64> = None;
+        let mut fields: BTreeMap<String, Value> = BTreeMap::new();
+
+        for field in record.into_vec() {
+            let name = String::from(field.name()).replace('"', "");
+            // grab the timestamp.
+            if name == "timestamp" {
+                match parse_fit_timestamp(&field.value().to_string()) {
+                    Ok(ts) => {
+                        timestamp = ts.timestamp_nanos_opt();
+                        resolver.set_absolute(ts.timestamp());
+                    }
+                    Err(e) => {
+                        error!("Unable to parse timestamp from 'timestamp' field value: {} in record type {}. Error: {}", &field.value(), kind, e);
+                    }
+                }
+            // for 'monitoring' records, 'timestamp_16' represents offset from last epoch timestamp
+            } else if name == "timestamp_16" {
+                let timestamp_16 = field.value().to_string().parse::<i64>().unwrap();
+                timestamp = resolver
+                    .resolve_16(timestamp_16)
+                    .map(|seconds| seconds * 1_000_000_000);
+            }
+
+            if let Some(value) = resolve_field(&name, field.into_value()) {
+                fields.insert(name, value);
+            }
+        }
+
+        records.push(FitRecord {
+            kind,
+            timestamp,
+            fields,
+        });
+    }
+    Ok(records)
+}
+
+// records identifying the recording device, most specific first
+const DEVICE_RECORDS: [&str; 2] = ["file_id", "device_info"];
+
+/// Identifies the device that recorded a FIT file, e.g. "fenix7_3456789012", from the
+/// product and serial number of its 'file_id' record (or failing that, a 'device_info' record).
+///
+/// Returns None if neither is present.
+pub fn device_tag(records: &[FitRecord]) -> Option<String> {
+    DEVICE_RECORDS.iter().find_map(|kind| {
+        records
+            .iter()
+            .filter(|record| record.kind == *kind)
+            .find_map(|record| {
+                let product = record
+                    .fields
+                    .get("garmin_product")
+                    .or_else(|| record.fields.get("product"));
+                let parts: Vec<String> = [product, record.fields.get("serial_number")]
+                    .into_iter()
+                    .flatten()
+                    .map(|value| value.to_string())
+                    .collect();
+                (!parts.is_empty()).then(|| parts.join("_"))
+            })
+    })
+}
+
+/// Converts a FIT field value to an InfluxDB field. Integers stay integers with
+/// 'integers' set, and are uploaded as floats like every other number otherwise.
+///
+/// Enums fitparser knows about already come through as strings (e.g. "running"),
+/// unknown ones as their raw number. Timestamps and arrays have no field representation.
+pub fn to_field_value(value: &Value, integers: bool) -> Option<FieldValue> {
+    let field = match value {
+        Value::Float32(v) => Some(FieldValue::F64(*v as f64)),
+        Value::Float64(v) => Some(FieldValue::F64(*v)),
+        Value::Byte(v) | Value::Enum(v) | Value::UInt8(v) | Value::UInt8z(v) => {
+            Some(FieldValue::I64(*v as i64))
+        }
+        Value::SInt8(v) => Some(FieldValue::I64(*v as i64)),
+        Value::SInt16(v) => Some(FieldValue::I64(*v as i64)),
+        Value::UInt16(v) | Value::UInt16z(v) => Some(FieldValue::I64(*v as i64)),
+        Value::SInt32(v) => Some(FieldValue::I64(*v as i64)),
+        Value::UInt32(v) | Value::UInt32z(v) => Some(FieldValue::I64(*v as i64)),
+        Value::SInt64(v) => Some(FieldValue::I64(*v)),
+        Value::UInt64(v) | Value::UInt64z(v) => i64::try_from(*v).ok().map(FieldValue::I64),
+        Value::String(v) => Some(FieldValue::String(v.clone())),
+        Value::Timestamp(_) | Value::Array(_) => None,
+    };
+    match field {
+        Some(FieldValue::I64(v)) if !integers => Some(FieldValue::F64(v as f64)),
+        field => field,
+    }
+}
+
+/// Converts a FIT field to the field(s) it's uploaded as. Arrays, e.g. 'time_in_hr_zone',
+/// become a field per element: 'time_in_hr_zone_0', 'time_in_hr_zone_1' and so on.
+pub fn to_fields(name: &str, value: &Value, integers: bool) -> Vec<(String, FieldValue)> {
+    match value {
+        Value::Array(values) => values
+            .iter()
+            .enumerate()
+            .filter_map(|(index, value)| {
+                to_field_value(value, integers).map(|value| (format!("{}_{}", name, index), value))
+            })
+            .collect(),
+        value => to_field_value(value, integers)
+            .map(|value| vec![(String::from(name), value)])
+            .unwrap_or_default(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn timestamp_16_rollover_test() {
+        let mut resolver = TimestampResolver::default();
+        assert_eq!(resolver.resolve_16(10), None);
+
+        // garmin epoch seconds 0x0001_FF00, i.e. near the end of a 16 bit window
+        let start = GARMIN_EPOCH_OFFSET + 0x0001_FF00;
+        resolver.set_absolute(start);
+
+        // consecutive timestamp_16 values, wrapping the lower 16 bits several times
+        let mut previous = start;
+        let mut expected = start;
+        for step in [0x40, 0x100, 0x8000, 0x7FFF, 0xFFFF, 0x10, 0x9000, 0x9000] {
+            expected += step;
+            let resolved = resolver
+                .resolve_16((expected - GARMIN_EPOCH_OFFSET) & 0xFFFF)
+                .unwrap();
+            assert_eq!(resolved, expected);
+            assert!(resolved >= previous);
+            previous = resolved;
+        }
+        // crossed from window 0x0001 into 0x0005
+        assert_eq!((previous - GARMIN_EPOCH_OFFSET) >> 16, 5);
+
+        // a fresh absolute timestamp resets the reference
+        resolver.set_absolute(start);
+        assert_eq!(
+            resolver.resolve_16((start - GARMIN_EPOCH_OFFSET + 5) & 0xFFFF),
+            Some(start + 5)
+        );
+    }
+
+    #[test]
+    fn parse_fit_timestamp_test() {
+        let with_offset = parse_fit_timestamp("2024-02-01 06:00:00 -05:00").unwrap();
+        assert_eq!(with_offset.timestamp(), 1706785200);
+        let naive = parse_fit_timestamp("\"2024-02-01 06:00:00\"").unwrap();
+        assert_eq!(naive.timestamp(), 1706767200);
+        assert!(parse_fit_timestamp("yesterday").is_err());
+    }
+
+    #[test]
+    fn to_field_value_test() {
+        assert_eq!(
+            to_field_value(&Value::UInt16(12345), true),
+            Some(FieldValue::I64(12345))
+        );
+        assert_eq!(
+            to_field_value(&Value::SInt8(-5), true),
+            Some(FieldValue::I64(-5))
+        );
+        assert_eq!(to_field_value(&Value::UInt64(u64::MAX), true), None);
+        assert_eq!(
+            to_field_value(&Value::Float64(1.5), true),
+            Some(FieldValue::F64(1.5))
+        );
+        assert_eq!(
+            to_field_value(&Value::Enum(3), true),
+            Some(FieldValue::I64(3))
+        );
+        assert_eq!(
+            to_field_value(&Value::String(String::from("running")), true),
+            Some(FieldValue::String(String::from("running")))
+        );
+        assert_eq!(
+            to_field_value(&Value::Array(vec![Value::UInt8(1)]), true),
+            None
+        );
+        // integers are floats unless asked for
+        assert_eq!(
+            to_field_value(&Value::UInt16(12345), false),
+            Some(FieldValue::F64(12345.0))
+        );
+        assert_eq!(
+            to_field_value(&Value::String(String::from("running")), false),
+            Some(FieldValue::String(String::from("running")))
+        );
+    }
+
+    #[test]
+    fn to_fields_test() {
+        assert_eq!(
+            to_fields("heart_rate", &Value::UInt8(150), true),
+            vec![(String::from("heart_rate"), FieldValue::I64(150))]
+        );
+        assert_eq!(
+            to_fields(
+                "time_in_hr_zone",
+                &Value::Array(vec![
+                    Value::Float64(120.5),
+                    Value::Float64(600.0),
+                    Value::Timestamp(Default::default()),
+                    Value::Float64(30.0),
+                ]),
+                false
+            ),
+            vec![
+                (String::from("time_in_hr_zone_0"), FieldValue::F64(120.5)),
+                (String::from("time_in_hr_zone_1"), FieldValue::F64(600.0)),
+                (String::from("time_in_hr_zone_3"), FieldValue::F64(30.0)),
+            ]
+        );
+        assert!(to_fields("timestamps", &Value::Timestamp(Default::default()), false).is_empty());
+    }
+
+    #[test]
+    fn device_tag_test() {
+        let record = |kind: &str, fields: Vec<(&str, Value)>| FitRecord {
+            kind: String::from(kind),
+            timestamp: None,
+            fields: fields
+                .into_iter()
+                .map(|(name, value)| (String::from(name), value))
+                .collect(),
+        };
+
+        let records = vec![
+            record("device_info", vec![("serial_number", Value::UInt32z(1111))]),
+            record(
+                "file_id",
+                vec![
+                    ("garmin_product", Value::String(String::from("fenix7"))),
+                    ("serial_number", Value::UInt32z(3456789012)),
+                ],
+            ),
+            record("monitoring", vec![("heart_rate", Value::UInt8(60))]),
+        ];
+        assert_eq!(
+            device_tag(&records),
+            Some(String::from("fenix7_3456789012"))
+        );
+
+        // no file_id, falls back to device_info
+        assert_eq!(device_tag(&records[..1]), Some(String::from("1111")));
+        assert_eq!(device_tag(&records[2..]), None);
+    }
+
+    #[test]
+    fn invalid_position_test() {
+        assert_eq!(
+            resolve_field("position_lat", Value::SInt32(0x7FFFFFFF)),
+            None
+        );
+        assert_eq!(resolve_field("position_long", Value::SInt32(0)), None);
+        // 100 degrees of latitude
+        assert_eq!(
+            resolve_field("position_lat", Value::SInt32(1_193_046_500)),
+            None
+        );
+        assert_eq!(
+            resolve_field("position_long", Value::SInt32(1_193_046_500)),
+            Some(Value::Float64(100.0))
+        );
+        assert_eq!(
+            resolve_field("heart_rate", Value::UInt8(0)),
+            Some(Value::UInt8(0))
+        );
+    }
+}
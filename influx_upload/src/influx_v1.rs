@@ -0,0 +1,344 @@
+use std::fmt;
+
+use chrono::{DateTime, NaiveDate};
+use futures::future::BoxFuture;
+use influxdb2::models::data_point::DataPoint;
+use log::info;
+use reqwest::header::CONTENT_ENCODING;
+use reqwest::{Client, RequestBuilder, StatusCode};
+use serde_json::Value;
+
+use crate::line_protocol;
+use crate::sink::{self, DataSink, WriteError};
+
+/// Why a request to an InfluxDB 1.x server failed.
+#[derive(Debug)]
+pub enum InfluxV1Error {
+    /// The request itself didn't go through.
+    Request(reqwest::Error),
+    /// The server responded with an error, e.g. an unknown database or bad credentials.
+    Status(StatusCode, String),
+    /// The query response wasn't the JSON we expected.
+    InvalidResponse(String),
+}
+
+impl fmt::Display for InfluxV1Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            InfluxV1Error::Request(e) => write!(f, "request failed: {}", e),
+            InfluxV1Error::Status(status, body) => {
+                write!(f, "server responded {}: {}", status, body)
+            }
+            InfluxV1Error::InvalidResponse(reason) => write!(f, "invalid response: {}", reason),
+        }
+    }
+}
+
+impl std::error::Error for InfluxV1Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            InfluxV1Error::Request(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<reqwest::Error> for InfluxV1Error {
+    fn from(e: reqwest::Error) -> Self {
+        InfluxV1Error::Request(e)
+    }
+}
+
+/// Writes to (and queries) an InfluxDB 1.x server through its /write and /query
+/// endpoints, for servers without the 2.x API the influxdb2 client expects.
+pub struct InfluxV1Client {
+    client: Client,
+    url: String,
+    database: String,
+    retention_policy: String,
+    username: String,
+    password: String,
+    compress: bool,
+}
+
+impl InfluxV1Client {
+    /// An empty 'retention_policy' uses the database's default, and an empty
+    /// 'username' skips authentication.
+    pub fn new(
+        url: &str,
+        database: &str,
+        retention_policy: &str,
+        username: &str,
+        password: &str,
+    ) -> InfluxV1Client {
+        InfluxV1Client {
+            client: Client::new(),
+            url: String::from(url.trim_end_matches('/')),
+            database: String::from(database),
+            retention_policy: String::from(retention_policy),
+            username: String::from(username),
+            password: String::from(password),
+            compress: false,
+        }
+    }
+
+    /// Gzip compresses the body of every write.
+    pub fn set_compress_writes(&mut self, compress: bool) {
+        self.compress = compress;
+    }
+
+    /// Writes the points as line protocol with nanosecond timestamps.
+    pub async fn write(&self, points: &[DataPoint]) -> Result<(), InfluxV1Error> {
+        let mut params = self.params();
+        params.push(("precision", "ns"));
+        let mut request = self.authorized(self.client.post(format!("{}/write", self.url)), &params);
+        request = if self.compress {
+            request
+                .header(CONTENT_ENCODING, "gzip")
+                .body(line_protocol::encode_gzip(points))
+        } else {
+            request.body(line_protocol::encode(points))
+        };
+        let response = request.send().await?;
+        check_status(response).await.map(|_| ())
+    }
+
+    /// Every value of 'tag' within 'measurement'.
+    pub async fn tag_values(
+        &self,
+        measurement: &str,
+        tag: &str,
+    ) -> Result<Vec<String>, InfluxV1Error> {
+        let query = format!(
+            "SHOW TAG VALUES FROM \"{}\" WITH KEY = \"{}\"",
+            measurement, tag
+        );
+        parse_tag_values(&self.query(&query).await?)
+    }
+
+    /// The start of every day with points in 'measurement', as RFC3339 timestamps.
+    /// Days start at midnight UTC, shifted 'shift_secs' seconds earlier, e.g. to
+    /// local midnight.
+    pub async fn days_with_points(
+        &self,
+        measurement: &str,
+        shift_secs: i64,
+    ) -> Result<Vec<String>, InfluxV1Error> {
+        let query = format!(
+            "SELECT COUNT(*) FROM \"{}\" WHERE time >= 0 GROUP BY time(1d, {}s) fill(none)",
+            measurement, -shift_secs
+        );
+        parse_column(&self.query(&query).await?, 0)
+    }
+
+    /// Every database on the server, e.g. to check the configured one exists.
+    pub async fn databases(&self) -> Result<Vec<String>, InfluxV1Error> {
+        let json = self.query("SHOW DATABASES").await?;
+        parse_column(&json, 0)
+    }
+
+    async fn query(&self, query: &str) -> Result<Value, InfluxV1Error> {
+        let mut params = self.params();
+        params.push(("q", query));
+        let response = self
+            .authorized(self.client.get(format!("{}/query", self.url)), &params)
+            .send()
+            .await?;
+        let body = check_status(response).await?;
+        serde_json::from_str(&body).map_err(|e| InfluxV1Error::InvalidResponse(e.to_string()))
+    }
+
+    // database and retention policy, shared by every request
+    fn params(&self) -> Vec<(&str, &str)> {
+        let mut params = vec![("db", self.database.as_str())];
+        if !self.retention_policy.is_empty() {
+            params.push(("rp", &self.retention_policy));
+        }
+        params
+    }
+
+    // the credentials go in a basic auth header rather than the query string, which
+    // ends up in proxy and server logs
+    fn authorized(&self, request: RequestBuilder, params: &[(&str, &str)]) -> RequestBuilder {
+        let request = request.query(params);
+        if self.username.is_empty() {
+            return request;
+        }
+        request.basic_auth(&self.username, Some(&self.password))
+    }
+}
+
+impl InfluxV1Client {
+    async fn check_database(&self) -> Result<(), String> {
+        let databases = self.databases().await.map_err(|e| match e {
+            InfluxV1Error::Status(StatusCode::UNAUTHORIZED, _) => {
+                format!("{} rejected the configured username/password", self.url)
+            }
+            e => format!("unable to query {}: {}", self.url, e),
+        })?;
+        if !databases.contains(&self.database) {
+            return Err(format!(
+                "database '{}' doesn't exist on {}",
+                self.database, self.url
+            ));
+        }
+        info!("Connected to database '{}' on {}", self.database, self.url);
+        Ok(())
+    }
+}
+
+impl DataSink for InfluxV1Client {
+    fn write(&mut self, points: Vec<DataPoint>) -> BoxFuture<'_, Result<(), WriteError>> {
+        Box::pin(async move {
+            InfluxV1Client::write(self, &points)
+                .await
+                .map_err(|e| match e {
+                    InfluxV1Error::Status(status, _) => {
+                        WriteError::from_status(status, e.to_string())
+                    }
+                    e => WriteError::Unavailable(e.to_string()),
+                })?;
+            info!("Published {} datapoints!", points.len());
+            Ok(())
+        })
+    }
+
+    fn reconnect(&mut self) -> Result<(), String> {
+        self.client = Client::new();
+        Ok(())
+    }
+
+    fn validate(&mut self) -> BoxFuture<'_, Result<(), String>> {
+        Box::pin(self.check_database())
+    }
+
+    fn tag_values<'a>(
+        &'a mut self,
+        measurement: &'a str,
+        tag: &'a str,
+    ) -> BoxFuture<'a, Result<Vec<String>, String>> {
+        Box::pin(async move {
+            InfluxV1Client::tag_values(self, measurement, tag)
+                .await
+                .map_err(|e| e.to_string())
+        })
+    }
+
+    fn existing_dates<'a>(
+        &'a mut self,
+        measurement: &'a str,
+        shift_secs: i64,
+    ) -> BoxFuture<'a, Result<Vec<NaiveDate>, String>> {
+        Box::pin(async move {
+            let starts = self
+                .days_with_points(measurement, shift_secs)
+                .await
+                .map_err(|e| e.to_string())?;
+            Ok(starts
+                .iter()
+                .filter_map(|start| DateTime::parse_from_rfc3339(start).ok())
+                .filter_map(|start| sink::date_of_day_start(start, shift_secs))
+                .collect())
+        })
+    }
+
+    fn writes_offline(&self) -> bool {
+        true
+    }
+}
+
+async fn check_status(response: reqwest::Response) -> Result<String, InfluxV1Error> {
+    let status = response.status();
+    let body = response.text().await?;
+    if status.is_success() {
+        Ok(body)
+    } else {
+        Err(InfluxV1Error::Status(status, body))
+    }
+}
+
+// pulls the values out of a SHOW TAG VALUES response, e.g.
+// {"results":[{"series":[{"columns":["key","value"],"values":[["activityId","1234"]]}]}]}
+fn parse_tag_values(json: &Value) -> Result<Vec<String>, InfluxV1Error> {
+    parse_column(json, 1)
+}
+
+// pulls the 'column'th value of every row out of a query response
+fn parse_column(json: &Value, column: usize) -> Result<Vec<String>, InfluxV1Error> {
+    let Some(results) = json["results"].as_array() else {
+        return Err(InfluxV1Error::InvalidResponse(String::from(
+            "missing 'results'",
+        )));
+    };
+    let mut values: Vec<String> = Vec::new();
+    for result in results {
+        if let Some(error) = result["error"].as_str() {
+            return Err(InfluxV1Error::InvalidResponse(String::from(error)));
+        }
+        // a measurement without any points has no series at all
+        for series in result["series"].as_array().into_iter().flatten() {
+            for row in series["values"].as_array().into_iter().flatten() {
+                if let Some(value) = row.get(column).and_then(Value::as_str) {
+                    values.push(String::from(value));
+                }
+            }
+        }
+    }
+    Ok(values)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_tag_values_test() {
+        let json: Value = serde_json::from_str(
+            r#"{"results":[{"statement_id":0,"series":[{"name":"activity_details",
+                "columns":["key","value"],
+                "values":[["activityId","1234"],["activityId","5678"]]}]}]}"#,
+        )
+        .unwrap();
+        assert_eq!(parse_tag_values(&json).unwrap(), vec!["1234", "5678"]);
+
+        let empty: Value = serde_json::from_str(r#"{"results":[{"statement_id":0}]}"#).unwrap();
+        assert!(parse_tag_values(&empty).unwrap().is_empty());
+
+        let error: Value =
+            serde_json::from_str(r#"{"results":[{"error":"database not found: garmin"}]}"#)
+                .unwrap();
+        assert!(parse_tag_values(&error).is_err());
+    }
+
+    #[test]
+    fn parse_databases_test() {
+        let json: Value = serde_json::from_str(
+            r#"{"results":[{"statement_id":0,"series":[{"name":"databases",
+                "columns":["name"],"values":[["_internal"],["garmin"]]}]}]}"#,
+        )
+        .unwrap();
+        assert_eq!(parse_column(&json, 0).unwrap(), vec!["_internal", "garmin"]);
+    }
+
+    #[test]
+    fn request_params_test() {
+        let client = InfluxV1Client::new("http://localhost:8086/", "garmin", "", "", "");
+        assert_eq!(client.url, "http://localhost:8086");
+        assert_eq!(client.params(), vec![("db", "garmin")]);
+
+        let client = InfluxV1Client::new("http://localhost:8086", "garmin", "autogen", "me", "pw");
+        assert_eq!(client.params(), vec![("db", "garmin"), ("rp", "autogen")]);
+        let request = client
+            .authorized(
+                client.client.get("http://localhost:8086/query"),
+                &client.params(),
+            )
+            .build()
+            .unwrap();
+        assert_eq!(request.url().query(), Some("db=garmin&rp=autogen"));
+        assert_eq!(
+            request.headers()[reqwest::header::AUTHORIZATION],
+            "Basic bWU6cHc="
+        );
+    }
+}
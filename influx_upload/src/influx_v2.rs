@@ -0,0 +1,216 @@
+use chrono::NaiveDate;
+use futures::future::BoxFuture;
+use futures::stream;
+use influxdb2::api::buckets::ListBucketsRequest;
+use influxdb2::models::data_point::DataPoint;
+use influxdb2::models::Query;
+use influxdb2::{Client, ClientBuilder, RequestError};
+use log::{info, warn};
+use reqwest::StatusCode;
+
+use crate::compressed_write::CompressedWriter;
+use crate::sink::{self, DataSink, WriteError};
+
+/// Writes to (and queries) an InfluxDB 2.x bucket through the influxdb2 client,
+/// or through CompressedWriter with 'compress_writes' set.
+pub struct InfluxV2Sink {
+    url: String,
+    org: String,
+    bucket: String,
+    token: String,
+    // built on first use
+    client: Option<Client>,
+    compressed_writer: Option<CompressedWriter>,
+}
+
+impl InfluxV2Sink {
+    pub fn new(url: &str, org: &str, bucket: &str, token: &str, compress: bool) -> InfluxV2Sink {
+        InfluxV2Sink {
+            url: String::from(url),
+            org: String::from(org),
+            bucket: String::from(bucket),
+            token: String::from(token),
+            client: None,
+            compressed_writer: compress.then(|| CompressedWriter::new(url, org, bucket, token)),
+        }
+    }
+
+    fn client(&mut self) -> Result<&Client, String> {
+        if self.client.is_none() {
+            let client = ClientBuilder::new(&self.url, &self.org, &self.token)
+                .build()
+                .map_err(|e| format!("unable to create a client for {}: {}", self.url, e))?;
+            info!("Built influx client: {:?}", client);
+            self.client = Some(client);
+        }
+        Ok(self.client.as_ref().unwrap())
+    }
+
+    async fn write_points(&mut self, points: Vec<DataPoint>) -> Result<(), WriteError> {
+        let num = points.len();
+        if let Some(writer) = self.compressed_writer.as_ref() {
+            writer.write(&points).await.map_err(write_error)?;
+        } else {
+            let bucket = self.bucket.clone();
+            self.client()
+                .map_err(WriteError::Unavailable)?
+                .write(&bucket, stream::iter(points))
+                .await
+                .map_err(write_error)?;
+        }
+        info!("Published {} datapoints!", num);
+        Ok(())
+    }
+
+    async fn check_bucket(&mut self) -> Result<(), String> {
+        let request = ListBucketsRequest {
+            name: Some(self.bucket.clone()),
+            org: Some(self.org.clone()),
+            ..Default::default()
+        };
+        let (url, org) = (self.url.clone(), self.org.clone());
+        let buckets = match self.client()?.list_buckets(Some(request)).await {
+            Ok(buckets) => buckets,
+            // a token that's only allowed to write can't list buckets either
+            Err(RequestError::Http { status, .. })
+                if status == StatusCode::UNAUTHORIZED || status == StatusCode::FORBIDDEN =>
+            {
+                return self.check_write_access().await;
+            }
+            Err(RequestError::Http { status, text }) if status == StatusCode::NOT_FOUND => {
+                return Err(format!("org '{}' not found on {}: {}", org, url, text));
+            }
+            Err(e) => return Err(format!("unable to reach {}: {}", url, e)),
+        };
+        if buckets.buckets.is_empty() {
+            return self.check_write_access().await;
+        }
+        info!("Connected to bucket '{}' on {}", self.bucket, self.url);
+        Ok(())
+    }
+
+    // for tokens that can't see the bucket, writes nothing to it to find out whether
+    // the token is allowed to write there at all
+    async fn check_write_access(&self) -> Result<(), String> {
+        let response = reqwest::Client::new()
+            .post(format!("{}/api/v2/write", self.url.trim_end_matches('/')))
+            .query(&[("org", self.org.as_str()), ("bucket", self.bucket.as_str())])
+            .header("Authorization", format!("Token {}", self.token))
+            .send()
+            .await
+            .map_err(|e| format!("unable to reach {}: {}", self.url, e))?;
+        match response.status() {
+            StatusCode::UNAUTHORIZED | StatusCode::FORBIDDEN => {
+                Err(format!("{} rejected the configured token", self.url))
+            }
+            StatusCode::NOT_FOUND => Err(format!(
+                "bucket '{}' doesn't exist in org '{}'",
+                self.bucket, self.org
+            )),
+            // anything else (e.g. a 400 for the missing points) got past the token check
+            _ => {
+                warn!(
+                    "The token can't read bucket '{}', but is allowed to write to it",
+                    self.bucket
+                );
+                Ok(())
+            }
+        }
+    }
+
+    async fn query_tag_values(
+        &mut self,
+        measurement: &str,
+        tag: &str,
+    ) -> Result<Vec<String>, String> {
+        let bucket = self.bucket.clone();
+        self.client()?
+            .list_measurement_tag_values(&bucket, measurement, tag, None, None)
+            .await
+            .map_err(|e| format!("{:?}", e))
+    }
+
+    async fn query_existing_dates(
+        &mut self,
+        measurement: &str,
+        shift_secs: i64,
+    ) -> Result<Vec<NaiveDate>, String> {
+        let query = format!(
+            r#"from(bucket: "{}")
+                |> range(start: time(v: 0))
+                |> filter(fn: (r) => r._measurement == "{}")
+                |> aggregateWindow(every: 1d, offset: {}s, fn: count, createEmpty: false, timeSrc: "_start")
+                |> keep(columns: ["_time"])
+                |> group()
+                |> unique(column: "_time")"#,
+            self.bucket, measurement, -shift_secs
+        );
+        let records = self
+            .client()?
+            .query_raw(Some(Query::new(query)))
+            .await
+            .map_err(|e| format!("{:?}", e))?;
+        Ok(records
+            .iter()
+            .filter_map(|record| match record.values.get("_time") {
+                Some(influxdb2_structmap::value::Value::TimeRFC(start)) => {
+                    sink::date_of_day_start(*start, shift_secs)
+                }
+                _ => None,
+            })
+            .collect())
+    }
+}
+
+// only an error response says anything about the points, anything else is the
+// connection
+fn write_error(e: RequestError) -> WriteError {
+    match e {
+        RequestError::Http { status, .. } => WriteError::from_status(status, format!("{:?}", e)),
+        e => WriteError::Unavailable(format!("{:?}", e)),
+    }
+}
+
+impl DataSink for InfluxV2Sink {
+    fn write(&mut self, points: Vec<DataPoint>) -> BoxFuture<'_, Result<(), WriteError>> {
+        Box::pin(self.write_points(points))
+    }
+
+    fn reconnect(&mut self) -> Result<(), String> {
+        // built again on the next write
+        self.client = None;
+        if self.compressed_writer.is_some() {
+            self.compressed_writer = Some(CompressedWriter::new(
+                &self.url,
+                &self.org,
+                &self.bucket,
+                &self.token,
+            ));
+        }
+        Ok(())
+    }
+
+    fn validate(&mut self) -> BoxFuture<'_, Result<(), String>> {
+        Box::pin(self.check_bucket())
+    }
+
+    fn tag_values<'a>(
+        &'a mut self,
+        measurement: &'a str,
+        tag: &'a str,
+    ) -> BoxFuture<'a, Result<Vec<String>, String>> {
+        Box::pin(self.query_tag_values(measurement, tag))
+    }
+
+    fn existing_dates<'a>(
+        &'a mut self,
+        measurement: &'a str,
+        shift_secs: i64,
+    ) -> BoxFuture<'a, Result<Vec<NaiveDate>, String>> {
+        Box::pin(self.query_existing_dates(measurement, shift_secs))
+    }
+
+    fn writes_offline(&self) -> bool {
+        true
+    }
+}
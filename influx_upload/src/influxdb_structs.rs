@@ -1,16 +1,112 @@
+use std::collections::HashMap;
+
 use serde_derive::{Deserialize, Serialize};
 use serde_json::Value;
 
+use crate::prune::PruneRule;
+
 #[derive(Debug, Deserialize, Default, Serialize)]
 pub struct InfluxDbConfig {
     pub url: String,
     pub org: String,
     pub bucket: String,
     pub token: String,
+    // "v2" (default) writes through the influxdb2 client, "v1" through the 1.x /write API
+    #[serde(default)]
+    pub influx_version: String,
+    // InfluxDB 1.x only: empty retention_policy uses the database default, empty username skips auth
+    #[serde(default)]
+    pub database: String,
+    #[serde(default)]
+    pub retention_policy: String,
+    #[serde(default)]
+    pub username: String,
+    #[serde(default)]
+    pub password: String,
     pub file_base_path: String,
     pub upload_json_files: bool,
     pub upload_fit_files: bool,
     pub records_to_include: Value,
-    pub files_to_prune: Value,
+    // only upload FIT fields listed in msg_type_map, rather than every numeric field
+    #[serde(default)]
+    pub filter_known_fields: bool,
+    // upload integer FIT fields as integers rather than floats, which InfluxDB won't
+    // accept into a field that already has floats, so needs a fresh bucket/measurement
+    #[serde(default)]
+    pub integer_fit_fields: bool,
+    // local files deleted after upload, per subfolder of file_base_path
+    #[serde(default, deserialize_with = "crate::prune::deserialize_rules")]
+    pub files_to_prune: Vec<PruneRule>,
+    // move pruned files into file_base_path/archive/<YYYY-MM>.tar.gz rather than deleting them
+    #[serde(default)]
+    pub archive_downloads: bool,
     pub override_activites: bool,
+    // summaryDTO fields uploaded per activity, see DEFAULT_ACTIVITY_SUMMARY_FIELDS when empty
+    #[serde(default)]
+    pub activity_summary_fields: Vec<SummaryField>,
+    // also upload each overnight SpO2 reading from the sleep files, for watches with pulse ox
+    #[serde(default)]
+    pub upload_sleep_spo2: bool,
+    // renames datasets, e.g. { "activity_summary": "activities" }, see measurements.rs
+    #[serde(default)]
+    pub measurement_names: HashMap<String, String>,
+    // FIT records written to their own measurement by kind, e.g. { "lap": "activity_laps" },
+    // anything not listed stays in its file's measurement (e.g. activity_details)
+    #[serde(default)]
+    pub record_measurements: HashMap<String, String>,
+    // tags added to every datapoint, e.g. { "source": "garmin", "user": "alex" };
+    // a point's own tags win when the key is the same
+    #[serde(default)]
+    pub global_tags: HashMap<String, String>,
+    // also tag every datapoint with the isoWeek, dayOfWeek and month of its timestamp
+    #[serde(default)]
+    pub add_calendar_tags: bool,
+    // "influxdb" (default), "prometheus" or "sqlite"
+    #[serde(default)]
+    pub backend: String,
+    #[serde(default)]
+    pub prometheus_file: String,
+    #[serde(default)]
+    pub sqlite_file: String,
+    // write InfluxDB points to 'offline_file' as line protocol instead of uploading them
+    #[serde(default)]
+    pub offline_mode: bool,
+    // also where points go when an InfluxDB write fails, defaults to file_base_path/garmin_offline.lp
+    #[serde(default)]
+    pub offline_file: String,
+    // hashes of uploaded monitoring files, defaults to file_base_path/.garmin_upload_state.json
+    #[serde(default)]
+    pub upload_state_file: String,
+    // gzip InfluxDB writes, to save bandwidth on slow or metered connections
+    #[serde(default)]
+    pub compress_writes: bool,
+    // datapoints are written in batches of this size across files, 0 writes each file on its own
+    #[serde(default = "default_batch_size")]
+    pub batch_size: usize,
+    // how many FIT files are parsed at once while earlier ones are written
+    #[serde(default = "default_upload_concurrency")]
+    pub upload_concurrency: usize,
+}
+
+/// An activity summaryDTO key to upload, e.g. { "key": "elevationGain", "type": "float" }.
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+pub struct SummaryField {
+    pub key: String,
+    #[serde(rename = "type")]
+    pub field_type: SummaryFieldType,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SummaryFieldType {
+    Float,
+    Int,
+}
+
+fn default_batch_size() -> usize {
+    5000
+}
+
+fn default_upload_concurrency() -> usize {
+    4
 }
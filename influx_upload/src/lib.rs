@@ -1,66 +1,662 @@
-use chrono::{DateTime, Local, NaiveDateTime};
+use chrono::{DateTime, Local, NaiveDate, NaiveDateTime, TimeZone};
 use std::collections::HashMap;
 use std::collections::HashSet;
 use std::ffi::OsStr;
-use std::fs::File;
+use std::fs::{self, File};
 use std::io::BufReader;
-use std::path::Path;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
 
-use async_recursion::async_recursion;
 use config::Config;
-use futures::stream;
-use influxdb2::models::data_point::DataPoint;
-use influxdb2::{Client, ClientBuilder};
+use futures::stream::{self, StreamExt};
+use influxdb2::models::data_point::{DataPoint, DataPointBuilder};
 use log::{error, info, warn};
 use regex::Regex;
+use serde_derive::Serialize;
+
+mod fit_records;
+pub use fit_records::{
+    device_tag, parse_fit_to_records, read_fit_records, to_field_value, to_fields, FitRecord,
+};
+
+mod archive;
+
+mod compressed_write;
+
+mod influx_v1;
+use influx_v1::InfluxV1Client;
+
+mod influx_v2;
+use influx_v2::InfluxV2Sink;
 
 mod influxdb_structs;
-use influxdb_structs::InfluxDbConfig;
+use influxdb_structs::{InfluxDbConfig, SummaryField, SummaryFieldType};
 
+mod line_protocol;
+mod measurements;
 mod msg_type_map;
 
+mod prometheus;
+use prometheus::PrometheusExporter;
+
+mod prune;
+pub use prune::PruneRule;
+
+mod sink;
+pub use sink::{DataSink, WriteError};
+
+mod sqlite;
+use sqlite::SqliteSink;
+
+mod upload_state;
+use upload_state::{UploadState, UPLOAD_STATE_FILE};
+
 // actually contains a T but we'll replace that with a
 // space since the DateTime mod can't decode that for
 // some reason.
 const GARMIN_JSON_DATE_FORMAT: &str = "%Y-%m-%d %H:%M:%S%.3f";
-const GARMIN_FIT_DATE_FORMAT: &str = "%Y-%m-%d %H:%M:%S %z";
-const GARMIN_EPOCH_OFFSET: i64 = 631065600;
-const GARMIN_POSITION_FACTOR: f64 = 11930465.0;
+
+// failed batches in a row (each retried once) before the rest of a run isn't written
+const MAX_CONSECUTIVE_WRITE_FAILURES: usize = 3;
+
+// datasets stamped with when they actually happened, rather than with their calendar date
+// at midnight (which is stored as if it were UTC). sleep is stamped with when it started.
+const INSTANT_DATASETS: [&str; 7] = [
+    "monitoring",
+    "activity_details",
+    "sleep",
+    "spo2",
+    "floors",
+    "intensity_minutes",
+    "weight",
+];
+
+// how far to shift a dataset's timestamps so their UTC date is the calendar 'date' they
+// belong to, going by the UTC offset on that date so days either side of a DST change
+// line up too
+fn date_shift_secs(dataset: &str, date: NaiveDate) -> i64 {
+    if !INSTANT_DATASETS.contains(&dataset) {
+        return 0;
+    }
+    let local = Local
+        .offset_from_local_date(&date)
+        .earliest()
+        .map_or(0, |offset| offset.local_minus_utc() as i64);
+    if dataset == "sleep" {
+        // a night's sleep counts towards the day it ends on
+        local + 12 * 60 * 60
+    } else {
+        local
+    }
+}
+
+// daily summary fields uploaded to 'daily_summary', when the day has them
+const DAILY_SUMMARY_FIELDS: [&str; 16] = [
+    "totalSteps",
+    "dailyStepGoal",
+    "totalDistanceMeters",
+    "totalKilocalories",
+    "activeKilocalories",
+    "bmrKilocalories",
+    "activeSeconds",
+    "sedentarySeconds",
+    "moderateIntensityMinutes",
+    "vigorousIntensityMinutes",
+    "floorsAscended",
+    "minHeartRate",
+    "maxHeartRate",
+    "restingHeartRate",
+    "averageStressLevel",
+    "bodyBatteryHighestValue",
+];
+
+// body composition fields uploaded alongside each weigh-in, when the scale measures them
+const WEIGHT_FIELDS: [&str; 5] = ["bmi", "bodyFat", "bodyWater", "boneMass", "muscleMass"];
+
+// keys (as paths into the JSON) an activity info file can't be uploaded without
+const REQUIRED_ACTIVITY_KEYS: [&[&str]; 3] = [
+    &["activityId"],
+    &["activityTypeDTO", "typeKey"],
+    &["summaryDTO", "startTimeLocal"],
+];
+
+// summaryDTO fields uploaded when 'activity_summary_fields' is empty
+const DEFAULT_ACTIVITY_SUMMARY_FIELDS: [(&str, SummaryFieldType); 19] = [
+    ("activityTrainingLoad", SummaryFieldType::Float),
+    ("anaerobicTrainingEffect", SummaryFieldType::Float),
+    ("averageHR", SummaryFieldType::Float),
+    ("averageSpeed", SummaryFieldType::Float),
+    ("avgRespirationRate", SummaryFieldType::Float),
+    ("bmrCalories", SummaryFieldType::Float),
+    ("calories", SummaryFieldType::Float),
+    ("distance", SummaryFieldType::Float),
+    ("duration", SummaryFieldType::Float),
+    ("elapsedDuration", SummaryFieldType::Float),
+    ("maxHR", SummaryFieldType::Float),
+    ("maxRespirationRate", SummaryFieldType::Float),
+    ("minActivityLapDuration", SummaryFieldType::Float),
+    ("minRespirationRate", SummaryFieldType::Float),
+    ("movingDuration", SummaryFieldType::Float),
+    ("trainingEffect", SummaryFieldType::Float),
+    ("steps", SummaryFieldType::Int),
+    ("moderateIntensityMinutes", SummaryFieldType::Int),
+    ("vigorousIntensityMinutes", SummaryFieldType::Int),
+];
+
+// the first of 'required' missing from 'json' (or null), e.g. "summaryDTO.startTimeLocal"
+fn find_missing_key(json: &serde_json::Value, required: &[&[&str]]) -> Option<String> {
+    required
+        .iter()
+        .find(|path| {
+            path.iter()
+                .try_fold(json, |value, key| value.get(key))
+                .is_none_or(|value| value.is_null())
+        })
+        .map(|path| path.join("."))
+}
+
+// where 'key' is in each row of a chart array, going by its list of value descriptors,
+// e.g. [{ "key": "floorsAscended", "index": 2 }]. 'default' if the list doesn't say.
+fn value_index(descriptors: &serde_json::Value, key: &str, default: usize) -> usize {
+    descriptors
+        .as_array()
+        .into_iter()
+        .flatten()
+        .find(|descriptor| descriptor["key"] == key)
+        .and_then(|descriptor| descriptor["index"].as_u64())
+        .map_or(default, |index| index as usize)
+}
+
+// the isoWeek (e.g. "2024-W05"), dayOfWeek (e.g. "Thu") and month (e.g. "2024-02") tags
+// for a point 'timestamp' nanoseconds since the epoch, on the 'local' date or the UTC one
+fn calendar_tags(timestamp: i64, local: bool) -> Vec<(String, String)> {
+    let Some(time) = DateTime::from_timestamp(
+        timestamp.div_euclid(1_000_000_000),
+        timestamp.rem_euclid(1_000_000_000) as u32,
+    ) else {
+        return Vec::new();
+    };
+    let date = if local {
+        time.with_timezone(&Local).date_naive()
+    } else {
+        time.date_naive()
+    };
+    vec![
+        (String::from("isoWeek"), date.format("%G-W%V").to_string()),
+        (String::from("dayOfWeek"), date.format("%a").to_string()),
+        (String::from("month"), date.format("%Y-%m").to_string()),
+    ]
+}
+
+// a FIT file waiting to be parsed. 'measurement' is the dataset name, i.e. before any
+// 'measurement_names' renaming. with 'tag_device' set, every datapoint is also tagged with
+// the device that recorded the file (see fit_records::device_tag), if the file says which.
+struct FitUpload {
+    filename: String,
+    measurement: &'static str,
+    tags: Vec<(String, String)>,
+    tag_device: bool,
+    // upload_state key and content hash, recorded once the file is uploaded
+    hash: Option<(String, String)>,
+}
+
+// a file whose datapoints are queued, see queue_file(). its points are the
+// 'first_point'..'last_point' queued this run
+struct PendingFile {
+    filename: String,
+    // upload_state key and content hash, recorded once all of its points are written
+    hash: Option<(String, String)>,
+    first_point: usize,
+    last_point: usize,
+    failed: bool,
+}
+
+/// Counts from the last upload_all() run.
+#[derive(Debug, Default, Clone, PartialEq, Eq, Serialize)]
+pub struct UploadSummary {
+    pub files_processed: usize,
+    /// Files that couldn't be read or parsed.
+    pub files_skipped: usize,
+    pub points_written: usize,
+    /// Points written to the offline file instead, to be imported later.
+    pub points_written_offline: usize,
+    /// Points that couldn't be written anywhere.
+    pub points_failed: usize,
+}
 
 // Class for downloading health data from Garmin Connect.
 pub struct UploadManager {
     influx_config: InfluxDbConfig,
-    influx_client: Option<Client>,
+    // where datapoints are written, picked by 'backend' (and 'influx_version')
+    sink: Box<dyn DataSink>,
+    // per upload_all() run, for the summary at the end
+    processed_files: usize,
+    skipped_files: HashSet<String>,
+    points_written: usize,
+    points_written_offline: usize,
+    points_failed: usize,
+    // reset by every successful write, see write_to_sink()
+    consecutive_write_failures: usize,
+    // datapoints waiting for a full batch, see queue_data()
+    pending_points: Vec<DataPoint>,
+    // points queued and written this run, to tell which files a batch held
+    queued_points: usize,
+    written_points: usize,
+    // files with points that aren't all written yet
+    pending_files: Vec<PendingFile>,
+    // files whose points were all written this run, see prune_files()
+    uploaded_files: HashSet<PathBuf>,
+    // hashes of the monitoring files uploaded by earlier runs
+    upload_state: UploadState,
+    force_upload: bool,
+    // with upload_files(), the only files uploaded instead of everything on disk
+    stream_files: Option<Vec<PathBuf>>,
+    // logs every FIT field written or skipped, see reupload_fit_file()
+    explain_fields: bool,
 }
 
 impl UploadManager {
     pub fn new(config: Config) -> UploadManager {
+        let influx_config: InfluxDbConfig = config.try_deserialize().unwrap();
+        let sink: Box<dyn DataSink> = match influx_config.backend.as_str() {
+            "" | "influxdb" => match influx_config.influx_version.as_str() {
+                "" | "v2" => {
+                    info!(
+                        "Uploading to bucket '{}' in org '{}' at {}",
+                        influx_config.bucket, influx_config.org, influx_config.url
+                    );
+                    Box::new(InfluxV2Sink::new(
+                        &influx_config.url,
+                        &influx_config.org,
+                        &influx_config.bucket,
+                        &influx_config.token,
+                        influx_config.compress_writes,
+                    ))
+                }
+                "v1" => {
+                    let mut client = InfluxV1Client::new(
+                        &influx_config.url,
+                        &influx_config.database,
+                        &influx_config.retention_policy,
+                        &influx_config.username,
+                        &influx_config.password,
+                    );
+                    client.set_compress_writes(influx_config.compress_writes);
+                    Box::new(client)
+                }
+                version => panic!(
+                    "Unknown influx_version '{}', expected 'v1' or 'v2'",
+                    version
+                ),
+            },
+            "prometheus" => Box::new(PrometheusExporter::new(&influx_config.prometheus_file)),
+            "sqlite" => match SqliteSink::new(&influx_config.sqlite_file) {
+                Ok(sink) => Box::new(sink),
+                Err(e) => panic!(
+                    "Unable to open sqlite database {}, error: {}",
+                    influx_config.sqlite_file, e
+                ),
+            },
+            backend => panic!(
+                "Unknown upload backend '{}', expected 'influxdb', 'prometheus' or 'sqlite'",
+                backend
+            ),
+        };
+        UploadManager::from_parts(influx_config, sink)
+    }
+
+    /// Uploads to 'sink' rather than the configured 'backend', e.g. a backend
+    /// this crate doesn't know about.
+    pub fn with_sink(config: Config, sink: Box<dyn DataSink>) -> UploadManager {
+        UploadManager::from_parts(config.try_deserialize().unwrap(), sink)
+    }
+
+    fn from_parts(influx_config: InfluxDbConfig, sink: Box<dyn DataSink>) -> UploadManager {
+        if let Err(e) = measurements::validate(&influx_config.measurement_names) {
+            panic!("Invalid 'measurement_names' in influxdb config: {}", e);
+        }
+        if let Err(e) = measurements::validate_records(&influx_config.record_measurements) {
+            panic!("Invalid 'record_measurements' in influxdb config: {}", e);
+        }
         UploadManager {
-            influx_config: config.try_deserialize().unwrap(),
-            influx_client: None,
+            influx_config,
+            sink,
+            processed_files: 0,
+            skipped_files: HashSet::new(),
+            points_written: 0,
+            points_written_offline: 0,
+            points_failed: 0,
+            consecutive_write_failures: 0,
+            pending_points: Vec::new(),
+            queued_points: 0,
+            written_points: 0,
+            pending_files: Vec::new(),
+            uploaded_files: HashSet::new(),
+            upload_state: UploadState::default(),
+            force_upload: false,
+            stream_files: None,
+            explain_fields: false,
         }
     }
 
+    /// Uploads every monitoring FIT file again, even the ones that haven't changed
+    /// since they were last uploaded.
+    pub fn set_force_upload(&mut self, force_upload: bool) {
+        self.force_upload = force_upload;
+    }
+
+    /// Uploads just 'files' (e.g. what a download has just saved) in place of everything
+    /// under file_base_path, so no folder is scanned. Files outside the folders
+    /// upload_all() reads from are ignored.
+    pub async fn upload_files(&mut self, files: &[PathBuf]) {
+        self.stream_files = Some(
+            files
+                .iter()
+                .filter_map(|file| fs::canonicalize(file).ok())
+                .collect(),
+        );
+        self.upload_all().await;
+        self.stream_files = None;
+    }
+
     pub async fn upload_all(&mut self) {
+        self.processed_files = 0;
+        self.skipped_files.clear();
+        self.points_written = 0;
+        self.points_written_offline = 0;
+        self.points_failed = 0;
+        self.consecutive_write_failures = 0;
+        self.queued_points = 0;
+        self.written_points = 0;
+        self.pending_files.clear();
+        self.uploaded_files.clear();
+        self.upload_state = UploadState::load(&self.upload_state_file());
+
+        // rather than finding out from every failed write. nothing is pruned either, so
+        // it's all uploaded by the next run once the config is fixed
+        if let Err(e) = self.validate().await {
+            error!(
+                "Not uploading anything, unable to upload to InfluxDB: {}. Check the config \
+                 with --test_influx, or set offline_mode to write to {} instead",
+                e,
+                self.offline_file().display()
+            );
+            return;
+        }
+
         // first get set of all previously uploaded activity IDs
         let previous_activity_ids = self.get_activity_ids().await;
 
         if self.influx_config.upload_json_files {
             self.upload_activity_info(&previous_activity_ids).await;
-            self.upload_heart_rate_data();
-            self.upload_summary_data();
-            self.upload_weight_data();
-            self.upload_sleep();
+            self.upload_splits().await;
+            self.upload_activity_weather().await;
+            self.upload_heart_rate_data().await;
+            self.upload_summary_data().await;
+            self.upload_weight_data().await;
+            self.upload_sleep().await;
+            self.upload_spo2().await;
+            self.upload_floors().await;
+            self.upload_intensity_minutes().await;
+            self.upload_hrv().await;
+            self.upload_hydration().await;
+            self.upload_vo2max().await;
+            self.upload_training_status().await;
         } else {
             info!("Ignoring JSON file uploads");
         }
 
         if self.influx_config.upload_fit_files {
-            self.upload_monitoring().await;
-            self.upload_activity_details(&previous_activity_ids).await;
+            let mut files = self.monitoring_files();
+            files.extend(self.activity_detail_files(&previous_activity_ids));
+            self.upload_fit_files(files).await;
         } else {
             info!("Ignoring FIT file uploads");
         }
+
+        // the last batch is usually partial
+        self.flush_data().await;
+        // some sinks only write out what they were given here, so nothing counts as
+        // uploaded until this succeeds
+        let flushed = match self.sink.flush() {
+            Ok(()) => true,
+            Err(e) => {
+                error!("Unable to flush uploaded data: {}", e);
+                false
+            }
+        };
+        if self.influx_config.upload_fit_files && flushed {
+            if let Err(e) = self.upload_state.save(&self.upload_state_file()) {
+                error!(
+                    "Unable to save upload state {}, error: {}",
+                    self.upload_state_file().display(),
+                    e
+                );
+            }
+        }
+
+        info!(
+            "Upload finished: {} files processed, {} skipped",
+            self.processed_files,
+            self.skipped_files.len()
+        );
+        if !self.skipped_files.is_empty() {
+            warn!(
+                "Skipped {} unreadable files, see errors above",
+                self.skipped_files.len()
+            );
+        }
+    }
+
+    /// What the last upload_all() processed and wrote, e.g. for a run report.
+    pub fn get_upload_summary(&self) -> UploadSummary {
+        UploadSummary {
+            files_processed: self.processed_files,
+            files_skipped: self.skipped_files.len(),
+            points_written: self.points_written,
+            points_written_offline: self.points_written_offline,
+            points_failed: self.points_failed,
+        }
+    }
+
+    /// Returns the IDs of all activities already uploaded to the configured backend, sorted.
+    pub async fn get_uploaded_activity_ids(&mut self) -> Vec<String> {
+        let mut ids = self.get_activity_ids().await;
+        // IDs are numeric, so shorter ones sort first
+        ids.sort_by(|a, b| a.len().cmp(&b.len()).then_with(|| a.cmp(b)));
+        ids.dedup();
+        ids
+    }
+
+    /// Returns every calendar date with points in 'dataset' (e.g. "daily_summary" or
+    /// "hrv", renamed per 'measurement_names'), e.g. so --fill_gaps only downloads
+    /// the days that are missing.
+    ///
+    /// Daily values are stored at midnight of their calendar date as if it were UTC,
+    /// so their dates are read in UTC. Datasets stamped with the actual time, like
+    /// monitoring, are read in the local timezone instead.
+    pub async fn get_existing_dates(&mut self, dataset: &str) -> HashSet<NaiveDate> {
+        let measurement = String::from(self.measurement(dataset));
+        if self.influx_config.offline_mode && self.sink.writes_offline() {
+            warn!(
+                "Unable to look up existing {} dates in offline mode, treating all as missing",
+                measurement
+            );
+            return HashSet::new();
+        }
+        // backends split days with a single offset, so each UTC offset the dates turn out
+        // to have (e.g. summer and winter time) is looked up on its own, keeping only the
+        // dates that offset applies to
+        let mut shifts = vec![date_shift_secs(dataset, Local::now().date_naive())];
+        let mut existing: HashSet<NaiveDate> = HashSet::new();
+        let mut index = 0;
+        while let Some(&shift) = shifts.get(index) {
+            index += 1;
+            let dates = match self.sink.existing_dates(&measurement, shift).await {
+                Ok(dates) => dates,
+                Err(e) => {
+                    warn!(
+                        "Unable to look up existing {} dates ({}), treating all as missing",
+                        measurement, e
+                    );
+                    return HashSet::new();
+                }
+            };
+            for date in dates {
+                let date_shift = date_shift_secs(dataset, date);
+                if date_shift == shift {
+                    existing.insert(date);
+                } else if !shifts.contains(&date_shift) {
+                    shifts.push(date_shift);
+                }
+            }
+        }
+        existing
+    }
+
+    /// Deletes local files older than each 'files_to_prune' rule allows, once they've been uploaded.
+    /// With 'archive_downloads' set, they're moved into a monthly archive instead, see archive_files().
+    ///
+    /// Only files confirmed uploaded are pruned, i.e. written by the last upload_all(),
+    /// activity files whose activity ID is in the backend, and monitoring files the
+    /// upload state has as uploaded. With 'dry_run' set, only logs what would be deleted.
+    pub async fn prune_files(&mut self, dry_run: bool) {
+        if self.influx_config.files_to_prune.is_empty() {
+            return;
+        }
+        let uploaded_ids: HashSet<String> = self.get_activity_ids().await.into_iter().collect();
+        let now = SystemTime::now();
+        let mut pruned = 0;
+        let mut to_archive: Vec<PathBuf> = Vec::new();
+
+        for rule in self.influx_config.files_to_prune.clone() {
+            let folder = Path::new(&self.influx_config.file_base_path).join(&rule.folder);
+            if !folder.exists() {
+                warn!("Folder {} does not exist!", folder.display());
+                continue;
+            }
+            for path in prune::expired_files(&folder, rule.max_age_days, now) {
+                let filename = path.to_str().unwrap();
+                if !self.confirmed_uploaded(&rule.folder, &path, &uploaded_ids) {
+                    info!("Not pruning {}, it isn't confirmed uploaded", filename);
+                    continue;
+                }
+
+                if dry_run {
+                    info!("Would prune {}", filename);
+                    pruned += 1;
+                    continue;
+                }
+                if self.influx_config.archive_downloads {
+                    to_archive.push(path);
+                    continue;
+                }
+                match fs::remove_file(&path) {
+                    Ok(()) => {
+                        info!("Pruned {}", filename);
+                        pruned += 1;
+                    }
+                    Err(e) => error!("Unable to prune {}, error: {}", filename, e),
+                }
+            }
+        }
+        pruned += self.archive_files(to_archive);
+        if dry_run {
+            info!("Dry run: would have pruned {} files", pruned);
+        } else {
+            info!("Pruned {} files", pruned);
+        }
+    }
+
+    // whether the contents of 'path', in the 'folder' subfolder, are known to be in the
+    // backend: its points were written this run, or it's an activity file whose ID is
+    // there, or a monitoring file the upload state has with its current contents
+    fn confirmed_uploaded(
+        &self,
+        folder: &str,
+        path: &Path,
+        uploaded_ids: &HashSet<String>,
+    ) -> bool {
+        let canonical = fs::canonicalize(path).unwrap_or(path.to_path_buf());
+        if self.uploaded_files.contains(&canonical) {
+            return true;
+        }
+        let filename = path.to_str().unwrap();
+        match folder {
+            "activities" => {
+                prune::activity_id_from_filename(path).is_some_and(|id| uploaded_ids.contains(&id))
+            }
+            "monitoring" if self.get_extension_from_filename(filename) == Some("fit") => {
+                let Some(metric) = self.get_monitoring_metric_from_filename(filename) else {
+                    return false;
+                };
+                let key = upload_state::file_key(&metric, path);
+                upload_state::file_hash(path)
+                    .is_ok_and(|hash| self.upload_state.is_unchanged(&key, &hash))
+            }
+            _ => false,
+        }
+    }
+
+    // moves each file into file_base_path/archive/<YYYY-MM>.tar.gz by its last modified
+    // month, as <subfolder>/<filename>, returning how many were archived. Files are only
+    // removed once their archive is written.
+    fn archive_files(&self, files: Vec<PathBuf>) -> usize {
+        let base_path = Path::new(&self.influx_config.file_base_path);
+        let mut by_archive: HashMap<PathBuf, Vec<PathBuf>> = HashMap::new();
+        for path in files {
+            match path.metadata().and_then(|metadata| metadata.modified()) {
+                Ok(modified) => by_archive
+                    .entry(archive::archive_for(&base_path.join("archive"), modified))
+                    .or_default()
+                    .push(path),
+                Err(e) => error!("Unable to archive {}, error: {}", path.display(), e),
+            }
+        }
+
+        let mut archived = 0;
+        for (archive_path, paths) in by_archive {
+            let mut entries: Vec<archive::Entry> = Vec::new();
+            // only what made it into the archive is removed
+            let mut read: Vec<PathBuf> = Vec::new();
+            for path in paths {
+                let name = path.strip_prefix(base_path).unwrap_or(&path);
+                match fs::read(&path) {
+                    Ok(data) => {
+                        entries.push(archive::Entry {
+                            name: name.to_string_lossy().replace('\\', "/"),
+                            data,
+                        });
+                        read.push(path);
+                    }
+                    Err(e) => error!("Unable to archive {}, error: {}", path.display(), e),
+                }
+            }
+            if let Err(e) = archive::add(&archive_path, entries) {
+                error!(
+                    "Unable to write archive {}, error: {}",
+                    archive_path.display(),
+                    e
+                );
+                continue;
+            }
+            for path in read {
+                match fs::remove_file(&path) {
+                    Ok(()) => {
+                        info!("Archived {} to {}", path.display(), archive_path.display());
+                        archived += 1;
+                    }
+                    Err(e) => error!("Unable to remove archived {}, error: {}", path.display(), e),
+                }
+            }
+        }
+        archived
+    }
+
+    // logs and counts a file that couldn't be parsed, so one bad file doesn't stop the upload
+    fn skip_file(&mut self, filename: &str, reason: &str) {
+        error!("Skipping unreadable file {}: {}", filename, reason);
+        self.skipped_files.insert(String::from(filename));
     }
 
     fn garmin_ts_to_nanos_since_epoch(&self, ts: &str) -> i64 {
@@ -77,86 +673,319 @@ impl UploadManager {
         }
     }
 
-    fn build_client(&mut self) -> bool {
-        let url: &str = &self.influx_config.url;
-        let org: &str = &self.influx_config.org;
-        let token: &str = &self.influx_config.token;
+    /// Checks that InfluxDB is reachable, the token (or v1 credentials) are accepted
+    /// and the configured bucket (or database) exists. Other backends, and
+    /// 'offline_mode', have nothing to check.
+    pub async fn validate(&mut self) -> Result<(), String> {
+        if self.influx_config.offline_mode {
+            return Ok(());
+        }
+        self.sink.validate().await
+    }
 
-        match ClientBuilder::new(url, org, token).build() {
-            Ok(client) => {
-                info!("Built influx client: {:?}", client);
-                self.influx_client = Some(client);
-                true
+    async fn get_activity_ids(&mut self) -> Vec<String> {
+        if self.influx_config.offline_mode && self.sink.writes_offline() {
+            // nothing to ask, activities are written to the offline file again
+            return vec![];
+        }
+        let measurement = String::from(self.measurement("activity_details"));
+        match self.sink.tag_values(&measurement, "activityId").await {
+            Ok(ids) => {
+                info!("Got {} previous activity ids", ids.len());
+                ids
             }
             Err(e) => {
+                error!("Unable to get previous activity ids: {}", e);
+                vec![]
+            }
+        }
+    }
+
+    async fn write_data(&mut self, data: Vec<DataPoint>) -> bool {
+        let data = self.add_tags(data);
+        let num = data.len();
+        if !self.sink.writes_offline() {
+            return match self.write_to_sink(&data).await {
+                Ok(()) => {
+                    self.points_written += num;
+                    true
+                }
+                Err(e) => {
+                    error!("Unable to write data point(s): {}", e);
+                    self.points_failed += num;
+                    false
+                }
+            };
+        }
+        let fallback = if self.influx_config.offline_mode {
+            data
+        } else {
+            match self.write_to_sink(&data).await {
+                Ok(()) => {
+                    self.points_written += num;
+                    return true;
+                }
+                // they'd only be rejected again when the offline file is uploaded
+                Err(e @ WriteError::Rejected(_)) => {
+                    error!("Unable to write data point(s): {}", e);
+                    self.points_failed += num;
+                    return false;
+                }
+                Err(e) => {
+                    error!("Unable to write data point(s): {}", e);
+                    data
+                }
+            }
+        };
+        let written = self.write_offline(&fallback);
+        if written {
+            self.points_written_offline += num;
+        } else {
+            self.points_failed += num;
+        }
+        written
+    }
+
+    // writes to the sink, reconnecting and trying once more if it's unavailable (e.g.
+    // InfluxDB was restarted). after MAX_CONSECUTIVE_WRITE_FAILURES unavailable batches
+    // in a row the sink isn't tried again this run, rather than failing on every
+    // remaining file. rejected points only fail their own batch.
+    async fn write_to_sink(&mut self, data: &[DataPoint]) -> Result<(), WriteError> {
+        if self.consecutive_write_failures >= MAX_CONSECUTIVE_WRITE_FAILURES {
+            return Err(WriteError::Unavailable(String::from(
+                "gave up after repeated failures",
+            )));
+        }
+        // the sink consumes the points, so each attempt gets a copy
+        let result = match self.sink.write(data.to_vec()).await {
+            Err(WriteError::Unavailable(e)) => {
+                warn!(
+                    "Unable to write data point(s): {}. Reconnecting to retry...",
+                    e
+                );
+                match self.sink.reconnect() {
+                    Ok(()) => self.sink.write(data.to_vec()).await,
+                    Err(reconnect) => Err(WriteError::Unavailable(format!(
+                        "{}, and unable to reconnect: {}",
+                        e, reconnect
+                    ))),
+                }
+            }
+            result => result,
+        };
+        match result {
+            Ok(()) => {
+                self.consecutive_write_failures = 0;
+                return result;
+            }
+            Err(WriteError::Rejected(_)) => return result,
+            Err(WriteError::Unavailable(_)) => {}
+        }
+        self.consecutive_write_failures += 1;
+        if self.consecutive_write_failures == MAX_CONSECUTIVE_WRITE_FAILURES {
+            if self.sink.writes_offline() {
                 error!(
-                    "Unable to create client with:\nurl: {}\norg: {}\ntoken: {}\nerror: {}",
-                    url, org, token, e
+                    "{} writes in a row failed, writing the rest of this run to {} instead",
+                    MAX_CONSECUTIVE_WRITE_FAILURES,
+                    self.offline_file().display()
+                );
+            } else {
+                error!(
+                    "{} writes in a row failed, not writing anything else this run",
+                    MAX_CONSECUTIVE_WRITE_FAILURES
                 );
-                false
             }
         }
+        result
     }
 
-    #[async_recursion]
-    async fn get_activity_ids(&mut self) -> Vec<String> {
-        match self.influx_client.as_ref() {
-            Some(client) => {
-                let ids = client
-                    .list_measurement_tag_values(
-                        &self.influx_config.bucket,
-                        "activity_details",
-                        "activityId",
-                        None,
-                        None,
-                    )
-                    .await
-                    .unwrap();
+    // 'global_tags', which the point's own tags take precedence over
+    fn add_tags(&self, data: Vec<DataPoint>) -> Vec<DataPoint> {
+        let global_tags = &self.influx_config.global_tags;
+        if global_tags.is_empty() {
+            return data;
+        }
+        data.into_iter()
+            .map(|point| line_protocol::with_tags(point, global_tags))
+            .collect()
+    }
 
-                info!("Got {} previous activity ids", ids.len());
-                ids
+    // with 'add_calendar_tags', tags a point with the calendar date of its 'timestamp'.
+    // 'local' for points that are a moment in time, which fall on their local date, rather
+    // than daily values (or local times) already stored as if they were UTC
+    fn calendar(&self, point: DataPointBuilder, timestamp: i64, local: bool) -> DataPointBuilder {
+        if !self.influx_config.add_calendar_tags {
+            return point;
+        }
+        calendar_tags(timestamp, local)
+            .into_iter()
+            .fold(point, |point, (key, value)| point.tag(key, value))
+    }
+
+    // holds datapoints until there's a full 'batch_size' of them, so a large
+    // archive doesn't turn into thousands of small writes
+    async fn queue_data(&mut self, data: Vec<DataPoint>) {
+        self.queued_points += data.len();
+        self.pending_points.extend(data);
+        let batch_size = self.influx_config.batch_size;
+        if batch_size == 0 {
+            self.flush_data().await;
+            return;
+        }
+        while self.pending_points.len() >= batch_size {
+            let batch: Vec<DataPoint> = self.pending_points.drain(..batch_size).collect();
+            self.write_batch(batch).await;
+        }
+    }
+
+    // queue_data() for the datapoints of 'filename', whose 'hash' (if any) is only
+    // recorded in the upload state once every one of them is written
+    async fn queue_file(
+        &mut self,
+        filename: &str,
+        hash: Option<(String, String)>,
+        data: Vec<DataPoint>,
+    ) {
+        self.pending_files.push(PendingFile {
+            filename: String::from(filename),
+            hash,
+            first_point: self.queued_points,
+            last_point: self.queued_points + data.len(),
+            failed: false,
+        });
+        self.queue_data(data).await;
+        self.settle_files();
+    }
+
+    // writes whatever is left over from queue_data()
+    async fn flush_data(&mut self) -> bool {
+        if self.pending_points.is_empty() {
+            return true;
+        }
+        let batch = std::mem::take(&mut self.pending_points);
+        self.write_batch(batch).await
+    }
+
+    // writes the next batch of queued points, marking the files it held as failed if
+    // that doesn't work out
+    async fn write_batch(&mut self, batch: Vec<DataPoint>) -> bool {
+        let start = self.written_points;
+        self.written_points += batch.len();
+        let end = self.written_points;
+        let written = self.write_data(batch).await;
+        if !written {
+            for file in self
+                .pending_files
+                .iter_mut()
+                .filter(|file| file.first_point < end && file.last_point > start)
+            {
+                file.failed = true;
             }
-            None => {
-                warn!("InfluxDb client not configured yet!");
-                if !self.build_client() {
-                    return vec![];
-                }
-                return self.get_activity_ids().await;
+        }
+        self.settle_files();
+        written
+    }
+
+    // records every pending file whose points have all been written as uploaded
+    fn settle_files(&mut self) {
+        let written_points = self.written_points;
+        let (done, pending): (Vec<PendingFile>, Vec<PendingFile>) =
+            std::mem::take(&mut self.pending_files)
+                .into_iter()
+                .partition(|file| file.last_point <= written_points);
+        self.pending_files = pending;
+        for file in done {
+            if file.failed {
+                warn!(
+                    "Not all of {} was written, it'll be uploaded again next time",
+                    file.filename
+                );
+                continue;
             }
+            if let Some((key, hash)) = file.hash {
+                self.upload_state.record(key, hash);
+            }
+            let path = fs::canonicalize(&file.filename).unwrap_or(PathBuf::from(file.filename));
+            self.uploaded_files.insert(path);
         }
     }
 
-    #[async_recursion]
-    async fn write_data(&mut self, data: Vec<DataPoint>) -> bool {
-        match self.influx_client.as_ref() {
-            Some(client) => {
-                let num = data.len();
+    // every file directly within 'folder', or with upload_files() just the given ones
+    fn files_in(&self, folder: &Path) -> Vec<PathBuf> {
+        let Some(files) = &self.stream_files else {
+            return folder
+                .read_dir()
+                .unwrap_or_else(|_| panic!("Could not open folder {:?} for reading", folder))
+                .flatten()
+                .map(|entry| entry.path())
+                .collect();
+        };
+        // the download and upload configs needn't spell file_base_path the same way
+        let Ok(folder) = fs::canonicalize(folder) else {
+            return Vec::new();
+        };
+        files
+            .iter()
+            .filter(|file| file.parent() == Some(folder.as_path()))
+            .cloned()
+            .collect()
+    }
 
-                match client
-                    .write(&self.influx_config.bucket, stream::iter(data))
-                    .await
-                {
-                    Ok(_) => {
-                        info!("Published {} datapoints!", num);
-                        return true;
-                    }
-                    Err(e) => {
-                        error!("Unable to write data point(s): {:?}", e);
-                        return false;
-                    }
-                }
+    // the configured measurement name for one of measurements::DATASETS
+    fn measurement<'a>(&'a self, dataset: &'a str) -> &'a str {
+        measurements::resolve(&self.influx_config.measurement_names, dataset)
+    }
+
+    fn upload_state_file(&self) -> PathBuf {
+        if self.influx_config.upload_state_file.is_empty() {
+            Path::new(&self.influx_config.file_base_path).join(UPLOAD_STATE_FILE)
+        } else {
+            PathBuf::from(&self.influx_config.upload_state_file)
+        }
+    }
+
+    fn offline_file(&self) -> PathBuf {
+        if self.influx_config.offline_file.is_empty() {
+            Path::new(&self.influx_config.file_base_path).join("garmin_offline.lp")
+        } else {
+            PathBuf::from(&self.influx_config.offline_file)
+        }
+    }
+
+    // appends points to the offline line protocol file, so they can be imported with `influx write --file` later
+    fn write_offline(&self, data: &[DataPoint]) -> bool {
+        let path = self.offline_file();
+        match line_protocol::append_to_file(&path, data) {
+            Ok(()) => {
+                info!("Wrote {} datapoints to {}", data.len(), path.display());
+                true
             }
-            None => {
-                warn!("InfluxDb client not configured yet!");
-                if !self.build_client() {
-                    return false;
-                }
-                return self.write_data(data).await;
+            Err(e) => {
+                error!(
+                    "Unable to write data point(s) to {}, error: {}",
+                    path.display(),
+                    e
+                );
+                false
             }
         }
     }
 
-    fn get_extension_from_filename<'a>(&'a self, filename: &'a str) -> Option<&str> {
+    // the configured 'activity_summary_fields', or the defaults if there aren't any
+    fn activity_summary_fields(&self) -> Vec<SummaryField> {
+        if !self.influx_config.activity_summary_fields.is_empty() {
+            return self.influx_config.activity_summary_fields.clone();
+        }
+        DEFAULT_ACTIVITY_SUMMARY_FIELDS
+            .iter()
+            .map(|(key, field_type)| SummaryField {
+                key: String::from(*key),
+                field_type: *field_type,
+            })
+            .collect()
+    }
+
+    fn get_extension_from_filename<'a>(&'a self, filename: &'a str) -> Option<&'a str> {
         Path::new(filename).extension().and_then(OsStr::to_str)
     }
 
@@ -174,455 +1003,1481 @@ impl UploadManager {
         }
     }
 
-    async fn upload_activity_info(&mut self, prev_ids: &Vec<String>) {
+    async fn upload_activity_info(&mut self, prev_ids: &[String]) {
         let base_path = String::from(&self.influx_config.file_base_path);
         let folder = Path::new(&base_path).join("activities");
         if !folder.exists() {
             warn!("Folder {} does not exist!", folder.display());
             return;
         }
-        for entry in folder
-            .read_dir()
-            .expect(&format!("Could not open folder {:?} for reading", folder))
-        {
-            if let Ok(entry) = entry {
-                let filename: String = String::from(entry.path().to_str().unwrap());
-                if self.get_extension_from_filename(&filename) == Some("json") {
-                    match File::open(entry.path()) {
-                        Ok(file) => {
-                            let reader = BufReader::new(file);
-                            let activity: HashMap<String, serde_json::Value> =
-                                serde_json::from_reader(reader).unwrap();
-                            let activity_data = &activity["summaryDTO"];
-                            let activity_id = &activity["activityId"].to_string().replace('"', "");
-
-                            let timestamp = self.garmin_ts_to_nanos_since_epoch(
-                                activity_data["startTimeLocal"].as_str().unwrap(),
-                            );
-
-                            if prev_ids.contains(&activity_id) {
-                                if !self.influx_config.override_activites {
-                                    info!("Id {} already exists, not overriding...", activity_id);
-                                    continue;
-                                }
+        let activity_gear = self.get_activity_gear();
+        for path in self.files_in(&folder) {
+            let filename: String = String::from(path.to_str().unwrap());
+            if self.get_extension_from_filename(&filename) == Some("json") {
+                match File::open(&path) {
+                    Ok(file) => {
+                        let reader = BufReader::new(file);
+                        let activity: serde_json::Value = match serde_json::from_reader(reader) {
+                            Ok(activity) => activity,
+                            Err(e) => {
+                                self.skip_file(&filename, &e.to_string());
+                                continue;
                             }
+                        };
+                        // Garmin changes these responses every now and then
+                        if let Some(key) = find_missing_key(&activity, &REQUIRED_ACTIVITY_KEYS) {
+                            self.skip_file(&filename, &format!("missing {}", key));
+                            continue;
+                        }
+                        let activity_data = &activity["summaryDTO"];
+                        let activity_id = &activity["activityId"].to_string().replace('"', "");
 
-                            let mut data = DataPoint::builder("activity_summary")
-                                .tag(
-                                    "activityName",
-                                    activity["activityTypeDTO"]["typeKey"]
-                                        .to_string()
-                                        .replace('"', ""),
-                                )
-                                .tag("activityId", activity_id)
-                                .field(
-                                    "name",
-                                    activity["activityName"].to_string().replace('"', ""),
-                                );
-
-                            if let Some(float) =
-                                self.search_for_float(activity_data, "activityTrainingLoad")
-                            {
-                                data = data.field("activityTrainingLoad", float);
-                            }
-                            if let Some(float) =
-                                self.search_for_float(activity_data, "anaerobicTrainingEffect")
-                            {
-                                data = data.field("anaerobicTrainingEffect", float);
-                            }
-                            if let Some(float) = self.search_for_float(activity_data, "averageHR") {
-                                data = data.field("averageHR", float);
-                            }
-                            if let Some(float) =
-                                self.search_for_float(activity_data, "averageSpeed")
-                            {
-                                data = data.field("averageSpeed", float);
-                            }
-                            if let Some(float) =
-                                self.search_for_float(activity_data, "avgRespirationRate")
-                            {
-                                data = data.field("avgRespirationRate", float);
-                            }
-                            if let Some(float) = self.search_for_float(activity_data, "bmrCalories")
-                            {
-                                data = data.field("bmrCalories", float);
-                            }
-                            if let Some(float) = self.search_for_float(activity_data, "calories") {
-                                data = data.field("calories", float);
-                            }
-                            if let Some(float) = self.search_for_float(activity_data, "distance") {
-                                data = data.field("distance", float);
-                            }
-                            if let Some(float) = self.search_for_float(activity_data, "duration") {
-                                data = data.field("duration", float);
-                            }
-                            if let Some(float) =
-                                self.search_for_float(activity_data, "elapsedDuration")
-                            {
-                                data = data.field("elapsedDuration", float);
-                            }
-                            if let Some(float) = self.search_for_float(activity_data, "maxHR") {
-                                data = data.field("maxHR", float);
-                            }
-                            if let Some(float) =
-                                self.search_for_float(activity_data, "maxRespirationRate")
-                            {
-                                data = data.field("maxRespirationRate", float);
-                            }
-                            if let Some(float) =
-                                self.search_for_float(activity_data, "minActivityLapDuration")
-                            {
-                                data = data.field("minActivityLapDuration", float);
-                            }
-                            if let Some(float) =
-                                self.search_for_float(activity_data, "minRespirationRate")
-                            {
-                                data = data.field("minRespirationRate", float);
-                            }
-                            if let Some(float) =
-                                self.search_for_float(activity_data, "movingDuration")
-                            {
-                                data = data.field("movingDuration", float);
-                            }
-                            if let Some(float) =
-                                self.search_for_float(activity_data, "trainingEffect")
-                            {
-                                data = data.field("trainingEffect", float);
-                            }
+                        let Some(start_time) = activity_data["startTimeLocal"].as_str() else {
+                            self.skip_file(&filename, "startTimeLocal isn't a timestamp");
+                            continue;
+                        };
+                        let timestamp = self.garmin_ts_to_nanos_since_epoch(start_time);
 
-                            if let Some(int) = self.search_for_i64(activity_data, "steps") {
-                                data = data.field("steps", int);
-                            }
-                            if let Some(int) =
-                                self.search_for_i64(activity_data, "moderateIntensityMinutes")
-                            {
-                                data = data.field("moderateIntensityMinutes", int);
-                            }
-                            if let Some(int) =
-                                self.search_for_i64(activity_data, "vigorousIntensityMinutes")
-                            {
-                                data = data.field("vigorousIntensityMinutes", int);
-                            }
+                        if prev_ids.contains(activity_id) && !self.influx_config.override_activites
+                        {
+                            info!("Id {} already exists, not overriding...", activity_id);
+                            continue;
+                        }
 
-                            self.write_data(vec![data.timestamp(timestamp).build().unwrap()])
-                                .await;
+                        let mut data = DataPoint::builder(self.measurement("activity_summary"))
+                            .tag(
+                                "activityName",
+                                activity["activityTypeDTO"]["typeKey"]
+                                    .to_string()
+                                    .replace('"', ""),
+                            )
+                            .tag("activityId", activity_id);
+                        if let Some(gear) = activity_gear.get(activity_id) {
+                            data = data.tag("gear", gear);
                         }
-                        Err(e) => {
-                            error!("Failed to open file {:?}, error: {}", entry.path(), e);
+                        if let Some(name) = activity["activityName"].as_str() {
+                            data = data.field("name", name);
+                        }
+
+                        for field in self.activity_summary_fields() {
+                            let key = field.key;
+                            data = match field.field_type {
+                                SummaryFieldType::Float => {
+                                    match self.search_for_float(activity_data, &key) {
+                                        Some(float) => data.field(key, float),
+                                        None => data,
+                                    }
+                                }
+                                SummaryFieldType::Int => {
+                                    match self.search_for_i64(activity_data, &key) {
+                                        Some(int) => data.field(key, int),
+                                        None => data,
+                                    }
+                                }
+                            };
                         }
+
+                        let data = self.calendar(data, timestamp, false);
+                        self.queue_file(
+                            &filename,
+                            None,
+                            vec![data.timestamp(timestamp).build().unwrap()],
+                        )
+                        .await;
+                        self.processed_files += 1;
+                    }
+                    Err(e) => {
+                        error!("Failed to open file {:?}, error: {}", path, e);
                     }
                 }
             }
         }
     }
 
-    async fn upload_activity_details(&mut self, prev_ids: &Vec<String>) {
+    // maps activity ID -> gear display name(s), from files saved under <base>/gear
+    fn get_activity_gear(&self) -> HashMap<String, String> {
+        let mut activity_gear: HashMap<String, String> = HashMap::new();
         let base_path = String::from(&self.influx_config.file_base_path);
-        let folder = Path::new(&base_path).join("activities");
-        if !folder.exists() {
-            warn!("Folder {} does not exist!", folder.display());
-            return;
+        let folder = Path::new(&base_path).join("gear");
+        let Ok(entries) = folder.read_dir() else {
+            // gear downloads are optional
+            return activity_gear;
+        };
+        let re = Regex::new(r"-(\d+)\.json$").unwrap();
+        for entry in entries.flatten() {
+            let filename: String = String::from(entry.path().to_str().unwrap());
+            let Some((_, [id])) = re.captures_iter(&filename).map(|c| c.extract()).next() else {
+                warn!("Unable to parse activity id in gear filename: {}", filename);
+                continue;
+            };
+            let gear: serde_json::Value = match File::open(entry.path()) {
+                Ok(file) => match serde_json::from_reader(BufReader::new(file)) {
+                    Ok(gear) => gear,
+                    Err(e) => {
+                        warn!("Skipping corrupt gear file {}, error: {}", filename, e);
+                        continue;
+                    }
+                },
+                Err(e) => {
+                    error!("Failed to open file {:?}, error: {}", entry.path(), e);
+                    continue;
+                }
+            };
+            let names: Vec<String> = gear
+                .as_array()
+                .map(|gear| {
+                    gear.iter()
+                        .filter_map(|g| {
+                            g["displayName"]
+                                .as_str()
+                                .or(g["customMakeModel"].as_str())
+                                .map(String::from)
+                        })
+                        .collect()
+                })
+                .unwrap_or_default();
+            if !names.is_empty() {
+                activity_gear.insert(String::from(id), names.join(","));
+            }
         }
-        for entry in folder
-            .read_dir()
-            .expect(&format!("Could not open folder {:?} for reading", folder))
-        {
-            if let Ok(entry) = entry {
-                let filename: String = String::from(entry.path().to_str().unwrap());
-                if self.get_extension_from_filename(&filename) == Some("fit") {
-                    // we could use the below mapping to filter out fields for certain record kinds,
-                    // but for now we'll scrape ALL valid fields and upload to DB.
-                    // let msp_field_mapping: HashMap<&str, HashSet<&str>> = msg_type_map::get_map();
-                    let activity_id = self.get_activity_id_from_filename(&filename);
-                    if prev_ids.contains(&activity_id) {
-                        if !self.influx_config.override_activites {
-                            info!("Id {} already exists, not overriding...", activity_id);
-                            continue;
-                        }
+        activity_gear
+    }
+
+    // one 'splits' point per lap of each activity, from files saved under <base>/splits
+    async fn upload_splits(&mut self) {
+        let base_path = String::from(&self.influx_config.file_base_path);
+        let folder = Path::new(&base_path).join("splits");
+        if !folder.exists() {
+            // splits downloads are optional
+            return;
+        }
+        let re = Regex::new(r"-(\d+)\.json$").unwrap();
+        for path in self.files_in(&folder) {
+            let filename: String = String::from(path.to_str().unwrap());
+            let Some((_, [id])) = re.captures_iter(&filename).map(|c| c.extract()).next() else {
+                continue;
+            };
+            let splits: serde_json::Value = match File::open(&path) {
+                Ok(file) => match serde_json::from_reader(BufReader::new(file)) {
+                    Ok(splits) => splits,
+                    Err(e) => {
+                        self.skip_file(&filename, &e.to_string());
+                        continue;
                     }
+                },
+                Err(e) => {
+                    error!("Failed to open file {:?}, error: {}", path, e);
+                    continue;
+                }
+            };
+            self.processed_files += 1;
 
-                    self.parse_fit_file(
-                        &filename,
-                        "activity_details",
-                        Some(vec![("activityId".to_string(), activity_id)]),
-                    )
-                    .await;
+            let mut data: Vec<DataPoint> = Vec::new();
+            for (index, lap) in splits["lapDTOs"]
+                .as_array()
+                .into_iter()
+                .flatten()
+                .enumerate()
+            {
+                let (Some(start), Some(distance), Some(duration)) = (
+                    lap["startTimeGMT"].as_str(),
+                    self.search_for_float(lap, "distance"),
+                    self.search_for_float(lap, "duration"),
+                ) else {
+                    continue;
+                };
+                let split = self
+                    .search_for_i64(lap, "lapIndex")
+                    .unwrap_or(index as i64 + 1);
+                let mut point = DataPoint::builder(self.measurement("splits"))
+                    .tag("activityId", id)
+                    .tag("split", split.to_string())
+                    .field("distance", distance)
+                    .field("duration", duration);
+                // seconds per km, undefined for laps without any distance
+                if distance > 0.0 {
+                    point = point.field("pace", duration / (distance / 1000.0));
                 }
+                for key in ["averageSpeed", "averageHR", "maxHR", "elevationGain"] {
+                    if let Some(value) = self.search_for_float(lap, key) {
+                        point = point.field(key, value);
+                    }
+                }
+                let timestamp = self.garmin_ts_to_nanos_since_epoch(start);
+                let point = self.calendar(point, timestamp, true);
+                data.push(point.timestamp(timestamp).build().unwrap());
+            }
+            if data.is_empty() {
+                info!("No splits in file: {}", filename);
+                continue;
             }
+            self.queue_file(&filename, None, data).await;
         }
     }
 
-    fn get_activity_id_from_filename<'a>(&self, filename: &'a str) -> String {
-        let re = Regex::new(r".*[\/|\\](\d+)_ACTIVITY\.fit").unwrap();
-        for (_, [id]) in re.captures_iter(filename).map(|c| c.extract()) {
-            return String::from(id);
+    // one 'activity_weather' point per activity, from files saved under <base>/weather.
+    // temperatures are in Fahrenheit and wind speeds in mph, as Garmin reports them
+    async fn upload_activity_weather(&mut self) {
+        let base_path = String::from(&self.influx_config.file_base_path);
+        let folder = Path::new(&base_path).join("weather");
+        if !folder.exists() {
+            // weather downloads are optional
+            return;
         }
-        error!("====================================================");
-        panic!("Unable to parse activity id in filename: {}", filename);
-    }
+        let re = Regex::new(r"-(\d+)\.json$").unwrap();
+        for path in self.files_in(&folder) {
+            let filename: String = String::from(path.to_str().unwrap());
+            let Some((_, [id])) = re.captures_iter(&filename).map(|c| c.extract()).next() else {
+                continue;
+            };
+            let weather: serde_json::Value = match File::open(&path) {
+                Ok(file) => match serde_json::from_reader(BufReader::new(file)) {
+                    Ok(weather) => weather,
+                    Err(e) => {
+                        self.skip_file(&filename, &e.to_string());
+                        continue;
+                    }
+                },
+                Err(e) => {
+                    error!("Failed to open file {:?}, error: {}", path, e);
+                    continue;
+                }
+            };
+            self.processed_files += 1;
 
-    fn get_monitoring_metric_from_filename<'a>(&self, filename: &'a str) -> String {
-        let re = Regex::new(r".*[\/|\\]\d*_(.*)\.fit").unwrap();
-        for (_, [metric]) in re.captures_iter(filename).map(|c| c.extract()) {
-            return String::from(metric);
+            // e.g. "2024-02-01T06:00:00.000+0000", absent for indoor activities
+            let Some(issued) = weather["issueDate"]
+                .as_str()
+                .and_then(|date| DateTime::parse_from_str(date, "%Y-%m-%dT%H:%M:%S%.f%z").ok())
+            else {
+                info!("No weather in file: {}", filename);
+                continue;
+            };
+            let mut point =
+                DataPoint::builder(self.measurement("activity_weather")).tag("activityId", id);
+            let mut has_fields = false;
+            for key in [
+                "temp",
+                "apparentTemp",
+                "dewPoint",
+                "relativeHumidity",
+                "windSpeed",
+                "windGust",
+                "windDirection",
+            ] {
+                if let Some(value) = self.search_for_float(&weather, key) {
+                    point = point.field(key, value);
+                    has_fields = true;
+                }
+            }
+            if !has_fields {
+                info!("No weather in file: {}", filename);
+                continue;
+            }
+            if let Some(conditions) = weather["weatherTypeDTO"]["desc"].as_str() {
+                point = point.field("conditions", conditions);
+            }
+            let timestamp = issued.timestamp_nanos_opt().unwrap();
+            let point = self.calendar(point, timestamp, true);
+            self.queue_file(
+                &filename,
+                None,
+                vec![point.timestamp(timestamp).build().unwrap()],
+            )
+            .await;
         }
-        error!("====================================================");
-        panic!(
-            "Unable to parse monitoring metrics in filename: {}",
-            filename
-        );
     }
 
-    fn upload_sleep(&mut self) {
+    fn activity_detail_files(&self, prev_ids: &[String]) -> Vec<FitUpload> {
         let base_path = String::from(&self.influx_config.file_base_path);
-        let folder = Path::new(&base_path).join("sleep");
+        let folder = Path::new(&base_path).join("activities");
+        let mut files: Vec<FitUpload> = Vec::new();
         if !folder.exists() {
             warn!("Folder {} does not exist!", folder.display());
-            return;
+            return files;
         }
-        for entry in folder
-            .read_dir()
-            .expect(&format!("Could not open folder {:?} for reading", folder))
-        {
-            if let Ok(entry) = entry {
-                match File::open(entry.path()) {
-                    Ok(_file) => {
-                        // let reader = BufReader::new(file);
-                        // let sleep: HashMap<String, serde_json::Value> = serde_json::from_reader(reader).unwrap();
-
-                        // let restless_moments = json!(sleep["sleepRestlessMoments"]);
-                        // let sleep_levels = json!(sleep["sleepLevels"]);
-                        // let hrv = json!(sleep["hrv"]);
-                        // let sleep_stress = json!(sleep["sleepStress"]);
-                        // let sleep_movement = json!(sleep["sleepMovement"]);
-                    }
-                    Err(e) => {
-                        error!(
-                            "Unable to open file: {}, error: {:?}",
-                            entry.path().to_str().unwrap(),
-                            e
-                        )
-                    }
+        for path in self.files_in(&folder) {
+            let filename: String = String::from(path.to_str().unwrap());
+            if self.get_extension_from_filename(&filename) == Some("fit") {
+                let Some(activity_id) = prune::activity_id_from_filename(&path) else {
+                    warn!(
+                        "Unable to parse activity id in filename: {}, skipping",
+                        filename
+                    );
+                    continue;
+                };
+                if prev_ids.contains(&activity_id) && !self.influx_config.override_activites {
+                    info!("Id {} already exists, not overriding...", activity_id);
+                    continue;
                 }
+
+                files.push(FitUpload {
+                    filename,
+                    measurement: "activity_details",
+                    tags: vec![("activityId".to_string(), activity_id)],
+                    tag_device: false,
+                    hash: None,
+                });
             }
         }
+        files
+    }
+
+    // e.g. "WELLNESS" for <path>/1234_WELLNESS.fit, None for a name without one
+    fn get_monitoring_metric_from_filename(&self, filename: &str) -> Option<String> {
+        let re = Regex::new(r".*[\/|\\]\d*_(.*)\.fit").unwrap();
+        let (_, [metric]) = re.captures_iter(filename).map(|c| c.extract()).next()?;
+        Some(String::from(metric))
     }
-    fn upload_heart_rate_data(&mut self) {
+
+    // one 'sleep' point per night from its dailySleepDTO, plus (with 'upload_sleep_spo2')
+    // a 'spo2' point per overnight pulse ox reading
+    async fn upload_sleep(&mut self) {
         let base_path = String::from(&self.influx_config.file_base_path);
-        let folder = Path::new(&base_path).join("heartrate");
+        let folder = Path::new(&base_path).join("sleep");
         if !folder.exists() {
             warn!("Folder {} does not exist!", folder.display());
             return;
         }
-        for entry in folder
-            .read_dir()
-            .expect(&format!("Could not open folder {:?} for reading", folder))
-        {
-            if let Ok(entry) = entry {
-                warn!(
-                    "Currently unable to parse summary json. File: {:?}",
-                    entry.path()
+        for path in self.files_in(&folder) {
+            let filename: String = String::from(path.to_str().unwrap());
+            if self.get_extension_from_filename(&filename) != Some("json") {
+                continue;
+            }
+            let sleep: serde_json::Value = match fs::read_to_string(&filename)
+                .map_err(serde_json::Error::io)
+                .and_then(|contents| serde_json::from_str(&contents))
+            {
+                Ok(sleep) => sleep,
+                Err(e) => {
+                    self.skip_file(&filename, &e.to_string());
+                    continue;
+                }
+            };
+
+            let mut data: Vec<DataPoint> = Vec::new();
+            let summary = &sleep["dailySleepDTO"];
+            // days without any recorded sleep have no start time
+            if let Some(start) = self.search_for_i64(summary, "sleepStartTimestampGMT") {
+                let timestamp = start * 1_000_000;
+                let mut point = self.calendar(
+                    DataPoint::builder(self.measurement("sleep")),
+                    timestamp,
+                    true,
                 );
+                point = point.timestamp(timestamp);
+                for key in [
+                    "sleepTimeSeconds",
+                    "deepSleepSeconds",
+                    "lightSleepSeconds",
+                    "remSleepSeconds",
+                    "awakeSleepSeconds",
+                    "averageSpO2Value",
+                    "lowestSpO2Value",
+                    "averageRespirationValue",
+                    "avgSleepStress",
+                ] {
+                    if let Some(value) = self.search_for_float(summary, key) {
+                        point = point.field(key, value);
+                    }
+                }
+                if let Some(score) = summary["sleepScores"]["overall"]["value"].as_f64() {
+                    point = point.field("sleepScore", score);
+                }
+                // e.g. "NONE", "LOW"
+                if let Some(severity) = sleep["breathingDisruptionSeverity"]
+                    .as_str()
+                    .or_else(|| summary["breathingDisruptionSeverity"].as_str())
+                {
+                    point = point.field("breathingDisruptionSeverity", severity.to_string());
+                }
+                if let Ok(point) = point.build() {
+                    data.push(point);
+                }
+            }
+
+            if self.influx_config.upload_sleep_spo2 {
+                for reading in sleep["wellnessEpochSPO2DataDTOList"]
+                    .as_array()
+                    .into_iter()
+                    .flatten()
+                {
+                    let (Some(time), Some(value)) = (
+                        reading["epochTimestamp"].as_str(),
+                        self.search_for_float(reading, "spo2Reading"),
+                    ) else {
+                        continue;
+                    };
+                    let timestamp = self.garmin_ts_to_nanos_since_epoch(time);
+                    let point = DataPoint::builder(self.measurement("spo2"))
+                        .field("spo2Reading", value)
+                        .timestamp(timestamp);
+                    data.push(self.calendar(point, timestamp, true).build().unwrap());
+                }
+            }
+
+            if data.is_empty() {
+                warn!("No sleep data found in file: {}", filename);
+                continue;
             }
+            self.queue_file(&filename, None, data).await;
+            self.processed_files += 1;
         }
     }
-    fn upload_weight_data(&mut self) {
+
+    // one 'spo2' point per all-day pulse ox reading, the same field as the overnight
+    // readings from upload_sleep(), so readings in both files overwrite each other
+    async fn upload_spo2(&mut self) {
         let base_path = String::from(&self.influx_config.file_base_path);
-        let folder = Path::new(&base_path).join("weight");
+        let folder = Path::new(&base_path).join("spo2");
         if !folder.exists() {
             warn!("Folder {} does not exist!", folder.display());
             return;
         }
-        for entry in folder
-            .read_dir()
-            .expect(&format!("Could not open folder {:?} for reading", folder))
-        {
-            if let Ok(entry) = entry {
-                warn!(
-                    "Currently unable to parse summary json. File: {:?}",
-                    entry.path()
-                );
+        for path in self.files_in(&folder) {
+            let filename: String = String::from(path.to_str().unwrap());
+            if self.get_extension_from_filename(&filename) != Some("json") {
+                continue;
+            }
+            match File::open(&path) {
+                Ok(file) => {
+                    let reader = BufReader::new(file);
+                    let spo2: serde_json::Value = match serde_json::from_reader(reader) {
+                        Ok(spo2) => spo2,
+                        Err(e) => {
+                            self.skip_file(&filename, &e.to_string());
+                            continue;
+                        }
+                    };
+                    self.processed_files += 1;
+
+                    // [timestamp (ms), reading] pairs, with a null reading when there was none
+                    let data: Vec<DataPoint> = spo2["spo2ValuesArray"]
+                        .as_array()
+                        .into_iter()
+                        .flatten()
+                        .filter_map(|reading| {
+                            let timestamp = reading.get(0)?.as_i64()?;
+                            let value = reading.get(1)?.as_f64()?;
+                            let point = DataPoint::builder(self.measurement("spo2"))
+                                .field("spo2Reading", value)
+                                .timestamp(timestamp * 1_000_000);
+                            self.calendar(point, timestamp * 1_000_000, true)
+                                .build()
+                                .ok()
+                        })
+                        .collect();
+                    // days without pulse ox data come back as null (or without any readings)
+                    if data.is_empty() {
+                        info!("No SpO2 data in file: {}", filename);
+                        continue;
+                    }
+                    self.queue_file(&filename, None, data).await;
+                }
+                Err(e) => {
+                    error!(
+                        "Unable to open file: {}, error: {:?}",
+                        path.to_str().unwrap(),
+                        e
+                    )
+                }
             }
         }
     }
 
-    fn upload_summary_data(&mut self) {
+    async fn upload_hrv(&mut self) {
         let base_path = String::from(&self.influx_config.file_base_path);
-        let folder = Path::new(&base_path).join("day_summary");
+        let folder = Path::new(&base_path).join("hrv");
         if !folder.exists() {
             warn!("Folder {} does not exist!", folder.display());
             return;
         }
-        for entry in folder
-            .read_dir()
-            .expect(&format!("Could not open folder {:?} for reading", folder))
-        {
-            if let Ok(entry) = entry {
-                warn!(
-                    "Currently unable to parse summary json. File: {:?}",
-                    entry.path()
-                );
+        for path in self.files_in(&folder) {
+            let filename: String = String::from(path.to_str().unwrap());
+            if self.get_extension_from_filename(&filename) != Some("json") {
+                continue;
+            }
+            match File::open(&path) {
+                Ok(file) => {
+                    let reader = BufReader::new(file);
+                    let hrv: serde_json::Value = match serde_json::from_reader(reader) {
+                        Ok(hrv) => hrv,
+                        Err(e) => {
+                            self.skip_file(&filename, &e.to_string());
+                            continue;
+                        }
+                    };
+
+                    let mut data: Vec<DataPoint> = Vec::new();
+                    if let Some(readings) = hrv["hrvReadings"].as_array() {
+                        for reading in readings {
+                            let (Some(time), Some(value)) = (
+                                reading["readingTimeLocal"].as_str(),
+                                self.search_for_float(reading, "hrvValue"),
+                            ) else {
+                                continue;
+                            };
+                            let timestamp = self.garmin_ts_to_nanos_since_epoch(time);
+                            let point = DataPoint::builder(self.measurement("hrv"))
+                                .field("hrvValue", value)
+                                .timestamp(timestamp);
+                            data.push(self.calendar(point, timestamp, false).build().unwrap());
+                        }
+                    }
+                    if data.is_empty() {
+                        warn!("No HRV readings found in file: {}", filename);
+                        continue;
+                    }
+                    self.queue_file(&filename, None, data).await;
+                    self.processed_files += 1;
+                }
+                Err(e) => {
+                    error!(
+                        "Unable to open file: {}, error: {:?}",
+                        path.to_str().unwrap(),
+                        e
+                    )
+                }
             }
         }
     }
 
-    async fn upload_monitoring(&mut self) {
+    async fn upload_hydration(&mut self) {
         let base_path = String::from(&self.influx_config.file_base_path);
-        let folder = Path::new(&base_path).join("monitoring");
+        let folder = Path::new(&base_path).join("hydration");
         if !folder.exists() {
             warn!("Folder {} does not exist!", folder.display());
             return;
         }
-        for entry in folder
-            .read_dir()
-            .expect(&format!("Could not open folder {:?} for reading", folder))
-        {
-            if let Ok(entry) = entry {
-                let filename: String = String::from(entry.path().to_str().unwrap());
-                if self.get_extension_from_filename(&filename) == Some("fit") {
-                    // we could use the below mapping to filter out fields for certain record kinds,
-                    // but for now we'll scrape ALL valid fields and upload to DB.
-                    // let msp_field_mapping: HashMap<&str, HashSet<&str>> = msg_type_map::get_monitoring_map();
-                    let monitoring_metric = self.get_monitoring_metric_from_filename(&filename);
-                    self.parse_fit_file(
+        for path in self.files_in(&folder) {
+            let filename: String = String::from(path.to_str().unwrap());
+            if self.get_extension_from_filename(&filename) != Some("json") {
+                continue;
+            }
+            match File::open(&path) {
+                Ok(file) => {
+                    let reader = BufReader::new(file);
+                    let hydration: serde_json::Value = match serde_json::from_reader(reader) {
+                        Ok(hydration) => hydration,
+                        Err(e) => {
+                            self.skip_file(&filename, &e.to_string());
+                            continue;
+                        }
+                    };
+                    self.processed_files += 1;
+
+                    // days without any logged intake come back with null values
+                    let (Some(date), Some(value)) = (
+                        hydration["calendarDate"].as_str(),
+                        self.search_for_float(&hydration, "valueInML"),
+                    ) else {
+                        info!("No hydration data in file: {}", filename);
+                        continue;
+                    };
+                    let mut data =
+                        DataPoint::builder(self.measurement("hydration")).field("valueInML", value);
+                    if let Some(goal) = self.search_for_float(&hydration, "goalInML") {
+                        data = data.field("goalInML", goal);
+                    }
+                    let timestamp =
+                        self.garmin_ts_to_nanos_since_epoch(&format!("{} 00:00:00.000", date));
+                    let data = self.calendar(data, timestamp, false);
+                    self.queue_file(
                         &filename,
-                        "monitoring",
-                        Some(vec![("metric".to_string(), monitoring_metric)]),
+                        None,
+                        vec![data.timestamp(timestamp).build().unwrap()],
                     )
                     .await;
                 }
+                Err(e) => {
+                    error!(
+                        "Unable to open file: {}, error: {:?}",
+                        path.to_str().unwrap(),
+                        e
+                    )
+                }
             }
         }
     }
 
-    pub fn examine_fit_file_records(&self, filename: &str) {
-        // use this to print all fields in all records in a fit file. just prints them to screen.
-        let mut fp = File::open(filename).unwrap();
-        let mut record_map: HashMap<String, HashSet<String>> = HashMap::new();
-
-        for record in fitparser::from_reader(&mut fp).unwrap() {
-            let kind: &str = &record.kind().to_string();
+    // one 'floors' point per interval of the day (usually 15 minutes), at its start
+    async fn upload_floors(&mut self) {
+        let base_path = String::from(&self.influx_config.file_base_path);
+        let folder = Path::new(&base_path).join("floors");
+        if !folder.exists() {
+            warn!("Folder {} does not exist!", folder.display());
+            return;
+        }
+        for path in self.files_in(&folder) {
+            let filename: String = String::from(path.to_str().unwrap());
+            if self.get_extension_from_filename(&filename) != Some("json") {
+                continue;
+            }
+            match File::open(&path) {
+                Ok(file) => {
+                    let reader = BufReader::new(file);
+                    let floors: serde_json::Value = match serde_json::from_reader(reader) {
+                        Ok(floors) => floors,
+                        Err(e) => {
+                            self.skip_file(&filename, &e.to_string());
+                            continue;
+                        }
+                    };
+                    self.processed_files += 1;
 
-            match record_map.get_mut(kind) {
-                Some(set) => {
-                    for field in record.fields() {
-                        set.insert(String::from(field.name()).replace('"', ""));
+                    // [startTimeGMT, endTimeGMT, floorsAscended, floorsDescended] per interval
+                    let descriptors = &floors["floorsValueDescriptorDTOList"];
+                    let start = value_index(descriptors, "startTimeGMT", 0);
+                    let ascended = value_index(descriptors, "floorsAscended", 2);
+                    let descended = value_index(descriptors, "floorsDescended", 3);
+                    let mut data: Vec<DataPoint> = Vec::new();
+                    for interval in floors["floorValuesArray"].as_array().into_iter().flatten() {
+                        let (Some(start), Some(up), Some(down)) = (
+                            interval[start].as_str(),
+                            interval[ascended].as_f64(),
+                            interval[descended].as_f64(),
+                        ) else {
+                            continue;
+                        };
+                        let timestamp = self.garmin_ts_to_nanos_since_epoch(start);
+                        let point = DataPoint::builder(self.measurement("floors"))
+                            .field("floorsAscended", up)
+                            .field("floorsDescended", down)
+                            .timestamp(timestamp);
+                        data.push(self.calendar(point, timestamp, true).build().unwrap());
                     }
-                }
-                None => {
-                    let mut set: HashSet<String> = HashSet::new();
-                    for field in record.fields() {
-                        set.insert(String::from(field.name()).replace('"', ""));
+                    if data.is_empty() {
+                        info!("No floors data in file: {}", filename);
+                        continue;
                     }
-                    record_map.insert(kind.to_string(), set);
+                    self.queue_file(&filename, None, data).await;
+                }
+                Err(e) => {
+                    error!(
+                        "Unable to open file: {}, error: {:?}",
+                        path.to_str().unwrap(),
+                        e
+                    )
                 }
             }
         }
+    }
+
+    // one 'intensity_minutes' point per interval that earned any, with the running
+    // total for the day so it's easy to see when the goal was reached
+    async fn upload_intensity_minutes(&mut self) {
+        let base_path = String::from(&self.influx_config.file_base_path);
+        let folder = Path::new(&base_path).join("intensity_minutes");
+        if !folder.exists() {
+            warn!("Folder {} does not exist!", folder.display());
+            return;
+        }
+        for path in self.files_in(&folder) {
+            let filename: String = String::from(path.to_str().unwrap());
+            if self.get_extension_from_filename(&filename) != Some("json") {
+                continue;
+            }
+            match File::open(&path) {
+                Ok(file) => {
+                    let reader = BufReader::new(file);
+                    let minutes: serde_json::Value = match serde_json::from_reader(reader) {
+                        Ok(minutes) => minutes,
+                        Err(e) => {
+                            self.skip_file(&filename, &e.to_string());
+                            continue;
+                        }
+                    };
+                    self.processed_files += 1;
 
-        for (rec_type, field_names) in record_map {
-            println!("{}: {:?}", rec_type, field_names);
+                    // [timestamp (ms), minutes] pairs, in order
+                    let descriptors = &minutes["imValueDescriptorsDTOList"];
+                    let time = value_index(descriptors, "timestamp", 0);
+                    let value = value_index(descriptors, "value", 1);
+                    let goal = self.search_for_float(&minutes, "weeklyGoal");
+                    let mut total = 0.0;
+                    let mut data: Vec<DataPoint> = Vec::new();
+                    for interval in minutes["imValuesArray"].as_array().into_iter().flatten() {
+                        let (Some(timestamp), Some(value)) =
+                            (interval[time].as_i64(), interval[value].as_f64())
+                        else {
+                            continue;
+                        };
+                        total += value;
+                        let mut point = DataPoint::builder(self.measurement("intensity_minutes"))
+                            .field("intensityMinutes", value)
+                            .field("dailyTotal", total);
+                        if let Some(goal) = goal {
+                            point = point.field("weeklyGoal", goal);
+                        }
+                        let point = self.calendar(point, timestamp * 1_000_000, true);
+                        data.push(point.timestamp(timestamp * 1_000_000).build().unwrap());
+                    }
+                    if data.is_empty() {
+                        info!("No intensity minutes in file: {}", filename);
+                        continue;
+                    }
+                    self.queue_file(&filename, None, data).await;
+                }
+                Err(e) => {
+                    error!(
+                        "Unable to open file: {}, error: {:?}",
+                        path.to_str().unwrap(),
+                        e
+                    )
+                }
+            }
         }
     }
 
-    async fn parse_fit_file(
-        &mut self,
-        filename: &str,
-        measurement: &str,
-        tags: Option<Vec<(String, String)>>,
-    ) {
-        let mut fp = File::open(filename).unwrap();
-        let mut datapoints: Vec<DataPoint> = Vec::new();
-        let records_to_include: Vec<String> =
-            serde_json::from_value(self.influx_config.records_to_include.clone()).unwrap();
-        let mut last_timestamp: HashMap<String, i64> = HashMap::new();
+    // one 'vo2max' point per sport ("generic" for running, "cycling") with an estimate that day
+    async fn upload_vo2max(&mut self) {
+        let base_path = String::from(&self.influx_config.file_base_path);
+        let folder = Path::new(&base_path).join("vo2max");
+        if !folder.exists() {
+            warn!("Folder {} does not exist!", folder.display());
+            return;
+        }
+        for path in self.files_in(&folder) {
+            let filename: String = String::from(path.to_str().unwrap());
+            if self.get_extension_from_filename(&filename) != Some("json") {
+                continue;
+            }
+            match File::open(&path) {
+                Ok(file) => {
+                    let reader = BufReader::new(file);
+                    let days: serde_json::Value = match serde_json::from_reader(reader) {
+                        Ok(days) => days,
+                        Err(e) => {
+                            self.skip_file(&filename, &e.to_string());
+                            continue;
+                        }
+                    };
+                    self.processed_files += 1;
 
-        for record in fitparser::from_reader(&mut fp).unwrap() {
-            let kind: &str = &record.kind().to_string();
+                    let mut data: Vec<DataPoint> = Vec::new();
+                    for day in days.as_array().into_iter().flatten() {
+                        for sport in ["generic", "cycling"] {
+                            let estimate = &day[sport];
+                            let (Some(date), Some(value)) = (
+                                estimate["calendarDate"].as_str(),
+                                self.search_for_float(estimate, "vo2MaxPreciseValue"),
+                            ) else {
+                                continue;
+                            };
+                            let mut point = DataPoint::builder(self.measurement("vo2max"))
+                                .tag("sport", sport)
+                                .field("vo2MaxPreciseValue", value);
+                            if let Some(rounded) = self.search_for_float(estimate, "vo2MaxValue") {
+                                point = point.field("vo2MaxValue", rounded);
+                            }
+                            if let Some(age) = self.search_for_float(estimate, "fitnessAge") {
+                                point = point.field("fitnessAge", age);
+                            }
+                            let timestamp = self
+                                .garmin_ts_to_nanos_since_epoch(&format!("{} 00:00:00.000", date));
+                            let point = self.calendar(point, timestamp, false);
+                            data.push(point.timestamp(timestamp).build().unwrap());
+                        }
+                    }
+                    if data.is_empty() {
+                        info!("No VO2max data in file: {}", filename);
+                        continue;
+                    }
+                    self.queue_file(&filename, None, data).await;
+                }
+                Err(e) => {
+                    error!(
+                        "Unable to open file: {}, error: {:?}",
+                        path.to_str().unwrap(),
+                        e
+                    )
+                }
+            }
+        }
+    }
 
-            // ignore this entire data point if the record isn't on 'the list'
-            if !records_to_include.contains(&kind.to_string()) {
+    // one 'training_status' point per device that reported a status that day
+    async fn upload_training_status(&mut self) {
+        let base_path = String::from(&self.influx_config.file_base_path);
+        let folder = Path::new(&base_path).join("training_status");
+        if !folder.exists() {
+            warn!("Folder {} does not exist!", folder.display());
+            return;
+        }
+        for path in self.files_in(&folder) {
+            let filename: String = String::from(path.to_str().unwrap());
+            if self.get_extension_from_filename(&filename) != Some("json") {
                 continue;
             }
+            match File::open(&path) {
+                Ok(file) => {
+                    let reader = BufReader::new(file);
+                    let status: serde_json::Value = match serde_json::from_reader(reader) {
+                        Ok(status) => status,
+                        Err(e) => {
+                            self.skip_file(&filename, &e.to_string());
+                            continue;
+                        }
+                    };
+                    self.processed_files += 1;
 
-            let mut data = DataPoint::builder(measurement);
-            if let Some(ref t) = tags {
-                for (tag, value) in t {
-                    data = data.tag(tag.replace('"', ""), value.replace('"', ""));
+                    let mut data: Vec<DataPoint> = Vec::new();
+                    // keyed by device ID
+                    let devices = status["mostRecentTrainingStatus"]["latestTrainingStatusData"]
+                        .as_object()
+                        .into_iter()
+                        .flatten();
+                    for (device_id, device) in devices {
+                        let (Some(date), Some(training_status)) = (
+                            device["calendarDate"].as_str(),
+                            self.search_for_float(device, "trainingStatus"),
+                        ) else {
+                            continue;
+                        };
+                        let mut point = DataPoint::builder(self.measurement("training_status"))
+                            .tag("deviceId", device_id.as_str())
+                            .field("trainingStatus", training_status);
+                        // e.g. "PRODUCTIVE_6"
+                        if let Some(phrase) = device["trainingStatusFeedbackPhrase"].as_str() {
+                            point = point.field("trainingStatusFeedbackPhrase", phrase.to_string());
+                        }
+                        let load = &device["acuteTrainingLoadDTO"];
+                        for key in [
+                            "dailyTrainingLoadAcute",
+                            "dailyTrainingLoadChronic",
+                            "dailyAcuteChronicWorkloadRatio",
+                        ] {
+                            if let Some(value) = self.search_for_float(load, key) {
+                                point = point.field(key, value);
+                            }
+                        }
+                        let timestamp =
+                            self.garmin_ts_to_nanos_since_epoch(&format!("{} 00:00:00.000", date));
+                        let point = self.calendar(point, timestamp, false);
+                        data.push(point.timestamp(timestamp).build().unwrap());
+                    }
+                    if data.is_empty() {
+                        info!("No training status data in file: {}", filename);
+                        continue;
+                    }
+                    self.queue_file(&filename, None, data).await;
+                }
+                Err(e) => {
+                    error!(
+                        "Unable to open file: {}, error: {:?}",
+                        path.to_str().unwrap(),
+                        e
+                    )
                 }
             }
+        }
+    }
 
-            for field in record.into_vec() {
-                // grab the timestamp.
-                if field.name() == "timestamp" {
-                    match DateTime::parse_from_str(
-                        &field.value().to_string().replace('"', ""),
-                        GARMIN_FIT_DATE_FORMAT,
-                    ) {
-                        Ok(ts) => {
-                            data = data.timestamp(ts.timestamp_nanos_opt().unwrap());
-                            last_timestamp.insert(kind.to_string(), ts.timestamp());
-                        }
+    // one 'resting_heart_rate' point per day, at midnight of its calendarDate
+    async fn upload_heart_rate_data(&mut self) {
+        let base_path = String::from(&self.influx_config.file_base_path);
+        let folder = Path::new(&base_path).join("heartrate");
+        if !folder.exists() {
+            warn!("Folder {} does not exist!", folder.display());
+            return;
+        }
+        for path in self.files_in(&folder) {
+            let filename: String = String::from(path.to_str().unwrap());
+            if self.get_extension_from_filename(&filename) != Some("json") {
+                continue;
+            }
+            match File::open(&path) {
+                Ok(file) => {
+                    let reader = BufReader::new(file);
+                    let heart_rate: serde_json::Value = match serde_json::from_reader(reader) {
+                        Ok(heart_rate) => heart_rate,
                         Err(e) => {
-                            error!("Unable to parse timestamp from 'timestamp' field value: {} in record type {}. Error: {}", &field.value(), kind, e);
-                            break;
+                            self.skip_file(&filename, &e.to_string());
+                            continue;
                         }
+                    };
+                    self.processed_files += 1;
+
+                    let Some(entries) = heart_rate
+                        .pointer("/allMetrics/metricsMap/WELLNESS_RESTING_HEART_RATE")
+                        .and_then(|entries| entries.as_array())
+                    else {
+                        info!("No resting heart rate data in file: {}", filename);
+                        continue;
+                    };
+                    let mut data: Vec<DataPoint> = Vec::new();
+                    for entry in entries {
+                        // days the watch wasn't worn come back with a null value
+                        let (Some(date), Some(value)) = (
+                            entry["calendarDate"].as_str(),
+                            self.search_for_float(entry, "value"),
+                        ) else {
+                            continue;
+                        };
+                        let point = DataPoint::builder(self.measurement("resting_heart_rate"))
+                            .field("restingHeartRate", value);
+                        let timestamp =
+                            self.garmin_ts_to_nanos_since_epoch(&format!("{} 00:00:00.000", date));
+                        let point = self.calendar(point, timestamp, false);
+                        data.push(point.timestamp(timestamp).build().unwrap());
                     }
-                // for 'monitoring' records, 'timestamp_16' represents offset from last epoch timestamp
-                } else if field.name() == "timestamp_16" {
-                    let timestamp_16 = field.value().to_string().parse::<i64>().unwrap();
-                    if let Some(dt) = last_timestamp.get(&kind.to_string()) {
-                        // dt is unix epoch seconds, in GMT - convert to garmin epoch
-                        let mut garmin_date = *dt - GARMIN_EPOCH_OFFSET;
-
-                        // increase by difference of lower 2 bytes of timestamp
-                        garmin_date += (timestamp_16 - (garmin_date & 0xFFFF)) & 0xFFFF;
-
-                        // convert back to unix epoch
-                        garmin_date += GARMIN_EPOCH_OFFSET;
-                        let metric_date =
-                            NaiveDateTime::from_timestamp_opt(garmin_date, 0).unwrap();
-                        data = data.timestamp(metric_date.timestamp_nanos_opt().unwrap());
-                    }
-                // garmin represents position data as 32 bit unsigned int, so we have to divide by representation
-                // range to get actual float.
-                } else if field.name().contains("_lat") || field.name().contains("_long") {
-                    if let Ok(value) = field.value().to_string().parse::<f64>() {
-                        data =
-                            data.field(String::from(field.name()), value / GARMIN_POSITION_FACTOR);
-                    }
-                // some records have fields like 'unknown_field_X' - ignore those.
-                // some records have another field called 'local_timestamp' - just ignore those too.
-                } else if !field.name().contains("unknown") && !field.name().contains("timestamp") {
-                    if let Ok(value) = field.value().to_string().parse::<f64>() {
-                        data = data.field(String::from(field.name()), value);
+                    if data.is_empty() {
+                        info!("No resting heart rate data in file: {}", filename);
+                        continue;
                     }
+                    self.queue_file(&filename, None, data).await;
+                }
+                Err(e) => {
+                    error!(
+                        "Unable to open file: {}, error: {:?}",
+                        path.to_str().unwrap(),
+                        e
+                    )
                 }
             }
+        }
+    }
 
-            match data.build() {
-                Ok(datapoint) => {
-                    datapoints.push(datapoint);
+    // one 'weight' point per weigh-in, at the time it was taken. weight and the
+    // body composition masses are in grams, as Garmin reports them
+    async fn upload_weight_data(&mut self) {
+        let base_path = String::from(&self.influx_config.file_base_path);
+        let folder = Path::new(&base_path).join("weight");
+        if !folder.exists() {
+            warn!("Folder {} does not exist!", folder.display());
+            return;
+        }
+        for path in self.files_in(&folder) {
+            let filename: String = String::from(path.to_str().unwrap());
+            if self.get_extension_from_filename(&filename) != Some("json") {
+                continue;
+            }
+            match File::open(&path) {
+                Ok(file) => {
+                    let reader = BufReader::new(file);
+                    let weight: serde_json::Value = match serde_json::from_reader(reader) {
+                        Ok(weight) => weight,
+                        Err(e) => {
+                            self.skip_file(&filename, &e.to_string());
+                            continue;
+                        }
+                    };
+                    self.processed_files += 1;
+
+                    let mut data: Vec<DataPoint> = Vec::new();
+                    let entries = weight["dateWeightList"].as_array().into_iter().flatten();
+                    for entry in entries {
+                        let (Some(timestamp), Some(value)) = (
+                            self.search_for_i64(entry, "timestampGMT"),
+                            self.search_for_float(entry, "weight"),
+                        ) else {
+                            continue;
+                        };
+                        let mut point =
+                            DataPoint::builder(self.measurement("weight")).field("weight", value);
+                        for key in WEIGHT_FIELDS {
+                            if let Some(value) = self.search_for_float(entry, key) {
+                                point = point.field(key, value);
+                            }
+                        }
+                        let point = self.calendar(point, timestamp * 1_000_000, true);
+                        data.push(point.timestamp(timestamp * 1_000_000).build().unwrap());
+                    }
+                    if data.is_empty() {
+                        info!("No weight data in file: {}", filename);
+                        continue;
+                    }
+                    self.queue_file(&filename, None, data).await;
+                }
+                Err(e) => {
+                    error!(
+                        "Unable to open file: {}, error: {:?}",
+                        path.to_str().unwrap(),
+                        e
+                    )
                 }
-                Err(_) => {}
             }
         }
-
-        self.write_data(datapoints).await;
     }
-}
-
-#[cfg(test)]
-mod tests {
 
-    use crate::UploadManager;
-    use config::{Config, File, FileFormat};
-    use std::env::current_dir;
+    // one 'daily_summary' point per day, at midnight of its calendarDate
+    async fn upload_summary_data(&mut self) {
+        let base_path = String::from(&self.influx_config.file_base_path);
+        let folder = Path::new(&base_path).join("day_summary");
+        if !folder.exists() {
+            warn!("Folder {} does not exist!", folder.display());
+            return;
+        }
+        for path in self.files_in(&folder) {
+            let filename: String = String::from(path.to_str().unwrap());
+            if self.get_extension_from_filename(&filename) != Some("json") {
+                continue;
+            }
+            match File::open(&path) {
+                Ok(file) => {
+                    let reader = BufReader::new(file);
+                    let summary: serde_json::Value = match serde_json::from_reader(reader) {
+                        Ok(summary) => summary,
+                        Err(e) => {
+                            self.skip_file(&filename, &e.to_string());
+                            continue;
+                        }
+                    };
+                    self.processed_files += 1;
 
-    #[test]
-    fn timestamp_to_nanos_test() {
-        let config = Config::builder()
-            .add_source(File::new(
-                current_dir()
+                    let Some(date) = summary["calendarDate"].as_str() else {
+                        info!("No daily summary in file: {}", filename);
+                        continue;
+                    };
+                    let mut data = DataPoint::builder(self.measurement("daily_summary"));
+                    let mut fields = 0;
+                    for key in DAILY_SUMMARY_FIELDS {
+                        if let Some(value) = self.search_for_float(&summary, key) {
+                            data = data.field(key, value);
+                            fields += 1;
+                        }
+                    }
+                    // days the watch wasn't worn come back with null values
+                    if fields == 0 {
+                        info!("No daily summary in file: {}", filename);
+                        continue;
+                    }
+                    let timestamp =
+                        self.garmin_ts_to_nanos_since_epoch(&format!("{} 00:00:00.000", date));
+                    let data = self.calendar(data, timestamp, false);
+                    self.queue_file(
+                        &filename,
+                        None,
+                        vec![data.timestamp(timestamp).build().unwrap()],
+                    )
+                    .await;
+                }
+                Err(e) => {
+                    error!(
+                        "Unable to open file: {}, error: {:?}",
+                        path.to_str().unwrap(),
+                        e
+                    )
+                }
+            }
+        }
+    }
+
+    fn monitoring_files(&self) -> Vec<FitUpload> {
+        let base_path = String::from(&self.influx_config.file_base_path);
+        let folder = Path::new(&base_path).join("monitoring");
+        let mut files: Vec<FitUpload> = Vec::new();
+        if !folder.exists() {
+            warn!("Folder {} does not exist!", folder.display());
+            return files;
+        }
+        for path in self.files_in(&folder) {
+            let filename: String = String::from(path.to_str().unwrap());
+            if self.get_extension_from_filename(&filename) == Some("fit") {
+                let Some(monitoring_metric) = self.get_monitoring_metric_from_filename(&filename)
+                else {
+                    error!(
+                        "Unable to parse monitoring metric in filename: {}",
+                        filename
+                    );
+                    continue;
+                };
+                // monitoring files are rewritten with every download, so go by their contents
+                let key = upload_state::file_key(&monitoring_metric, &path);
+                let hash = match upload_state::file_hash(&path) {
+                    Ok(hash) => hash,
+                    Err(e) => {
+                        error!("Unable to read file: {}, error: {}", filename, e);
+                        continue;
+                    }
+                };
+                if !self.force_upload && self.upload_state.is_unchanged(&key, &hash) {
+                    info!("{} is unchanged since it was uploaded, skipping", filename);
+                    continue;
+                }
+                files.push(FitUpload {
+                    filename,
+                    measurement: "monitoring",
+                    tags: vec![("metric".to_string(), monitoring_metric)],
+                    tag_device: true,
+                    hash: Some((key, hash)),
+                });
+            }
+        }
+        files
+    }
+
+    /// Writes a summary of every activity JSON file to a CSV file at 'path', one row per
+    /// activity sorted by start time, for use in a spreadsheet. Returns the number of rows.
+    pub fn export_activities_csv(&self, path: &str) -> Result<usize, csv::Error> {
+        let folder = Path::new(&self.influx_config.file_base_path).join("activities");
+        let mut rows: Vec<[String; 8]> = Vec::new();
+        if let Ok(entries) = folder.read_dir() {
+            for entry in entries.flatten() {
+                let filename: String = String::from(entry.path().to_str().unwrap());
+                if self.get_extension_from_filename(&filename) != Some("json") {
+                    continue;
+                }
+                let activity: serde_json::Value = match fs::read_to_string(&filename)
+                    .map_err(serde_json::Error::io)
+                    .and_then(|contents| serde_json::from_str(&contents))
+                {
+                    Ok(activity) => activity,
+                    Err(e) => {
+                        warn!("Not exporting unreadable file {}: {}", filename, e);
+                        continue;
+                    }
+                };
+                let summary = &activity["summaryDTO"];
+                let float = |key: &str| {
+                    self.search_for_float(summary, key)
+                        .map(|value| value.to_string())
+                        .unwrap_or_default()
+                };
+                rows.push([
+                    self.search_for_i64(&activity, "activityId")
+                        .map(|id| id.to_string())
+                        .unwrap_or_default(),
+                    String::from(
+                        activity["activityTypeDTO"]["typeKey"]
+                            .as_str()
+                            .unwrap_or(""),
+                    ),
+                    String::from(summary["startTimeLocal"].as_str().unwrap_or("")),
+                    float("distance"),
+                    float("duration"),
+                    float("calories"),
+                    float("averageHR"),
+                    float("trainingEffect"),
+                ]);
+            }
+        } else {
+            warn!("Folder {} does not exist!", folder.display());
+        }
+        rows.sort_by(|a, b| a[2].cmp(&b[2]));
+
+        let mut writer = csv::Writer::from_path(path)?;
+        writer.write_record([
+            "activityId",
+            "type",
+            "date",
+            "distance",
+            "duration",
+            "calories",
+            "avgHR",
+            "trainingEffect",
+        ])?;
+        for row in &rows {
+            writer.write_record(row)?;
+        }
+        writer.flush()?;
+        info!("Exported {} activities to {}", rows.len(), path);
+        Ok(rows.len())
+    }
+
+    /// Maps each record kind in a FIT file to every field name seen in records of
+    /// that kind, e.g. to find out what to put in 'records_to_include'.
+    pub fn examine_fit_file_records(
+        &self,
+        filename: &str,
+    ) -> Result<HashMap<String, HashSet<String>>, String> {
+        // files moved into an archive by 'archive_downloads' are read back out of it, e.g.
+        // <file_base_path>/archive/2024-02.tar.gz/monitoring/1234_WELLNESS.fit
+        let records = match archive::split_archived_path(filename) {
+            Some((archive_path, name)) if !Path::new(filename).exists() => {
+                let data = archive::read_entry(&archive_path, &name)
+                    .map_err(|e| {
+                        format!(
+                            "Unable to read archive {}, error: {}",
+                            archive_path.display(),
+                            e
+                        )
+                    })?
+                    .ok_or_else(|| format!("No {} in archive {}", name, archive_path.display()))?;
+                read_fit_records(&mut data.as_slice())
+            }
+            _ => parse_fit_to_records(filename),
+        }
+        .map_err(|e| format!("Unable to parse {}: {}", filename, e))?;
+
+        let mut record_map: HashMap<String, HashSet<String>> = HashMap::new();
+        for record in records {
+            record_map
+                .entry(record.kind)
+                .or_default()
+                .extend(record.fields.into_keys());
+        }
+        Ok(record_map)
+    }
+
+    /// Parses and uploads a single FIT file, logging every record and field that's
+    /// written or skipped (and why), e.g. to try out a change to 'records_to_include'
+    /// without a whole upload_all(). <id>_ACTIVITY.fit files go to activity_details,
+    /// anything else to monitoring. Returns the number of datapoints written.
+    pub async fn reupload_fit_file(&mut self, filename: &str) -> Result<usize, String> {
+        let records = parse_fit_to_records(filename)
+            .map_err(|e| format!("Unable to parse {}: {}", filename, e))?;
+        info!("Read {} records from {}", records.len(), filename);
+        let activity_id = Regex::new(r"(\d+)_ACTIVITY\.fit$")
+            .unwrap()
+            .captures(filename)
+            .map(|c| String::from(&c[1]));
+        let (measurement, tags, device) = match activity_id {
+            Some(id) => (
+                "activity_details",
+                vec![(String::from("activityId"), id)],
+                None,
+            ),
+            None => {
+                // e.g. ./1234_WELLNESS.fit, whose name is only matched as part of a path
+                let path = fs::canonicalize(filename).unwrap_or_else(|_| PathBuf::from(filename));
+                let metric = self
+                    .get_monitoring_metric_from_filename(&path.to_string_lossy())
+                    .ok_or_else(|| format!("Unable to parse monitoring metric in {}", filename))?;
+                (
+                    "monitoring",
+                    vec![(String::from("metric"), metric)],
+                    device_tag(&records),
+                )
+            }
+        };
+
+        self.explain_fields = true;
+        let datapoints = self.fit_datapoints(records, measurement, &tags, device.as_deref());
+        self.explain_fields = false;
+        let num = datapoints.len();
+        if !self.write_data(datapoints).await {
+            return Err(format!("Unable to write {} datapoints", num));
+        }
+        self.sink.flush()?;
+        Ok(num)
+    }
+
+    // parses up to 'upload_concurrency' files at a time on the blocking pool while the
+    // parsed ones are written, in order. only that many parsed files are held at once.
+    async fn upload_fit_files(&mut self, files: Vec<FitUpload>) {
+        let concurrency = self.influx_config.upload_concurrency.max(1);
+        let mut parsed = stream::iter(files)
+            .map(|upload| async move {
+                let filename = upload.filename.clone();
+                let records =
+                    tokio::task::spawn_blocking(move || parse_fit_to_records(&filename)).await;
+                (upload, records)
+            })
+            .buffered(concurrency);
+
+        while let Some((upload, records)) = parsed.next().await {
+            let records = match records {
+                Ok(Ok(records)) => records,
+                Ok(Err(e)) => {
+                    self.skip_file(&upload.filename, &e.to_string());
+                    continue;
+                }
+                // the parser panicked
+                Err(e) => {
+                    self.skip_file(&upload.filename, &e.to_string());
+                    continue;
+                }
+            };
+            self.processed_files += 1;
+            let device = if upload.tag_device {
+                device_tag(&records)
+            } else {
+                None
+            };
+            let datapoints =
+                self.fit_datapoints(records, upload.measurement, &upload.tags, device.as_deref());
+            self.queue_file(&upload.filename, upload.hash, datapoints)
+                .await;
+        }
+    }
+
+    // one datapoint per included record, written to 'measurement' unless its kind is
+    // routed elsewhere by 'record_measurements', e.g. { "lap": "activity_laps" }
+    fn fit_datapoints(
+        &self,
+        records: Vec<FitRecord>,
+        measurement: &str,
+        tags: &[(String, String)],
+        device: Option<&str>,
+    ) -> Vec<DataPoint> {
+        let mut datapoints: Vec<DataPoint> = Vec::new();
+        let records_to_include: Vec<String> =
+            serde_json::from_value(self.influx_config.records_to_include.clone()).unwrap();
+        // by default every numeric field is scraped
+        let known_fields = if self.influx_config.filter_known_fields {
+            msg_type_map::get_map_for_measurement(measurement)
+        } else {
+            None
+        };
+
+        for record in records {
+            // ignore this entire data point if the record isn't on 'the list'
+            if !records_to_include.contains(&record.kind) {
+                if self.explain_fields {
+                    info!("Skipping {} record, not in records_to_include", record.kind);
+                }
+                continue;
+            }
+
+            let routed = self.influx_config.record_measurements.get(&record.kind);
+            let mut data = DataPoint::builder(
+                routed.map_or_else(|| self.measurement(measurement), String::as_str),
+            );
+            for (tag, value) in tags {
+                data = data.tag(tag.replace('"', ""), value.replace('"', ""));
+            }
+            if let Some(device) = device {
+                data = data.tag("device", device);
+            }
+            // laps of the same activity are only told apart by their index
+            if routed.is_some() && record.kind == "lap" {
+                if let Some(index) = record.fields.get("message_index") {
+                    data = data.tag("lapIndex", index.to_string());
+                }
+            }
+            // an activity has zones for the whole session and for each lap
+            if routed.is_some() && record.kind == "time_in_zone" {
+                if let Some(mesg) = record.fields.get("reference_mesg") {
+                    data = data.tag("referenceMesg", mesg.to_string());
+                }
+                if let Some(index) = record.fields.get("reference_index") {
+                    data = data.tag("referenceIndex", index.to_string());
+                }
+            }
+            if let Some(timestamp) = record.timestamp {
+                data = self.calendar(data.timestamp(timestamp), timestamp, true);
+            }
+
+            for (name, value) in &record.fields {
+                // some records have fields like 'unknown_field_X' - ignore those.
+                // some records have another field called 'local_timestamp' - just ignore those too.
+                if name.contains("unknown") || name.contains("timestamp") {
+                    if self.explain_fields {
+                        info!(
+                            "Skipping {}.{}, unknown or timestamp field",
+                            record.kind, name
+                        );
+                    }
+                    continue;
+                }
+                if let Some(known) = known_fields.as_ref() {
+                    if !known
+                        .get(record.kind.as_str())
+                        .is_some_and(|fields| fields.contains(name.as_str()))
+                    {
+                        if self.explain_fields {
+                            info!("Skipping {}.{}, not a known field", record.kind, name);
+                        }
+                        continue;
+                    }
+                }
+                let fields = to_fields(name, value, self.influx_config.integer_fit_fields);
+                if self.explain_fields {
+                    if fields.is_empty() {
+                        info!(
+                            "Skipping {}.{} = {}, no field representation",
+                            record.kind, name, value
+                        );
+                    }
+                    for (name, value) in &fields {
+                        info!("Writing {}.{} = {:?}", record.kind, name, value);
+                    }
+                }
+                for (name, value) in fields {
+                    data = data.field(name, value);
+                }
+            }
+
+            match data.build() {
+                Ok(datapoint) => datapoints.push(datapoint),
+                Err(_) if self.explain_fields => {
+                    info!("Skipping {} record, no fields left to write", record.kind)
+                }
+                Err(_) => {}
+            }
+        }
+        datapoints
+    }
+}
+
+/// Uploads the same data as UploadManager, but into a local SQLite database
+/// (the 'sqlite_file' config value) rather than InfluxDB.
+///
+/// Equivalent to an UploadManager configured with "backend": "sqlite".
+pub struct SqliteUploadManager {
+    upload_manager: UploadManager,
+}
+
+impl SqliteUploadManager {
+    pub fn new(config: Config) -> SqliteUploadManager {
+        let mut influx_config: InfluxDbConfig = config.try_deserialize().unwrap();
+        influx_config.backend = String::from("sqlite");
+        let sqlite_sink = match SqliteSink::new(&influx_config.sqlite_file) {
+            Ok(sink) => sink,
+            Err(e) => panic!(
+                "Unable to open sqlite database {}, error: {}",
+                influx_config.sqlite_file, e
+            ),
+        };
+        SqliteUploadManager {
+            upload_manager: UploadManager::from_parts(influx_config, Box::new(sqlite_sink)),
+        }
+    }
+
+    pub async fn upload_all(&mut self) {
+        self.upload_manager.upload_all().await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use crate::{
+        archive, calendar_tags, find_missing_key, line_protocol, upload_state, DataSink, FitRecord,
+        PruneRule, SqliteUploadManager, SummaryField, SummaryFieldType, UploadManager, WriteError,
+        MAX_CONSECUTIVE_WRITE_FAILURES, REQUIRED_ACTIVITY_KEYS,
+    };
+    use chrono::{Local, NaiveDate, TimeZone};
+    use config::{Config, File, FileFormat};
+    use futures::future::{self, BoxFuture};
+    use influxdb2::models::data_point::{DataPoint, FieldValue};
+    use std::collections::HashSet;
+    use std::env::current_dir;
+    use std::path::Path;
+    use std::sync::{Arc, Mutex};
+
+    // an sqlite backed manager reading from 'base_path' (created if need be) and writing
+    // to garmin.db in it, with FIT uploads off. tests change influx_config for anything else
+    fn sqlite_test_manager(base_path: &Path) -> SqliteUploadManager {
+        std::fs::create_dir_all(base_path).unwrap();
+        let config = Config::builder()
+            .add_source(File::new(
+                current_dir()
+                    .unwrap()
+                    .join("..")
+                    .join("config")
+                    .join("influxdb_config.json")
+                    .to_str()
+                    .unwrap(),
+                FileFormat::Json,
+            ))
+            .set_override("file_base_path", base_path.to_str().unwrap())
+            .unwrap()
+            .set_override("sqlite_file", base_path.join("garmin.db").to_str().unwrap())
+            .unwrap()
+            .set_override("upload_fit_files", false)
+            .unwrap()
+            .build()
+            .unwrap();
+        SqliteUploadManager::new(config)
+    }
+
+    #[test]
+    fn timestamp_to_nanos_test() {
+        let config = Config::builder()
+            .add_source(File::new(
+                current_dir()
                     .unwrap()
                     .join("..")
                     .join("config")
@@ -641,6 +2496,23 @@ mod tests {
         );
     }
 
+    #[test]
+    fn find_missing_key_test() {
+        let activity = serde_json::json!({
+            "activityId": 1234,
+            "activityTypeDTO": { "typeKey": null },
+        });
+        assert_eq!(
+            find_missing_key(&activity, &REQUIRED_ACTIVITY_KEYS),
+            Some(String::from("activityTypeDTO.typeKey"))
+        );
+        assert_eq!(
+            find_missing_key(&activity, &[&["activityId"], &["summaryDTO"]]),
+            Some(String::from("summaryDTO"))
+        );
+        assert_eq!(find_missing_key(&activity, &[&["activityId"]]), None);
+    }
+
     #[test]
     fn search_for_float_test() {
         let config = Config::builder()
@@ -703,7 +2575,49 @@ mod tests {
     }
 
     #[test]
-    fn get_activity_id_from_filename_test() {
+    fn activity_detail_files_test() {
+        let base_path =
+            std::env::temp_dir().join(format!("garmin_detail_files_test_{}", std::process::id()));
+        let activities = base_path.join("activities");
+        std::fs::create_dir_all(&activities).unwrap();
+        std::fs::write(activities.join("1234_ACTIVITY.fit"), "").unwrap();
+        std::fs::write(activities.join("5678_ACTIVITY.fit"), "").unwrap();
+        // e.g. a file copied in by hand
+        std::fs::write(activities.join("notes.fit"), "").unwrap();
+
+        let um = sqlite_test_manager(&base_path);
+        let files = um
+            .upload_manager
+            .activity_detail_files(&[String::from("5678")]);
+        let tags: Vec<&Vec<(String, String)>> = files.iter().map(|file| &file.tags).collect();
+        assert_eq!(
+            tags,
+            vec![&vec![(String::from("activityId"), String::from("1234"))]]
+        );
+
+        std::fs::remove_dir_all(&base_path).unwrap();
+    }
+
+    #[test]
+    fn calendar_tags_test() {
+        // 2024-01-01 is a Monday in ISO week 1
+        let tags = calendar_tags(1704067200000000000, false);
+        assert_eq!(
+            tags,
+            vec![
+                (String::from("isoWeek"), String::from("2024-W01")),
+                (String::from("dayOfWeek"), String::from("Mon")),
+                (String::from("month"), String::from("2024-01")),
+            ]
+        );
+        // while 2021-01-01 is still in the last ISO week of 2020
+        let tags = calendar_tags(1609459200000000000, false);
+        assert_eq!(tags[0].1, "2020-W53");
+        assert_eq!(tags[1].1, "Fri");
+    }
+
+    #[test]
+    fn get_monitoring_metric_from_filename_test() {
         let config = Config::builder()
             .add_source(File::new(
                 current_dir()
@@ -719,13 +2633,14 @@ mod tests {
             .unwrap();
         let um = UploadManager::new(config);
         assert_eq!(
-            um.get_activity_id_from_filename("./1234_ACTIVITY.fit"),
-            "1234"
+            um.get_monitoring_metric_from_filename("./1234_HRV_STATUS.fit"),
+            Some(String::from("HRV_STATUS"))
         );
+        assert_eq!(um.get_monitoring_metric_from_filename("./notes.fit"), None);
     }
 
     #[test]
-    fn get_monitoring_metric_from_filename_test() {
+    fn examine_fit_file_records_test() {
         let config = Config::builder()
             .add_source(File::new(
                 current_dir()
@@ -740,9 +2655,1894 @@ mod tests {
             .build()
             .unwrap();
         let um = UploadManager::new(config);
+        let fixture = current_dir()
+            .unwrap()
+            .join("tests")
+            .join("fixtures")
+            .join("MonitoringFile.fit");
+        let record_map = um
+            .examine_fit_file_records(fixture.to_str().unwrap())
+            .unwrap();
+        let mut kinds: Vec<&str> = record_map.keys().map(String::as_str).collect();
+        kinds.sort();
         assert_eq!(
-            um.get_monitoring_metric_from_filename("./1234_HRV_STATUS.fit"),
-            "HRV_STATUS"
+            kinds,
+            vec![
+                "device_info",
+                "event",
+                "file_id",
+                "monitoring",
+                "monitoring_info"
+            ]
         );
+        for field in ["activity_type", "cycles", "intensity", "steps"] {
+            assert!(record_map["monitoring"].contains(field), "{}", field);
+        }
+
+        let Err(e) = um.examine_fit_file_records("/nonexistent/1234_WELLNESS.fit") else {
+            panic!("expected an error examining a missing file");
+        };
+        assert!(e.starts_with("Unable to parse"));
+    }
+
+    // keeps every log message, for tests of what gets logged. shared by every test,
+    // so check for messages only the test itself would log
+    struct CapturingLogger;
+
+    static LOG_MESSAGES: Mutex<Vec<String>> = Mutex::new(Vec::new());
+
+    impl log::Log for CapturingLogger {
+        fn enabled(&self, metadata: &log::Metadata) -> bool {
+            metadata.level() <= log::Level::Info
+        }
+
+        fn log(&self, record: &log::Record) {
+            if self.enabled(record.metadata()) {
+                LOG_MESSAGES.lock().unwrap().push(record.args().to_string());
+            }
+        }
+
+        fn flush(&self) {}
+    }
+
+    fn capture_logs() {
+        if log::set_logger(&CapturingLogger).is_ok() {
+            log::set_max_level(log::LevelFilter::Info);
+        }
+    }
+
+    #[tokio::test]
+    async fn reupload_fit_file_test() {
+        capture_logs();
+        let base_path =
+            std::env::temp_dir().join(format!("garmin_reupload_fit_test_{}", std::process::id()));
+        std::fs::create_dir_all(&base_path).unwrap();
+        let path = base_path.join("1234_WELLNESS.fit");
+        std::fs::copy(
+            current_dir()
+                .unwrap()
+                .join("tests")
+                .join("fixtures")
+                .join("MonitoringFile.fit"),
+            &path,
+        )
+        .unwrap();
+        let config = Config::builder()
+            .add_source(File::new(
+                current_dir()
+                    .unwrap()
+                    .join("..")
+                    .join("config")
+                    .join("influxdb_config.json")
+                    .to_str()
+                    .unwrap(),
+                FileFormat::Json,
+            ))
+            .build()
+            .unwrap();
+        let points = Arc::new(Mutex::new(Vec::new()));
+        let sink = RecordingSink {
+            points: points.clone(),
+            flushed: Arc::new(Mutex::new(false)),
+        };
+        let mut um = UploadManager::with_sink(config, Box::new(sink));
+        let written = um.reupload_fit_file(path.to_str().unwrap()).await.unwrap();
+
+        let points = points.lock().unwrap();
+        assert_eq!(written, points.len());
+        assert!(written > 0);
+        for point in points.iter() {
+            let parsed = line_protocol::parse_point(point).unwrap();
+            assert_eq!(parsed.measurement, "monitoring");
+            assert_eq!(parsed.tags["metric"], "WELLNESS");
+        }
+        let metabolic_rates: Vec<FieldValue> = points
+            .iter()
+            .filter_map(|point| {
+                let parsed = line_protocol::parse_point(point)?;
+                parsed.fields.get("resting_metabolic_rate").cloned()
+            })
+            .collect();
+        // integers are uploaded as floats by default
+        assert_eq!(metabolic_rates, vec![FieldValue::F64(2042.0)]);
+
+        let messages = LOG_MESSAGES.lock().unwrap();
+        for expected in [
+            "Skipping event record, not in records_to_include",
+            "Skipping monitoring_info.local_timestamp, unknown or timestamp field",
+            "Writing monitoring_info.resting_metabolic_rate = F64(2042.0)",
+        ] {
+            assert!(
+                messages.iter().any(|message| message == expected),
+                "{}",
+                expected
+            );
+        }
+
+        std::fs::remove_dir_all(&base_path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn reupload_unreadable_fit_file_test() {
+        let path = std::env::temp_dir().join(format!(
+            "garmin_reupload_test_{}_ACTIVITY.fit",
+            std::process::id()
+        ));
+        std::fs::write(&path, "not a FIT file").unwrap();
+        let config = Config::builder()
+            .add_source(File::new(
+                current_dir()
+                    .unwrap()
+                    .join("..")
+                    .join("config")
+                    .join("influxdb_config.json")
+                    .to_str()
+                    .unwrap(),
+                FileFormat::Json,
+            ))
+            .build()
+            .unwrap();
+        let mut um = UploadManager::new(config);
+        let Err(e) = um.reupload_fit_file(path.to_str().unwrap()).await else {
+            panic!("expected an error reuploading {}", path.display());
+        };
+        assert!(e.starts_with("Unable to parse"));
+        assert_eq!(um.get_upload_summary().points_written, 0);
+
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn sqlite_upload_activity_summary_test() {
+        let base_path =
+            std::env::temp_dir().join(format!("garmin_sqlite_test_{}", std::process::id()));
+        let activities = base_path.join("activities");
+        std::fs::create_dir_all(&activities).unwrap();
+        std::fs::write(
+            activities.join("2024-02-01-1234.json"),
+            r#"{
+                "activityId": 1234,
+                "activityName": "Morning Run",
+                "activityTypeDTO": { "typeKey": "running" },
+                "summaryDTO": {
+                    "startTimeLocal": "2024-02-01T00:00:00.0",
+                    "distance": 5012.5,
+                    "steps": 4800
+                }
+            }"#,
+        )
+        .unwrap();
+        let db_path = base_path.join("garmin.db");
+
+        let mut um = sqlite_test_manager(&base_path);
+        um.upload_all().await;
+
+        let conn = rusqlite::Connection::open(&db_path).unwrap();
+        let (timestamp, id, name, distance, steps): (i64, String, String, f64, i64) = conn
+            .query_row(
+                "SELECT timestamp, activityId, name, distance, steps FROM activity_summary",
+                [],
+                |row| {
+                    Ok((
+                        row.get(0)?,
+                        row.get(1)?,
+                        row.get(2)?,
+                        row.get(3)?,
+                        row.get(4)?,
+                    ))
+                },
+            )
+            .unwrap();
+        assert_eq!(timestamp, 1706745600000000000);
+        assert_eq!(id, "1234");
+        assert_eq!(name, "Morning Run");
+        assert_eq!(distance, 5012.5);
+        assert_eq!(steps, 4800);
+
+        std::fs::remove_dir_all(&base_path).unwrap();
+    }
+
+    // keeps every written point, to check what UploadManager hands its sink
+    struct RecordingSink {
+        points: Arc<Mutex<Vec<DataPoint>>>,
+        flushed: Arc<Mutex<bool>>,
+    }
+
+    impl DataSink for RecordingSink {
+        fn write(&mut self, points: Vec<DataPoint>) -> BoxFuture<'_, Result<(), WriteError>> {
+            self.points.lock().unwrap().extend(points);
+            Box::pin(future::ready(Ok(())))
+        }
+
+        fn flush(&mut self) -> Result<(), String> {
+            *self.flushed.lock().unwrap() = true;
+            Ok(())
+        }
+    }
+
+    #[tokio::test]
+    async fn custom_sink_test() {
+        let base_path =
+            std::env::temp_dir().join(format!("garmin_custom_sink_test_{}", std::process::id()));
+        let activities = base_path.join("activities");
+        std::fs::create_dir_all(&activities).unwrap();
+        std::fs::write(
+            activities.join("2024-02-01-1234.json"),
+            r#"{
+                "activityId": 1234,
+                "activityName": "Morning Run",
+                "activityTypeDTO": { "typeKey": "running" },
+                "summaryDTO": { "startTimeLocal": "2024-02-01T00:00:00.0", "distance": 5012.5 }
+            }"#,
+        )
+        .unwrap();
+
+        let config = Config::builder()
+            .add_source(File::new(
+                current_dir()
+                    .unwrap()
+                    .join("..")
+                    .join("config")
+                    .join("influxdb_config.json")
+                    .to_str()
+                    .unwrap(),
+                FileFormat::Json,
+            ))
+            .set_override("file_base_path", base_path.to_str().unwrap())
+            .unwrap()
+            .set_override("upload_fit_files", false)
+            .unwrap()
+            .build()
+            .unwrap();
+        let points = Arc::new(Mutex::new(Vec::new()));
+        let flushed = Arc::new(Mutex::new(false));
+        let sink = RecordingSink {
+            points: points.clone(),
+            flushed: flushed.clone(),
+        };
+        let mut um = UploadManager::with_sink(config, Box::new(sink));
+        um.upload_all().await;
+
+        assert_eq!(points.lock().unwrap().len(), 1);
+        assert!(*flushed.lock().unwrap());
+        let summary = um.get_upload_summary();
+        assert_eq!(summary.files_processed, 1);
+        assert_eq!(summary.points_written, 1);
+        assert_eq!(summary.points_failed, 0);
+
+        std::fs::remove_dir_all(&base_path).unwrap();
+    }
+
+    // fails every write until it's reconnected, or every write at all with 'broken' set.
+    // with 'rejecting' set it rejects the points of every write instead.
+    struct FlakySink {
+        connected: bool,
+        broken: bool,
+        rejecting: bool,
+        writes: Arc<Mutex<usize>>,
+        reconnects: Arc<Mutex<usize>>,
+    }
+
+    impl DataSink for FlakySink {
+        fn write(&mut self, _points: Vec<DataPoint>) -> BoxFuture<'_, Result<(), WriteError>> {
+            *self.writes.lock().unwrap() += 1;
+            let result = if self.rejecting {
+                Err(WriteError::Rejected(String::from("field type conflict")))
+            } else if self.connected && !self.broken {
+                Ok(())
+            } else {
+                Err(WriteError::Unavailable(String::from("connection reset")))
+            };
+            Box::pin(future::ready(result))
+        }
+
+        fn reconnect(&mut self) -> Result<(), String> {
+            *self.reconnects.lock().unwrap() += 1;
+            self.connected = true;
+            Ok(())
+        }
+    }
+
+    #[tokio::test]
+    async fn failed_write_is_not_recorded_test() {
+        let config = Config::builder()
+            .add_source(File::new(
+                current_dir()
+                    .unwrap()
+                    .join("..")
+                    .join("config")
+                    .join("influxdb_config.json")
+                    .to_str()
+                    .unwrap(),
+                FileFormat::Json,
+            ))
+            .set_override("batch_size", 2)
+            .unwrap()
+            .build()
+            .unwrap();
+        let point = |value: f64| {
+            DataPoint::builder("monitoring")
+                .field("heart_rate", value)
+                .timestamp(1706745600000000000)
+                .build()
+                .unwrap()
+        };
+        let hash = |name: &str| Some((format!("WELLNESS/{}", name), String::from("hash")));
+
+        let sink = FlakySink {
+            connected: true,
+            broken: true,
+            rejecting: false,
+            writes: Arc::new(Mutex::new(0)),
+            reconnects: Arc::new(Mutex::new(0)),
+        };
+        let mut um = UploadManager::with_sink(config, Box::new(sink));
+        // the first file fills a batch, the second is still queued
+        um.queue_file("1.fit", hash("1.fit"), vec![point(60.0), point(61.0)])
+            .await;
+        um.queue_file("2.fit", hash("2.fit"), vec![point(62.0)])
+            .await;
+        assert!(!um.upload_state.is_unchanged("WELLNESS/1.fit", "hash"));
+        assert_eq!(um.pending_files.len(), 1);
+
+        // only once its batch is written
+        um.sink = Box::new(FlakySink {
+            connected: true,
+            broken: false,
+            rejecting: false,
+            writes: Arc::new(Mutex::new(0)),
+            reconnects: Arc::new(Mutex::new(0)),
+        });
+        um.flush_data().await;
+        assert!(!um.upload_state.is_unchanged("WELLNESS/1.fit", "hash"));
+        assert!(um.upload_state.is_unchanged("WELLNESS/2.fit", "hash"));
+        assert!(um.pending_files.is_empty());
+    }
+
+    #[tokio::test]
+    async fn write_failure_reconnect_test() {
+        let base_path =
+            std::env::temp_dir().join(format!("garmin_reconnect_test_{}", std::process::id()));
+        let activities = base_path.join("activities");
+        std::fs::create_dir_all(&activities).unwrap();
+        for id in 1..=5 {
+            std::fs::write(
+                activities.join(format!("2024-02-01-{}.json", id)),
+                format!(
+                    r#"{{
+                        "activityId": {},
+                        "activityTypeDTO": {{ "typeKey": "running" }},
+                        "summaryDTO": {{ "startTimeLocal": "2024-02-01T00:00:00.0", "distance": 5000.0 }}
+                    }}"#,
+                    id
+                ),
+            )
+            .unwrap();
+        }
+        let config = || {
+            Config::builder()
+                .add_source(File::new(
+                    current_dir()
+                        .unwrap()
+                        .join("..")
+                        .join("config")
+                        .join("influxdb_config.json")
+                        .to_str()
+                        .unwrap(),
+                    FileFormat::Json,
+                ))
+                .set_override("file_base_path", base_path.to_str().unwrap())
+                .unwrap()
+                .set_override("upload_fit_files", false)
+                .unwrap()
+                // one write per file
+                .set_override("batch_size", 0)
+                .unwrap()
+                .build()
+                .unwrap()
+        };
+
+        // the first write fails, and goes through once reconnected
+        let (writes, reconnects) = (Arc::new(Mutex::new(0)), Arc::new(Mutex::new(0)));
+        let sink = FlakySink {
+            connected: false,
+            broken: false,
+            rejecting: false,
+            writes: writes.clone(),
+            reconnects: reconnects.clone(),
+        };
+        let mut um = UploadManager::with_sink(config(), Box::new(sink));
+        um.upload_all().await;
+        assert_eq!(*reconnects.lock().unwrap(), 1);
+        assert_eq!(*writes.lock().unwrap(), 6);
+        let summary = um.get_upload_summary();
+        assert_eq!(summary.points_written, 5);
+        assert_eq!(summary.points_failed, 0);
+
+        // a sink that never recovers is only tried for the first few files
+        let (writes, reconnects) = (Arc::new(Mutex::new(0)), Arc::new(Mutex::new(0)));
+        let sink = FlakySink {
+            connected: true,
+            broken: true,
+            rejecting: false,
+            writes: writes.clone(),
+            reconnects: reconnects.clone(),
+        };
+        let mut um = UploadManager::with_sink(config(), Box::new(sink));
+        um.upload_all().await;
+        assert_eq!(*reconnects.lock().unwrap(), MAX_CONSECUTIVE_WRITE_FAILURES);
+        assert_eq!(*writes.lock().unwrap(), 2 * MAX_CONSECUTIVE_WRITE_FAILURES);
+        let summary = um.get_upload_summary();
+        assert_eq!(summary.points_written, 0);
+        assert_eq!(summary.points_failed, 5);
+
+        // rejected points only fail their own write, without reconnecting
+        let (writes, reconnects) = (Arc::new(Mutex::new(0)), Arc::new(Mutex::new(0)));
+        let sink = FlakySink {
+            connected: true,
+            broken: false,
+            rejecting: true,
+            writes: writes.clone(),
+            reconnects: reconnects.clone(),
+        };
+        let mut um = UploadManager::with_sink(config(), Box::new(sink));
+        um.upload_all().await;
+        assert_eq!(*reconnects.lock().unwrap(), 0);
+        assert_eq!(*writes.lock().unwrap(), 5);
+        assert_eq!(um.consecutive_write_failures, 0);
+        let summary = um.get_upload_summary();
+        assert_eq!(summary.points_written, 0);
+        assert_eq!(summary.points_failed, 5);
+
+        std::fs::remove_dir_all(&base_path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn activity_summary_fields_test() {
+        let base_path =
+            std::env::temp_dir().join(format!("garmin_summary_fields_{}", std::process::id()));
+        let activities = base_path.join("activities");
+        std::fs::create_dir_all(&activities).unwrap();
+        std::fs::write(
+            activities.join("2024-02-01-1234.json"),
+            r#"{
+                "activityId": 1234,
+                "activityTypeDTO": { "typeKey": "cycling" },
+                "summaryDTO": {
+                    "startTimeLocal": "2024-02-01T00:00:00.0",
+                    "distance": 40012.5,
+                    "elevationGain": 512.0,
+                    "normalizedPower": 231
+                }
+            }"#,
+        )
+        .unwrap();
+        let db_path = base_path.join("garmin.db");
+
+        let mut um = sqlite_test_manager(&base_path);
+        um.upload_manager.influx_config.activity_summary_fields = vec![
+            SummaryField {
+                key: String::from("elevationGain"),
+                field_type: SummaryFieldType::Float,
+            },
+            SummaryField {
+                key: String::from("normalizedPower"),
+                field_type: SummaryFieldType::Int,
+            },
+            // not in this activity
+            SummaryField {
+                key: String::from("avgPower"),
+                field_type: SummaryFieldType::Float,
+            },
+        ];
+        um.upload_all().await;
+
+        let conn = rusqlite::Connection::open(&db_path).unwrap();
+        let (elevation_gain, power): (f64, i64) = conn
+            .query_row(
+                "SELECT elevationGain, normalizedPower FROM activity_summary",
+                [],
+                |row| Ok((row.get(0)?, row.get(1)?)),
+            )
+            .unwrap();
+        assert_eq!(elevation_gain, 512.0);
+        assert_eq!(power, 231);
+        // only the configured fields, not the defaults
+        let columns: Vec<String> = conn
+            .prepare("SELECT name FROM pragma_table_info('activity_summary')")
+            .unwrap()
+            .query_map([], |row| row.get(0))
+            .unwrap()
+            .map(|name| name.unwrap())
+            .collect();
+        assert!(!columns.contains(&String::from("distance")));
+        assert!(!columns.contains(&String::from("avgPower")));
+
+        std::fs::remove_dir_all(&base_path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn get_existing_dates_test() {
+        let base_path =
+            std::env::temp_dir().join(format!("garmin_existing_dates_test_{}", std::process::id()));
+        let mut um = sqlite_test_manager(&base_path);
+        let date = |d: &str| NaiveDate::parse_from_str(d, "%Y-%m-%d").unwrap();
+        assert!(um
+            .upload_manager
+            .get_existing_dates("hydration")
+            .await
+            .is_empty());
+
+        // daily values are stored at midnight as if it were UTC
+        let mut points: Vec<DataPoint> = ["2024-02-01", "2024-02-03"]
+            .into_iter()
+            .map(|day| {
+                let midnight = date(day).and_hms_opt(0, 0, 0).unwrap().and_utc();
+                DataPoint::builder("hydration")
+                    .field("valueInML", 500.0)
+                    .timestamp(midnight.timestamp_nanos_opt().unwrap())
+                    .build()
+                    .unwrap()
+            })
+            .collect();
+        // a night's sleep starts the evening before the day it's for
+        let bedtime = Local.with_ymd_and_hms(2024, 2, 1, 23, 0, 0).unwrap();
+        points.push(
+            DataPoint::builder("sleep")
+                .field("sleepTimeSeconds", 28800)
+                .timestamp(bedtime.timestamp_nanos_opt().unwrap())
+                .build()
+                .unwrap(),
+        );
+        // late on a winter night and early on a summer morning, which have different UTC
+        // offsets wherever there's daylight saving time
+        for (month, hour) in [(1, 23), (7, 0)] {
+            let time = Local
+                .with_ymd_and_hms(2024, month, 15, hour, 30, 0)
+                .unwrap();
+            points.push(
+                DataPoint::builder("monitoring")
+                    .field("heart_rate", 60.0)
+                    .timestamp(time.timestamp_nanos_opt().unwrap())
+                    .build()
+                    .unwrap(),
+            );
+        }
+        um.upload_manager.sink.write(points).await.unwrap();
+
+        assert_eq!(
+            um.upload_manager.get_existing_dates("monitoring").await,
+            HashSet::from([date("2024-01-15"), date("2024-07-15")])
+        );
+        assert_eq!(
+            um.upload_manager.get_existing_dates("hydration").await,
+            HashSet::from([date("2024-02-01"), date("2024-02-03")])
+        );
+        assert_eq!(
+            um.upload_manager.get_existing_dates("sleep").await,
+            HashSet::from([date("2024-02-02")])
+        );
+
+        std::fs::remove_dir_all(&base_path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn sqlite_upload_hrv_test() {
+        let base_path =
+            std::env::temp_dir().join(format!("garmin_hrv_test_{}", std::process::id()));
+        let hrv = base_path.join("hrv");
+        std::fs::create_dir_all(&hrv).unwrap();
+        std::fs::write(
+            hrv.join("2024-02-01.json"),
+            r#"{
+                "hrvSummary": { "lastNightAvg": 48 },
+                "hrvReadings": [
+                    { "hrvValue": 45, "readingTimeLocal": "2024-02-01T00:00:00.0" },
+                    { "hrvValue": 51, "readingTimeLocal": "2024-02-01T00:05:00.0" },
+                    { "readingTimeLocal": "2024-02-01T00:10:00.0" }
+                ]
+            }"#,
+        )
+        .unwrap();
+        let db_path = base_path.join("garmin.db");
+
+        let mut um = sqlite_test_manager(&base_path);
+        um.upload_all().await;
+
+        let conn = rusqlite::Connection::open(&db_path).unwrap();
+        let mut statement = conn
+            .prepare("SELECT timestamp, hrvValue FROM hrv ORDER BY timestamp")
+            .unwrap();
+        let rows: Vec<(i64, f64)> = statement
+            .query_map([], |row| Ok((row.get(0)?, row.get(1)?)))
+            .unwrap()
+            .flatten()
+            .collect();
+        assert_eq!(
+            rows,
+            vec![(1706745600000000000, 45.0), (1706745900000000000, 51.0)]
+        );
+
+        std::fs::remove_dir_all(&base_path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn sqlite_upload_sleep_test() {
+        let base_path =
+            std::env::temp_dir().join(format!("garmin_sleep_test_{}", std::process::id()));
+        let sleep = base_path.join("sleep");
+        std::fs::create_dir_all(&sleep).unwrap();
+        std::fs::write(
+            sleep.join("2024-02-01.json"),
+            r#"{
+                "dailySleepDTO": {
+                    "sleepTimeSeconds": 27000,
+                    "deepSleepSeconds": 5400,
+                    "sleepStartTimestampGMT": 1706745600000,
+                    "averageSpO2Value": 94.0,
+                    "sleepScores": { "overall": { "value": 81 } }
+                },
+                "breathingDisruptionSeverity": "LOW",
+                "wellnessEpochSPO2DataDTOList": [
+                    { "epochTimestamp": "2024-02-01T00:00:00.0", "spo2Reading": 93 },
+                    { "epochTimestamp": "2024-02-01T00:01:00.0", "spo2Reading": 95 }
+                ]
+            }"#,
+        )
+        .unwrap();
+        // a night the watch wasn't worn
+        std::fs::write(sleep.join("2024-02-02.json"), r#"{ "dailySleepDTO": {} }"#).unwrap();
+        let db_path = base_path.join("garmin.db");
+
+        let mut um = sqlite_test_manager(&base_path);
+        um.upload_manager.influx_config.upload_sleep_spo2 = true;
+        um.upload_all().await;
+
+        let conn = rusqlite::Connection::open(&db_path).unwrap();
+        let night: (i64, f64, f64, String) = conn
+            .query_row(
+                "SELECT timestamp, sleepTimeSeconds, sleepScore, breathingDisruptionSeverity FROM sleep",
+                [],
+                |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?, row.get(3)?)),
+            )
+            .unwrap();
+        assert_eq!(
+            night,
+            (1706745600000000000, 27000.0, 81.0, String::from("LOW"))
+        );
+        let readings: Vec<f64> = conn
+            .prepare("SELECT spo2Reading FROM spo2 ORDER BY timestamp")
+            .unwrap()
+            .query_map([], |row| row.get(0))
+            .unwrap()
+            .flatten()
+            .collect();
+        assert_eq!(readings, vec![93.0, 95.0]);
+
+        std::fs::remove_dir_all(&base_path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn concurrent_fit_upload_skips_unreadable_files_test() {
+        let base_path = std::env::temp_dir().join(format!(
+            "garmin_fit_concurrency_test_{}",
+            std::process::id()
+        ));
+        let monitoring = base_path.join("monitoring");
+        let activities = base_path.join("activities");
+        std::fs::create_dir_all(&monitoring).unwrap();
+        std::fs::create_dir_all(&activities).unwrap();
+        for i in 0..5 {
+            std::fs::write(
+                monitoring.join(format!("{}_WELLNESS.fit", 1000 + i)),
+                "not a fit file",
+            )
+            .unwrap();
+        }
+        std::fs::write(
+            activities.join("1234_ACTIVITY.fit"),
+            "not a fit file either",
+        )
+        .unwrap();
+
+        let mut um = sqlite_test_manager(&base_path);
+        um.upload_manager.influx_config.upload_fit_files = true;
+        um.upload_manager.influx_config.upload_json_files = false;
+        um.upload_manager.influx_config.upload_concurrency = 2;
+        um.upload_all().await;
+
+        // every file is still tried once, and one bad file doesn't stop the rest
+        assert_eq!(um.upload_manager.processed_files, 0);
+        assert_eq!(um.upload_manager.skipped_files.len(), 6);
+
+        std::fs::remove_dir_all(&base_path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn sqlite_upload_vo2max_training_status_test() {
+        let base_path =
+            std::env::temp_dir().join(format!("garmin_vo2max_test_{}", std::process::id()));
+        let vo2max = base_path.join("vo2max");
+        let training_status = base_path.join("training_status");
+        std::fs::create_dir_all(&vo2max).unwrap();
+        std::fs::create_dir_all(&training_status).unwrap();
+        std::fs::write(
+            vo2max.join("2024-02-01.json"),
+            r#"[{
+                "generic": { "calendarDate": "2024-02-01", "vo2MaxPreciseValue": 51.3, "vo2MaxValue": 51.0 },
+                "cycling": null
+            }]"#,
+        )
+        .unwrap();
+        std::fs::write(
+            training_status.join("2024-02-01.json"),
+            r#"{
+                "mostRecentTrainingStatus": {
+                    "latestTrainingStatusData": {
+                        "3412345678": {
+                            "calendarDate": "2024-02-01",
+                            "trainingStatus": 4,
+                            "trainingStatusFeedbackPhrase": "PRODUCTIVE_6",
+                            "acuteTrainingLoadDTO": { "dailyTrainingLoadAcute": 512, "dailyTrainingLoadChronic": 430 }
+                        }
+                    }
+                }
+            }"#,
+        )
+        .unwrap();
+        let db_path = base_path.join("garmin.db");
+
+        let mut um = sqlite_test_manager(&base_path);
+        um.upload_all().await;
+
+        let conn = rusqlite::Connection::open(&db_path).unwrap();
+        let estimate: (i64, String, f64) = conn
+            .query_row(
+                "SELECT timestamp, sport, vo2MaxPreciseValue FROM vo2max",
+                [],
+                |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)),
+            )
+            .unwrap();
+        assert_eq!(
+            estimate,
+            (1706745600000000000, String::from("generic"), 51.3)
+        );
+        let status: (String, f64, String, f64) = conn
+            .query_row(
+                "SELECT deviceId, trainingStatus, trainingStatusFeedbackPhrase, dailyTrainingLoadAcute FROM training_status",
+                [],
+                |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?, row.get(3)?)),
+            )
+            .unwrap();
+        assert_eq!(
+            status,
+            (
+                String::from("3412345678"),
+                4.0,
+                String::from("PRODUCTIVE_6"),
+                512.0
+            )
+        );
+
+        std::fs::remove_dir_all(&base_path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn sqlite_upload_spo2_test() {
+        let base_path =
+            std::env::temp_dir().join(format!("garmin_spo2_test_{}", std::process::id()));
+        let spo2 = base_path.join("spo2");
+        std::fs::create_dir_all(&spo2).unwrap();
+        std::fs::write(
+            spo2.join("2024-02-01.json"),
+            r#"{
+                "calendarDate": "2024-02-01",
+                "spo2ValuesArray": [[1706774400000, 96], [1706774460000, null], [1706774520000, 94]]
+            }"#,
+        )
+        .unwrap();
+        // no pulse ox data that day
+        std::fs::write(spo2.join("2024-02-02.json"), "null").unwrap();
+        let db_path = base_path.join("garmin.db");
+
+        let mut um = sqlite_test_manager(&base_path);
+        um.upload_all().await;
+
+        let conn = rusqlite::Connection::open(&db_path).unwrap();
+        let mut statement = conn
+            .prepare("SELECT timestamp, spo2Reading FROM spo2 ORDER BY timestamp")
+            .unwrap();
+        let rows: Vec<(i64, f64)> = statement
+            .query_map([], |row| Ok((row.get(0)?, row.get(1)?)))
+            .unwrap()
+            .flatten()
+            .collect();
+        assert_eq!(
+            rows,
+            vec![(1706774400000000000, 96.0), (1706774520000000000, 94.0)]
+        );
+        assert!(um.upload_manager.skipped_files.is_empty());
+
+        std::fs::remove_dir_all(&base_path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn sqlite_upload_floors_and_intensity_minutes_test() {
+        let base_path =
+            std::env::temp_dir().join(format!("garmin_floors_test_{}", std::process::id()));
+        let floors = base_path.join("floors");
+        let intensity_minutes = base_path.join("intensity_minutes");
+        std::fs::create_dir_all(&floors).unwrap();
+        std::fs::create_dir_all(&intensity_minutes).unwrap();
+        std::fs::write(
+            floors.join("2024-02-01.json"),
+            r#"{
+                "floorsValueDescriptorDTOList": [
+                    { "key": "startTimeGMT", "index": 0 }, { "key": "endTimeGMT", "index": 1 },
+                    { "key": "floorsAscended", "index": 2 }, { "key": "floorsDescended", "index": 3 }
+                ],
+                "floorValuesArray": [
+                    ["2024-02-01T06:00:00.0", "2024-02-01T06:15:00.0", 3, 1],
+                    ["2024-02-01T06:15:00.0", "2024-02-01T06:30:00.0", 0, 2]
+                ]
+            }"#,
+        )
+        .unwrap();
+        std::fs::write(
+            intensity_minutes.join("2024-02-01.json"),
+            r#"{
+                "calendarDate": "2024-02-01",
+                "weeklyGoal": 150,
+                "imValueDescriptorsDTOList": [
+                    { "key": "timestamp", "index": 0 }, { "key": "value", "index": 1 }
+                ],
+                "imValuesArray": [[1706767200000, 10], [1706768100000, 15]]
+            }"#,
+        )
+        .unwrap();
+        // no intensity minutes that day
+        std::fs::write(
+            intensity_minutes.join("2024-02-02.json"),
+            r#"{ "calendarDate": "2024-02-02", "imValuesArray": [] }"#,
+        )
+        .unwrap();
+        let db_path = base_path.join("garmin.db");
+
+        let mut um = sqlite_test_manager(&base_path);
+        um.upload_all().await;
+
+        let conn = rusqlite::Connection::open(&db_path).unwrap();
+        let mut statement = conn
+            .prepare(
+                "SELECT timestamp, floorsAscended, floorsDescended FROM floors ORDER BY timestamp",
+            )
+            .unwrap();
+        let rows: Vec<(i64, f64, f64)> = statement
+            .query_map([], |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)))
+            .unwrap()
+            .flatten()
+            .collect();
+        assert_eq!(
+            rows,
+            vec![
+                (1706767200000000000, 3.0, 1.0),
+                (1706768100000000000, 0.0, 2.0)
+            ]
+        );
+        let mut statement = conn
+            .prepare(
+                "SELECT timestamp, intensityMinutes, dailyTotal FROM intensity_minutes ORDER BY timestamp",
+            )
+            .unwrap();
+        let rows: Vec<(i64, f64, f64)> = statement
+            .query_map([], |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)))
+            .unwrap()
+            .flatten()
+            .collect();
+        assert_eq!(
+            rows,
+            vec![
+                (1706767200000000000, 10.0, 10.0),
+                (1706768100000000000, 15.0, 25.0)
+            ]
+        );
+        assert!(um.upload_manager.skipped_files.is_empty());
+
+        std::fs::remove_dir_all(&base_path).unwrap();
+    }
+
+    #[test]
+    fn unchanged_monitoring_files_are_skipped_test() {
+        let base_path = std::env::temp_dir().join(format!(
+            "garmin_unchanged_files_test_{}",
+            std::process::id()
+        ));
+        let monitoring = base_path.join("monitoring");
+        std::fs::create_dir_all(&monitoring).unwrap();
+        let uploaded = monitoring.join("1000_WELLNESS.fit");
+        let changed = monitoring.join("1000_HRV_STATUS.fit");
+        std::fs::write(&uploaded, "wellness").unwrap();
+        std::fs::write(&changed, "hrv status").unwrap();
+
+        let mut um = sqlite_test_manager(&base_path).upload_manager;
+        um.upload_state.record(
+            upload_state::file_key("WELLNESS", &uploaded),
+            upload_state::file_hash(&uploaded).unwrap(),
+        );
+        um.upload_state.record(
+            upload_state::file_key("HRV_STATUS", &changed),
+            String::from("stale"),
+        );
+
+        let files: Vec<String> = um
+            .monitoring_files()
+            .into_iter()
+            .map(|file| file.filename)
+            .collect();
+        assert_eq!(files, vec![changed.to_str().unwrap()]);
+
+        um.set_force_upload(true);
+        assert_eq!(um.monitoring_files().len(), 2);
+
+        std::fs::remove_dir_all(&base_path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn sqlite_upload_hydration_test() {
+        let base_path =
+            std::env::temp_dir().join(format!("garmin_hydration_test_{}", std::process::id()));
+        let hydration = base_path.join("hydration");
+        std::fs::create_dir_all(&hydration).unwrap();
+        std::fs::write(
+            hydration.join("2024-02-01.json"),
+            r#"{ "calendarDate": "2024-02-01", "valueInML": 1500.0, "goalInML": 2000.0 }"#,
+        )
+        .unwrap();
+        std::fs::write(
+            hydration.join("2024-02-02.json"),
+            r#"{ "calendarDate": "2024-02-02", "valueInML": null, "goalInML": 2000.0 }"#,
+        )
+        .unwrap();
+        let db_path = base_path.join("garmin.db");
+
+        let mut um = sqlite_test_manager(&base_path);
+        um.upload_all().await;
+
+        let conn = rusqlite::Connection::open(&db_path).unwrap();
+        let mut statement = conn
+            .prepare("SELECT timestamp, valueInML, goalInML FROM hydration")
+            .unwrap();
+        let rows: Vec<(i64, f64, f64)> = statement
+            .query_map([], |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)))
+            .unwrap()
+            .flatten()
+            .collect();
+        assert_eq!(rows, vec![(1706745600000000000, 1500.0, 2000.0)]);
+
+        std::fs::remove_dir_all(&base_path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn sqlite_upload_heart_rate_and_weight_test() {
+        let base_path =
+            std::env::temp_dir().join(format!("garmin_rhr_weight_test_{}", std::process::id()));
+        let heartrate = base_path.join("heartrate");
+        let weight = base_path.join("weight");
+        std::fs::create_dir_all(&heartrate).unwrap();
+        std::fs::create_dir_all(&weight).unwrap();
+        std::fs::write(
+            heartrate.join("2024-02-01.json"),
+            r#"{ "allMetrics": { "metricsMap": { "WELLNESS_RESTING_HEART_RATE": [
+                { "value": 52.0, "calendarDate": "2024-02-01" },
+                { "value": null, "calendarDate": "2024-02-02" }
+            ] } } }"#,
+        )
+        .unwrap();
+        std::fs::write(
+            weight.join("2024-02-01.json"),
+            r#"{ "dateWeightList": [
+                { "calendarDate": "2024-02-01", "timestampGMT": 1706774400000,
+                  "weight": 80000.0, "bmi": 24.5, "bodyFat": null }
+            ], "totalAverage": null }"#,
+        )
+        .unwrap();
+        let db_path = base_path.join("garmin.db");
+
+        let mut um = sqlite_test_manager(&base_path);
+        um.upload_all().await;
+
+        let conn = rusqlite::Connection::open(&db_path).unwrap();
+        let row: (i64, f64) = conn
+            .query_row(
+                "SELECT timestamp, restingHeartRate FROM resting_heart_rate",
+                [],
+                |row| Ok((row.get(0)?, row.get(1)?)),
+            )
+            .unwrap();
+        assert_eq!(row, (1706745600000000000, 52.0));
+        let row: (i64, f64, f64) = conn
+            .query_row("SELECT timestamp, weight, bmi FROM weight", [], |row| {
+                Ok((row.get(0)?, row.get(1)?, row.get(2)?))
+            })
+            .unwrap();
+        assert_eq!(row, (1706774400000000000, 80000.0, 24.5));
+
+        std::fs::remove_dir_all(&base_path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn sqlite_upload_daily_summary_test() {
+        let base_path =
+            std::env::temp_dir().join(format!("garmin_summary_test_{}", std::process::id()));
+        let day_summary = base_path.join("day_summary");
+        std::fs::create_dir_all(&day_summary).unwrap();
+        std::fs::write(
+            day_summary.join("2024-02-01.json"),
+            r#"{ "calendarDate": "2024-02-01", "totalSteps": 8000, "restingHeartRate": 52,
+                 "userProfileId": 1234 }"#,
+        )
+        .unwrap();
+        std::fs::write(
+            day_summary.join("2024-02-02.json"),
+            r#"{ "calendarDate": "2024-02-02", "totalSteps": null }"#,
+        )
+        .unwrap();
+        let db_path = base_path.join("garmin.db");
+
+        let mut um = sqlite_test_manager(&base_path);
+        um.upload_all().await;
+
+        let conn = rusqlite::Connection::open(&db_path).unwrap();
+        let mut statement = conn
+            .prepare("SELECT timestamp, totalSteps, restingHeartRate FROM daily_summary")
+            .unwrap();
+        let rows: Vec<(i64, f64, f64)> = statement
+            .query_map([], |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)))
+            .unwrap()
+            .flatten()
+            .collect();
+        assert_eq!(rows, vec![(1706745600000000000, 8000.0, 52.0)]);
+        assert_eq!(
+            um.upload_manager.get_existing_dates("daily_summary").await,
+            HashSet::from([NaiveDate::from_ymd_opt(2024, 2, 1).unwrap()])
+        );
+
+        std::fs::remove_dir_all(&base_path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn upload_files_test() {
+        let base_path =
+            std::env::temp_dir().join(format!("garmin_stream_test_{}", std::process::id()));
+        let hydration = base_path.join("hydration");
+        std::fs::create_dir_all(&hydration).unwrap();
+        std::fs::write(
+            hydration.join("2024-02-01.json"),
+            r#"{ "calendarDate": "2024-02-01", "valueInML": 1500.0 }"#,
+        )
+        .unwrap();
+        std::fs::write(
+            hydration.join("2024-02-02.json"),
+            r#"{ "calendarDate": "2024-02-02", "valueInML": 1800.0 }"#,
+        )
+        .unwrap();
+        let db_path = base_path.join("garmin.db");
+
+        let mut um = sqlite_test_manager(&base_path);
+        // as if only the second day had just been downloaded
+        um.upload_manager
+            .upload_files(&[hydration.join(".").join("2024-02-02.json")])
+            .await;
+        assert_eq!(um.upload_manager.get_upload_summary().files_processed, 1);
+
+        let conn = rusqlite::Connection::open(&db_path).unwrap();
+        let values: Vec<f64> = conn
+            .prepare("SELECT valueInML FROM hydration")
+            .unwrap()
+            .query_map([], |row| row.get(0))
+            .unwrap()
+            .flatten()
+            .collect();
+        assert_eq!(values, vec![1800.0]);
+
+        std::fs::remove_dir_all(&base_path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn calendar_tags_upload_test() {
+        let base_path =
+            std::env::temp_dir().join(format!("garmin_calendar_test_{}", std::process::id()));
+        let hydration = base_path.join("hydration");
+        let weather = base_path.join("weather");
+        std::fs::create_dir_all(&hydration).unwrap();
+        std::fs::create_dir_all(&weather).unwrap();
+        std::fs::write(
+            hydration.join("2024-02-01.json"),
+            r#"{ "calendarDate": "2024-02-01", "valueInML": 1500.0 }"#,
+        )
+        .unwrap();
+        std::fs::write(
+            weather.join("2024-02-01-1234.json"),
+            r#"{ "issueDate": "2024-02-01T23:30:00.000+0000", "temp": 41.0 }"#,
+        )
+        .unwrap();
+        let db_path = base_path.join("garmin.db");
+
+        let mut um = sqlite_test_manager(&base_path);
+        um.upload_manager.influx_config.add_calendar_tags = true;
+        um.upload_all().await;
+
+        let conn = rusqlite::Connection::open(&db_path).unwrap();
+        let row: (String, String, String) = conn
+            .query_row(
+                "SELECT isoWeek, dayOfWeek, month FROM hydration",
+                [],
+                |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)),
+            )
+            .unwrap();
+        // daily values keep their calendar date, whatever the local timezone
+        assert_eq!(
+            row,
+            (
+                String::from("2024-W05"),
+                String::from("Thu"),
+                String::from("2024-02")
+            )
+        );
+        // while a moment in time falls on its local date
+        let row: (String, String, String) = conn
+            .query_row(
+                "SELECT isoWeek, dayOfWeek, month FROM activity_weather",
+                [],
+                |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)),
+            )
+            .unwrap();
+        let local = calendar_tags(1706830200000000000, true);
+        assert_eq!(
+            row,
+            (local[0].1.clone(), local[1].1.clone(), local[2].1.clone())
+        );
+
+        std::fs::remove_dir_all(&base_path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn renamed_measurement_test() {
+        let base_path =
+            std::env::temp_dir().join(format!("garmin_rename_test_{}", std::process::id()));
+        let hydration = base_path.join("hydration");
+        std::fs::create_dir_all(&hydration).unwrap();
+        std::fs::write(
+            hydration.join("2024-02-01.json"),
+            r#"{ "calendarDate": "2024-02-01", "valueInML": 1500.0 }"#,
+        )
+        .unwrap();
+        let db_path = base_path.join("garmin.db");
+
+        let mut um = sqlite_test_manager(&base_path);
+        um.upload_manager
+            .influx_config
+            .measurement_names
+            .insert(String::from("hydration"), String::from("water"));
+        um.upload_all().await;
+
+        let conn = rusqlite::Connection::open(&db_path).unwrap();
+        let value: f64 = conn
+            .query_row("SELECT valueInML FROM water", [], |row| row.get(0))
+            .unwrap();
+        assert_eq!(value, 1500.0);
+
+        std::fs::remove_dir_all(&base_path).unwrap();
+    }
+
+    #[test]
+    fn export_activities_csv_test() {
+        let base_path =
+            std::env::temp_dir().join(format!("garmin_csv_test_{}", std::process::id()));
+        let activities = base_path.join("activities");
+        std::fs::create_dir_all(&activities).unwrap();
+        std::fs::write(
+            activities.join("2024-02-02-5678.json"),
+            r#"{
+                "activityId": 5678,
+                "activityTypeDTO": { "typeKey": "cycling" },
+                "summaryDTO": { "startTimeLocal": "2024-02-02T07:00:00.0", "distance": 20000.5 }
+            }"#,
+        )
+        .unwrap();
+        std::fs::write(
+            activities.join("2024-02-01-1234.json"),
+            r#"{
+                "activityId": 1234,
+                "activityTypeDTO": { "typeKey": "running" },
+                "summaryDTO": {
+                    "startTimeLocal": "2024-02-01T07:00:00.0",
+                    "distance": 5000.0,
+                    "duration": 1500.0,
+                    "calories": 400.0,
+                    "averageHR": 150.0,
+                    "trainingEffect": 3.2
+                }
+            }"#,
+        )
+        .unwrap();
+        let csv_path = base_path.join("activities.csv");
+
+        let config = Config::builder()
+            .add_source(File::new(
+                current_dir()
+                    .unwrap()
+                    .join("..")
+                    .join("config")
+                    .join("influxdb_config.json")
+                    .to_str()
+                    .unwrap(),
+                FileFormat::Json,
+            ))
+            .set_override("file_base_path", base_path.to_str().unwrap())
+            .unwrap()
+            .build()
+            .unwrap();
+        let um = UploadManager::new(config);
+        assert_eq!(
+            um.export_activities_csv(csv_path.to_str().unwrap())
+                .unwrap(),
+            2
+        );
+
+        let contents = std::fs::read_to_string(&csv_path).unwrap();
+        assert_eq!(
+            contents.lines().collect::<Vec<&str>>(),
+            vec![
+                "activityId,type,date,distance,duration,calories,avgHR,trainingEffect",
+                "1234,running,2024-02-01T07:00:00.0,5000,1500,400,150,3.2",
+                // missing fields are left empty
+                "5678,cycling,2024-02-02T07:00:00.0,20000.5,,,,",
+            ]
+        );
+
+        std::fs::remove_dir_all(&base_path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn sqlite_upload_activity_gear_test() {
+        let base_path =
+            std::env::temp_dir().join(format!("garmin_gear_test_{}", std::process::id()));
+        let activities = base_path.join("activities");
+        let gear = base_path.join("gear");
+        std::fs::create_dir_all(&activities).unwrap();
+        std::fs::create_dir_all(&gear).unwrap();
+        for id in ["1234", "5678"] {
+            std::fs::write(
+                activities.join(format!("2024-02-01-{}.json", id)),
+                format!(
+                    r#"{{
+                        "activityId": {},
+                        "activityName": "Run",
+                        "activityTypeDTO": {{ "typeKey": "running" }},
+                        "summaryDTO": {{ "startTimeLocal": "2024-02-01T00:00:00.0" }}
+                    }}"#,
+                    id
+                ),
+            )
+            .unwrap();
+        }
+        std::fs::write(
+            gear.join("2024-02-01-1234.json"),
+            r#"[{ "displayName": "Pegasus 40", "customMakeModel": "Nike Pegasus" }]"#,
+        )
+        .unwrap();
+        // no gear for this one
+        std::fs::write(gear.join("2024-02-01-5678.json"), "[]").unwrap();
+        let db_path = base_path.join("garmin.db");
+
+        let mut um = sqlite_test_manager(&base_path);
+        um.upload_all().await;
+
+        let conn = rusqlite::Connection::open(&db_path).unwrap();
+        let mut statement = conn
+            .prepare("SELECT activityId, gear FROM activity_summary ORDER BY activityId")
+            .unwrap();
+        let rows: Vec<(String, Option<String>)> = statement
+            .query_map([], |row| Ok((row.get(0)?, row.get(1)?)))
+            .unwrap()
+            .flatten()
+            .collect();
+        assert_eq!(
+            rows,
+            vec![
+                (String::from("1234"), Some(String::from("Pegasus 40"))),
+                (String::from("5678"), None)
+            ]
+        );
+
+        std::fs::remove_dir_all(&base_path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn sqlite_upload_splits_test() {
+        let base_path =
+            std::env::temp_dir().join(format!("garmin_splits_test_{}", std::process::id()));
+        let splits = base_path.join("splits");
+        std::fs::create_dir_all(&splits).unwrap();
+        std::fs::write(
+            splits.join("2024-02-01-1234.json"),
+            r#"{
+                "activityId": 1234,
+                "lapDTOs": [
+                    { "startTimeGMT": "2024-02-01T06:00:00.0", "lapIndex": 1,
+                      "distance": 1000.0, "duration": 300.0, "averageHR": 150.0 },
+                    { "startTimeGMT": "2024-02-01T06:05:00.0", "lapIndex": 2,
+                      "distance": 500.0, "duration": 160.0 }
+                ]
+            }"#,
+        )
+        .unwrap();
+        // e.g. a manually entered activity
+        std::fs::write(
+            splits.join("2024-02-01-5678.json"),
+            r#"{ "activityId": 5678, "lapDTOs": [] }"#,
+        )
+        .unwrap();
+        let db_path = base_path.join("garmin.db");
+
+        let mut um = sqlite_test_manager(&base_path);
+        um.upload_all().await;
+
+        let conn = rusqlite::Connection::open(&db_path).unwrap();
+        let mut statement = conn
+            .prepare(
+                "SELECT timestamp, activityId, split, pace, averageHR FROM splits ORDER BY split",
+            )
+            .unwrap();
+        let rows: Vec<(i64, String, String, f64, Option<f64>)> = statement
+            .query_map([], |row| {
+                Ok((
+                    row.get(0)?,
+                    row.get(1)?,
+                    row.get(2)?,
+                    row.get(3)?,
+                    row.get(4)?,
+                ))
+            })
+            .unwrap()
+            .flatten()
+            .collect();
+        assert_eq!(
+            rows,
+            vec![
+                (
+                    1706767200000000000,
+                    String::from("1234"),
+                    String::from("1"),
+                    300.0,
+                    Some(150.0)
+                ),
+                (
+                    1706767500000000000,
+                    String::from("1234"),
+                    String::from("2"),
+                    320.0,
+                    None
+                )
+            ]
+        );
+        assert_eq!(um.upload_manager.processed_files, 2);
+
+        std::fs::remove_dir_all(&base_path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn sqlite_upload_activity_weather_test() {
+        let base_path =
+            std::env::temp_dir().join(format!("garmin_weather_test_{}", std::process::id()));
+        let weather = base_path.join("weather");
+        std::fs::create_dir_all(&weather).unwrap();
+        std::fs::write(
+            weather.join("2024-02-01-1234.json"),
+            r#"{
+                "issueDate": "2024-02-01T06:00:00.000+0000",
+                "temp": 45, "apparentTemp": 40, "relativeHumidity": 70,
+                "windSpeed": 8, "windGust": null, "windDirection": 200,
+                "weatherTypeDTO": { "desc": "Cloudy" }
+            }"#,
+        )
+        .unwrap();
+        // an indoor activity
+        std::fs::write(weather.join("2024-02-01-5678.json"), "null").unwrap();
+        let db_path = base_path.join("garmin.db");
+
+        let mut um = sqlite_test_manager(&base_path);
+        um.upload_all().await;
+
+        let conn = rusqlite::Connection::open(&db_path).unwrap();
+        let rows: Vec<(i64, String, f64, f64, String)> = conn
+            .prepare(
+                "SELECT timestamp, activityId, temp, relativeHumidity, conditions \
+                 FROM activity_weather",
+            )
+            .unwrap()
+            .query_map([], |row| {
+                Ok((
+                    row.get(0)?,
+                    row.get(1)?,
+                    row.get(2)?,
+                    row.get(3)?,
+                    row.get(4)?,
+                ))
+            })
+            .unwrap()
+            .flatten()
+            .collect();
+        assert_eq!(
+            rows,
+            vec![(
+                1706767200000000000,
+                String::from("1234"),
+                45.0,
+                70.0,
+                String::from("Cloudy")
+            )]
+        );
+        assert_eq!(um.upload_manager.processed_files, 2);
+
+        std::fs::remove_dir_all(&base_path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn skip_corrupt_json_test() {
+        let base_path =
+            std::env::temp_dir().join(format!("garmin_corrupt_test_{}", std::process::id()));
+        let activities = base_path.join("activities");
+        let hrv = base_path.join("hrv");
+        std::fs::create_dir_all(&activities).unwrap();
+        std::fs::create_dir_all(&hrv).unwrap();
+        std::fs::write(
+            activities.join("2024-02-01-1234.json"),
+            r#"{
+                "activityId": 1234,
+                "activityName": "Morning Run",
+                "activityTypeDTO": { "typeKey": "running" },
+                "summaryDTO": { "startTimeLocal": "2024-02-01T00:00:00.0" }
+            }"#,
+        )
+        .unwrap();
+        // truncated by an interrupted download
+        std::fs::write(
+            activities.join("2024-02-01-5678.json"),
+            r#"{ "activityId": 5678, "summ"#,
+        )
+        .unwrap();
+        // a response shape the uploader doesn't know
+        std::fs::write(
+            activities.join("2024-02-01-9012.json"),
+            r#"{ "activityId": 9012, "activityTypeDTO": { "typeKey": "running" } }"#,
+        )
+        .unwrap();
+        std::fs::write(
+            activities.join("2024-02-01-3456.json"),
+            r#"{
+                "activityId": 3456,
+                "activityTypeDTO": { "typeKey": "running" },
+                "summaryDTO": { "startTimeLocal": 1706745600 }
+            }"#,
+        )
+        .unwrap();
+        std::fs::write(hrv.join("2024-02-01.json"), "").unwrap();
+        let db_path = base_path.join("garmin.db");
+
+        let mut um = sqlite_test_manager(&base_path);
+        um.upload_all().await;
+        assert_eq!(um.upload_manager.processed_files, 1);
+        assert_eq!(um.upload_manager.skipped_files.len(), 4);
+
+        let conn = rusqlite::Connection::open(&db_path).unwrap();
+        let count: i64 = conn
+            .query_row("SELECT COUNT(*) FROM activity_summary", [], |row| {
+                row.get(0)
+            })
+            .unwrap();
+        assert_eq!(count, 1);
+
+        std::fs::remove_dir_all(&base_path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn get_uploaded_activity_ids_test() {
+        let base_path =
+            std::env::temp_dir().join(format!("garmin_ids_test_{}", std::process::id()));
+        let mut um = sqlite_test_manager(&base_path).upload_manager;
+        let points: Vec<DataPoint> = [("10", 1), ("9", 2), ("10", 3)]
+            .iter()
+            .map(|(id, ts)| {
+                DataPoint::builder("activity_details")
+                    .tag("activityId", *id)
+                    .field("heart_rate", 120.0)
+                    .timestamp(*ts)
+                    .build()
+                    .unwrap()
+            })
+            .collect();
+        um.write_data(points).await;
+
+        assert_eq!(um.get_uploaded_activity_ids().await, vec!["9", "10"]);
+
+        std::fs::remove_dir_all(&base_path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn prune_uploaded_files_test() {
+        let base_path =
+            std::env::temp_dir().join(format!("garmin_prune_files_{}", std::process::id()));
+        let activities = base_path.join("activities");
+        std::fs::create_dir_all(&activities).unwrap();
+        let old = std::time::SystemTime::now() - std::time::Duration::from_secs(5 * 24 * 60 * 60);
+        for name in [
+            "1234_ACTIVITY.fit",
+            "5678_ACTIVITY.fit",
+            "9999_ACTIVITY.fit",
+        ] {
+            let file = std::fs::File::create(activities.join(name)).unwrap();
+            if name != "9999_ACTIVITY.fit" {
+                file.set_modified(old).unwrap();
+            }
+        }
+        let monitoring = base_path.join("monitoring");
+        std::fs::create_dir_all(&monitoring).unwrap();
+        for name in ["1000_WELLNESS.fit", "1000_HRV_STATUS.fit"] {
+            std::fs::write(monitoring.join(name), name).unwrap();
+            std::fs::File::options()
+                .write(true)
+                .open(monitoring.join(name))
+                .unwrap()
+                .set_modified(old)
+                .unwrap();
+        }
+
+        let mut um = sqlite_test_manager(&base_path).upload_manager;
+        um.influx_config.files_to_prune = vec![
+            PruneRule {
+                folder: String::from("activities"),
+                max_age_days: 1,
+            },
+            PruneRule {
+                folder: String::from("monitoring"),
+                max_age_days: 1,
+            },
+        ];
+        // an earlier run uploaded the WELLNESS file
+        let wellness = monitoring.join("1000_WELLNESS.fit");
+        um.upload_state.record(
+            upload_state::file_key("WELLNESS", &wellness),
+            upload_state::file_hash(&wellness).unwrap(),
+        );
+        // only 1234 and 9999 made it into the backend, and 9999 is too recent
+        let points: Vec<DataPoint> = ["1234", "9999"]
+            .iter()
+            .map(|id| {
+                DataPoint::builder("activity_details")
+                    .tag("activityId", *id)
+                    .field("heart_rate", 120.0)
+                    .timestamp(1)
+                    .build()
+                    .unwrap()
+            })
+            .collect();
+        um.write_data(points).await;
+
+        um.prune_files(true).await;
+        assert!(activities.join("1234_ACTIVITY.fit").exists());
+
+        um.prune_files(false).await;
+        assert!(!activities.join("1234_ACTIVITY.fit").exists());
+        assert!(activities.join("5678_ACTIVITY.fit").exists());
+        assert!(activities.join("9999_ACTIVITY.fit").exists());
+        assert!(!wellness.exists());
+        assert!(monitoring.join("1000_HRV_STATUS.fit").exists());
+
+        std::fs::remove_dir_all(&base_path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn archive_pruned_files_test() {
+        let base_path =
+            std::env::temp_dir().join(format!("garmin_archive_files_{}", std::process::id()));
+        let sleep = base_path.join("sleep");
+        std::fs::create_dir_all(&sleep).unwrap();
+        let old = std::time::SystemTime::now() - std::time::Duration::from_secs(5 * 24 * 60 * 60);
+        for name in ["2024-01-31.json", "2024-02-01.json", "2024-02-02.json"] {
+            std::fs::write(sleep.join(name), format!("{{\"file\": \"{}\"}}", name)).unwrap();
+            if name != "2024-02-02.json" {
+                std::fs::File::options()
+                    .write(true)
+                    .open(sleep.join(name))
+                    .unwrap()
+                    .set_modified(old)
+                    .unwrap();
+            }
+        }
+
+        let mut um = sqlite_test_manager(&base_path).upload_manager;
+        um.influx_config.archive_downloads = true;
+        um.influx_config.files_to_prune = vec![PruneRule {
+            folder: String::from("sleep"),
+            max_age_days: 1,
+        }];
+        // as if written by the last upload, unlike 2024-01-31.json
+        for name in ["2024-02-01.json", "2024-02-02.json"] {
+            let path = std::fs::canonicalize(sleep.join(name)).unwrap();
+            um.uploaded_files.insert(path);
+        }
+
+        um.prune_files(false).await;
+        assert!(sleep.join("2024-01-31.json").exists());
+        assert!(!sleep.join("2024-02-01.json").exists());
+        assert!(sleep.join("2024-02-02.json").exists());
+
+        let archive_path = archive::archive_for(&base_path.join("archive"), old);
+        assert_eq!(
+            archive::read_entry(&archive_path, "sleep/2024-02-01.json").unwrap(),
+            Some(br#"{"file": "2024-02-01.json"}"#.to_vec())
+        );
+
+        std::fs::remove_dir_all(&base_path).unwrap();
+    }
+
+    // serves each canned HTTP response in turn on a local port, returning its url
+    fn serve(responses: &'static [&'static [u8]]) -> String {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        std::thread::spawn(move || {
+            for response in responses {
+                let (mut stream, _) = listener.accept().unwrap();
+                let mut request = [0u8; 1024];
+                let _ = std::io::Read::read(&mut stream, &mut request);
+                std::io::Write::write_all(&mut stream, response).unwrap();
+            }
+        });
+        url
+    }
+
+    #[tokio::test]
+    async fn validate_test() {
+        let config_with_url = |url: &str| {
+            Config::builder()
+                .add_source(File::new(
+                    current_dir()
+                        .unwrap()
+                        .join("..")
+                        .join("config")
+                        .join("influxdb_config.json")
+                        .to_str()
+                        .unwrap(),
+                    FileFormat::Json,
+                ))
+                .set_override("url", url)
+                .unwrap()
+                .build()
+                .unwrap()
+        };
+        const UNAUTHORIZED: &[u8] =
+            b"HTTP/1.1 401 Unauthorized\r\nContent-Length: 2\r\nConnection: close\r\n\r\n{}";
+
+        // can't list buckets or write
+        let url = serve(&[UNAUTHORIZED, UNAUTHORIZED]);
+        let mut um = UploadManager::new(config_with_url(&url));
+        assert!(um.validate().await.unwrap_err().contains("token"));
+
+        // can't list buckets, but is allowed to write (and is only told it has no points)
+        let url = serve(&[
+            UNAUTHORIZED,
+            b"HTTP/1.1 400 Bad Request\r\nContent-Length: 2\r\nConnection: close\r\n\r\n{}",
+        ]);
+        let mut um = UploadManager::new(config_with_url(&url));
+        assert!(um.validate().await.is_ok());
+
+        let url = serve(&[
+            b"HTTP/1.1 200 OK\r\nContent-Type: application/json\r\nContent-Length: 14\r\nConnection: close\r\n\r\n{\"buckets\":[]}",
+            b"HTTP/1.1 404 Not Found\r\nContent-Length: 2\r\nConnection: close\r\n\r\n{}",
+        ]);
+        let mut um = UploadManager::new(config_with_url(&url));
+        assert!(um.validate().await.unwrap_err().contains("bucket"));
+
+        // nothing to check when writing to a file
+        let mut um = UploadManager::new(config_with_url("http://127.0.0.1:1"));
+        um.influx_config.offline_mode = true;
+        assert!(um.validate().await.is_ok());
+
+        // and nothing is uploaded without a working connection
+        let base_path =
+            std::env::temp_dir().join(format!("garmin_validate_test_{}", std::process::id()));
+        let sleep = base_path.join("sleep");
+        std::fs::create_dir_all(&sleep).unwrap();
+        std::fs::write(sleep.join("2024-02-01.json"), "{}").unwrap();
+        let mut um = UploadManager::new(config_with_url("http://127.0.0.1:1"));
+        um.influx_config.file_base_path = String::from(base_path.to_str().unwrap());
+        um.upload_all().await;
+        assert_eq!(um.processed_files, 0);
+        assert!(!um.offline_file().exists());
+
+        std::fs::remove_dir_all(&base_path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn offline_line_protocol_test() {
+        let base_path = std::env::temp_dir().join(format!("garmin_offline_{}", std::process::id()));
+        std::fs::create_dir_all(&base_path).unwrap();
+        let offline_file = base_path.join("garmin_offline.lp");
+
+        let config = Config::builder()
+            .add_source(File::new(
+                current_dir()
+                    .unwrap()
+                    .join("..")
+                    .join("config")
+                    .join("influxdb_config.json")
+                    .to_str()
+                    .unwrap(),
+                FileFormat::Json,
+            ))
+            // nothing listens here, so writes fail
+            .set_override("url", "http://127.0.0.1:1")
+            .unwrap()
+            .set_override("offline_file", offline_file.to_str().unwrap())
+            .unwrap()
+            .build()
+            .unwrap();
+        let mut um = UploadManager::new(config);
+        let point = |value: f64| {
+            DataPoint::builder("weight")
+                .field("weight", value)
+                .timestamp(1)
+                .build()
+                .unwrap()
+        };
+
+        // a failed write falls back to the offline file
+        assert!(um.write_data(vec![point(70.0)]).await);
+        um.influx_config.offline_mode = true;
+        assert!(um.write_data(vec![point(71.0)]).await);
+
+        let contents = std::fs::read_to_string(&offline_file).unwrap();
+        assert_eq!(
+            contents.lines().collect::<Vec<&str>>(),
+            vec!["weight weight=70 1", "weight weight=71 1"]
+        );
+
+        std::fs::remove_dir_all(&base_path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn batch_writes_test() {
+        let base_path = std::env::temp_dir().join(format!("garmin_batch_{}", std::process::id()));
+
+        let mut um = sqlite_test_manager(&base_path).upload_manager;
+        um.influx_config.batch_size = 2;
+        let point = |timestamp: i64| {
+            DataPoint::builder("weight")
+                .field("weight", 70.0)
+                .timestamp(timestamp)
+                .build()
+                .unwrap()
+        };
+        let conn = rusqlite::Connection::open(base_path.join("garmin.db")).unwrap();
+        let rows = || -> i64 {
+            conn.query_row("SELECT COUNT(*) FROM weight", [], |row| row.get(0))
+                .unwrap_or(0)
+        };
+
+        um.queue_data(vec![point(1)]).await;
+        assert_eq!(rows(), 0);
+        um.queue_data(vec![point(2), point(3)]).await;
+        assert_eq!(rows(), 2);
+        // the partial batch is only written on flush
+        assert!(um.flush_data().await);
+        assert_eq!(rows(), 3);
+        assert!(um.pending_points.is_empty());
+
+        std::fs::remove_dir_all(&base_path).unwrap();
+    }
+
+    #[test]
+    fn lap_records_measurement_test() {
+        let base_path = std::env::temp_dir().join(format!("garmin_laps_{}", std::process::id()));
+        let mut um = sqlite_test_manager(&base_path).upload_manager;
+        let record = |kind: &str| FitRecord {
+            kind: String::from(kind),
+            timestamp: Some(1_000_000_000),
+            fields: [
+                (String::from("message_index"), fitparser::Value::UInt16(2)),
+                (String::from("avg_heart_rate"), fitparser::Value::UInt8(150)),
+            ]
+            .into_iter()
+            .collect(),
+        };
+        let tags = [(String::from("activityId"), String::from("1234"))];
+
+        let points = um.fit_datapoints(
+            vec![record("record"), record("lap")],
+            "activity_details",
+            &tags,
+            None,
+        );
+        let points: Vec<_> = points
+            .iter()
+            .map(|point| line_protocol::parse_point(point).unwrap())
+            .collect();
+        assert_eq!(points[0].measurement, "activity_details");
+        assert!(!points[0].tags.contains_key("lapIndex"));
+        assert_eq!(points[1].measurement, "activity_laps");
+        assert_eq!(points[1].tags["lapIndex"], "2");
+        assert_eq!(points[1].tags["activityId"], "1234");
+
+        // without a mapping, laps stay in activity_details
+        um.influx_config.record_measurements.clear();
+        let points = um.fit_datapoints(vec![record("lap")], "activity_details", &tags, None);
+        let point = line_protocol::parse_point(&points[0]).unwrap();
+        assert_eq!(point.measurement, "activity_details");
+        assert!(!point.tags.contains_key("lapIndex"));
+
+        std::fs::remove_dir_all(&base_path).unwrap();
+    }
+
+    #[test]
+    fn time_in_zone_records_measurement_test() {
+        let base_path = std::env::temp_dir().join(format!("garmin_zones_{}", std::process::id()));
+        let um = sqlite_test_manager(&base_path).upload_manager;
+        let zones = FitRecord {
+            kind: String::from("time_in_zone"),
+            timestamp: Some(1_000_000_000),
+            fields: [
+                (
+                    String::from("reference_mesg"),
+                    fitparser::Value::String(String::from("session")),
+                ),
+                (String::from("reference_index"), fitparser::Value::UInt16(0)),
+                (
+                    String::from("time_in_hr_zone"),
+                    fitparser::Value::Array(vec![
+                        fitparser::Value::Float64(60.0),
+                        fitparser::Value::Float64(1200.5),
+                        fitparser::Value::Float64(300.0),
+                    ]),
+                ),
+            ]
+            .into_iter()
+            .collect(),
+        };
+        let tags = [(String::from("activityId"), String::from("1234"))];
+
+        let points = um.fit_datapoints(vec![zones], "activity_details", &tags, None);
+        let point = line_protocol::parse_point(&points[0]).unwrap();
+        assert_eq!(point.measurement, "activity_zones");
+        assert_eq!(point.tags["activityId"], "1234");
+        assert_eq!(point.tags["referenceMesg"], "session");
+        assert_eq!(point.tags["referenceIndex"], "0");
+        assert_eq!(point.fields["time_in_hr_zone_0"], FieldValue::F64(60.0));
+        assert_eq!(point.fields["time_in_hr_zone_1"], FieldValue::F64(1200.5));
+        assert_eq!(point.fields["time_in_hr_zone_2"], FieldValue::F64(300.0));
+        assert!(!point.fields.contains_key("time_in_hr_zone"));
+
+        std::fs::remove_dir_all(&base_path).unwrap();
     }
 }
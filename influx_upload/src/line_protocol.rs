@@ -0,0 +1,290 @@
+use std::collections::{BTreeMap, HashMap};
+use std::fs::OpenOptions;
+use std::io::{self, Write};
+use std::path::Path;
+
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use influxdb2::models::data_point::{DataPoint, FieldValue, WriteDataPoint};
+
+// influxdb2 keeps the contents of a DataPoint private and only exposes its
+// line protocol serialization, so any non-influx sink has to go through here.
+
+/// Owned, inspectable copy of a DataPoint.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ParsedPoint {
+    pub measurement: String,
+    pub tags: BTreeMap<String, String>,
+    pub fields: BTreeMap<String, FieldValue>,
+    pub timestamp: Option<i64>,
+}
+
+/// Renders a single DataPoint as a line protocol string (including the trailing newline).
+pub fn to_line_protocol(point: &DataPoint) -> String {
+    let mut buffer: Vec<u8> = Vec::new();
+    // writing into a Vec can't fail
+    point.write_data_point_to(&mut buffer).unwrap();
+    String::from_utf8_lossy(&buffer).to_string()
+}
+
+/// Renders the points as line protocol, one per line.
+pub fn encode(points: &[DataPoint]) -> Vec<u8> {
+    let mut buffer: Vec<u8> = Vec::new();
+    for point in points {
+        // writing into a Vec can't fail
+        point.write_data_point_to(&mut buffer).unwrap();
+    }
+    buffer
+}
+
+/// Renders the points as gzip compressed line protocol, for 'compress_writes'.
+pub fn encode_gzip(points: &[DataPoint]) -> Vec<u8> {
+    let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+    // writing into a Vec can't fail
+    encoder.write_all(&encode(points)).unwrap();
+    encoder.finish().unwrap()
+}
+
+/// Appends the points to a line protocol file at 'path', e.g. for a later `influx write --file`.
+pub fn append_to_file(path: &Path, points: &[DataPoint]) -> io::Result<()> {
+    let mut file = OpenOptions::new().create(true).append(true).open(path)?;
+    file.write_all(&encode(points))
+}
+
+/// Converts a DataPoint into a ParsedPoint by round tripping through line protocol.
+pub fn parse_point(point: &DataPoint) -> Option<ParsedPoint> {
+    parse_line(&to_line_protocol(point))
+}
+
+/// A copy of 'point' with 'tags' added, keeping the point's own value for any tag it already has.
+pub fn with_tags(point: DataPoint, tags: &HashMap<String, String>) -> DataPoint {
+    add_tags(point, |_| {
+        tags.iter()
+            .map(|(key, value)| (key.clone(), value.clone()))
+            .collect()
+    })
+}
+
+/// Like with_tags(), with the tags worked out from the point itself, e.g. its timestamp.
+pub fn add_tags(
+    point: DataPoint,
+    tags: impl FnOnce(&ParsedPoint) -> Vec<(String, String)>,
+) -> DataPoint {
+    let Some(mut parsed) = parse_point(&point) else {
+        return point;
+    };
+    for (key, value) in tags(&parsed) {
+        parsed.tags.entry(key).or_insert(value);
+    }
+    parsed.to_point().unwrap_or(point)
+}
+
+impl ParsedPoint {
+    /// Builds a DataPoint back up, None if there are no fields.
+    pub fn to_point(&self) -> Option<DataPoint> {
+        let mut builder = DataPoint::builder(&self.measurement);
+        for (key, value) in &self.tags {
+            builder = builder.tag(key, value);
+        }
+        for (key, value) in &self.fields {
+            builder = builder.field(key, value.clone());
+        }
+        if let Some(timestamp) = self.timestamp {
+            builder = builder.timestamp(timestamp);
+        }
+        builder.build().ok()
+    }
+}
+
+/// Parses one line of line protocol, as written by influxdb2's WriteDataPoint.
+pub fn parse_line(line: &str) -> Option<ParsedPoint> {
+    let sections = split_unescaped(line.trim_end_matches('\n'), ' ', true);
+    if sections.len() < 2 || sections.len() > 3 {
+        return None;
+    }
+
+    let mut series = split_unescaped(&sections[0], ',', false).into_iter();
+    let measurement = unescape(&series.next()?);
+    let mut tags: BTreeMap<String, String> = BTreeMap::new();
+    for tag in series {
+        let (key, value) = split_key_value(&tag)?;
+        tags.insert(unescape(&key), unescape(&value));
+    }
+
+    let mut fields: BTreeMap<String, FieldValue> = BTreeMap::new();
+    for field in split_unescaped(&sections[1], ',', true) {
+        let (key, value) = split_key_value(&field)?;
+        fields.insert(unescape(&key), parse_field_value(&value)?);
+    }
+
+    let timestamp = match sections.get(2) {
+        Some(ts) => Some(ts.parse::<i64>().ok()?),
+        None => None,
+    };
+
+    Some(ParsedPoint {
+        measurement,
+        tags,
+        fields,
+        timestamp,
+    })
+}
+
+fn parse_field_value(value: &str) -> Option<FieldValue> {
+    if value.len() >= 2 && value.starts_with('"') && value.ends_with('"') {
+        return Some(FieldValue::String(
+            value[1..value.len() - 1].replace("\\\"", "\""),
+        ));
+    }
+    match value {
+        "t" | "true" => Some(FieldValue::Bool(true)),
+        "f" | "false" => Some(FieldValue::Bool(false)),
+        _ => {
+            if let Some(int) = value.strip_suffix('i') {
+                int.parse::<i64>().ok().map(FieldValue::I64)
+            } else {
+                value.parse::<f64>().ok().map(FieldValue::F64)
+            }
+        }
+    }
+}
+
+// splits on the first '=' that isn't escaped
+fn split_key_value(pair: &str) -> Option<(String, String)> {
+    let mut escaped = false;
+    for (idx, c) in pair.char_indices() {
+        if escaped {
+            escaped = false;
+        } else if c == '\\' {
+            escaped = true;
+        } else if c == '=' {
+            return Some((pair[..idx].to_string(), pair[idx + 1..].to_string()));
+        }
+    }
+    None
+}
+
+// splits on a delimiter, ignoring escaped delimiters and (optionally) those inside quoted strings
+fn split_unescaped(text: &str, delimiter: char, respect_quotes: bool) -> Vec<String> {
+    let mut parts: Vec<String> = Vec::new();
+    let mut current = String::new();
+    let mut escaped = false;
+    let mut quoted = false;
+    for c in text.chars() {
+        if escaped {
+            current.push(c);
+            escaped = false;
+        } else if c == '\\' {
+            current.push(c);
+            escaped = true;
+        } else if c == '"' && respect_quotes {
+            current.push(c);
+            quoted = !quoted;
+        } else if c == delimiter && !quoted {
+            parts.push(current);
+            current = String::new();
+        } else {
+            current.push(c);
+        }
+    }
+    parts.push(current);
+    parts
+}
+
+fn unescape(text: &str) -> String {
+    let mut result = String::with_capacity(text.len());
+    let mut escaped = false;
+    for c in text.chars() {
+        if escaped {
+            if ![',', '=', ' '].contains(&c) {
+                result.push('\\');
+            }
+            result.push(c);
+            escaped = false;
+        } else if c == '\\' {
+            escaped = true;
+        } else {
+            result.push(c);
+        }
+    }
+    if escaped {
+        result.push('\\');
+    }
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn round_trip_point_test() {
+        let point = DataPoint::builder("activity summary")
+            .tag("activityName", "trail,running")
+            .tag("activityId", "1234")
+            .field("name", "Morning \"Run\", with spaces")
+            .field("distance", 1234.5)
+            .field("steps", 42i64)
+            .field("manual", false)
+            .timestamp(1706745600000000000)
+            .build()
+            .unwrap();
+
+        let parsed = parse_point(&point).unwrap();
+        assert_eq!(parsed.measurement, "activity summary");
+        assert_eq!(parsed.tags["activityName"], "trail,running");
+        assert_eq!(parsed.tags["activityId"], "1234");
+        assert_eq!(
+            parsed.fields["name"],
+            FieldValue::String(String::from("Morning \"Run\", with spaces"))
+        );
+        assert_eq!(parsed.fields["distance"], FieldValue::F64(1234.5));
+        assert_eq!(parsed.fields["steps"], FieldValue::I64(42));
+        assert_eq!(parsed.fields["manual"], FieldValue::Bool(false));
+        assert_eq!(parsed.timestamp, Some(1706745600000000000));
+    }
+
+    #[test]
+    fn with_tags_test() {
+        let point = DataPoint::builder("monitoring")
+            .tag("source", "fenix")
+            .field("heart_rate", 60i64)
+            .timestamp(1)
+            .build()
+            .unwrap();
+        let tags = HashMap::from([
+            (String::from("source"), String::from("garmin")),
+            (String::from("user"), String::from("alex")),
+        ]);
+
+        let tagged = parse_point(&with_tags(point, &tags)).unwrap();
+        assert_eq!(tagged.tags["source"], "fenix");
+        assert_eq!(tagged.tags["user"], "alex");
+        assert_eq!(tagged.fields["heart_rate"], FieldValue::I64(60));
+        assert_eq!(tagged.timestamp, Some(1));
+    }
+
+    #[test]
+    fn append_to_file_test() {
+        let path = std::env::temp_dir().join(format!("garmin_lp_test_{}.lp", std::process::id()));
+        let point = |value: f64| {
+            DataPoint::builder("weight")
+                .field("weight", value)
+                .timestamp(1)
+                .build()
+                .unwrap()
+        };
+        append_to_file(&path, &[point(70.0)]).unwrap();
+        append_to_file(&path, &[point(71.0), point(72.0)]).unwrap();
+
+        let contents = std::fs::read_to_string(&path).unwrap();
+        let lines: Vec<&str> = contents.lines().collect();
+        assert_eq!(lines.len(), 3);
+        assert_eq!(
+            parse_line(lines[2]).unwrap().fields["weight"],
+            FieldValue::F64(72.0)
+        );
+
+        std::fs::remove_file(&path).unwrap();
+    }
+}
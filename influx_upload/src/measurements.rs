@@ -0,0 +1,80 @@
+use std::collections::HashMap;
+
+/// Datasets written by the uploader, each renameable via the 'measurement_names' config.
+pub const DATASETS: [&str; 16] = [
+    "activity_summary",
+    "activity_details",
+    "monitoring",
+    "hrv",
+    "hydration",
+    "splits",
+    "activity_weather",
+    "sleep",
+    "spo2",
+    "floors",
+    "intensity_minutes",
+    "vo2max",
+    "training_status",
+    "resting_heart_rate",
+    "weight",
+    "daily_summary",
+];
+
+/// Checks the 'measurement_names' config, e.g. { "monitoring": "garmin_monitoring" }.
+pub fn validate(names: &HashMap<String, String>) -> Result<(), String> {
+    for (dataset, name) in names {
+        if !DATASETS.contains(&dataset.as_str()) {
+            return Err(format!(
+                "unknown dataset '{}' in measurement_names, expected one of: {}",
+                dataset,
+                DATASETS.join(", ")
+            ));
+        }
+        if name.trim().is_empty() {
+            return Err(format!("empty measurement name for '{}'", dataset));
+        }
+    }
+    Ok(())
+}
+
+/// Checks the 'record_measurements' config, e.g. { "lap": "activity_laps" }.
+pub fn validate_records(records: &HashMap<String, String>) -> Result<(), String> {
+    match records.iter().find(|(_, name)| name.trim().is_empty()) {
+        Some((kind, _)) => Err(format!("empty measurement name for '{}' records", kind)),
+        None => Ok(()),
+    }
+}
+
+/// The measurement 'dataset' is written to, the dataset name itself unless renamed.
+pub fn resolve<'a>(names: &'a HashMap<String, String>, dataset: &'a str) -> &'a str {
+    names.get(dataset).map_or(dataset, String::as_str)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn measurement_names_test() {
+        let mut names = HashMap::from([(
+            String::from("monitoring"),
+            String::from("garmin_monitoring"),
+        )]);
+        assert!(validate(&names).is_ok());
+        assert_eq!(resolve(&names, "monitoring"), "garmin_monitoring");
+        assert_eq!(resolve(&names, "hrv"), "hrv");
+
+        names.insert(String::from("hrv"), String::from(" "));
+        assert!(validate(&names).is_err());
+        names.remove("hrv");
+        names.insert(String::from("sleep"), String::from("garmin_sleep"));
+        assert!(validate(&names).is_ok());
+        names.insert(String::from("stress"), String::from("stress"));
+        assert!(validate(&names).is_err());
+
+        let mut records = HashMap::from([(String::from("lap"), String::from("activity_laps"))]);
+        assert!(validate_records(&records).is_ok());
+        records.insert(String::from("session"), String::new());
+        assert!(validate_records(&records).is_err());
+    }
+}
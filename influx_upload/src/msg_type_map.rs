@@ -1,7 +1,6 @@
 use std::collections::{HashMap, HashSet};
 
 // this map provides all (currently known) FitDataFields for each FitDataRecordType
-#[allow(dead_code)]
 pub fn get_activity_map() -> HashMap<&'static str, HashSet<&'static str>> {
     HashMap::from([
         (
@@ -317,7 +316,6 @@ pub fn get_activity_map() -> HashMap<&'static str, HashSet<&'static str>> {
     ])
 }
 
-#[allow(dead_code)]
 pub fn get_monitoring_map() -> HashMap<&'static str, HashSet<&'static str>> {
     HashMap::from([
         (
@@ -448,3 +446,29 @@ pub fn get_monitoring_map() -> HashMap<&'static str, HashSet<&'static str>> {
         ("hrv_value", HashSet::from(["value", "timestamp"])),
     ])
 }
+
+/// The known fields for the FIT files uploaded under 'measurement', if there's a map for it.
+pub fn get_map_for_measurement(
+    measurement: &str,
+) -> Option<HashMap<&'static str, HashSet<&'static str>>> {
+    match measurement {
+        "activity_details" => Some(get_activity_map()),
+        "monitoring" => Some(get_monitoring_map()),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn get_map_for_measurement_test() {
+        let monitoring = get_map_for_measurement("monitoring").unwrap();
+        assert!(monitoring["monitoring"].contains("heart_rate"));
+        assert!(get_map_for_measurement("activity_details")
+            .unwrap()
+            .contains_key("record"));
+        assert!(get_map_for_measurement("sleep").is_none());
+    }
+}
@@ -0,0 +1,187 @@
+use std::collections::BTreeMap;
+use std::fs::{self, File};
+use std::io::{BufWriter, Write};
+use std::path::Path;
+
+use futures::future::{self, BoxFuture};
+use influxdb2::models::data_point::{DataPoint, FieldValue};
+use log::{info, warn};
+
+use crate::line_protocol;
+use crate::sink::{DataSink, WriteError};
+
+const METRIC_PREFIX: &str = "garmin";
+
+struct Sample {
+    timestamp: i64,
+    value: f64,
+}
+
+/// Collects datapoints and writes them as a Prometheus textfile-collector (.prom) file.
+///
+/// The textfile collector doesn't accept timestamps, so only the most recent value
+/// of each series (metric name + label set) is kept. Influx tags become labels and
+/// each numeric field becomes a gauge named garmin_<measurement>_<field>.
+pub struct PrometheusExporter {
+    path: String,
+    // metric name -> (rendered label set -> latest sample)
+    metrics: BTreeMap<String, BTreeMap<String, Sample>>,
+    help: BTreeMap<String, String>,
+}
+
+impl PrometheusExporter {
+    pub fn new(path: &str) -> PrometheusExporter {
+        PrometheusExporter {
+            path: String::from(path),
+            metrics: BTreeMap::new(),
+            help: BTreeMap::new(),
+        }
+    }
+
+    pub fn add(&mut self, data: &[DataPoint]) {
+        for point in data {
+            let Some(parsed) = line_protocol::parse_point(point) else {
+                warn!("Unable to convert datapoint for prometheus: {:?}", point);
+                continue;
+            };
+            let labels = render_labels(&parsed.tags);
+            let timestamp = parsed.timestamp.unwrap_or(0);
+
+            for (field, value) in &parsed.fields {
+                let value = match value {
+                    FieldValue::F64(v) => *v,
+                    FieldValue::I64(v) => *v as f64,
+                    FieldValue::Bool(v) => {
+                        if *v {
+                            1.0
+                        } else {
+                            0.0
+                        }
+                    }
+                    // prometheus has no notion of string samples
+                    FieldValue::String(_) => continue,
+                };
+
+                let name = format!(
+                    "{}_{}_{}",
+                    METRIC_PREFIX,
+                    sanitize_name(&parsed.measurement),
+                    sanitize_name(field)
+                );
+                self.help
+                    .entry(name.clone())
+                    .or_insert(format!("Garmin {} field '{}'", parsed.measurement, field));
+
+                let series = self.metrics.entry(name).or_default();
+                match series.get(&labels) {
+                    Some(sample) if sample.timestamp > timestamp => {}
+                    _ => {
+                        series.insert(labels.clone(), Sample { timestamp, value });
+                    }
+                }
+            }
+        }
+    }
+
+    /// Writes all collected series to the configured .prom file.
+    ///
+    /// The file is written next to the target and renamed into place so the
+    /// node exporter never scrapes a half written file.
+    pub fn flush(&self) -> std::io::Result<()> {
+        let tmp_path = format!("{}.tmp", self.path);
+        {
+            let mut writer = BufWriter::new(File::create(&tmp_path)?);
+            writer.write_all(self.render().as_bytes())?;
+            writer.flush()?;
+        }
+        fs::rename(&tmp_path, Path::new(&self.path))?;
+        info!(
+            "Wrote {} prometheus metrics to {}",
+            self.metrics.len(),
+            self.path
+        );
+        Ok(())
+    }
+
+    fn render(&self) -> String {
+        let mut output = String::new();
+        for (name, series) in &self.metrics {
+            output.push_str(&format!("# HELP {} {}\n", name, self.help[name]));
+            output.push_str(&format!("# TYPE {} gauge\n", name));
+            for (labels, sample) in series {
+                output.push_str(&format!("{}{} {}\n", name, labels, sample.value));
+            }
+        }
+        output
+    }
+}
+
+impl DataSink for PrometheusExporter {
+    fn write(&mut self, points: Vec<DataPoint>) -> BoxFuture<'_, Result<(), WriteError>> {
+        self.add(&points);
+        Box::pin(future::ready(Ok(())))
+    }
+
+    fn flush(&mut self) -> Result<(), String> {
+        PrometheusExporter::flush(self)
+            .map_err(|e| format!("unable to write prometheus file {}: {}", self.path, e))
+    }
+}
+
+fn render_labels(tags: &BTreeMap<String, String>) -> String {
+    if tags.is_empty() {
+        return String::new();
+    }
+    let labels: Vec<String> = tags
+        .iter()
+        .map(|(key, value)| {
+            format!(
+                "{}=\"{}\"",
+                sanitize_name(key),
+                value
+                    .replace('\\', "\\\\")
+                    .replace('"', "\\\"")
+                    .replace('\n', "\\n")
+            )
+        })
+        .collect();
+    format!("{{{}}}", labels.join(","))
+}
+
+// metric and label names may only contain [a-zA-Z0-9_]
+fn sanitize_name(name: &str) -> String {
+    name.chars()
+        .map(|c| if c.is_ascii_alphanumeric() { c } else { '_' })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn keeps_latest_sample_test() {
+        let mut exporter = PrometheusExporter::new("unused.prom");
+        let older = DataPoint::builder("monitoring")
+            .tag("metric", "WELLNESS")
+            .field("heart_rate", 61.0)
+            .field("activity_type", "walking")
+            .timestamp(1)
+            .build()
+            .unwrap();
+        let newer = DataPoint::builder("monitoring")
+            .tag("metric", "WELLNESS")
+            .field("heart_rate", 72.0)
+            .timestamp(2)
+            .build()
+            .unwrap();
+        exporter.add(&[newer, older]);
+
+        assert_eq!(
+            exporter.render(),
+            "# HELP garmin_monitoring_heart_rate Garmin monitoring field 'heart_rate'\n\
+             # TYPE garmin_monitoring_heart_rate gauge\n\
+             garmin_monitoring_heart_rate{metric=\"WELLNESS\"} 72\n"
+        );
+    }
+}
@@ -0,0 +1,152 @@
+use std::ffi::OsStr;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+
+use log::warn;
+use serde::{Deserialize as _, Deserializer};
+use serde_derive::{Deserialize, Serialize};
+
+// only files we actually upload are ever pruned, plus the zips activities come in
+const PRUNABLE_EXTENSIONS: [&str; 3] = ["json", "fit", "zip"];
+
+/// One entry of the 'files_to_prune' config, e.g. { "folder": "monitoring", "max_age_days": 30 }
+#[derive(Debug, Deserialize, Default, Serialize, Clone)]
+pub struct PruneRule {
+    /// Subfolder of file_base_path, e.g. "activities"
+    pub folder: String,
+    pub max_age_days: u64,
+}
+
+// an entry of 'files_to_prune', which used to be a list of file extensions
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum ConfigEntry {
+    Rule(PruneRule),
+    Extension(String),
+}
+
+/// Reads 'files_to_prune', ignoring (with a warning) the file extensions older configs
+/// list there, e.g. [".zip", ".json", ".fit"], since they don't say how old a file may get.
+pub fn deserialize_rules<'de, D>(deserializer: D) -> Result<Vec<PruneRule>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let entries: Vec<ConfigEntry> = Vec::deserialize(deserializer)?;
+    Ok(entries
+        .into_iter()
+        .filter_map(|entry| match entry {
+            ConfigEntry::Rule(rule) => Some(rule),
+            ConfigEntry::Extension(extension) => {
+                warn!(
+                    "Ignoring '{}' in files_to_prune, entries are now e.g. {{ \"folder\": \"monitoring\", \"max_age_days\": 7 }}",
+                    extension
+                );
+                None
+            }
+        })
+        .collect())
+}
+
+/// Returns the JSON/FIT/zip files in 'folder' last modified more than 'max_age_days' before 'now'.
+pub fn expired_files(folder: &Path, max_age_days: u64, now: SystemTime) -> Vec<PathBuf> {
+    // an age too large to count back to is older than any file, so nothing is pruned
+    let Some(cutoff) = max_age_days
+        .checked_mul(24 * 60 * 60)
+        .and_then(|secs| now.checked_sub(Duration::from_secs(secs)))
+    else {
+        return vec![];
+    };
+    let Ok(entries) = folder.read_dir() else {
+        warn!("Unable to read folder {} for pruning", folder.display());
+        return vec![];
+    };
+    let mut files: Vec<PathBuf> = entries
+        .flatten()
+        .map(|entry| entry.path())
+        .filter(|path| {
+            path.extension()
+                .and_then(OsStr::to_str)
+                .is_some_and(|ext| PRUNABLE_EXTENSIONS.contains(&ext))
+        })
+        .filter(|path| {
+            path.metadata()
+                .and_then(|metadata| metadata.modified())
+                .is_ok_and(|modified| modified < cutoff)
+        })
+        .collect();
+    files.sort();
+    files
+}
+
+/// Pulls the activity ID out of an activity file name, e.g. '1234_ACTIVITY.fit' or '2024-02-01-1234.json'.
+pub fn activity_id_from_filename(path: &Path) -> Option<String> {
+    let stem = path.file_stem()?.to_str()?;
+    stem.split(|c: char| !c.is_ascii_digit())
+        .rfind(|part| !part.is_empty())
+        .map(String::from)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    #[test]
+    fn expired_files_test() {
+        let folder = std::env::temp_dir().join(format!("garmin_prune_test_{}", std::process::id()));
+        fs::create_dir_all(&folder).unwrap();
+        let now = SystemTime::now();
+        let old = now - Duration::from_secs(10 * 24 * 60 * 60);
+        for name in ["old.json", "old.fit", "old.zip", "old.tcx", "new.json"] {
+            let file = fs::File::create(folder.join(name)).unwrap();
+            if name.starts_with("old") {
+                file.set_modified(old).unwrap();
+            }
+        }
+
+        assert_eq!(
+            expired_files(&folder, 7, now),
+            vec![
+                folder.join("old.fit"),
+                folder.join("old.json"),
+                folder.join("old.zip")
+            ]
+        );
+        assert!(expired_files(&folder, 30, now).is_empty());
+        // further back than SystemTime goes, or too many seconds for a u64
+        assert!(expired_files(&folder, u64::MAX / (24 * 60 * 60), now).is_empty());
+        assert!(expired_files(&folder, u64::MAX, now).is_empty());
+        assert!(expired_files(&folder.join("missing"), 7, now).is_empty());
+
+        fs::remove_dir_all(&folder).unwrap();
+    }
+
+    #[test]
+    fn old_config_format_test() {
+        #[derive(Deserialize)]
+        struct Config {
+            #[serde(deserialize_with = "deserialize_rules")]
+            files_to_prune: Vec<PruneRule>,
+        }
+        let config: Config = serde_json::from_str(
+            r#"{ "files_to_prune": [".zip", { "folder": "monitoring", "max_age_days": 7 }] }"#,
+        )
+        .unwrap();
+        assert_eq!(config.files_to_prune.len(), 1);
+        assert_eq!(config.files_to_prune[0].folder, "monitoring");
+        assert_eq!(config.files_to_prune[0].max_age_days, 7);
+    }
+
+    #[test]
+    fn activity_id_from_filename_test() {
+        assert_eq!(
+            activity_id_from_filename(Path::new("/data/activities/1234_ACTIVITY.fit")),
+            Some(String::from("1234"))
+        );
+        assert_eq!(
+            activity_id_from_filename(Path::new("/data/activities/2024-02-01-5678.json")),
+            Some(String::from("5678"))
+        );
+        assert_eq!(activity_id_from_filename(Path::new("notes.json")), None);
+    }
+}
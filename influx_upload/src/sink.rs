@@ -0,0 +1,99 @@
+use std::fmt;
+
+use chrono::{DateTime, FixedOffset, NaiveDate};
+use futures::future::{self, BoxFuture};
+use influxdb2::models::data_point::DataPoint;
+use reqwest::StatusCode;
+
+/// Why a write failed, which decides whether it's worth trying again.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum WriteError {
+    /// The sink couldn't be reached or failed on its side, e.g. a connection error,
+    /// timeout or 5xx response, so the same write may go through later.
+    Unavailable(String),
+    /// The points themselves were refused, e.g. a 4xx response for a field type
+    /// conflict or bad line protocol, so writing them again won't help.
+    Rejected(String),
+}
+
+impl WriteError {
+    /// Sorts an error response by its status. 429 (too many requests) is only
+    /// a matter of waiting, so it counts as unavailable.
+    pub fn from_status(status: StatusCode, message: String) -> WriteError {
+        if status.is_client_error() && status != StatusCode::TOO_MANY_REQUESTS {
+            WriteError::Rejected(message)
+        } else {
+            WriteError::Unavailable(message)
+        }
+    }
+}
+
+impl fmt::Display for WriteError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            WriteError::Unavailable(reason) => write!(f, "{}", reason),
+            WriteError::Rejected(reason) => write!(f, "points rejected: {}", reason),
+        }
+    }
+}
+
+/// Somewhere uploaded datapoints end up, picked by the 'backend' config. UploadManager
+/// hands every batch to its sink, so another backend is just another impl.
+///
+/// Sinks that can't be queried keep the default lookups, which treat nothing as
+/// uploaded yet.
+pub trait DataSink: Send {
+    /// Writes a batch of points.
+    fn write(&mut self, points: Vec<DataPoint>) -> BoxFuture<'_, Result<(), WriteError>>;
+
+    /// Called at the end of each upload, for sinks that only write out what they've
+    /// been given at the end (e.g. the prometheus textfile).
+    fn flush(&mut self) -> Result<(), String> {
+        Ok(())
+    }
+
+    /// Drops and rebuilds any connection, before a failed write is retried.
+    fn reconnect(&mut self) -> Result<(), String> {
+        Ok(())
+    }
+
+    /// Checks the sink is reachable and accepts the configured credentials.
+    fn validate(&mut self) -> BoxFuture<'_, Result<(), String>> {
+        Box::pin(future::ready(Ok(())))
+    }
+
+    /// Every value of 'tag' already written to 'measurement', e.g. uploaded activityIds.
+    fn tag_values<'a>(
+        &'a mut self,
+        _measurement: &'a str,
+        _tag: &'a str,
+    ) -> BoxFuture<'a, Result<Vec<String>, String>> {
+        Box::pin(future::ready(Ok(Vec::new())))
+    }
+
+    /// Every date with points in 'measurement', after shifting each timestamp
+    /// 'shift_secs' seconds (e.g. into the local timezone).
+    fn existing_dates<'a>(
+        &'a mut self,
+        _measurement: &'a str,
+        _shift_secs: i64,
+    ) -> BoxFuture<'a, Result<Vec<NaiveDate>, String>> {
+        Box::pin(future::ready(Err(String::from(
+            "this backend can't be queried",
+        ))))
+    }
+
+    /// Whether failed writes (and 'offline_mode') go to the offline line protocol
+    /// file instead, to be imported into InfluxDB later.
+    fn writes_offline(&self) -> bool {
+        false
+    }
+}
+
+// the date of a day starting at 'start', i.e. midnight UTC shifted 'shift_secs' earlier
+pub(crate) fn date_of_day_start(
+    start: DateTime<FixedOffset>,
+    shift_secs: i64,
+) -> Option<NaiveDate> {
+    DateTime::from_timestamp(start.timestamp() + shift_secs, 0).map(|time| time.date_naive())
+}
@@ -0,0 +1,232 @@
+use std::collections::{BTreeMap, HashMap, HashSet};
+
+use chrono::{DateTime, Days, NaiveDate};
+use futures::future::{self, BoxFuture};
+use influxdb2::models::data_point::{DataPoint, FieldValue};
+use log::{info, warn};
+use rusqlite::types::Value;
+use rusqlite::{params_from_iter, Connection};
+
+use crate::line_protocol;
+use crate::sink::{DataSink, WriteError};
+
+/// Writes datapoints into a local SQLite database, one table per measurement.
+///
+/// Tables are created the first time a measurement is seen, and a column is added
+/// for every tag and field as they show up. Each row is keyed by the point's
+/// timestamp plus its tag set, so uploading the same file twice updates rows
+/// rather than duplicating them.
+pub struct SqliteSink {
+    conn: Connection,
+    // table name -> known column names
+    columns: HashMap<String, HashSet<String>>,
+}
+
+impl SqliteSink {
+    pub fn new(path: &str) -> rusqlite::Result<SqliteSink> {
+        let conn = Connection::open(path)?;
+        info!("Opened sqlite database: {}", path);
+        Ok(SqliteSink {
+            conn,
+            columns: HashMap::new(),
+        })
+    }
+
+    /// Upserts all datapoints, returning the number of rows written.
+    pub fn write(&mut self, data: &[DataPoint]) -> rusqlite::Result<usize> {
+        let mut rows = 0;
+        for point in data {
+            let Some(parsed) = line_protocol::parse_point(point) else {
+                warn!("Unable to convert datapoint for sqlite: {:?}", point);
+                continue;
+            };
+            self.ensure_table(&parsed.measurement)?;
+
+            let mut names: Vec<String> = vec![String::from("timestamp"), String::from("tags")];
+            let mut values: Vec<Value> = vec![
+                Value::Integer(parsed.timestamp.unwrap_or(0)),
+                Value::Text(tag_key(&parsed.tags)),
+            ];
+
+            for (tag, value) in &parsed.tags {
+                self.ensure_column(&parsed.measurement, tag, "TEXT")?;
+                names.push(tag.clone());
+                values.push(Value::Text(value.clone()));
+            }
+            for (field, value) in &parsed.fields {
+                // tag columns win if a field happens to share a name with one
+                if parsed.tags.contains_key(field) {
+                    continue;
+                }
+                let (column_type, value) = match value {
+                    FieldValue::F64(v) => ("REAL", Value::Real(*v)),
+                    FieldValue::I64(v) => ("INTEGER", Value::Integer(*v)),
+                    FieldValue::Bool(v) => ("INTEGER", Value::Integer(*v as i64)),
+                    FieldValue::String(v) => ("TEXT", Value::Text(v.clone())),
+                };
+                self.ensure_column(&parsed.measurement, field, column_type)?;
+                names.push(field.clone());
+                values.push(value);
+            }
+
+            let quoted: Vec<String> = names.iter().map(|n| quote(n)).collect();
+            let placeholders: Vec<&str> = names.iter().map(|_| "?").collect();
+            let updates: Vec<String> = quoted[2..]
+                .iter()
+                .map(|n| format!("{}=excluded.{}", n, n))
+                .collect();
+            let mut sql = format!(
+                "INSERT INTO {} ({}) VALUES ({}) ON CONFLICT(timestamp, tags) DO ",
+                quote(&parsed.measurement),
+                quoted.join(", "),
+                placeholders.join(", ")
+            );
+            if updates.is_empty() {
+                sql.push_str("NOTHING");
+            } else {
+                sql.push_str(&format!("UPDATE SET {}", updates.join(", ")));
+            }
+
+            rows += self.conn.execute(&sql, params_from_iter(values))?;
+        }
+        Ok(rows)
+    }
+
+    /// Returns all distinct values stored for a tag, e.g. previously uploaded activityIds.
+    pub fn tag_values(&self, table: &str, tag: &str) -> Vec<String> {
+        let sql = format!(
+            "SELECT DISTINCT {} FROM {} WHERE {} IS NOT NULL",
+            quote(tag),
+            quote(table),
+            quote(tag)
+        );
+        // a missing table or column just means nothing has been uploaded yet
+        match self.conn.prepare(&sql) {
+            Ok(mut statement) => match statement.query_map([], |row| row.get::<_, String>(0)) {
+                Ok(rows) => rows.flatten().collect(),
+                Err(_) => vec![],
+            },
+            Err(_) => vec![],
+        }
+    }
+
+    /// Returns every distinct day (since the Unix epoch) with a row in 'table', after
+    /// shifting each timestamp 'shift_secs' seconds, e.g. into the local timezone.
+    pub fn days(&self, table: &str, shift_secs: i64) -> Vec<i64> {
+        let sql = format!(
+            "SELECT DISTINCT (timestamp + ?1) / 86400000000000 FROM {}",
+            quote(table)
+        );
+        // a missing table just means nothing has been uploaded yet
+        match self.conn.prepare(&sql) {
+            Ok(mut statement) => {
+                match statement.query_map([shift_secs * 1_000_000_000], |row| row.get::<_, i64>(0))
+                {
+                    Ok(rows) => rows.flatten().collect(),
+                    Err(_) => vec![],
+                }
+            }
+            Err(_) => vec![],
+        }
+    }
+
+    fn ensure_table(&mut self, table: &str) -> rusqlite::Result<()> {
+        if self.columns.contains_key(table) {
+            return Ok(());
+        }
+        self.conn.execute(
+            &format!(
+                "CREATE TABLE IF NOT EXISTS {} (timestamp INTEGER NOT NULL, tags TEXT NOT NULL, PRIMARY KEY (timestamp, tags))",
+                quote(table)
+            ),
+            [],
+        )?;
+
+        // pick up columns from any previous runs
+        let mut statement = self
+            .conn
+            .prepare(&format!("PRAGMA table_info({})", quote(table)))?;
+        let existing: HashSet<String> = statement
+            .query_map([], |row| row.get::<_, String>(1))?
+            .flatten()
+            .collect();
+        self.columns.insert(String::from(table), existing);
+        Ok(())
+    }
+
+    fn ensure_column(
+        &mut self,
+        table: &str,
+        column: &str,
+        column_type: &str,
+    ) -> rusqlite::Result<()> {
+        if self.columns[table].contains(column) {
+            return Ok(());
+        }
+        self.conn.execute(
+            &format!(
+                "ALTER TABLE {} ADD COLUMN {} {}",
+                quote(table),
+                quote(column),
+                column_type
+            ),
+            [],
+        )?;
+        self.columns
+            .get_mut(table)
+            .unwrap()
+            .insert(String::from(column));
+        Ok(())
+    }
+}
+
+impl DataSink for SqliteSink {
+    fn write(&mut self, points: Vec<DataPoint>) -> BoxFuture<'_, Result<(), WriteError>> {
+        let result = match SqliteSink::write(self, &points) {
+            Ok(rows) => {
+                info!("Wrote {} rows to sqlite!", rows);
+                Ok(())
+            }
+            Err(e) => Err(WriteError::Unavailable(format!("sqlite: {}", e))),
+        };
+        Box::pin(future::ready(result))
+    }
+
+    fn tag_values<'a>(
+        &'a mut self,
+        measurement: &'a str,
+        tag: &'a str,
+    ) -> BoxFuture<'a, Result<Vec<String>, String>> {
+        Box::pin(future::ready(Ok(SqliteSink::tag_values(
+            self,
+            measurement,
+            tag,
+        ))))
+    }
+
+    fn existing_dates<'a>(
+        &'a mut self,
+        measurement: &'a str,
+        shift_secs: i64,
+    ) -> BoxFuture<'a, Result<Vec<NaiveDate>, String>> {
+        let epoch = DateTime::UNIX_EPOCH.date_naive();
+        let dates = self
+            .days(measurement, shift_secs)
+            .into_iter()
+            .filter_map(|day| epoch.checked_add_days(Days::new(u64::try_from(day).ok()?)))
+            .collect();
+        Box::pin(future::ready(Ok(dates)))
+    }
+}
+
+// stable representation of the tag set, used as part of the row key
+fn tag_key(tags: &BTreeMap<String, String>) -> String {
+    tags.iter()
+        .map(|(k, v)| format!("{}={}", k, v))
+        .collect::<Vec<String>>()
+        .join(",")
+}
+
+fn quote(identifier: &str) -> String {
+    format!("\"{}\"", identifier.replace('"', "\"\""))
+}
@@ -0,0 +1,106 @@
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io;
+use std::path::Path;
+
+use log::warn;
+use sha2::{Digest, Sha256};
+
+/// Kept in file_base_path unless 'upload_state_file' says otherwise.
+pub const UPLOAD_STATE_FILE: &str = ".garmin_upload_state.json";
+
+/// Content hashes of the monitoring FIT files already uploaded, so a scheduled
+/// upload skips the ones that haven't changed since.
+#[derive(Debug, Default)]
+pub struct UploadState {
+    // file_key() -> sha256 of the file's contents
+    hashes: BTreeMap<String, String>,
+}
+
+impl UploadState {
+    /// Reads the hashes recorded by earlier uploads. Without a state file (or with one
+    /// that can't be read) nothing counts as uploaded, so every monitoring file is
+    /// uploaded again.
+    pub fn load(path: &Path) -> UploadState {
+        match garmin_client::load_json(path) {
+            Ok(hashes) => UploadState {
+                hashes: hashes.unwrap_or_default(),
+            },
+            Err(e) => {
+                warn!(
+                    "Ignoring unreadable upload state {}, error: {}",
+                    path.display(),
+                    e
+                );
+                UploadState::default()
+            }
+        }
+    }
+
+    pub fn is_unchanged(&self, key: &str, hash: &str) -> bool {
+        self.hashes
+            .get(key)
+            .is_some_and(|uploaded| uploaded == hash)
+    }
+
+    pub fn record(&mut self, key: String, hash: String) {
+        self.hashes.insert(key, hash);
+    }
+
+    /// Saves the hashes once an upload has written every point of their files, for
+    /// the next upload to compare against.
+    pub fn save(&self, path: &Path) -> io::Result<()> {
+        garmin_client::save_json(path, &self.hashes)
+    }
+}
+
+// one date has a file per monitoring metric, so the metric is part of the key
+pub fn file_key(metric: &str, path: &Path) -> String {
+    let name = path.file_name().unwrap_or_default().to_string_lossy();
+    format!("{}/{}", metric, name)
+}
+
+pub fn file_hash(path: &Path) -> io::Result<String> {
+    let mut hasher = Sha256::new();
+    io::copy(&mut File::open(path)?, &mut hasher)?;
+    Ok(hasher
+        .finalize()
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    #[test]
+    fn record_and_reload_state_test() {
+        let folder =
+            std::env::temp_dir().join(format!("garmin_upload_state_test_{}", std::process::id()));
+        fs::create_dir_all(&folder).unwrap();
+        let fit_file = folder.join("1234_WELLNESS.fit");
+        fs::write(&fit_file, "monitoring data").unwrap();
+        let state_file = folder.join(UPLOAD_STATE_FILE);
+
+        let hash = file_hash(&fit_file).unwrap();
+        let key = file_key("WELLNESS", &fit_file);
+        assert_eq!(key, "WELLNESS/1234_WELLNESS.fit");
+
+        let mut state = UploadState::load(&state_file);
+        assert!(!state.is_unchanged(&key, &hash));
+        state.record(key.clone(), hash.clone());
+        state.save(&state_file).unwrap();
+
+        let state = UploadState::load(&state_file);
+        assert!(state.is_unchanged(&key, &hash));
+        // same file name, but a different metric
+        assert!(!state.is_unchanged("HRV_STATUS/1234_WELLNESS.fit", &hash));
+
+        fs::write(&fit_file, "more monitoring data").unwrap();
+        assert!(!state.is_unchanged(&key, &file_hash(&fit_file).unwrap()));
+
+        fs::remove_dir_all(&folder).unwrap();
+    }
+}